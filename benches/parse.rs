@@ -0,0 +1,137 @@
+//! Reading a synthetic save of a few megabytes: many small objects of one class, and large primitive arrays.
+//!
+//! Run with `cargo bench`. Parsing from memory or through a BufReader is compared with parsing
+//! straight from a File, which reads every field of every record from the operating system.
+
+#![feature(test)]
+#![allow(non_snake_case)]
+
+extern crate test;
+extern crate csharp_deserialize;
+
+use std::fs::{File, OpenOptions};
+use std::io::BufReader;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use test::Bencher;
+
+use csharp_deserialize::records::*;
+use csharp_deserialize::{Primitive, read_document};
+
+const ENTITY_COUNT: i32 = 20000;
+const ARRAY_LENGTH: i32 = 200000;
+
+/// Save { positions: double[], ids: int[], entities: object[] } with the entities written after the arrays
+fn synthetic_stream() -> Vec<u8> {
+	let mut bytes = vec![];
+	let mut next_id = 1;
+	let mut new_id = || { next_id += 1; next_id - 1 };
+
+	let save_id = new_id();
+	SerializationHeaderRecord {
+		RootId: save_id,
+		HeaderId: -1,
+		MajorVersion: 1,
+		MinorVersion: 0,
+	}.write(&mut bytes);
+	BinaryLibraryRecord {
+		LibraryId: 2,
+		LibraryName: "Assembly-CSharp, Version=0.0.0.0, Culture=neutral, PublicKeyToken=null".to_string(),
+	}.write(&mut bytes);
+
+	let (positions_id, ids_id, entities_id) = (new_id(), new_id(), new_id());
+	let save = ClassWithMembersAndTypesRecord {
+		ClassInfo: ClassInfoRecord {
+			ObjectId: save_id,
+			Name: "Game.Save".to_string(),
+			MemberCount: 3,
+			MemberNames: vec!["positions".to_string(), "ids".to_string(), "entities".to_string()],
+		},
+		MemberTypeInfo: MemberTypeInfoRecord {
+			BinaryTypeEnums: vec![BinaryTypeEnumeration::PrimitiveArray, BinaryTypeEnumeration::PrimitiveArray, BinaryTypeEnumeration::ObjectArray],
+			AdditionalInfos: vec![AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Double), AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32), AdditionalInfo::None],
+		},
+		LibraryId: 2,
+	};
+	save.write_class(&mut bytes);
+	save.write_value(&mut bytes, &vec![
+		Value::MemberReference(MemberReferenceRecord { IdRef: positions_id }),
+		Value::MemberReference(MemberReferenceRecord { IdRef: ids_id }),
+		Value::MemberReference(MemberReferenceRecord { IdRef: entities_id }),
+	]);
+
+	ArraySinglePrimitiveRecord {
+		ArrayInfo: ArrayInfoRecord { ObjectId: positions_id, Length: ARRAY_LENGTH },
+		PrimitiveTypeEnum: PrimitiveTypeEnumeration::Double,
+		Values: PrimitiveValues::Double((0..ARRAY_LENGTH).map(|i| i as f64 * 0.5).collect()),
+	}.write(&mut bytes);
+	ArraySinglePrimitiveRecord {
+		ArrayInfo: ArrayInfoRecord { ObjectId: ids_id, Length: ARRAY_LENGTH },
+		PrimitiveTypeEnum: PrimitiveTypeEnumeration::Int32,
+		Values: PrimitiveValues::Int32((0..ARRAY_LENGTH).collect()),
+	}.write(&mut bytes);
+
+	let entity_ids: Vec<i32> = (0..ENTITY_COUNT).map(|_| new_id()).collect();
+	ArraySingleObjectRecord {
+		ArrayInfo: ArrayInfoRecord { ObjectId: entities_id, Length: ENTITY_COUNT },
+		Values: entity_ids.iter().map(|id| Value::MemberReference(MemberReferenceRecord { IdRef: *id })).collect(),
+	}.write(&mut bytes);
+
+	let entity = ClassWithMembersAndTypesRecord {
+		ClassInfo: ClassInfoRecord {
+			ObjectId: entity_ids[0],
+			Name: "Game.Entity".to_string(),
+			MemberCount: 4,
+			MemberNames: vec!["name".to_string(), "x".to_string(), "y".to_string(), "alive".to_string()],
+		},
+		MemberTypeInfo: MemberTypeInfoRecord {
+			BinaryTypeEnums: vec![BinaryTypeEnumeration::String, BinaryTypeEnumeration::Primitive, BinaryTypeEnumeration::Primitive, BinaryTypeEnumeration::Primitive],
+			AdditionalInfos: vec![AdditionalInfo::None, AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32), AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Single), AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Boolean)],
+		},
+		LibraryId: 2,
+	};
+	for (i, id) in entity_ids.iter().enumerate() {
+		if i == 0 {
+			entity.write_class(&mut bytes);
+		} else {
+			ClassWithIdRecord { ObjectId: *id, MetadataId: entity_ids[0] }.write(&mut bytes);
+		}
+		entity.write_value(&mut bytes, &vec![
+			Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: new_id(), Value: format!("entity {}", i) }),
+			Value::Primitive(Primitive::Int32(i as i32)),
+			Value::Primitive(Primitive::Single(i as f32 * 2.0)),
+			Value::Primitive(Primitive::Boolean(i % 2 == 0)),
+		]);
+	}
+
+	MessageEndRecord {}.write(&mut bytes);
+	bytes
+}
+
+fn synthetic_file() -> PathBuf {
+	let path = std::env::temp_dir().join("csharp_deserialize_bench.bytes");
+	OpenOptions::new().write(true).create(true).truncate(true).open(&path).unwrap().write_all(&synthetic_stream()).unwrap();
+	path
+}
+
+#[bench]
+fn read_from_slice(b: &mut Bencher) {
+	let bytes = synthetic_stream();
+	b.bytes = bytes.len() as u64;
+	b.iter(|| read_document(&bytes[..]).unwrap());
+}
+
+#[bench]
+fn read_from_buffered_file(b: &mut Bencher) {
+	let path = synthetic_file();
+	b.bytes = std::fs::metadata(&path).unwrap().len();
+	b.iter(|| read_document(BufReader::new(File::open(&path).unwrap())).unwrap());
+}
+
+#[bench]
+fn read_from_file(b: &mut Bencher) {
+	let path = synthetic_file();
+	b.bytes = std::fs::metadata(&path).unwrap().len();
+	b.iter(|| read_document(File::open(&path).unwrap()).unwrap());
+}
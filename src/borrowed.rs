@@ -0,0 +1,773 @@
+//! Parsing from a byte slice without copying, for scanning many files for a few fields.
+//!
+//! Strings, class names and member names are `&str` pointing into the input once checked to be UTF-8.
+//! Elements of fixed size of primitive arrays stay as the bytes of the stream and are decoded when
+//! accessed, so that no alignment is needed; byte arrays are plain `&[u8]`. Records written inline
+//! are kept as values of the record containing them, as in the lossless module. Records without
+//! strings are the record structs of the records module.
+
+use std;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::rc::Rc;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use records::*;
+use model::Primitive;
+use schema::Schema;
+
+/// Reads from a byte slice, handing out parts of it
+pub struct SliceReader<'a> {
+	bytes: &'a [u8],
+	offset: usize,
+}
+
+impl<'a> SliceReader<'a> {
+	pub fn new(bytes: &'a [u8]) -> Self {
+		SliceReader {
+			bytes: bytes,
+			offset: 0,
+		}
+	}
+
+	/// What is left to read
+	pub fn get_rest(&self) -> &'a [u8] {
+		&self.bytes[self.offset..]
+	}
+
+	/// At the end of the input, everything left is taken as read
+	pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], DeserializeError> {
+		let rest = self.get_rest();
+		if n > rest.len() {
+			self.offset = self.bytes.len();
+			return Err(DeserializeError::UnexpectedEof(error_at(self, 0)));
+		}
+		self.offset += n;
+		Ok(&rest[..n])
+	}
+
+	/// A LengthPrefixedString without copying
+	pub fn read_str(&mut self) -> Result<&'a str, DeserializeError> {
+		let length = try!(read_string_length(self));
+		let bytes = try!(self.read_bytes(length));
+		match std::str::from_utf8(bytes) {
+			Ok(s) => Ok(s),
+			Err(e) => Err(DeserializeError::InvalidUtf8(error_at(self, (length - e.valid_up_to()) as u64))),
+		}
+	}
+}
+
+impl<'a> Read for SliceReader<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = try!(self.get_rest().read(buf));
+		self.offset += n;
+		Ok(n)
+	}
+}
+
+impl<'a> OffsetRead for SliceReader<'a> {
+	fn offset(&self) -> u64 {
+		self.offset as u64
+	}
+}
+
+/// Type of a member or of the elements of an array. Class names come from the schema for classes written without member types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BorrowedMemberType<'a> {
+	Primitive(PrimitiveTypeEnumeration),
+	String,
+	Object,
+	SystemClass(Cow<'a, str>),
+	/// Name of the class and id of its library
+	Class(Cow<'a, str>, i32),
+	ObjectArray,
+	StringArray,
+	PrimitiveArray(PrimitiveTypeEnumeration),
+}
+
+#[derive(Debug)]
+pub struct BorrowedClass<'a> {
+	/// Object id of the record defining the class
+	pub id: i32,
+	pub name: &'a str,
+	/// None for classes of mscorlib
+	pub library_id: Option<i32>,
+	pub member_names: Vec<&'a str>,
+	pub member_types: Vec<BorrowedMemberType<'a>>,
+	/// One of the four records defining a class, i.e. whether the member types were written
+	pub record_type: RecordTypeEnumeration,
+}
+
+impl<'a> BorrowedClass<'a> {
+	pub fn get_member_index(&self, name: &str) -> Option<usize> {
+		self.member_names.iter().position(|member_name| *member_name == name)
+	}
+}
+
+/// Elements of a primitive array
+#[derive(Debug)]
+pub enum PrimitiveSlice<'a> {
+	/// Elements of fixed size as written, little endian
+	Fixed(PrimitiveTypeEnumeration, &'a [u8]),
+	Char(Vec<char>),
+	Decimal(Vec<&'a str>),
+	String(Vec<&'a str>),
+}
+
+impl<'a> PrimitiveSlice<'a> {
+	pub fn read(file: &mut SliceReader<'a>, primitive_type: &PrimitiveTypeEnumeration, length: usize) -> Result<Self, DeserializeError> {
+		use sub_records::PrimitiveTypeEnumeration as P;
+
+		if let Some(size) = primitive_type.get_size() {
+			let start = file.offset();
+			let bytes = try!(file.read_bytes(length * size).map_err(|e| {
+				let i = (e.offset() - start) / size as u64;
+				e.within(format!("element {}", i))
+			}));
+			return Ok(PrimitiveSlice::Fixed(*primitive_type, bytes));
+		}
+		Ok(match primitive_type {
+			&P::Char => {
+				let mut v = vec![];
+				for i in 0..length {
+					v.push(try!(read_char(file).map_err(|e| e.within(format!("element {}", i)))));
+				}
+				PrimitiveSlice::Char(v)
+			},
+			&P::Decimal | &P::String => {
+				let mut v = vec![];
+				for i in 0..length {
+					v.push(try!(file.read_str().map_err(|e| e.within(format!("element {}", i)))));
+				}
+				match primitive_type {
+					&P::Decimal => PrimitiveSlice::Decimal(v),
+					_ => PrimitiveSlice::String(v),
+				}
+			},
+			&P::Null => return Err(DeserializeError::InvalidEnumValue(error_at(file, 0), "PrimitiveTypeEnumeration of an array", P::Null as u8)),
+			_ => unreachable!(),
+		})
+	}
+
+	pub fn get_primitive_type(&self) -> PrimitiveTypeEnumeration {
+		match self {
+			&PrimitiveSlice::Fixed(primitive_type, _) => primitive_type,
+			&PrimitiveSlice::Char(_) => PrimitiveTypeEnumeration::Char,
+			&PrimitiveSlice::Decimal(_) => PrimitiveTypeEnumeration::Decimal,
+			&PrimitiveSlice::String(_) => PrimitiveTypeEnumeration::String,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		match self {
+			&PrimitiveSlice::Fixed(primitive_type, bytes) => bytes.len() / primitive_type.get_size().unwrap(),
+			&PrimitiveSlice::Char(ref v) => v.len(),
+			&PrimitiveSlice::Decimal(ref v) => v.len(),
+			&PrimitiveSlice::String(ref v) => v.len(),
+		}
+	}
+
+	/// None for the elements of String arrays, see as_strs
+	pub fn get(&self, i: usize) -> Option<Primitive> {
+		match self {
+			&PrimitiveSlice::Fixed(primitive_type, bytes) => {
+				let size = primitive_type.get_size().unwrap();
+				if (i + 1) * size <= bytes.len() {
+					Some(decode_primitive(&primitive_type, &bytes[i * size..(i + 1) * size]))
+				} else {
+					None
+				}
+			},
+			&PrimitiveSlice::Char(ref v) => v.get(i).map(|x| Primitive::Char(*x)),
+			&PrimitiveSlice::Decimal(ref v) => v.get(i).map(|x| Primitive::Decimal(Decimal { Value: x.to_string() })),
+			&PrimitiveSlice::String(_) => None,
+		}
+	}
+
+	/// The elements of a Byte array
+	pub fn as_bytes(&self) -> Option<&'a [u8]> {
+		match self {
+			&PrimitiveSlice::Fixed(PrimitiveTypeEnumeration::Byte, bytes) => Some(bytes),
+			_ => None,
+		}
+	}
+
+	/// The elements of a String array
+	pub fn as_strs(&self) -> Option<&[&'a str]> {
+		match self {
+			&PrimitiveSlice::String(ref v) => Some(&v[..]),
+			_ => None,
+		}
+	}
+}
+
+/// A value of fixed size from its bytes
+fn decode_primitive(primitive_type: &PrimitiveTypeEnumeration, bytes: &[u8]) -> Primitive {
+	use sub_records::PrimitiveTypeEnumeration as P;
+
+	match primitive_type {
+		&P::Boolean => Primitive::Boolean(bytes[0] == 1),
+		&P::Byte => Primitive::Byte(bytes[0]),
+		&P::SByte => Primitive::SByte(bytes[0] as i8),
+		&P::Double => Primitive::Double(LittleEndian::read_f64(bytes)),
+		&P::Int16 => Primitive::Int16(LittleEndian::read_i16(bytes)),
+		&P::Int32 => Primitive::Int32(LittleEndian::read_i32(bytes)),
+		&P::Int64 => Primitive::Int64(LittleEndian::read_i64(bytes)),
+		&P::Single => Primitive::Single(LittleEndian::read_f32(bytes)),
+		&P::TimeSpan => Primitive::TimeSpan(TimeSpan { Ticks: LittleEndian::read_i64(bytes) }),
+		&P::DateTime => Primitive::DateTime(DateTime::from_raw(LittleEndian::read_i64(bytes))),
+		&P::UInt16 => Primitive::UInt16(LittleEndian::read_u16(bytes)),
+		&P::UInt32 => Primitive::UInt32(LittleEndian::read_u32(bytes)),
+		&P::UInt64 => Primitive::UInt64(LittleEndian::read_u64(bytes)),
+		s @ _ => panic!("{:?} has no fixed size", s),
+	}
+}
+
+#[derive(Debug)]
+pub enum BorrowedElements<'a> {
+	Primitive(PrimitiveSlice<'a>),
+	Records(Vec<BorrowedValue<'a>>),
+}
+
+/// A BinaryArray record
+#[derive(Debug)]
+pub struct BorrowedArray<'a> {
+	pub id: i32,
+	pub array_type: BinaryArrayTypeEnumeration,
+	pub lengths: Vec<i32>,
+	pub lower_bounds: Option<Vec<i32>>,
+	pub element_type: BorrowedMemberType<'a>,
+	/// The last dimension varies fastest
+	pub elements: BorrowedElements<'a>,
+}
+
+#[derive(Debug)]
+pub enum BorrowedRecord<'a> {
+	SerializedStreamHeader(SerializationHeaderRecord),
+	/// An object of a class defined before
+	ClassWithId(ClassWithIdRecord, Rc<BorrowedClass<'a>>, Vec<BorrowedValue<'a>>),
+	/// An object defining its class, with one of the four record types given by the class
+	Class(Rc<BorrowedClass<'a>>, Vec<BorrowedValue<'a>>),
+	/// Object id and value
+	BinaryObjectString(i32, &'a str),
+	BinaryArray(BorrowedArray<'a>),
+	MemberPrimitiveTyped(MemberPrimitiveTypedRecord),
+	MemberReference(MemberReferenceRecord),
+	ObjectNull(ObjectNullRecord),
+	MessageEnd(MessageEndRecord),
+	/// Library id and name
+	BinaryLibrary(i32, &'a str),
+	ObjectNullMultiple256(ObjectNullMultiple256Record),
+	ObjectNullMultiple(ObjectNullMultipleRecord),
+	ArraySinglePrimitive(ArrayInfoRecord, PrimitiveSlice<'a>),
+	ArraySingleObject(ArrayInfoRecord, Vec<BorrowedValue<'a>>),
+	ArraySingleString(ArrayInfoRecord, Vec<BorrowedValue<'a>>),
+	/// A BinaryLibrary record written right before a member value or an array element needing it
+	LibraryWithValue(i32, &'a str, Box<BorrowedValue<'a>>),
+}
+
+impl<'a> BorrowedRecord<'a> {
+	/// Id of objects, strings and arrays
+	pub fn get_object_id(&self) -> Option<i32> {
+		use self::BorrowedRecord::*;
+		match self {
+			&ClassWithId(ref r, _, _) => Some(r.ObjectId),
+			&Class(ref class, _) => Some(class.id),
+			&BinaryObjectString(id, _) => Some(id),
+			&BinaryArray(ref array) => Some(array.id),
+			&ArraySinglePrimitive(ref info, _) | &ArraySingleObject(ref info, _) | &ArraySingleString(ref info, _) => Some(info.ObjectId),
+			_ => None,
+		}
+	}
+
+	/// The class of objects
+	pub fn get_class(&self) -> Option<&BorrowedClass<'a>> {
+		match self {
+			&BorrowedRecord::ClassWithId(_, ref class, _) | &BorrowedRecord::Class(ref class, _) => Some(&**class),
+			_ => None,
+		}
+	}
+
+	/// Member values of objects and elements of arrays holding records
+	pub fn get_values(&self) -> Option<&Vec<BorrowedValue<'a>>> {
+		use self::BorrowedRecord::*;
+		match self {
+			&ClassWithId(_, _, ref values) | &Class(_, ref values) => Some(values),
+			&BinaryArray(BorrowedArray { elements: BorrowedElements::Records(ref values), .. }) => Some(values),
+			&ArraySingleObject(_, ref values) | &ArraySingleString(_, ref values) => Some(values),
+			_ => None,
+		}
+	}
+
+	/// A member value of an object by name
+	pub fn get_member(&self, name: &str) -> Option<&BorrowedValue<'a>> {
+		match (self.get_class(), self.get_values()) {
+			(Some(class), Some(values)) => class.get_member_index(name).and_then(|i| values.get(i)),
+			_ => None,
+		}
+	}
+}
+
+/// A member value or an array element: a primitive without a record, or a record
+#[derive(Debug)]
+pub enum BorrowedValue<'a> {
+	Primitive(Primitive),
+	Record(BorrowedRecord<'a>),
+}
+
+impl<'a> BorrowedValue<'a> {
+	/// Number of array elements the value stands for
+	pub fn get_element_count(&self) -> usize {
+		match self {
+			&BorrowedValue::Record(BorrowedRecord::ObjectNullMultiple256(ref r)) => r.NullCount as usize,
+			&BorrowedValue::Record(BorrowedRecord::ObjectNullMultiple(ref r)) => r.NullCount as usize,
+			&BorrowedValue::Record(BorrowedRecord::LibraryWithValue(_, _, ref value)) => value.get_element_count(),
+			_ => 1,
+		}
+	}
+
+	/// Primitives, with or without a MemberPrimitiveTyped record
+	pub fn as_primitive(&self) -> Option<&Primitive> {
+		match self {
+			&BorrowedValue::Primitive(ref primitive) => Some(primitive),
+			&BorrowedValue::Record(BorrowedRecord::MemberPrimitiveTyped(ref r)) => Some(&r.Value),
+			_ => None,
+		}
+	}
+
+	/// Strings written inline
+	pub fn as_str(&self) -> Option<&'a str> {
+		match self {
+			&BorrowedValue::Record(BorrowedRecord::BinaryObjectString(_, s)) => Some(s),
+			_ => None,
+		}
+	}
+
+	pub fn as_reference(&self) -> Option<i32> {
+		match self {
+			&BorrowedValue::Record(BorrowedRecord::MemberReference(ref r)) => Some(r.IdRef),
+			_ => None,
+		}
+	}
+
+	pub fn as_record(&self) -> Option<&BorrowedRecord<'a>> {
+		match self {
+			&BorrowedValue::Record(ref record) => Some(record),
+			_ => None,
+		}
+	}
+}
+
+/// Classes read so far, by id for ClassWithId records and by name for the classes written without member types
+struct Context<'s, 'a> {
+	classes: HashMap<i32, Rc<BorrowedClass<'a>>>,
+	typed_classes: HashMap<&'a str, Rc<BorrowedClass<'a>>>,
+	schema: &'s Schema,
+	depth: usize,
+}
+
+impl<'s, 'a> Context<'s, 'a> {
+	/// Classes of the stream read with their member types come first, then the schema
+	fn get_member_types(&self, class_name: &'a str, member_names: &Vec<&'a str>) -> Result<Vec<BorrowedMemberType<'a>>, String> {
+		if let Some(class) = self.typed_classes.get(class_name) {
+			if class.member_names == *member_names {
+				return Ok(class.member_types.clone());
+			}
+		}
+		let member_type_info = try!(self.schema.get_member_types(class_name, member_names));
+		Ok(member_type_info.BinaryTypeEnums.iter().zip(member_type_info.AdditionalInfos.iter())
+			.map(|(binary_type, additional_info)| member_type_of(binary_type, additional_info))
+			.collect())
+	}
+}
+
+fn member_type_of<'a>(binary_type: &BinaryTypeEnumeration, additional_info: &AdditionalInfo) -> BorrowedMemberType<'a> {
+	match (binary_type, additional_info) {
+		(&BinaryTypeEnumeration::Primitive, &AdditionalInfo::Primitive(primitive_type)) => BorrowedMemberType::Primitive(primitive_type),
+		(&BinaryTypeEnumeration::PrimitiveArray, &AdditionalInfo::Primitive(primitive_type)) => BorrowedMemberType::PrimitiveArray(primitive_type),
+		(&BinaryTypeEnumeration::SystemClass, &AdditionalInfo::SystemClass(ref class_name)) => BorrowedMemberType::SystemClass(Cow::Owned(class_name.clone())),
+		(&BinaryTypeEnumeration::Class, &AdditionalInfo::Class(ref class_type)) => {
+			BorrowedMemberType::Class(Cow::Owned(class_type.TypeName.clone()), class_type.LibraryId)
+		},
+		(&BinaryTypeEnumeration::String, _) => BorrowedMemberType::String,
+		(&BinaryTypeEnumeration::Object, _) => BorrowedMemberType::Object,
+		(&BinaryTypeEnumeration::ObjectArray, _) => BorrowedMemberType::ObjectArray,
+		(&BinaryTypeEnumeration::StringArray, _) => BorrowedMemberType::StringArray,
+		(binary_type, additional_info) => unreachable!("{:?} with {:?}", binary_type, additional_info),
+	}
+}
+
+/// The additional info following a BinaryTypeEnumeration
+fn read_member_type<'a>(file: &mut SliceReader<'a>, binary_type: &BinaryTypeEnumeration) -> Result<BorrowedMemberType<'a>, DeserializeError> {
+	Ok(match binary_type {
+		&BinaryTypeEnumeration::Primitive => BorrowedMemberType::Primitive(try!(read_enum(file))),
+		&BinaryTypeEnumeration::String => BorrowedMemberType::String,
+		&BinaryTypeEnumeration::Object => BorrowedMemberType::Object,
+		&BinaryTypeEnumeration::SystemClass => BorrowedMemberType::SystemClass(Cow::Borrowed(try!(file.read_str()))),
+		&BinaryTypeEnumeration::Class => {
+			let type_name = try!(file.read_str());
+			BorrowedMemberType::Class(Cow::Borrowed(type_name), try!(read_l_i32(file)))
+		},
+		&BinaryTypeEnumeration::ObjectArray => BorrowedMemberType::ObjectArray,
+		&BinaryTypeEnumeration::StringArray => BorrowedMemberType::StringArray,
+		&BinaryTypeEnumeration::PrimitiveArray => BorrowedMemberType::PrimitiveArray(try!(read_enum(file))),
+		&BinaryTypeEnumeration::Unknown => unreachable!(),
+	})
+}
+
+/// The four records defining a class, registered before their values, which may be objects of the same class
+fn read_class<'s, 'a>(file: &mut SliceReader<'a>, context: &mut Context<'s, 'a>, record_type: RecordTypeEnumeration) -> Result<Rc<BorrowedClass<'a>>, DeserializeError> {
+	let id = try!(read_l_i32(file));
+	let name = try!(file.read_str());
+	let member_count = try!(read_count(file, "MemberCount")) as usize;
+	let mut member_names = vec![];
+	for _ in 0..member_count {
+		member_names.push(try!(file.read_str()));
+	}
+	let has_member_types = match record_type {
+		RecordTypeEnumeration::SystemClassWithMembersAndTypes | RecordTypeEnumeration::ClassWithMembersAndTypes => true,
+		_ => false,
+	};
+	let mut member_types = vec![];
+	if has_member_types {
+		let mut binary_types: Vec<BinaryTypeEnumeration> = vec![];
+		for _ in 0..member_count {
+			binary_types.push(try!(read_enum(file)));
+		}
+		for binary_type in binary_types.iter() {
+			member_types.push(try!(read_member_type(file, binary_type)));
+		}
+	}
+	let library_id = match record_type {
+		RecordTypeEnumeration::ClassWithMembers | RecordTypeEnumeration::ClassWithMembersAndTypes => Some(try!(read_l_i32(file))),
+		_ => None,
+	};
+	if !has_member_types {
+		member_types = try!(context.get_member_types(name, &member_names)
+			.map_err(|message| DeserializeError::MissingMemberTypes(error_at(file, 0), message)));
+	}
+
+	let class = Rc::new(BorrowedClass {
+		id: id,
+		name: name,
+		library_id: library_id,
+		member_names: member_names,
+		member_types: member_types,
+		record_type: record_type,
+	});
+	context.classes.insert(id, class.clone());
+	if has_member_types {
+		context.typed_classes.insert(name, class.clone());
+	}
+	Ok(class)
+}
+
+fn read_values<'s, 'a>(file: &mut SliceReader<'a>, context: &mut Context<'s, 'a>, class: &BorrowedClass<'a>) -> Result<Vec<BorrowedValue<'a>>, DeserializeError> {
+	let mut values = Vec::with_capacity(class.member_types.len());
+	for (i, member_type) in class.member_types.iter().enumerate() {
+		let value = match member_type {
+			&BorrowedMemberType::Primitive(ref primitive_type) => read_primitive(file, primitive_type).map(BorrowedValue::Primitive),
+			_ => read_value_record(file, context),
+		};
+		values.push(try!(value.map_err(|e| e.within(format!("{}.{}", class.name, class.member_names[i])))));
+	}
+	Ok(values)
+}
+
+/// Elements of string and object arrays are records, where a run of nulls may be a single record
+fn read_elements<'s, 'a>(file: &mut SliceReader<'a>, context: &mut Context<'s, 'a>, length: usize) -> Result<Vec<BorrowedValue<'a>>, DeserializeError> {
+	let mut values = vec![];
+	let mut count = 0;
+	while count < length {
+		let value = try!(read_value_record(file, context).map_err(|e| e.within(format!("element {}", count))));
+		count += value.get_element_count();
+		values.push(value);
+	}
+	Ok(values)
+}
+
+fn read_binary_array<'s, 'a>(file: &mut SliceReader<'a>, context: &mut Context<'s, 'a>) -> Result<BorrowedArray<'a>, DeserializeError> {
+	use sub_records::BinaryArrayTypeEnumeration::*;
+
+	let id = try!(read_l_i32(file));
+	let array_type = try!(read_enum(file));
+	let rank = try!(read_count(file, "Rank")) as usize;
+	let mut lengths = vec![];
+	for _ in 0..rank {
+		lengths.push(try!(read_count(file, "array length")));
+	}
+	let lower_bounds = match array_type {
+		SingleOffset | JaggedOffset | RectangularOffset => {
+			let mut lower_bounds = vec![];
+			for _ in 0..rank {
+				lower_bounds.push(try!(read_l_i32(file)));
+			}
+			Some(lower_bounds)
+		},
+		Single | Jagged | Rectangular => None,
+		Unknown => unreachable!(),
+	};
+	let binary_type: BinaryTypeEnumeration = try!(read_enum(file));
+	let element_type = try!(read_member_type(file, &binary_type));
+
+	let mut element_count: i64 = 1;
+	for length in lengths.iter() {
+		element_count = element_count.saturating_mul(*length as i64);
+	}
+	if element_count > MAX_COUNT {
+		return Err(DeserializeError::LimitExceeded(error_at(file, 0), "array element count", element_count));
+	}
+	let elements = match element_type {
+		BorrowedMemberType::Primitive(ref primitive_type) => {
+			BorrowedElements::Primitive(try!(PrimitiveSlice::read(file, primitive_type, element_count as usize)))
+		},
+		_ => BorrowedElements::Records(try!(read_elements(file, context, element_count as usize))),
+	};
+	Ok(BorrowedArray {
+		id: id,
+		array_type: array_type,
+		lengths: lengths,
+		lower_bounds: lower_bounds,
+		element_type: element_type,
+		elements: elements,
+	})
+}
+
+/// Reads one record together with the member values of objects and the elements of arrays
+fn read_record<'s, 'a>(file: &mut SliceReader<'a>, context: &mut Context<'s, 'a>) -> Result<BorrowedRecord<'a>, DeserializeError> {
+	if context.depth >= MAX_DEPTH {
+		return Err(DeserializeError::LimitExceeded(error_at(file, 0), "record depth", context.depth as i64));
+	}
+	let record_type: RecordTypeEnumeration = try!(read_enum(file));
+	context.depth += 1;
+	let record = read_record_of_type(file, context, record_type);
+	context.depth -= 1;
+	record.map_err(|e| e.within(format!("{:?}", record_type)))
+}
+
+fn read_record_of_type<'s, 'a>(file: &mut SliceReader<'a>, context: &mut Context<'s, 'a>, record_type: RecordTypeEnumeration) -> Result<BorrowedRecord<'a>, DeserializeError> {
+	use records::RecordTypeEnumeration::*;
+
+	Ok(match record_type {
+		SerializedStreamHeader => BorrowedRecord::SerializedStreamHeader(try!(SerializationHeaderRecord::new(file))),
+		ClassWithId => {
+			let class_with_id = try!(ClassWithIdRecord::new(file));
+			let class = match context.classes.get(&class_with_id.MetadataId) {
+				Some(class) => class.clone(),
+				None => return Err(DeserializeError::DanglingReference(error_at(file, 4), class_with_id.MetadataId)),
+			};
+			let values = try!(read_values(file, context, &class));
+			BorrowedRecord::ClassWithId(class_with_id, class, values)
+		},
+		SystemClassWithMembers | ClassWithMembers | SystemClassWithMembersAndTypes | ClassWithMembersAndTypes => {
+			let class = try!(read_class(file, context, record_type));
+			let values = try!(read_values(file, context, &class));
+			BorrowedRecord::Class(class, values)
+		},
+		BinaryObjectString => {
+			let id = try!(read_l_i32(file));
+			BorrowedRecord::BinaryObjectString(id, try!(file.read_str()))
+		},
+		BinaryArray => BorrowedRecord::BinaryArray(try!(read_binary_array(file, context))),
+		MemberPrimitiveTyped => BorrowedRecord::MemberPrimitiveTyped(try!(MemberPrimitiveTypedRecord::new(file))),
+		MemberReference => BorrowedRecord::MemberReference(try!(MemberReferenceRecord::new(file))),
+		ObjectNull => BorrowedRecord::ObjectNull(try!(ObjectNullRecord::new(file))),
+		MessageEnd => BorrowedRecord::MessageEnd(try!(MessageEndRecord::new(file))),
+		BinaryLibrary => {
+			let id = try!(read_l_i32(file));
+			BorrowedRecord::BinaryLibrary(id, try!(file.read_str()))
+		},
+		ObjectNullMultiple256 => BorrowedRecord::ObjectNullMultiple256(try!(ObjectNullMultiple256Record::new(file))),
+		ObjectNullMultiple => BorrowedRecord::ObjectNullMultiple(try!(ObjectNullMultipleRecord::new(file))),
+		ArraySinglePrimitive => {
+			let array_info = try!(ArrayInfoRecord::new(file));
+			let primitive_type = try!(read_enum(file));
+			let values = try!(PrimitiveSlice::read(file, &primitive_type, array_info.Length as usize));
+			BorrowedRecord::ArraySinglePrimitive(array_info, values)
+		},
+		ArraySingleObject => {
+			let array_info = try!(ArrayInfoRecord::new(file));
+			let values = try!(read_elements(file, context, array_info.Length as usize));
+			BorrowedRecord::ArraySingleObject(array_info, values)
+		},
+		ArraySingleString => {
+			let array_info = try!(ArrayInfoRecord::new(file));
+			let values = try!(read_elements(file, context, array_info.Length as usize));
+			BorrowedRecord::ArraySingleString(array_info, values)
+		},
+		MethodCall | MethodReturn | Unknown => {
+			return Err(DeserializeError::UnsupportedRecordType(error_at(file, 1), record_type as u8));
+		},
+	})
+}
+
+/// Reads the record of a member value or an array element
+fn read_value_record<'s, 'a>(file: &mut SliceReader<'a>, context: &mut Context<'s, 'a>) -> Result<BorrowedValue<'a>, DeserializeError> {
+	Ok(BorrowedValue::Record(match try!(read_record(file, context)) {
+		// The value is nested in the library, so libraries count towards the depth
+		BorrowedRecord::BinaryLibrary(id, name) => {
+			context.depth += 1;
+			let value = read_value_record(file, context);
+			context.depth -= 1;
+			let value = try!(value.map_err(|e| e.within("BinaryLibrary".to_string())));
+			BorrowedRecord::LibraryWithValue(id, name, box(value))
+		},
+		// The offsets are of the record type bytes, the header being 17 bytes long
+		BorrowedRecord::SerializedStreamHeader(_) => return Err(DeserializeError::UnsupportedRecordType(error_at(file, 17), 0)),
+		BorrowedRecord::MessageEnd(_) => return Err(DeserializeError::UnsupportedRecordType(error_at(file, 1), 11)),
+		r @ _ => r,
+	}))
+}
+
+/// Reads records until MessageEnd, the rest of the input being left in the reader.
+/// Classes written without member types are looked up in the classes read before, then in the schema.
+pub fn read_records<'a>(file: &mut SliceReader<'a>, schema: &Schema) -> Result<Vec<BorrowedRecord<'a>>, DeserializeError> {
+	let mut context = Context {
+		classes: HashMap::new(),
+		typed_classes: HashMap::new(),
+		schema: schema,
+		depth: 0,
+	};
+	let mut records = vec![];
+	loop {
+		let record = try!(read_record(file, &mut context).map_err(|e| e.within(format!("record {}", records.len()))));
+		let is_end = match record {
+			BorrowedRecord::MessageEnd(_) => true,
+			_ => false,
+		};
+		records.push(record);
+		if is_end {
+			break;
+		}
+	}
+	Ok(records)
+}
+
+/// Reads a stream whose classes are all written with their member types
+pub fn read_slice<'a>(bytes: &'a [u8]) -> Result<Vec<BorrowedRecord<'a>>, DeserializeError> {
+	read_records(&mut SliceReader::new(bytes), &Schema::new())
+}
+
+#[cfg(test)]
+mod tests {
+	use model::Primitive;
+	use records::*;
+	use super::{BorrowedRecord, read_slice};
+
+	/// An item with a name, scores and bytes
+	fn item() -> Vec<u8> {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		BinaryLibraryRecord { LibraryId: 2, LibraryName: "Game".to_string() }.write(&mut bytes);
+		ClassWithMembersAndTypesRecord {
+			ClassInfo: ClassInfoRecord {
+				ObjectId: 1,
+				Name: "Game.Item".to_string(),
+				MemberCount: 3,
+				MemberNames: vec!["Name".to_string(), "Scores".to_string(), "Data".to_string()],
+			},
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![BinaryTypeEnumeration::String, BinaryTypeEnumeration::PrimitiveArray, BinaryTypeEnumeration::PrimitiveArray],
+				AdditionalInfos: vec![
+					AdditionalInfo::None,
+					AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32),
+					AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Byte),
+				],
+			},
+			LibraryId: 2,
+		}.write(&mut bytes);
+		BinaryObjectStringRecord { ObjectId: 3, Value: "arrow".to_string() }.write(&mut bytes);
+		MemberReferenceRecord { IdRef: 4 }.write(&mut bytes);
+		MemberReferenceRecord { IdRef: 5 }.write(&mut bytes);
+		ArraySinglePrimitiveRecord {
+			ArrayInfo: ArrayInfoRecord { ObjectId: 4, Length: 2 },
+			PrimitiveTypeEnum: PrimitiveTypeEnumeration::Int32,
+			Values: PrimitiveValues::Int32(vec![3, 5]),
+		}.write(&mut bytes);
+		ArraySinglePrimitiveRecord {
+			ArrayInfo: ArrayInfoRecord { ObjectId: 5, Length: 3 },
+			PrimitiveTypeEnum: PrimitiveTypeEnumeration::Byte,
+			Values: PrimitiveValues::Byte(vec![1, 2, 3]),
+		}.write(&mut bytes);
+		MessageEndRecord {}.write(&mut bytes);
+		bytes
+	}
+
+	#[test]
+	fn strings_and_arrays_point_into_the_input() {
+		let bytes = item();
+		let is_in_input = |p: *const u8| p >= bytes.as_ptr() && p < bytes[bytes.len()..].as_ptr();
+		let records = read_slice(&bytes).unwrap();
+		let item = &records[2];
+		assert_eq!(item.get_class().unwrap().name, "Game.Item");
+		let name = item.get_member("Name").and_then(|name| name.as_str()).unwrap();
+		assert_eq!(name, "arrow");
+		assert!(is_in_input(name.as_ptr()));
+		match &records[3] {
+			&BorrowedRecord::ArraySinglePrimitive(_, ref scores) => {
+				assert_eq!((scores.len(), scores.get(1)), (2, Some(Primitive::Int32(5))));
+			},
+			record => panic!("Expected the scores, got {:?}", record),
+		}
+		match &records[4] {
+			&BorrowedRecord::ArraySinglePrimitive(_, ref data) => {
+				let data = data.as_bytes().unwrap();
+				assert_eq!(data, &[1, 2, 3]);
+				assert!(is_in_input(data.as_ptr()));
+			},
+			record => panic!("Expected the bytes, got {:?}", record),
+		}
+	}
+
+	#[test]
+	fn truncated_stream() {
+		let bytes = item();
+		// Without MessageEnd and the last two bytes of Data
+		match read_slice(&bytes[..bytes.len() - 3]) {
+			Err(e @ DeserializeError::UnexpectedEof(..)) => {
+				assert_eq!(e.offset(), bytes.len() as u64 - 3);
+				assert_eq!(e.path(), &vec!["record 4".to_string(), "ArraySinglePrimitive".to_string(), "element 1".to_string()]);
+			},
+			result => panic!("Expected the end of the stream, got {:?}", result.map(|records| records.len())),
+		}
+	}
+
+	#[test]
+	fn huge_counts_of_a_short_stream() {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		// A string[] claiming the largest length allowed, then the end of the input
+		bytes.extend_from_slice(&[17, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0x0f]);
+		match read_slice(&bytes) {
+			Err(DeserializeError::UnexpectedEof(..)) => {},
+			result => panic!("Expected the end of the stream, got {:?}", result.map(|records| records.len())),
+		}
+		// A class claiming the largest member count allowed
+		bytes.truncate(17);
+		bytes.extend_from_slice(&[4, 1, 0, 0, 0, 1, b'A', 0xff, 0xff, 0xff, 0x0f]);
+		match read_slice(&bytes) {
+			Err(DeserializeError::UnexpectedEof(..)) => {},
+			result => panic!("Expected the end of the stream, got {:?}", result.map(|records| records.len())),
+		}
+	}
+
+	#[test]
+	fn too_many_libraries_before_a_value() {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		// object[] of one element
+		bytes.extend_from_slice(&[16, 1, 0, 0, 0, 1, 0, 0, 0]);
+		for i in 0..100000 {
+			BinaryLibraryRecord { LibraryId: i + 2, LibraryName: "Game".to_string() }.write(&mut bytes);
+		}
+		ObjectNullRecord {}.write(&mut bytes);
+		MessageEndRecord {}.write(&mut bytes);
+		match read_slice(&bytes) {
+			Err(DeserializeError::LimitExceeded(_, "record depth", _)) => {},
+			result => panic!("Expected the depth to be exceeded, got {:?}", result.map(|records| records.len())),
+		}
+	}
+}
@@ -0,0 +1,495 @@
+//! Building streams by hand, e.g. for tests: define classes, create objects, arrays and strings,
+//! set their members and write the records.
+//!
+//! Object and library ids are given by the builder, and only the first object of a class is written
+//! with the member types, the next ones referring to it with ClassWithId records. Primitive members start
+//! as the default of their type, as in C#. The builder panics right away when misused (unknown ids or
+//! member names, values not fitting the member type), as that is a bug of the calling code rather than
+//! of the data.
+
+use std;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::collections::{HashMap, HashSet};
+
+use records::*;
+use model::{Data, MemberType, Primitive};
+
+#[derive(Debug)]
+pub enum BuildError {
+	/// Neither an object nor an array was created to be the root
+	NoRoot,
+	/// Writing the stream to the file failed
+	Io(io::Error),
+}
+
+impl fmt::Display for BuildError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			&BuildError::NoRoot => write!(f, "No object to write"),
+			&BuildError::Io(ref e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl std::error::Error for BuildError {
+	fn description(&self) -> &str {
+		match self {
+			&BuildError::NoRoot => "No object to write",
+			&BuildError::Io(ref e) => std::error::Error::description(e),
+		}
+	}
+}
+
+/// A class defined on the builder, to create objects of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClassHandle(usize);
+
+struct ClassDefinition {
+	name: String,
+	/// None for classes of mscorlib
+	library_id: Option<i32>,
+	member_names: Vec<String>,
+	member_types: Vec<MemberType>,
+}
+
+enum Built {
+	/// Index of the class and member values in the order of its member names
+	Object(usize, Vec<Data>),
+	/// Element type and elements of a single-dimensional array
+	Array(MemberType, Vec<Data>),
+	String(String),
+}
+
+/// What is written already while writing the stream
+struct Written {
+	next_id: i32,
+	libraries: HashSet<i32>,
+	/// Object ids of the records defining the classes, by class index
+	classes: HashMap<usize, i32>,
+	strings: HashSet<i32>,
+}
+
+/// Whether the value can be written for a member or element of that type.
+/// Objects and arrays are given by reference, and strings or primitives of `object` members are written with their type.
+/// Primitive members and elements cannot be null.
+fn fits(member_type: &MemberType, data: &Data) -> bool {
+	match (member_type, data) {
+		(&MemberType::Primitive(primitive_type), &Data::Primitive(ref primitive)) => primitive.get_primitive_type() == primitive_type,
+		(&MemberType::Primitive(_), _) => false,
+		(&MemberType::Object, &Data::Primitive(_)) => true,
+		(&MemberType::String, &Data::String(_)) | (&MemberType::Object, &Data::String(_)) => true,
+		(_, &Data::Null) | (_, &Data::Reference(_)) => true,
+		_ => false,
+	}
+}
+
+pub struct StreamBuilder {
+	next_id: i32,
+	libraries: Vec<(i32, String)>,
+	classes: Vec<ClassDefinition>,
+	/// Objects, arrays and strings in the order they were created
+	values: Vec<(i32, Built)>,
+	indices: HashMap<i32, usize>,
+	root_id: Option<i32>,
+}
+
+impl StreamBuilder {
+	pub fn new() -> Self {
+		StreamBuilder {
+			next_id: 1,
+			libraries: vec![],
+			classes: vec![],
+			values: vec![],
+			indices: HashMap::new(),
+			root_id: None,
+		}
+	}
+
+	fn new_id(&mut self) -> i32 {
+		let id = self.next_id;
+		self.next_id += 1;
+		id
+	}
+
+	/// Id of the library of that name, added if it is not there yet
+	pub fn add_library(&mut self, name: &str) -> i32 {
+		if let Some(&(id, _)) = self.libraries.iter().find(|&&(_, ref library_name)| library_name == name) {
+			return id;
+		}
+		let id = self.new_id();
+		self.libraries.push((id, name.to_string()));
+		id
+	}
+
+	/// Defines a class of the library with the given member names and types.
+	/// Members of class type are given as `MemberType::Class(name, library_id)`.
+	pub fn define_class(&mut self, name: &str, library_id: i32, members: Vec<(&str, MemberType)>) -> ClassHandle {
+		if !self.libraries.iter().any(|&(id, _)| id == library_id) {
+			panic!("No library {} for class {}", library_id, name);
+		}
+		self.add_class(name, Some(library_id), members)
+	}
+
+	/// Defines a class of mscorlib, e.g. System.Collections.Generic.List`1[[...]], which belongs to no library
+	pub fn define_system_class(&mut self, name: &str, members: Vec<(&str, MemberType)>) -> ClassHandle {
+		self.add_class(name, None, members)
+	}
+
+	fn add_class(&mut self, name: &str, library_id: Option<i32>, members: Vec<(&str, MemberType)>) -> ClassHandle {
+		self.classes.push(ClassDefinition {
+			name: name.to_string(),
+			library_id: library_id,
+			member_names: members.iter().map(|&(member_name, _)| member_name.to_string()).collect(),
+			member_types: members.into_iter().map(|(_, member_type)| member_type).collect(),
+		});
+		ClassHandle(self.classes.len() - 1)
+	}
+
+	fn add_value(&mut self, value: Built) -> i32 {
+		let id = self.new_id();
+		let index = self.values.len();
+		self.indices.insert(id, index);
+		self.values.push((id, value));
+		id
+	}
+
+	/// Creates an object of the class with default members: zero for primitives and null for the others.
+	/// The first object created is the root unless `set_root` is used.
+	pub fn new_object(&mut self, class: ClassHandle) -> i32 {
+		let members = self.classes[class.0].member_types.iter()
+			.map(|member_type| match member_type {
+				&MemberType::Primitive(ref primitive_type) => match Primitive::default_of(primitive_type) {
+					Some(primitive) => Data::Primitive(primitive),
+					None => panic!("{:?} is not a primitive type of members", primitive_type),
+				},
+				_ => Data::Null,
+			})
+			.collect();
+		let id = self.add_value(Built::Object(class.0, members));
+		if self.root_id.is_none() {
+			self.root_id = Some(id);
+		}
+		id
+	}
+
+	/// Creates a single-dimensional array, written as ArraySinglePrimitive, ArraySingleString or
+	/// ArraySingleObject for arrays of primitives, strings or `object`, and as BinaryArray otherwise
+	pub fn new_array(&mut self, element_type: MemberType, elements: Vec<Data>) -> i32 {
+		for (i, element) in elements.iter().enumerate() {
+			if !fits(&element_type, element) {
+				panic!("Element {} ({:?}) does not fit an array of {:?}", i, element, element_type);
+			}
+		}
+		self.add_value(Built::Array(element_type, elements))
+	}
+
+	/// Creates a string several members can refer to, written where it is first used
+	pub fn new_string(&mut self, value: &str) -> i32 {
+		self.add_value(Built::String(value.to_string()))
+	}
+
+	/// Makes an object or array the root of the stream
+	pub fn set_root(&mut self, id: i32) -> &mut Self {
+		match self.values[self.get_index(id)].1 {
+			Built::String(_) => panic!("The root {} is a string", id),
+			_ => self.root_id = Some(id),
+		}
+		self
+	}
+
+	fn get_index(&self, id: i32) -> usize {
+		match self.indices.get(&id) {
+			Some(&index) => index,
+			None => panic!("No object, array or string {}", id),
+		}
+	}
+
+	/// Sets a member of an object. Strings are written inline, and objects, arrays and strings
+	/// created on the builder are given by `Data::Reference(id)`.
+	pub fn set_member(&mut self, object_id: i32, name: &str, value: Data) -> &mut Self {
+		let index = self.get_index(object_id);
+		let class_index = match self.values[index].1 {
+			Built::Object(class_index, _) => class_index,
+			_ => panic!("{} is not an object", object_id),
+		};
+		if let Data::Reference(id) = value {
+			self.get_index(id);
+		}
+		let member_index = {
+			let class = &self.classes[class_index];
+			let member_index = match class.member_names.iter().position(|member_name| member_name == name) {
+				Some(member_index) => member_index,
+				None => panic!("No member {} in class {}", name, class.name),
+			};
+			if !fits(&class.member_types[member_index], &value) {
+				panic!("{:?} does not fit member {}.{} of type {:?}", value, class.name, name, class.member_types[member_index]);
+			}
+			member_index
+		};
+		match self.values[index].1 {
+			Built::Object(_, ref mut members) => members[member_index] = value,
+			_ => unreachable!(),
+		}
+		self
+	}
+
+	pub fn set_primitive(&mut self, object_id: i32, name: &str, value: Primitive) -> &mut Self {
+		self.set_member(object_id, name, Data::Primitive(value))
+	}
+
+	pub fn set_string(&mut self, object_id: i32, name: &str, value: &str) -> &mut Self {
+		self.set_member(object_id, name, Data::String(value.to_string()))
+	}
+
+	pub fn set_reference(&mut self, object_id: i32, name: &str, id: i32) -> &mut Self {
+		self.set_member(object_id, name, Data::Reference(id))
+	}
+
+	/// Writes the header, the root, the other objects and arrays in the order they were created and MessageEnd.
+	/// Libraries are written right before the first record using them.
+	pub fn write(&self, file: &mut Write) -> Result<(), BuildError> {
+		let bytes = try!(self.to_bytes());
+		file.write_all(&bytes).map_err(BuildError::Io)
+	}
+
+	/// The stream is built in memory, so nothing is written by `write` if there is no root
+	pub fn to_bytes(&self) -> Result<Vec<u8>, BuildError> {
+		let root_id = match self.root_id {
+			Some(root_id) => root_id,
+			None => return Err(BuildError::NoRoot),
+		};
+		let mut bytes = vec![];
+		let mut written = Written {
+			next_id: self.next_id,
+			libraries: HashSet::new(),
+			classes: HashMap::new(),
+			strings: HashSet::new(),
+		};
+
+		SerializationHeaderRecord { RootId: root_id, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		let root_index = self.get_index(root_id);
+		self.write_value(&mut bytes, &mut written, root_index);
+		for index in 0..self.values.len() {
+			if index != root_index {
+				self.write_value(&mut bytes, &mut written, index);
+			}
+		}
+		MessageEndRecord {}.write(&mut bytes);
+		Ok(bytes)
+	}
+
+	fn write_library(&self, file: &mut Write, written: &mut Written, library_id: i32) {
+		if written.libraries.insert(library_id) {
+			let &(_, ref name) = self.libraries.iter().find(|&&(id, _)| id == library_id).unwrap();
+			BinaryLibraryRecord { LibraryId: library_id, LibraryName: name.clone() }.write(file);
+		}
+	}
+
+	/// Strings are not written on their own but where they are first used
+	fn write_value(&self, file: &mut Write, written: &mut Written, index: usize) {
+		let (id, ref value) = self.values[index];
+		match value {
+			&Built::Object(class_index, ref members) => {
+				let class = &self.classes[class_index];
+				self.write_class(file, written, id, class_index);
+				for (member, member_type) in members.iter().zip(class.member_types.iter()) {
+					self.value_of(written, member, member_type).write(file);
+				}
+			},
+			&Built::Array(ref element_type, ref elements) => self.write_array(file, written, id, element_type, elements),
+			&Built::String(_) => {},
+		}
+	}
+
+	/// The first object of a class is written with the member types, the next ones refer to it
+	fn write_class(&self, file: &mut Write, written: &mut Written, id: i32, class_index: usize) {
+		if let Some(&class_id) = written.classes.get(&class_index) {
+			ClassWithIdRecord { ObjectId: id, MetadataId: class_id }.write(file);
+			return;
+		}
+
+		let class = &self.classes[class_index];
+		for member_type in class.member_types.iter() {
+			if let &MemberType::Class(_, library_id) = member_type {
+				self.write_library(file, written, library_id);
+			}
+		}
+		let class_info = ClassInfoRecord {
+			ObjectId: id,
+			Name: class.name.clone(),
+			MemberCount: class.member_names.len() as i32,
+			MemberNames: class.member_names.clone(),
+		};
+		let member_type_info = MemberTypeInfoRecord {
+			BinaryTypeEnums: class.member_types.iter().map(MemberType::get_binary_type).collect(),
+			AdditionalInfos: class.member_types.iter().map(MemberType::get_additional_info).collect(),
+		};
+		match class.library_id {
+			Some(library_id) => {
+				self.write_library(file, written, library_id);
+				ClassWithMembersAndTypesRecord { ClassInfo: class_info, MemberTypeInfo: member_type_info, LibraryId: library_id }.write(file);
+			},
+			None => SystemClassWithMembersAndTypesRecord { ClassInfo: class_info, MemberTypeInfo: member_type_info }.write(file),
+		}
+		written.classes.insert(class_index, id);
+	}
+
+	fn write_array(&self, file: &mut Write, written: &mut Written, id: i32, element_type: &MemberType, elements: &Vec<Data>) {
+		let array_info = ArrayInfoRecord { ObjectId: id, Length: elements.len() as i32 };
+		match element_type {
+			&MemberType::Primitive(primitive_type) => {
+				let mut values = PrimitiveValues::empty(&primitive_type);
+				for element in elements.iter() {
+					values.push(element.as_primitive().unwrap().clone());
+				}
+				ArraySinglePrimitiveRecord { ArrayInfo: array_info, PrimitiveTypeEnum: primitive_type, Values: values }.write(file);
+			},
+			&MemberType::String => {
+				let values = self.element_values(written, element_type, elements);
+				ArraySingleStringRecord { ArrayInfo: array_info, Values: values }.write(file);
+			},
+			&MemberType::Object => {
+				let values = self.element_values(written, element_type, elements);
+				ArraySingleObjectRecord { ArrayInfo: array_info, Values: values }.write(file);
+			},
+			_ => {
+				if let &MemberType::Class(_, library_id) = element_type {
+					self.write_library(file, written, library_id);
+				}
+				let values = self.element_values(written, element_type, elements);
+				BinaryArrayRecord {
+					ObjectId: id,
+					BinaryArrayTypeEnum: BinaryArrayTypeEnumeration::Single,
+					Rank: 1,
+					Lengths: vec![array_info.Length],
+					LowerBounds: None,
+					TypeEnum: element_type.get_binary_type(),
+					AdditionalTypeInfo: element_type.get_additional_info(),
+					Values: ArrayValues::Records(values),
+				}.write(file);
+			},
+		}
+	}
+
+	/// Consecutive nulls are written as a single record
+	fn element_values(&self, written: &mut Written, element_type: &MemberType, elements: &Vec<Data>) -> Vec<Value> {
+		let mut values = vec![];
+		let mut null_count = 0;
+		for element in elements.iter() {
+			if element.is_null() {
+				null_count += 1;
+				continue;
+			}
+			if null_count > 0 {
+				values.push(Value::null_run(null_count));
+				null_count = 0;
+			}
+			values.push(self.value_of(written, element, element_type));
+		}
+		if null_count > 0 {
+			values.push(Value::null_run(null_count));
+		}
+		values
+	}
+
+	/// A string created on the builder is written where it is first referenced, and referenced after that
+	fn value_of(&self, written: &mut Written, data: &Data, member_type: &MemberType) -> Value {
+		match (data, member_type) {
+			(&Data::Primitive(ref primitive), &MemberType::Primitive(_)) => Value::Primitive(primitive.clone()),
+			(&Data::Primitive(ref primitive), _) => {
+				Value::MemberPrimitiveTyped(MemberPrimitiveTypedRecord { PrimitiveTypeEnum: primitive.get_primitive_type(), Value: primitive.clone() })
+			},
+			(&Data::String(ref string), _) => {
+				let id = written.next_id;
+				written.next_id += 1;
+				Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: id, Value: string.clone() })
+			},
+			(&Data::Reference(id), _) => match self.values[self.get_index(id)].1 {
+				// Strings are written where they are first referenced
+				Built::String(ref string) if !written.strings.contains(&id) => {
+					written.strings.insert(id);
+					Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: id, Value: string.clone() })
+				},
+				_ => Value::MemberReference(MemberReferenceRecord { IdRef: id }),
+			},
+			_ => Value::ObjectNull(ObjectNullRecord {}),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use document::read_document;
+	use lossless::{read_records, write_records};
+	use model::{Data, MemberType, Primitive};
+	use records::*;
+	use schema::Schema;
+	use super::{BuildError, StreamBuilder};
+
+	/// An inventory of items sharing a string, with an array of items holding nulls and an `object` member
+	fn inventory() -> (StreamBuilder, i32) {
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let inventory = builder.define_class("Game.Inventory", library_id, vec![
+			("Items", MemberType::Class("Game.Item[]".to_string(), library_id)),
+			("Tag", MemberType::Object),
+		]);
+		let item = builder.define_class("Game.Item", library_id, vec![
+			("Name", MemberType::String),
+			("Count", MemberType::Primitive(PrimitiveTypeEnumeration::Int32)),
+		]);
+		let inventory_id = builder.new_object(inventory);
+		let name_id = builder.new_string("arrow");
+		let first_id = builder.new_object(item);
+		let second_id = builder.new_object(item);
+		builder.set_reference(first_id, "Name", name_id).set_primitive(first_id, "Count", Primitive::Int32(20))
+			.set_reference(second_id, "Name", name_id);
+		let items_id = builder.new_array(MemberType::Class("Game.Item".to_string(), library_id),
+			vec![Data::Reference(first_id), Data::Null, Data::Null, Data::Reference(second_id)]);
+		builder.set_reference(inventory_id, "Items", items_id).set_primitive(inventory_id, "Tag", Primitive::Int64(7));
+		(builder, second_id)
+	}
+
+	#[test]
+	fn lossless_round_trip() {
+		let bytes = inventory().0.to_bytes().unwrap();
+		let records = read_records(&mut OffsetReader::new(&bytes[..]), &mut ReadContext::new(&mut Schema::new())).unwrap();
+		let mut written = vec![];
+		write_records(&mut written, &records);
+		assert_eq!(written, bytes);
+	}
+
+	#[test]
+	fn read_back() {
+		let (builder, second_id) = inventory();
+		let document = read_document(&builder.to_bytes().unwrap()[..]).unwrap();
+		let second = document.get_object(second_id).unwrap();
+		// The string is written with the first item, the second one refers to it, and Count starts as zero
+		assert_eq!(second.members, vec![Data::Reference(3), Data::Primitive(Primitive::Int32(0))]);
+		assert_eq!(document.get_string(3), Some("arrow"));
+		let inventory = document.get_root().and_then(|root| root.as_object()).unwrap();
+		assert_eq!(document.get_member(inventory, "Tag"), Some(&Data::Primitive(Primitive::Int64(7))));
+		let items = document.get_member(inventory, "Items").and_then(|items| items.as_array()).unwrap();
+		assert_eq!(items.elements[1..3].to_vec(), vec![Data::Null, Data::Null]);
+	}
+
+	#[test]
+	fn no_root() {
+		match StreamBuilder::new().to_bytes() {
+			Err(BuildError::NoRoot) => {},
+			result => panic!("Expected no root, got {:?}", result),
+		}
+	}
+
+	#[test]
+	#[should_panic(expected = "does not fit member")]
+	fn null_primitive_member() {
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let item = builder.define_class("Game.Item", library_id, vec![("Count", MemberType::Primitive(PrimitiveTypeEnumeration::Int32))]);
+		let item_id = builder.new_object(item);
+		builder.set_member(item_id, "Count", Data::Null);
+	}
+}
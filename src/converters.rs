@@ -0,0 +1,259 @@
+//! Converters for the objects of given classes, written to json as something else than their members,
+//! e.g. a `UnityEngine.Vector3` as `[x, y, z]`, and read back from it.
+//!
+//! A converter works on the json of the members, as written by the editable or graph modules, and is registered
+//! for a pattern of class names. It is used for the objects inlined where they are referenced, as for the
+//! objects of the well-known classes of the known_types module, which converters take precedence over.
+//!
+//! A converters file gives declarative converters,
+//! `{"Converters": [{"Class": "UnityEngine.Vector3", "AsArray": ["x", "y", "z"]}, {"Class": "Game.ItemId", "AsValue": "id"}]}`.
+
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use serde_json::{Value as JValue, from_reader};
+
+use sub_records::BinaryTypeEnumeration;
+use model::Class;
+use json::*;
+
+pub trait Converter {
+	/// Whether the objects of a class with these members are converted, e.g. when the members are the expected ones
+	fn accepts(&self, class_name: &str, member_names: &[String], member_types: &[BinaryTypeEnumeration]) -> bool;
+
+	/// The json of an object from the json of its members, in the order of the member names.
+	/// It cannot be null or an object with an "IdRef" member, which are read back as references.
+	fn to_json(&self, class_name: &str, member_names: &[String], members: Vec<JValue>) -> JValue;
+
+	/// The json of the members, in the order of the member names, from the json given by to_json
+	fn from_json(&self, class_name: &str, member_names: &[String], json: &JValue) -> Result<Vec<JValue>, String>;
+}
+
+/// Objects as the array of the given members, which have to be all of their members, each given once
+pub struct MembersAsArray {
+	pub members: Vec<String>,
+}
+
+impl Converter for MembersAsArray {
+	fn accepts(&self, _: &str, member_names: &[String], _: &[BinaryTypeEnumeration]) -> bool {
+		let names: HashSet<&String> = self.members.iter().collect();
+		names.len() == self.members.len() && member_names.len() == names.len() && member_names.iter().all(|name| names.contains(name))
+	}
+
+	fn to_json(&self, _: &str, member_names: &[String], members: Vec<JValue>) -> JValue {
+		JValue::Array(self.members.iter()
+			.map(|name| members[member_names.iter().position(|member_name| member_name == name).unwrap()].clone())
+			.collect())
+	}
+
+	fn from_json(&self, class_name: &str, member_names: &[String], json: &JValue) -> Result<Vec<JValue>, String> {
+		let elements = match json.as_array() {
+			Some(elements) if elements.len() == self.members.len() => elements,
+			_ => return Err(format!("Expect [{}] for {} but got {:?}", self.members.join(", "), class_name, json)),
+		};
+		Ok(member_names.iter()
+			.map(|member_name| elements[self.members.iter().position(|name| name == member_name).unwrap()].clone())
+			.collect())
+	}
+}
+
+/// Objects with a single member of a primitive type as its value, which cannot be null
+pub struct MemberAsValue {
+	pub member: String,
+}
+
+impl Converter for MemberAsValue {
+	fn accepts(&self, _: &str, member_names: &[String], member_types: &[BinaryTypeEnumeration]) -> bool {
+		member_names.len() == 1 && member_names[0] == self.member && member_types[0] == BinaryTypeEnumeration::Primitive
+	}
+
+	fn to_json(&self, _: &str, _: &[String], members: Vec<JValue>) -> JValue {
+		members.into_iter().next().unwrap()
+	}
+
+	fn from_json(&self, _: &str, _: &[String], json: &JValue) -> Result<Vec<JValue>, String> {
+		Ok(vec![json.clone()])
+	}
+}
+
+/// Whether a class name matches a pattern, where `*` stands for any characters. A pattern without type arguments
+/// also matches the generic classes of that name, e.g. "System.Collections.Generic.List`1" matches lists of any type.
+pub fn matches_class_name(pattern: &str, class_name: &str) -> bool {
+	if matches_glob(pattern, class_name) {
+		return true;
+	}
+	match class_name.find('[') {
+		Some(i) if !pattern.contains('[') => matches_glob(pattern, &class_name[..i]),
+		_ => false,
+	}
+}
+
+fn matches_glob(pattern: &str, s: &str) -> bool {
+	let parts: Vec<&str> = pattern.split('*').collect();
+	if parts.len() == 1 {
+		return pattern == s;
+	}
+	let first = parts[0];
+	let last = parts[parts.len() - 1];
+	if s.len() < first.len() + last.len() || !s.starts_with(first) || !s.ends_with(last) {
+		return false;
+	}
+	let mut rest = &s[first.len()..s.len() - last.len()];
+	for part in parts[1..parts.len() - 1].iter() {
+		match rest.find(part) {
+			Some(i) => rest = &rest[i + part.len()..],
+			None => return false,
+		}
+	}
+	true
+}
+
+/// Converters by pattern of class names, see matches_class_name
+pub struct Converters {
+	converters: Vec<(String, Box<Converter>)>,
+}
+
+impl Converters {
+	pub fn new() -> Self {
+		Converters {
+			converters: vec![],
+		}
+	}
+
+	/// Converters registered later take precedence over those before
+	pub fn register(&mut self, pattern: &str, converter: Box<Converter>) -> &mut Self {
+		self.converters.push((pattern.to_string(), converter));
+		self
+	}
+
+	/// Adds the converters of a converters file
+	pub fn load(&mut self, path: &Path) -> Result<(), String> {
+		let file = try!(OpenOptions::new().read(true).open(path).map_err(|e| format!("Error while opening converters file: {}", e)));
+		let json: JValue = try!(from_reader(file).map_err(|e| format!("Error while reading converters file: {:?}", e)));
+		for converter_json in try!(get_array(&json, "Converters")).iter() {
+			let pattern = try!(get_str(converter_json, "Class"));
+			let converter: Box<Converter> = match (converter_json.find("AsArray"), converter_json.find("AsValue")) {
+				(Some(&JValue::Array(ref members)), None) => {
+					let mut names = vec![];
+					for member in members.iter() {
+						let name = match member.as_str() {
+							Some(name) => name.to_string(),
+							None => return Err(format!("Expect member names in {:?}", converter_json)),
+						};
+						if names.contains(&name) {
+							return Err(format!("Member {} is given twice in {:?}", name, converter_json));
+						}
+						names.push(name);
+					}
+					box(MembersAsArray { members: names })
+				},
+				(None, Some(&JValue::String(ref member))) => box(MemberAsValue { member: member.clone() }),
+				_ => return Err(format!("Expect either AsArray or AsValue in {:?}", converter_json)),
+			};
+			self.register(pattern, converter);
+		}
+		Ok(())
+	}
+
+	/// The last registered converter matching the class name and accepting the class
+	pub fn get(&self, class_name: &str, member_names: &[String], member_types: &[BinaryTypeEnumeration]) -> Option<&Converter> {
+		self.converters.iter().rev()
+			.find(|&&(ref pattern, ref converter)| {
+				matches_class_name(pattern, class_name) && converter.accepts(class_name, member_names, member_types)
+			})
+			.map(|&(_, ref converter)| &**converter)
+	}
+
+	pub fn get_for_class(&self, class: &Class) -> Option<&Converter> {
+		if self.converters.is_empty() {
+			return None;
+		}
+		let member_types: Vec<BinaryTypeEnumeration> = class.member_types.iter().map(|member_type| member_type.get_binary_type()).collect();
+		self.get(&class.name, &class.member_names, &member_types)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.converters.is_empty()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env::temp_dir;
+	use std::fs::File;
+	use std::io::Write;
+	use std::path::PathBuf;
+
+	use serde_json::Value as JValue;
+
+	use sub_records::BinaryTypeEnumeration;
+	use super::{Converter, Converters, MembersAsArray, matches_class_name, matches_glob};
+
+	fn names(names: &[&str]) -> Vec<String> {
+		names.iter().map(|name| name.to_string()).collect()
+	}
+
+	fn converters_file(name: &str, json: &str) -> PathBuf {
+		let path = temp_dir().join(name);
+		File::create(&path).unwrap().write_all(json.as_bytes()).unwrap();
+		path
+	}
+
+	#[test]
+	fn glob() {
+		assert!(matches_glob("Game.Point", "Game.Point"));
+		assert!(!matches_glob("Game.Point", "Game.Points"));
+		assert!(matches_glob("Game.*", "Game.Point"));
+		assert!(matches_glob("*.Point", "Game.Point"));
+		assert!(matches_glob("Game.*.Point", "Game.Map.Point"));
+		assert!(matches_glob("*a*", "Game"));
+		assert!(!matches_glob("Game.*.Point", "Game.Point"));
+		assert!(!matches_glob("Unity*", "Game.Point"));
+	}
+
+	#[test]
+	fn class_name() {
+		let list = "System.Collections.Generic.List`1[[System.Int32, mscorlib]]";
+		assert!(matches_class_name("System.Collections.Generic.List`1", list));
+		assert!(matches_class_name("*List`1", list));
+		assert!(matches_class_name(list, list));
+		assert!(!matches_class_name("System.Collections.Generic.List`1[[System.String, mscorlib]]", list));
+		assert!(!matches_class_name("System.Collections.Generic.List", list));
+	}
+
+	#[test]
+	fn members_as_array() {
+		let types = vec![BinaryTypeEnumeration::Primitive; 3];
+		let converter = MembersAsArray { members: names(&["x", "y", "z"]) };
+		assert!(converter.accepts("Vector3", &names(&["z", "x", "y"]), &types));
+		assert!(!converter.accepts("Vector3", &names(&["x", "y"]), &types[..2]));
+		assert!(!converter.accepts("Vector3", &names(&["x", "y", "w"]), &types));
+		assert!(!MembersAsArray { members: names(&["x", "x", "y"]) }.accepts("Vector3", &names(&["x", "y", "z"]), &types));
+
+		let member_names = names(&["z", "x", "y"]);
+		let json = converter.to_json("Vector3", &member_names, vec![JValue::I64(3), JValue::I64(1), JValue::I64(2)]);
+		assert_eq!(json, JValue::Array(vec![JValue::I64(1), JValue::I64(2), JValue::I64(3)]));
+		assert_eq!(converter.from_json("Vector3", &member_names, &json), Ok(vec![JValue::I64(3), JValue::I64(1), JValue::I64(2)]));
+		assert!(converter.from_json("Vector3", &member_names, &JValue::Array(vec![JValue::I64(1)])).is_err());
+		assert!(converter.from_json("Vector3", &member_names, &JValue::I64(1)).is_err());
+	}
+
+	#[test]
+	fn load() {
+		let types = vec![BinaryTypeEnumeration::Primitive; 2];
+		let mut converters = Converters::new();
+		let path = converters_file("converters_load.json",
+			r#"{"Converters": [{"Class": "Game.*", "AsArray": ["x", "y"]}, {"Class": "Game.ItemId", "AsValue": "id"}]}"#);
+		assert_eq!(converters.load(&path), Ok(()));
+		assert!(converters.get("Game.Point", &names(&["y", "x"]), &types).is_some());
+		assert!(converters.get("Game.ItemId", &names(&["id"]), &types[..1]).is_some());
+		assert!(converters.get("Other.Point", &names(&["x", "y"]), &types).is_none());
+
+		let path = converters_file("converters_twice.json", r#"{"Converters": [{"Class": "Game.Point", "AsArray": ["x", "x"]}]}"#);
+		assert!(Converters::new().load(&path).is_err());
+		let path = converters_file("converters_both.json", r#"{"Converters": [{"Class": "Game.Point", "AsArray": ["x"], "AsValue": "x"}]}"#);
+		assert!(Converters::new().load(&path).is_err());
+		assert!(Converters::new().load(&temp_dir().join("converters_missing.json")).is_err());
+	}
+}
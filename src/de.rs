@@ -0,0 +1,649 @@
+//! Filling Rust types deriving `Deserialize` from the values of a stream.
+//!
+//! The stream is read into a `Document` first, and the deserializer walks its values from the root
+//! object. Members are matched to fields by name, the backing fields of C# auto-properties
+//! (`<Name>k__BackingField`) to a field `Name`. Arrays fill sequences, nested by rank for
+//! multi-dimensional arrays, and references are looked up wherever they point to.
+
+use std;
+use std::fmt;
+use std::io::Read;
+
+use serde::de;
+use serde::de::value::ValueDeserializer;
+
+use error::DeserializeError;
+use model::{Primitive, Data, Class, MemberType};
+use document::{Document, read_document};
+use sub_records::PrimitiveTypeEnumeration;
+
+/// Values nested deeper than this are taken as objects referencing each other. Every level takes several frames
+/// of the visitors of serde, so this is lower than for records to fit in the 2 MiB stack of a spawned thread.
+pub const MAX_NESTING: usize = 128;
+
+#[derive(Debug)]
+pub enum Error {
+	/// The stream could not be read
+	Read(DeserializeError),
+	/// A value which does not fit the Rust type, and the members and elements leading to it, outermost first
+	Mismatch(String, Vec<String>),
+}
+
+impl Error {
+	/// Adds the member or element being deserialized around the place of the error
+	pub fn within(self, segment: String) -> Self {
+		match self {
+			Error::Mismatch(message, mut path) => {
+				path.insert(0, segment);
+				Error::Mismatch(message, path)
+			},
+			e @ _ => e,
+		}
+	}
+}
+
+impl From<DeserializeError> for Error {
+	fn from(e: DeserializeError) -> Self {
+		Error::Read(e)
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			&Error::Read(ref e) => write!(f, "{}", e),
+			&Error::Mismatch(ref message, ref path) => {
+				try!(write!(f, "{}", message));
+				if !path.is_empty() {
+					try!(write!(f, " in {}", path.join(" > ")));
+				}
+				Ok(())
+			},
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn description(&self) -> &str {
+		match self {
+			&Error::Read(ref e) => e.description(),
+			&Error::Mismatch(ref message, _) => &message[..],
+		}
+	}
+}
+
+impl de::Error for Error {
+	fn custom<T: Into<String>>(message: T) -> Self {
+		Error::Mismatch(message.into(), vec![])
+	}
+
+	fn end_of_stream() -> Self {
+		Error::Mismatch("No value left".to_string(), vec![])
+	}
+}
+
+/// What the deserializer gives next
+#[derive(Clone, Copy)]
+enum Input<'d> {
+	Data(&'d Data),
+	/// Elements of a row of a multi-dimensional array, and the lengths of the dimensions left
+	Rows(&'d [Data], &'d [i32]),
+	/// A field without a member of that name, which only fills an Option
+	Missing(&'static str),
+}
+
+pub struct Deserializer<'d> {
+	document: &'d Document,
+	input: Option<Input<'d>>,
+	/// Objects and arrays being deserialized inside each other, which references may make endless
+	depth: usize,
+}
+
+impl<'d> Deserializer<'d> {
+	/// Deserializes the given value of the document, usually its root
+	pub fn new(document: &'d Document, data: &'d Data) -> Self {
+		Deserializer {
+			document: document,
+			input: Some(Input::Data(data)),
+			depth: 0,
+		}
+	}
+
+	fn nested(document: &'d Document, input: Input<'d>, depth: usize) -> Self {
+		Deserializer {
+			document: document,
+			input: Some(input),
+			depth: depth + 1,
+		}
+	}
+
+	/// The next input, with references looked up
+	fn take(&mut self) -> Result<Input<'d>, Error> {
+		if self.depth > MAX_NESTING {
+			return Err(de::Error::custom(format!("Values nested deeper than {}, e.g. objects referencing each other", MAX_NESTING)));
+		}
+		match self.input.take() {
+			Some(Input::Data(&Data::Reference(id))) => match self.document.get(id) {
+				Some(data) => Ok(Input::Data(data)),
+				None => Err(de::Error::custom(format!("Reference to {} which is not in the stream", id))),
+			},
+			Some(input) => Ok(input),
+			None => Err(de::Error::end_of_stream()),
+		}
+	}
+
+	/// Objects give the fields of structs, matching backing fields to them
+	fn deserialize_input<V: de::Visitor>(&mut self, fields: &'static [&'static str], mut visitor: V) -> Result<V::Value, Error> {
+		match try!(self.take()) {
+			Input::Data(&Data::Primitive(ref primitive)) => visit_primitive(primitive, visitor),
+			Input::Data(&Data::String(ref s)) => visitor.visit_str(s),
+			Input::Data(&Data::Null) => visitor.visit_unit(),
+			Input::Data(&Data::Object(ref object)) => {
+				let class = match self.document.get_class(object.class_id) {
+					Some(class) => class,
+					None => return Err(de::Error::custom(format!("Class {} of object {} is not in the stream", object.class_id, object.id))),
+				};
+				visitor.visit_map(MembersVisitor {
+					document: self.document,
+					depth: self.depth,
+					class: class,
+					members: &object.members,
+					fields: fields,
+					i: 0,
+				})
+			},
+			Input::Data(&Data::Array(ref array)) => visitor.visit_seq(ElementsVisitor::new(self, &array.elements, &array.lengths)),
+			Input::Data(&Data::Reference(id)) => Err(de::Error::custom(format!("Reference {} to a reference", id))),
+			Input::Rows(elements, lengths) => visitor.visit_seq(ElementsVisitor::new(self, elements, lengths)),
+			Input::Missing(field) => Err(de::Error::missing_field(field)),
+		}
+	}
+}
+
+fn visit_primitive<V: de::Visitor>(primitive: &Primitive, mut visitor: V) -> Result<V::Value, Error> {
+	match primitive {
+		&Primitive::Boolean(x) => visitor.visit_bool(x),
+		&Primitive::Byte(x) => visitor.visit_u8(x),
+		&Primitive::Char(x) => visitor.visit_char(x),
+		&Primitive::Decimal(ref x) => visitor.visit_str(&x.Value),
+		&Primitive::Double(x) => visitor.visit_f64(x),
+		&Primitive::Int16(x) => visitor.visit_i16(x),
+		&Primitive::Int32(x) => visitor.visit_i32(x),
+		&Primitive::Int64(x) => visitor.visit_i64(x),
+		&Primitive::SByte(x) => visitor.visit_i8(x),
+		&Primitive::Single(x) => visitor.visit_f32(x),
+		&Primitive::TimeSpan(ref x) => visitor.visit_string(x.to_string()),
+		&Primitive::DateTime(ref x) => visitor.visit_string(x.to_iso_string()),
+		&Primitive::UInt16(x) => visitor.visit_u16(x),
+		&Primitive::UInt32(x) => visitor.visit_u32(x),
+		&Primitive::UInt64(x) => visitor.visit_u64(x),
+	}
+}
+
+/// Auto-implemented properties of C# are written as "<Name>k__BackingField", which fills a field "Name"
+/// unless there is a field of the whole name
+fn field_name<'a>(member_name: &'a str, fields: &[&str]) -> &'a str {
+	const SUFFIX: &'static str = ">k__BackingField";
+	if !fields.contains(&member_name) && member_name.starts_with('<') && member_name.ends_with(SUFFIX) {
+		return &member_name[1..member_name.len() - SUFFIX.len()];
+	}
+	member_name
+}
+
+struct MembersVisitor<'d> {
+	document: &'d Document,
+	depth: usize,
+	class: &'d Class,
+	members: &'d [Data],
+	fields: &'static [&'static str],
+	/// Index of the next member
+	i: usize,
+}
+
+impl<'d> de::MapVisitor for MembersVisitor<'d> {
+	type Error = Error;
+
+	fn visit_key<K>(&mut self) -> Result<Option<K>, Error>
+		where K: de::Deserialize
+	{
+		if self.i >= self.members.len() {
+			return Ok(None);
+		}
+		let key = field_name(&self.class.member_names[self.i], self.fields);
+		K::deserialize(&mut <&str as ValueDeserializer<Error>>::into_deserializer(key)).map(Some)
+	}
+
+	fn visit_value<V>(&mut self) -> Result<V, Error>
+		where V: de::Deserialize
+	{
+		let i = self.i;
+		self.i += 1;
+		let mut deserializer = Deserializer::nested(self.document, Input::Data(&self.members[i]), self.depth);
+		V::deserialize(&mut deserializer).map_err(|e| e.within(format!("{}.{}", self.class.name, self.class.member_names[i])))
+	}
+
+	fn end(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let left = self.members.len() - self.i;
+		(left, Some(left))
+	}
+
+	/// Fields of older or newer versions of a class are left to Option
+	fn missing_field<V>(&mut self, field: &'static str) -> Result<V, Error>
+		where V: de::Deserialize
+	{
+		V::deserialize(&mut Deserializer::nested(self.document, Input::Missing(field), self.depth))
+	}
+}
+
+struct ElementsVisitor<'d> {
+	document: &'d Document,
+	depth: usize,
+	elements: &'d [Data],
+	/// Length of every dimension left, the elements being split in rows of the first one when there are several
+	lengths: &'d [i32],
+	/// Index of the next element or row
+	i: usize,
+}
+
+impl<'d> ElementsVisitor<'d> {
+	fn new(deserializer: &Deserializer<'d>, elements: &'d [Data], lengths: &'d [i32]) -> Self {
+		ElementsVisitor {
+			document: deserializer.document,
+			depth: deserializer.depth,
+			elements: elements,
+			lengths: lengths,
+			i: 0,
+		}
+	}
+
+	fn len(&self) -> usize {
+		if self.lengths.len() > 1 {
+			self.lengths[0] as usize
+		} else {
+			self.elements.len()
+		}
+	}
+}
+
+impl<'d> de::SeqVisitor for ElementsVisitor<'d> {
+	type Error = Error;
+
+	fn visit<T>(&mut self) -> Result<Option<T>, Error>
+		where T: de::Deserialize
+	{
+		let i = self.i;
+		if i >= self.len() {
+			return Ok(None);
+		}
+		self.i += 1;
+		let input = if self.lengths.len() > 1 {
+			let row_length = self.elements.len() / self.len();
+			Input::Rows(&self.elements[i * row_length..(i + 1) * row_length], &self.lengths[1..])
+		} else {
+			Input::Data(&self.elements[i])
+		};
+		T::deserialize(&mut Deserializer::nested(self.document, input, self.depth))
+			.map(Some)
+			.map_err(|e| e.within(format!("element {}", i)))
+	}
+
+	fn end(&mut self) -> Result<(), Error> {
+		if self.i < self.len() {
+			return Err(de::Error::invalid_length(self.len()));
+		}
+		Ok(())
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let left = self.len() - self.i;
+		(left, Some(left))
+	}
+}
+
+impl<'d> de::Deserializer for Deserializer<'d> {
+	type Error = Error;
+
+	fn deserialize<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize_input(&[], visitor)
+	}
+
+	fn deserialize_bool<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_usize<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_u8<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_u16<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_u32<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_u64<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_isize<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_i8<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_i16<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_i32<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_i64<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_f32<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_f64<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_char<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_str<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_string<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_unit<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	/// Nulls and missing members are None
+	fn deserialize_option<V>(&mut self, mut visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		let is_none = match self.input {
+			Some(Input::Data(&Data::Null)) | Some(Input::Missing(_)) => true,
+			_ => false,
+		};
+		if is_none {
+			self.input = None;
+			visitor.visit_none()
+		} else {
+			visitor.visit_some(self)
+		}
+	}
+
+	fn deserialize_seq<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_seq_fixed_size<V>(&mut self, _: usize, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	/// Byte arrays are given at once
+	fn deserialize_bytes<V>(&mut self, mut visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		if let Some(Input::Data(&Data::Array(ref array))) = self.input.clone() {
+			if array.element_type == MemberType::Primitive(PrimitiveTypeEnumeration::Byte) {
+				self.input = None;
+				let bytes = array.elements.iter().map(|element| match element {
+					&Data::Primitive(Primitive::Byte(x)) => x,
+					_ => unreachable!(),
+				}).collect();
+				return visitor.visit_byte_buf(bytes);
+			}
+		}
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_map<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_unit_struct<V>(&mut self, _: &'static str, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_newtype_struct<V>(&mut self, _: &'static str, mut visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		visitor.visit_newtype_struct(self)
+	}
+
+	fn deserialize_tuple_struct<V>(&mut self, _: &'static str, _: usize, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_struct<V>(&mut self, _: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize_input(fields, visitor)
+	}
+
+	fn deserialize_struct_field<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_tuple<V>(&mut self, _: usize, visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.deserialize(visitor)
+	}
+
+	fn deserialize_enum<V>(&mut self, _: &'static str, _: &'static [&'static str], _: V) -> Result<V::Value, Error>
+		where V: de::EnumVisitor
+	{
+		Err(de::Error::invalid_type(de::Type::Enum))
+	}
+
+	/// Members which are not fields are skipped without being walked
+	fn deserialize_ignored_any<V>(&mut self, mut visitor: V) -> Result<V::Value, Error>
+		where V: de::Visitor
+	{
+		self.input = None;
+		visitor.visit_unit()
+	}
+}
+
+/// Fills a Rust type from the given value of a document
+pub fn from_data<T: de::Deserialize>(document: &Document, data: &Data) -> Result<T, Error> {
+	T::deserialize(&mut Deserializer::new(document, data))
+}
+
+/// Fills a Rust type from the root object of a document
+pub fn from_document<T: de::Deserialize>(document: &Document) -> Result<T, Error> {
+	match document.get_root() {
+		Some(root) => from_data(document, root),
+		None => Err(de::Error::custom(format!("The root object {} is not in the stream", document.root_id))),
+	}
+}
+
+/// Fills a Rust type from the root object of a stream whose classes are all written with their member types.
+/// Use Document::read and from_document for a schema.
+pub fn from_reader<T: de::Deserialize, R: Read>(reader: R) -> Result<T, Error> {
+	let document = try!(read_document(reader));
+	from_document(&document)
+}
+
+#[cfg(test)]
+mod tests {
+	use model::Primitive;
+	use records::*;
+	use super::{Error, from_reader};
+
+	#[derive(Deserialize, Debug, PartialEq)]
+	struct Save {
+		gold: i32,
+		name: String,
+		scores: Vec<i32>,
+		next: Option<Box<Save>>,
+	}
+
+	#[derive(Deserialize, Debug)]
+	struct NamedScores {
+		scores: Vec<String>,
+	}
+
+	/// A save with a name and scores, then another one of id 3 with the same scores, given what their next members are
+	fn saves(root_next: Value, next_next: Value) -> Vec<u8> {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		BinaryLibraryRecord { LibraryId: 2, LibraryName: "Game".to_string() }.write(&mut bytes);
+		ClassWithMembersAndTypesRecord {
+			ClassInfo: ClassInfoRecord {
+				ObjectId: 1,
+				Name: "Game.Save".to_string(),
+				MemberCount: 4,
+				MemberNames: vec!["gold".to_string(), "<name>k__BackingField".to_string(), "scores".to_string(), "next".to_string()],
+			},
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![BinaryTypeEnumeration::Primitive, BinaryTypeEnumeration::String, BinaryTypeEnumeration::PrimitiveArray, BinaryTypeEnumeration::Class],
+				AdditionalInfos: vec![
+					AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32),
+					AdditionalInfo::None,
+					AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32),
+					AdditionalInfo::Class(ClassTypeInfoRecord { TypeName: "Game.Save".to_string(), LibraryId: 2 }),
+				],
+			},
+			LibraryId: 2,
+		}.write(&mut bytes);
+		write_primitive(&mut bytes, &Primitive::Int32(10));
+		BinaryObjectStringRecord { ObjectId: 4, Value: "first".to_string() }.write(&mut bytes);
+		MemberReferenceRecord { IdRef: 5 }.write(&mut bytes);
+		root_next.write(&mut bytes);
+		ClassWithIdRecord { ObjectId: 3, MetadataId: 1 }.write(&mut bytes);
+		write_primitive(&mut bytes, &Primitive::Int32(0));
+		BinaryObjectStringRecord { ObjectId: 6, Value: "second".to_string() }.write(&mut bytes);
+		MemberReferenceRecord { IdRef: 5 }.write(&mut bytes);
+		next_next.write(&mut bytes);
+		ArraySinglePrimitiveRecord {
+			ArrayInfo: ArrayInfoRecord { ObjectId: 5, Length: 2 },
+			PrimitiveTypeEnum: PrimitiveTypeEnumeration::Int32,
+			Values: PrimitiveValues::Int32(vec![3, 5]),
+		}.write(&mut bytes);
+		MessageEndRecord {}.write(&mut bytes);
+		bytes
+	}
+
+	fn reference(id: i32) -> Value {
+		Value::MemberReference(MemberReferenceRecord { IdRef: id })
+	}
+
+	fn null() -> Value {
+		Value::ObjectNull(ObjectNullRecord {})
+	}
+
+	#[test]
+	fn fields_from_members() {
+		let bytes = saves(reference(3), null());
+		let save: Save = from_reader(&bytes[..]).unwrap();
+		assert_eq!(save, Save {
+			gold: 10,
+			name: "first".to_string(),
+			scores: vec![3, 5],
+			next: Some(Box::new(Save { gold: 0, name: "second".to_string(), scores: vec![3, 5], next: None })),
+		});
+	}
+
+	#[test]
+	fn mismatch_path() {
+		let bytes = saves(null(), null());
+		match from_reader::<NamedScores, _>(&bytes[..]) {
+			Err(Error::Mismatch(_, path)) => assert_eq!(path, vec!["Game.Save.scores".to_string(), "element 0".to_string()]),
+			result => panic!("Expected a mismatch, got {:?}", result),
+		}
+	}
+
+	#[test]
+	fn objects_referencing_each_other() {
+		let bytes = saves(reference(3), reference(1));
+		match from_reader::<Save, _>(&bytes[..]) {
+			Err(Error::Mismatch(message, _)) => assert!(message.starts_with("Values nested deeper than")),
+			result => panic!("Expected the depth to be exceeded, got {:?}", result),
+		}
+	}
+
+	#[test]
+	fn truncated_stream() {
+		let bytes = saves(null(), null());
+		match from_reader::<Save, _>(&bytes[..bytes.len() - 10]) {
+			Err(Error::Read(e)) => {
+				assert_eq!(e.offset(), bytes.len() as u64 - 10);
+				assert_eq!(e.path(), &vec!["record 4".to_string(), "ArraySinglePrimitive".to_string()]);
+			},
+			result => panic!("Expected a read error, got {:?}", result),
+		}
+	}
+}
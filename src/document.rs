@@ -0,0 +1,501 @@
+//! A parsed stream: the records, and the classes and values defined by them as plain data.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use itertools::Zip;
+
+use records::*;
+use lossless::{LosslessRecord, read_records};
+use model::*;
+use schema::Schema;
+
+/// Holds every value of a stream. Objects, arrays and strings are looked up by id wherever they were written.
+pub struct Document {
+	/// Every record in stream order, records written inline being kept in the values of the record containing them
+	pub records: Vec<LosslessRecord>,
+	pub root_id: i32,
+	/// Libraries by id in stream order
+	pub libraries: Vec<(i32, String)>,
+	classes: Vec<Class>,
+	class_indices: HashMap<i32, usize>,
+	/// Values of the records written on their own, i.e. not as a member value or an array element
+	values: Vec<Data>,
+	/// Where a value with an id is: the index in values, then the member or element index at every level
+	paths: HashMap<i32, Vec<usize>>,
+}
+
+impl Document {
+	/// Reads a stream from any reader, up to the MessageEnd record.
+	/// Classes written without member types are looked up in the schema,
+	/// which gets the classes written with their member types in turn.
+	/// Records are read a few bytes at a time, so give a byte slice or a BufReader rather than a File.
+	pub fn read<R: Read>(reader: R, schema: &mut Schema) -> Result<Self, DeserializeError> {
+		let mut file = OffsetReader::new(reader);
+		let mut context = ReadContext::new(schema);
+		let records = try!(read_records(&mut file, &mut context));
+		let root_id = match records.first() {
+			Some(&LosslessRecord::SerializedStreamHeader(ref header)) => header.RootId,
+			Some(record) => {
+				let location = Location {
+					offset: 0,
+					path: vec!["record 0".to_string()],
+				};
+				return Err(DeserializeError::UnsupportedRecordType(location, record.get_record_type() as u8));
+			},
+			None => unreachable!(),
+		};
+		Ok(Document::new(records, root_id))
+	}
+
+	/// The records are read, so every ClassWithId has its class defined before
+	fn new(records: Vec<LosslessRecord>, root_id: i32) -> Self {
+		let mut builder = Builder {
+			libraries: vec![],
+			classes: vec![],
+			paths: HashMap::new(),
+			path: vec![],
+		};
+		let mut values = vec![];
+		for record in records.iter() {
+			builder.path = vec![values.len()];
+			if let Some(data) = builder.record_data(record) {
+				values.push(data);
+			}
+		}
+		let class_indices = builder.classes.iter().enumerate().map(|(i, class)| (class.id, i)).collect();
+		Document {
+			records: records,
+			root_id: root_id,
+			libraries: builder.libraries,
+			classes: builder.classes,
+			class_indices: class_indices,
+			values: values,
+			paths: builder.paths,
+		}
+	}
+
+	/// The object, array or string of the given id
+	pub fn get(&self, id: i32) -> Option<&Data> {
+		let path = match self.paths.get(&id) {
+			Some(path) => path,
+			None => return None,
+		};
+		let mut data = &self.values[path[0]];
+		for i in path[1..].iter() {
+			data = &data.get_children()[*i];
+		}
+		Some(data)
+	}
+
+	/// Looks up references, other values are returned as they are
+	pub fn resolve<'a>(&'a self, data: &'a Data) -> Option<&'a Data> {
+		match data {
+			&Data::Reference(id) => self.get(id),
+			_ => Some(data),
+		}
+	}
+
+	pub fn get_root(&self) -> Option<&Data> {
+		self.get(self.root_id)
+	}
+
+	/// Values of the records written on their own in stream order, objects written inline being inside them
+	pub fn get_values(&self) -> &Vec<Data> {
+		&self.values
+	}
+
+	/// The class defined by the record of the given object id
+	pub fn get_class(&self, class_id: i32) -> Option<&Class> {
+		self.class_indices.get(&class_id).map(|i| &self.classes[*i])
+	}
+
+	/// Classes in stream order
+	pub fn get_classes(&self) -> &Vec<Class> {
+		&self.classes
+	}
+
+	pub fn get_string(&self, id: i32) -> Option<&str> {
+		self.get(id).and_then(|data| data.as_str())
+	}
+
+	pub fn get_object(&self, id: i32) -> Option<&Object> {
+		self.get(id).and_then(|data| data.as_object())
+	}
+
+	/// Every object in stream order, including those written inline
+	pub fn get_objects(&self) -> Vec<&Object> {
+		let mut objects = vec![];
+		for data in self.values.iter() {
+			push_objects(data, &mut objects);
+		}
+		objects
+	}
+
+	/// Number of places every object, array and string is referenced from, objects and arrays
+	/// written inline counting as referenced where they are written
+	pub fn get_reference_counts(&self) -> HashMap<i32, usize> {
+		let mut reference_counts = HashMap::new();
+		for data in self.values.iter() {
+			count_references(data, &mut reference_counts);
+		}
+		reference_counts
+	}
+
+	/// A member value of the object by name, references being looked up
+	pub fn get_member<'a>(&'a self, object: &'a Object, name: &str) -> Option<&'a Data> {
+		self.get_class(object.class_id)
+			.and_then(|class| class.get_member_index(name))
+			.and_then(|i| self.resolve(&object.members[i]))
+	}
+}
+
+fn count_references(data: &Data, reference_counts: &mut HashMap<i32, usize>) {
+	for child in data.get_children().iter() {
+		let id = match child {
+			&Data::Reference(id) => id,
+			&Data::Object(ref object) => object.id,
+			&Data::Array(ref array) => array.id,
+			_ => continue,
+		};
+		*reference_counts.entry(id).or_insert(0) += 1;
+		count_references(child, reference_counts);
+	}
+}
+
+fn push_objects<'a>(data: &'a Data, objects: &mut Vec<&'a Object>) {
+	if let &Data::Object(ref object) = data {
+		objects.push(object);
+	}
+	for child in data.get_children().iter() {
+		push_objects(child, objects);
+	}
+}
+
+/// Converts the records to Data, keeping where every value with an id goes
+struct Builder {
+	libraries: Vec<(i32, String)>,
+	classes: Vec<Class>,
+	paths: HashMap<i32, Vec<usize>>,
+	/// Where the value being converted goes
+	path: Vec<usize>,
+}
+
+impl Builder {
+	fn add_id(&mut self, id: i32) {
+		self.paths.insert(id, self.path.clone());
+	}
+
+	fn add_library(&mut self, library: &BinaryLibraryRecord) {
+		self.libraries.push((library.LibraryId, library.LibraryName.clone()));
+	}
+
+	fn add_class(&mut self, class_id: i32, class: &ClassRecordForClassWithId) {
+		self.classes.push(class_of(class_id, class));
+	}
+
+	/// None for records without a value, i.e. the header, libraries and MessageEnd
+	fn record_data(&mut self, record: &LosslessRecord) -> Option<Data> {
+		use lossless::LosslessRecord::*;
+
+		if let Some((class_id, values)) = match record {
+			&ClassWithId(ref r, ref values) => Some((r.MetadataId, values)),
+			&SystemClassWithMembers(ref r, ref values) => { self.add_class(r.ClassInfo.ObjectId, &**r); Some((r.ClassInfo.ObjectId, values)) },
+			&ClassWithMembers(ref r, ref values) => { self.add_class(r.ClassInfo.ObjectId, &**r); Some((r.ClassInfo.ObjectId, values)) },
+			&SystemClassWithMembersAndTypes(ref r, ref values) => { self.add_class(r.ClassInfo.ObjectId, &**r); Some((r.ClassInfo.ObjectId, values)) },
+			&ClassWithMembersAndTypes(ref r, ref values) => { self.add_class(r.ClassInfo.ObjectId, &**r); Some((r.ClassInfo.ObjectId, values)) },
+			_ => None,
+		} {
+			let id = record.get_object_id().unwrap();
+			self.add_id(id);
+			let mut members = Vec::with_capacity(values.len());
+			for value in values.iter() {
+				self.path.push(members.len());
+				members.push(self.value_data(value));
+				self.path.pop();
+			}
+			return Some(Data::Object(Object {
+				id: id,
+				class_id: class_id,
+				members: members,
+			}));
+		}
+
+		Some(match record {
+			&BinaryObjectString(ref r) => {
+				self.add_id(r.ObjectId);
+				Data::String(r.Value.clone())
+			},
+			&BinaryArray(ref r) => {
+				self.add_id(r.ObjectId);
+				Data::Array(Array {
+					id: r.ObjectId,
+					element_type: member_type_of(&r.TypeEnum, &r.AdditionalTypeInfo),
+					lengths: r.Lengths.clone(),
+					lower_bounds: r.LowerBounds.clone(),
+					elements: match r.Values {
+						ArrayValues::Primitive(ref values) => primitive_values_data(values),
+						ArrayValues::Records(ref values) => self.elements_data(values),
+					},
+				})
+			},
+			&ArraySinglePrimitive(ref r) => {
+				self.add_id(r.ArrayInfo.ObjectId);
+				Data::Array(Array {
+					id: r.ArrayInfo.ObjectId,
+					element_type: MemberType::Primitive(r.Values.get_primitive_type()),
+					lengths: vec![r.ArrayInfo.Length],
+					lower_bounds: None,
+					elements: primitive_values_data(&r.Values),
+				})
+			},
+			&ArraySingleObject(ref r) => {
+				self.add_id(r.ArrayInfo.ObjectId);
+				Data::Array(Array {
+					id: r.ArrayInfo.ObjectId,
+					element_type: MemberType::Object,
+					lengths: vec![r.ArrayInfo.Length],
+					lower_bounds: None,
+					elements: self.elements_data(&r.Values),
+				})
+			},
+			&ArraySingleString(ref r) => {
+				self.add_id(r.ArrayInfo.ObjectId);
+				Data::Array(Array {
+					id: r.ArrayInfo.ObjectId,
+					element_type: MemberType::String,
+					lengths: vec![r.ArrayInfo.Length],
+					lower_bounds: None,
+					elements: self.elements_data(&r.Values),
+				})
+			},
+			&MemberPrimitiveTyped(ref r) => Data::Primitive(r.Value.clone()),
+			&MemberReference(ref r) => Data::Reference(r.IdRef),
+			&ObjectNull(_) | &ObjectNullMultiple256(_) | &ObjectNullMultiple(_) => Data::Null,
+			&LibraryWithValue(ref library, ref value) => {
+				self.add_library(library);
+				self.value_data(&**value)
+			},
+			&BinaryLibrary(ref library) => {
+				self.add_library(library);
+				return None;
+			},
+			_ => return None,
+		})
+	}
+
+	/// Runs of nulls are expanded to an element each
+	fn elements_data(&mut self, values: &Vec<Value>) -> Vec<Data> {
+		let mut elements = vec![];
+		for value in values.iter() {
+			let count = value.get_element_count();
+			if count == 1 {
+				self.path.push(elements.len());
+				elements.push(self.value_data(value));
+				self.path.pop();
+			} else {
+				for _ in 0..count {
+					elements.push(Data::Null);
+				}
+			}
+		}
+		elements
+	}
+
+	fn value_data(&mut self, value: &Value) -> Data {
+		match value {
+			&Value::Primitive(ref primitive) => Data::Primitive(primitive.clone()),
+			&Value::BinaryObjectString(ref s) => {
+				self.add_id(s.ObjectId);
+				Data::String(s.Value.clone())
+			},
+			&Value::MemberPrimitiveTyped(ref r) => Data::Primitive(r.Value.clone()),
+			&Value::MemberReference(ref r) => Data::Reference(r.IdRef),
+			&Value::ObjectNull(_) | &Value::ObjectNullMultiple256(_) | &Value::ObjectNullMultiple(_) => Data::Null,
+			&Value::Record(ref record) => self.record_data(record).unwrap_or(Data::Null),
+		}
+	}
+}
+
+fn primitive_vec_data<T: Clone, F: Fn(T) -> Primitive>(values: &Vec<T>, primitive: F) -> Vec<Data> {
+	values.iter().map(|x| Data::Primitive(primitive(x.clone()))).collect()
+}
+
+/// Elements of a primitive array, where strings are Data::String
+fn primitive_values_data(values: &PrimitiveValues) -> Vec<Data> {
+	use records::PrimitiveValues as V;
+
+	match values {
+		&V::Boolean(ref v) => primitive_vec_data(v, Primitive::Boolean),
+		&V::Byte(ref v) => primitive_vec_data(v, Primitive::Byte),
+		&V::Char(ref v) => primitive_vec_data(v, Primitive::Char),
+		&V::Decimal(ref v) => primitive_vec_data(v, Primitive::Decimal),
+		&V::Double(ref v) => primitive_vec_data(v, Primitive::Double),
+		&V::Int16(ref v) => primitive_vec_data(v, Primitive::Int16),
+		&V::Int32(ref v) => primitive_vec_data(v, Primitive::Int32),
+		&V::Int64(ref v) => primitive_vec_data(v, Primitive::Int64),
+		&V::SByte(ref v) => primitive_vec_data(v, Primitive::SByte),
+		&V::Single(ref v) => primitive_vec_data(v, Primitive::Single),
+		&V::TimeSpan(ref v) => primitive_vec_data(v, Primitive::TimeSpan),
+		&V::DateTime(ref v) => primitive_vec_data(v, Primitive::DateTime),
+		&V::UInt16(ref v) => primitive_vec_data(v, Primitive::UInt16),
+		&V::UInt32(ref v) => primitive_vec_data(v, Primitive::UInt32),
+		&V::UInt64(ref v) => primitive_vec_data(v, Primitive::UInt64),
+		&V::String(ref v) => v.iter().map(|s| Data::String(s.clone())).collect(),
+	}
+}
+
+/// The class defined by a class record, as plain data
+pub fn class_of(class_id: i32, class: &ClassRecordForClassWithId) -> Class {
+	let (name, member_names, binary_types, additional_infos) = class.get_info_for_json();
+	Class {
+		id: class_id,
+		name: name,
+		library_id: class.get_library_id(),
+		member_names: member_names.clone(),
+		member_types: Zip::new((binary_types, additional_infos))
+			.map(|(binary_type, additional_info)| member_type_of(binary_type, additional_info))
+			.collect(),
+	}
+}
+
+pub fn member_type_of(binary_type: &BinaryTypeEnumeration, additional_info: &AdditionalInfo) -> MemberType {
+	match (binary_type, additional_info) {
+		(&BinaryTypeEnumeration::Primitive, &AdditionalInfo::Primitive(primitive_type)) => MemberType::Primitive(primitive_type),
+		(&BinaryTypeEnumeration::PrimitiveArray, &AdditionalInfo::Primitive(primitive_type)) => MemberType::PrimitiveArray(primitive_type),
+		(&BinaryTypeEnumeration::SystemClass, &AdditionalInfo::SystemClass(ref class_name)) => MemberType::SystemClass(class_name.clone()),
+		(&BinaryTypeEnumeration::Class, &AdditionalInfo::Class(ref class_type)) => MemberType::Class(class_type.TypeName.clone(), class_type.LibraryId),
+		(&BinaryTypeEnumeration::String, _) => MemberType::String,
+		(&BinaryTypeEnumeration::Object, _) => MemberType::Object,
+		(&BinaryTypeEnumeration::ObjectArray, _) => MemberType::ObjectArray,
+		(&BinaryTypeEnumeration::StringArray, _) => MemberType::StringArray,
+		(binary_type, additional_info) => unreachable!("{:?} with {:?}", binary_type, additional_info),
+	}
+}
+
+/// Reads a stream whose classes are all written with their member types
+pub fn read_document<R: Read>(reader: R) -> Result<Document, DeserializeError> {
+	Document::read(reader, &mut Schema::new())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::rc::Rc;
+
+	use records::*;
+	use lossless::{LosslessRecord, write_records};
+	use model::*;
+	use super::*;
+
+	fn read_bytes(bytes: &[u8]) -> Result<Document, DeserializeError> {
+		read_document(bytes)
+	}
+
+	/// Two Game.Item objects with a name and a count, the second using the class of the first
+	fn items() -> Vec<u8> {
+		let class = Rc::new(SystemClassWithMembersAndTypesRecord {
+			ClassInfo: ClassInfoRecord {
+				ObjectId: 1,
+				Name: "Game.Item".to_string(),
+				MemberCount: 2,
+				MemberNames: vec!["Name".to_string(), "Count".to_string()],
+			},
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![BinaryTypeEnumeration::String, BinaryTypeEnumeration::Primitive],
+				AdditionalInfos: vec![AdditionalInfo::None, AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32)],
+			},
+		});
+		let name = |id: i32, value: &str| Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: id, Value: value.to_string() });
+		let mut bytes = vec![];
+		write_records(&mut bytes, &vec![
+			LosslessRecord::SerializedStreamHeader(SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }),
+			LosslessRecord::SystemClassWithMembersAndTypes(class, vec![name(2, "sword"), Value::Primitive(Primitive::Int32(5))]),
+			LosslessRecord::ClassWithId(ClassWithIdRecord { ObjectId: 3, MetadataId: 1 }, vec![name(4, "shield"), Value::Primitive(Primitive::Int32(6))]),
+			LosslessRecord::MessageEnd(MessageEndRecord {}),
+		]);
+		bytes
+	}
+
+	#[test]
+	fn objects_of_a_document() {
+		let document = read_bytes(&items()).unwrap();
+		assert_eq!(document.root_id, 1);
+		assert_eq!(document.records.len(), 4);
+		assert_eq!(document.get_objects().iter().map(|object| object.id).collect::<Vec<_>>(), vec![1, 3]);
+		let class = document.get_class(1).unwrap();
+		assert_eq!(class.name, "Game.Item");
+		assert_eq!(class.library_id, None);
+		assert_eq!(class.member_types, vec![MemberType::String, MemberType::Primitive(PrimitiveTypeEnumeration::Int32)]);
+		assert!(document.get_class(3).is_none());
+		assert_eq!(document.get_string(4), Some("shield"));
+
+		let root = document.get_object(1).unwrap();
+		assert_eq!(root.class_id, 1);
+		assert_eq!(document.get_member(root, "Name").and_then(Data::as_str), Some("sword"));
+		assert_eq!(document.get_member(root, "Count").and_then(Data::as_primitive), Some(&Primitive::Int32(5)));
+		assert!(document.get_member(root, "Weight").is_none());
+		let other = document.get_object(3).unwrap();
+		assert_eq!(other.class_id, 1);
+		assert_eq!(other.members, vec![Data::String("shield".to_string()), Data::Primitive(Primitive::Int32(6))]);
+		assert!(document.get_object(2).is_none());
+	}
+
+	#[test]
+	fn references_and_arrays() {
+		let class = Rc::new(SystemClassWithMembersAndTypesRecord {
+			ClassInfo: ClassInfoRecord {
+				ObjectId: 1,
+				Name: "Game.Bag".to_string(),
+				MemberCount: 2,
+				MemberNames: vec!["Items".to_string(), "Counts".to_string()],
+			},
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![BinaryTypeEnumeration::StringArray, BinaryTypeEnumeration::PrimitiveArray],
+				AdditionalInfos: vec![AdditionalInfo::None, AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32)],
+			},
+		});
+		let items = ArraySingleStringRecord {
+			ArrayInfo: ArrayInfoRecord { ObjectId: 3, Length: 4 },
+			Values: vec![
+				Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: 4, Value: "sword".to_string() }),
+				Value::ObjectNullMultiple256(ObjectNullMultiple256Record { NullCount: 2 }),
+				Value::MemberReference(MemberReferenceRecord { IdRef: 4 }),
+			],
+		};
+		let mut bytes = vec![];
+		write_records(&mut bytes, &vec![
+			LosslessRecord::SerializedStreamHeader(SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }),
+			LosslessRecord::SystemClassWithMembersAndTypes(class, vec![Value::MemberReference(MemberReferenceRecord { IdRef: 3 }), Value::MemberReference(MemberReferenceRecord { IdRef: 5 })]),
+			LosslessRecord::ArraySingleString(items),
+			LosslessRecord::ArraySinglePrimitive(ArraySinglePrimitiveRecord {
+				ArrayInfo: ArrayInfoRecord { ObjectId: 5, Length: 2 },
+				PrimitiveTypeEnum: PrimitiveTypeEnumeration::Int32,
+				Values: PrimitiveValues::Int32(vec![7, 8]),
+			}),
+			LosslessRecord::MessageEnd(MessageEndRecord {}),
+		]);
+		let document = read_bytes(&bytes).unwrap();
+		assert_eq!(document.get_values().len(), 3);
+
+		let root = document.get_object(1).unwrap();
+		assert_eq!(root.members, vec![Data::Reference(3), Data::Reference(5)]);
+		let items = document.get_member(root, "Items").and_then(Data::as_array).unwrap();
+		assert_eq!(items.element_type, MemberType::String);
+		assert_eq!(items.elements, vec![Data::String("sword".to_string()), Data::Null, Data::Null, Data::Reference(4)]);
+		assert_eq!(document.resolve(&items.elements[3]).and_then(Data::as_str), Some("sword"));
+		let counts = document.get_member(root, "Counts").and_then(Data::as_array).unwrap();
+		assert_eq!(counts.element_type, MemberType::Primitive(PrimitiveTypeEnumeration::Int32));
+		assert_eq!(counts.lengths, vec![2]);
+		assert_eq!(counts.elements, vec![Data::Primitive(Primitive::Int32(7)), Data::Primitive(Primitive::Int32(8))]);
+	}
+
+	#[test]
+	fn document_without_header() {
+		let mut bytes = vec![];
+		MessageEndRecord {}.write(&mut bytes);
+		match read_bytes(&bytes) {
+			Err(DeserializeError::UnsupportedRecordType(_, 11)) => {},
+			result => panic!("Expected the header to be missing, got {:?}", result.map(|document| document.records.len())),
+		}
+	}
+}
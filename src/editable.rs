@@ -0,0 +1,1532 @@
+//! The json file for reading and editing.
+//!
+//! Objects are listed by id with their members. Strings and arrays are inlined where they are referenced,
+//! other references are kept as {"IdRef": id}. Record ids, metadata reuse and string interning are not kept,
+//! see the lossless module for those. An array containing itself is written as {"ArrayId": id, "Values": [...]},
+//! the id it is referenced by from within.
+//!
+//! Objects of well-known classes referenced from a single place are inlined there as the values they hold,
+//! e.g. a `List<T>` or `Queue<T>` as the array of its items, a `Dictionary<string, T>` as an object or a `Guid`
+//! as a string, and built back from them when written.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+use std::rc::Rc;
+
+use num::ToPrimitive;
+use serde_json::{Value as JValue, Map, to_value};
+use itertools::Zip;
+
+use records::*;
+use json::*;
+use lossless::LosslessRecord;
+use model::{Data, Object, Array, MemberType, Primitive};
+use document::Document;
+use converters::{Converter, Converters};
+use known_types::{Known, KnownClass, recognize, known_class, map_keys, get_prime, get_enum_names, parse_guid, parse_version,
+	parse_date_time_offset};
+
+/// Inlines strings and arrays where they are referenced, other references are kept as {"IdRef"}
+struct ReferenceResolver<'a> {
+	document: &'a Document,
+	/// Objects of well-known or converted classes written where they are referenced rather than with the other objects
+	inlined: HashSet<i32>,
+	converters: &'a Converters,
+}
+
+impl<'a> ReferenceResolver<'a> {
+	/// Primitives keep their type where the member or element type does not give it.
+	/// Visiting holds the arrays being resolved, and whether they have been referenced from within.
+	fn resolve(&self, data: &Data, member_type: &MemberType, visiting: &mut Vec<(i32, bool)>) -> JValue {
+		match data {
+			&Data::Primitive(ref primitive) => match member_type {
+				&MemberType::Primitive(_) => primitive_to_json(primitive),
+				_ => typed_primitive_to_json(primitive),
+			},
+			&Data::String(ref s) => JValue::String(s.clone()),
+			&Data::Null => JValue::Null,
+			&Data::Object(ref object) if self.inlined.contains(&object.id) => self.resolve_known(object, visiting),
+			// Objects written inline are listed with the other objects
+			&Data::Object(ref object) => to_value(&MemberReferenceRecord { IdRef: object.id }),
+			&Data::Array(ref array) => self.resolve_array(array, visiting),
+			&Data::Reference(id) => match self.document.get(id) {
+				Some(&Data::String(ref s)) => JValue::String(s.clone()),
+				// An array containing itself stays a reference
+				Some(&Data::Array(ref array)) => match visiting.iter().position(|&(visiting_id, _)| visiting_id == id) {
+					Some(i) => {
+						visiting[i].1 = true;
+						to_value(&MemberReferenceRecord { IdRef: id })
+					},
+					None => self.resolve_array(array, visiting),
+				},
+				Some(&Data::Object(ref object)) if self.inlined.contains(&id) => self.resolve_known(object, visiting),
+				_ => to_value(&MemberReferenceRecord { IdRef: id }),
+			},
+		}
+	}
+
+	fn resolve_array(&self, array: &Array, visiting: &mut Vec<(i32, bool)>) -> JValue {
+		visiting.push((array.id, false));
+		let elements = array.elements.iter()
+			.map(|element| self.resolve(element, &array.element_type, visiting))
+			.collect();
+		let (_, is_referenced) = visiting.pop().unwrap();
+		let json = shape_array_json(elements, &array.lengths, &array.lower_bounds);
+		if !is_referenced {
+			return json;
+		}
+		let mut m = match json {
+			JValue::Object(m) => m,
+			values => {
+				let mut m = Map::new();
+				m.insert("Values".to_string(), values);
+				m
+			},
+		};
+		m.insert("ArrayId".to_string(), JValue::I64(array.id as i64));
+		JValue::Object(m)
+	}
+
+	fn resolve_known(&self, object: &Object, visiting: &mut Vec<(i32, bool)>) -> JValue {
+		let class = self.document.get_class(object.class_id).unwrap();
+		if let Some(converter) = self.converters.get_for_class(class) {
+			let members = Zip::new((&class.member_types, &object.members))
+				.map(|(member_type, member)| self.resolve(member, member_type, visiting))
+				.collect();
+			let json = converter.to_json(&class.name, &class.member_names, members);
+			if json.is_null() || json.find("IdRef").is_some() {
+				panic!("The converter of {} gave {:?}, which would be read back as a reference", class.name, json);
+			}
+			return json;
+		}
+		match recognize(self.document, object) {
+			Some(Known::Sequence(sequence)) => {
+				let mut elements = vec![];
+				for &item in sequence.items.iter() {
+					elements.push(self.resolve(item, &sequence.item_type, visiting));
+				}
+				JValue::Array(elements)
+			},
+			Some(Known::Map(map)) => match map_keys(self.document, &map) {
+				// An object with the single key "IdRef" would be read back as a reference
+				Some(ref keys) if !(keys.len() == 1 && keys[0] == "IdRef") => {
+					let mut m = Map::new();
+					for (key, &(_, value)) in keys.iter().zip(map.pairs.iter()) {
+						m.insert(key.clone(), self.resolve(value, &map.value_type, visiting));
+					}
+					JValue::Object(m)
+				},
+				_ => {
+					let mut pairs = vec![];
+					for &(key, value) in map.pairs.iter() {
+						let mut m = Map::new();
+						m.insert("key".to_string(), self.resolve(key, &map.key_type, visiting));
+						m.insert("value".to_string(), self.resolve(value, &map.value_type, visiting));
+						pairs.push(JValue::Object(m));
+					}
+					JValue::Array(pairs)
+				},
+			},
+			Some(Known::Text(s)) => JValue::String(s),
+			Some(Known::Nullable(Some(value), value_type)) => self.resolve(value, value_type, visiting),
+			Some(Known::Nullable(None, _)) => JValue::Null,
+			Some(Known::Pair(key, value, key_type, value_type)) => {
+				let mut m = Map::new();
+				m.insert("key".to_string(), self.resolve(key, key_type, visiting));
+				m.insert("value".to_string(), self.resolve(value, value_type, visiting));
+				JValue::Object(m)
+			},
+			Some(Known::Enum(value, name)) => enum_to_json(value, name),
+			None => unreachable!(),
+		}
+	}
+}
+
+/// What is not listed with the other objects
+struct Inlined {
+	/// Objects of well-known classes written where they are referenced
+	objects: HashSet<i32>,
+	/// The inlined objects and those only used by their internal members, such as the KeyValuePair objects
+	/// and comparers of dictionaries
+	hidden: HashSet<i32>,
+	/// Comparer of the inlined collections of every class having one, written back with the class
+	comparers: HashMap<String, JValue>,
+}
+
+/// Well-known objects referenced from a single member or element declared with their own class,
+/// so that the class is known when writing them back
+fn find_inlined(document: &Document, data: &Data, reference_counts: &HashMap<i32, usize>, known_types: bool, converters: &Converters,
+	candidates: &mut HashSet<i32>) {
+	let member_types: Vec<&MemberType> = match data {
+		&Data::Object(ref object) => match document.get_class(object.class_id) {
+			Some(class) => class.member_types.iter().collect(),
+			None => return,
+		},
+		&Data::Array(ref array) => array.elements.iter().map(|_| &array.element_type).collect(),
+		_ => return,
+	};
+	for (child, member_type) in data.get_children().iter().zip(member_types.into_iter()) {
+		find_inlined(document, child, reference_counts, known_types, converters, candidates);
+		if let Some(&Data::Object(ref object)) = document.resolve(child) {
+			let is_declared = match member_type {
+				&MemberType::SystemClass(ref name) | &MemberType::Class(ref name, _) => {
+					document.get_class(object.class_id).map_or(false, |class| class.name == *name)
+				},
+				_ => false,
+			};
+			let is_referenced_once = reference_counts.get(&object.id).map_or(false, |count| *count == 1);
+			let is_converted = document.get_class(object.class_id).map_or(false, |class| converters.get_for_class(class).is_some());
+			let is_known = known_types && recognize(document, object).is_some();
+			if is_declared && is_referenced_once && object.id != document.root_id && (is_converted || is_known) {
+				candidates.insert(object.id);
+			}
+		}
+	}
+}
+
+/// A plain comparer as its class and the comparers it holds, kept with the class of the collections using it
+fn comparer_to_json(document: &Document, comparer: &Data) -> JValue {
+	match document.resolve(comparer) {
+		Some(&Data::Object(ref object)) => {
+			let class = document.get_class(object.class_id).unwrap();
+			let mut members = Map::new();
+			for (name, member) in class.member_names.iter().zip(object.members.iter()) {
+				members.insert(name.clone(), comparer_to_json(document, member));
+			}
+			let mut m = Map::new();
+			m.insert("Class".to_string(), JValue::String(class.name.clone()));
+			m.insert("Members".to_string(), JValue::Object(members));
+			JValue::Object(m)
+		},
+		_ => JValue::Null,
+	}
+}
+
+/// The object or array a value is or refers to, with its id
+fn resolve_node<'d>(document: &'d Document, data: &'d Data) -> Option<(i32, &'d Data)> {
+	document.resolve(data).and_then(|node| match node {
+		&Data::Object(ref object) => Some((object.id, node)),
+		&Data::Array(ref array) => Some((array.id, node)),
+		_ => None,
+	})
+}
+
+/// Counts the references to the objects and arrays reached through the internal members of an inlined object,
+/// stopping at those of the values it holds, given by id. Shared ones are only followed the first time.
+fn count_internal_references(document: &Document, children: &[Data], value_ids: &HashSet<i32>, visited: &mut HashSet<i32>,
+	counts: &mut HashMap<i32, usize>) {
+	for child in children.iter() {
+		let (id, node) = match resolve_node(document, child) {
+			Some((id, node)) if !value_ids.contains(&id) => (id, node),
+			_ => continue,
+		};
+		*counts.entry(id).or_insert(0) += 1;
+		if visited.insert(id) {
+			count_internal_references(document, node.get_children(), value_ids, visited, counts);
+		}
+	}
+}
+
+fn inline_known_objects(document: &Document, known_types: bool, converters: &Converters) -> Inlined {
+	let reference_counts = document.get_reference_counts();
+	let mut candidates = HashSet::new();
+	for data in document.get_values().iter() {
+		find_inlined(document, data, &reference_counts, known_types, converters, &mut candidates);
+	}
+
+	let mut inlined = Inlined {
+		objects: HashSet::new(),
+		hidden: HashSet::new(),
+		comparers: HashMap::new(),
+	};
+	let mut visited = HashSet::new();
+	let mut internal_counts = HashMap::new();
+	// In stream order, so that the comparer kept for a class is that of its first collection
+	for object in document.get_objects().into_iter() {
+		if !candidates.contains(&object.id) {
+			continue;
+		}
+		let class = document.get_class(object.class_id).unwrap();
+		let class_name = &class.name;
+		let is_converted = converters.get_for_class(class).is_some();
+		let known = if is_converted { None } else { recognize(document, object) };
+		let (values, comparer) = match known {
+			// Converted objects hold all their members
+			None if is_converted => (object.members.iter().collect(), None),
+			Some(Known::Sequence(sequence)) => (sequence.items, sequence.comparer),
+			Some(Known::Map(map)) => (map.pairs.iter().flat_map(|&(key, value)| vec![key, value].into_iter()).collect(), map.comparer),
+			Some(Known::Nullable(Some(value), _)) => (vec![value], None),
+			Some(Known::Pair(key, value, _, _)) => (vec![key, value], None),
+			Some(_) => (vec![], None),
+			None => unreachable!(),
+		};
+		let value_ids = values.into_iter().filter_map(|value| resolve_node(document, value).map(|(id, _)| id)).collect();
+		if let Some(comparer) = comparer {
+			let comparer = comparer_to_json(document, comparer);
+			if *inlined.comparers.entry(class_name.clone()).or_insert(comparer.clone()) != comparer {
+				continue;
+			}
+		}
+		count_internal_references(document, &object.members[..], &value_ids, &mut visited, &mut internal_counts);
+		inlined.objects.insert(object.id);
+		inlined.hidden.insert(object.id);
+	}
+	// Default comparers are shared by the collections with the same type of keys
+	for (id, count) in internal_counts.into_iter() {
+		if reference_counts.get(&id) == Some(&count) {
+			inlined.hidden.insert(id);
+		}
+	}
+	inlined
+}
+
+/// The objects by id with their members, strings and arrays being inlined where they are referenced.
+/// The header, the libraries and the member types of every class are kept so that the json can be written back.
+/// Objects of well-known classes such as `List<T>` are inlined where they are referenced unless known_types is false,
+/// and so are those of the classes of the converters.
+pub fn document_to_json(document: &Document, known_types: bool, converters: &Converters) -> JValue {
+	let inlined = inline_known_objects(document, known_types, converters);
+
+	let header_json = match document.records.first() {
+		Some(&LosslessRecord::SerializedStreamHeader(ref header)) => {
+			let mut m = Map::new();
+			m.insert("RootId".to_string(), JValue::I64(header.RootId as i64));
+			m.insert("HeaderId".to_string(), JValue::I64(header.HeaderId as i64));
+			m.insert("MajorVersion".to_string(), JValue::I64(header.MajorVersion as i64));
+			m.insert("MinorVersion".to_string(), JValue::I64(header.MinorVersion as i64));
+			JValue::Object(m)
+		},
+		_ => JValue::Null,
+	};
+
+	let library_json_vec = document.libraries.iter()
+		.map(|&(library_id, ref library_name)| {
+			let mut m = Map::new();
+			m.insert("LibraryId".to_string(), JValue::I64(library_id as i64));
+			m.insert("LibraryName".to_string(), JValue::String(library_name.clone()));
+			JValue::Object(m)
+		})
+		.collect();
+
+	// The type information of every class is kept so that the json file can be serialized back
+	let class_json_vec = document.get_classes().iter()
+		.map(|class| {
+			let mut m = Map::new();
+			m.insert("Name".to_string(), JValue::String(class.name.clone()));
+			m.insert("LibraryId".to_string(), match class.library_id {
+				Some(library_id) => JValue::I64(library_id as i64),
+				None => JValue::Null,
+			});
+			m.insert("Members".to_string(), JValue::Array(Zip::new((&class.member_names, &class.member_types))
+				.map(|(name, member_type)| model_member_type_to_json(name, member_type))
+				.collect()));
+			if let Some(comparer) = inlined.comparers.get(&class.name) {
+				m.insert("Comparer".to_string(), comparer.clone());
+			}
+			JValue::Object(m)
+		})
+		.collect();
+
+	let resolver = ReferenceResolver { document: document, inlined: inlined.objects, converters: converters };
+	let mut json_vec = vec![];
+	for object in document.get_objects().into_iter() {
+		if inlined.hidden.contains(&object.id) {
+			continue;
+		}
+		let class = document.get_class(object.class_id).unwrap();
+		let mut map = Map::new();
+		for (name, member_type, member) in Zip::new((&class.member_names, &class.member_types, &object.members)) {
+			map.insert(name.clone(), resolver.resolve(member, member_type, &mut vec![]));
+		}
+
+		json_vec.push({
+			let mut m = Map::new();
+			m.insert("ObjectId".to_string(), JValue::I64(object.id as i64));
+			m.insert("Class".to_string(), JValue::String(class.name.clone()));
+			m.insert("Members".to_string(), JValue::Object(map));
+			JValue::Object(m)
+		});
+	}
+
+	let mut document_json = Map::new();
+	document_json.insert("Header".to_string(), header_json);
+	document_json.insert("Libraries".to_string(), JValue::Array(library_json_vec));
+	document_json.insert("Classes".to_string(), JValue::Array(class_json_vec));
+	document_json.insert("Objects".to_string(), JValue::Array(json_vec));
+	JValue::Object(document_json)
+}
+
+fn class_info_from_json(object_id: i32, class_json: &JValue) -> Result<(ClassInfoRecord, MemberTypeInfoRecord), String> {
+	let mut member_names = vec![];
+	let mut binary_types = vec![];
+	let mut additional_infos = vec![];
+	for member_type in try!(get_array(class_json, "Members")).iter() {
+		let (name, binary_type, additional_info) = try!(member_type_from_json(member_type));
+		member_names.push(name);
+		binary_types.push(binary_type);
+		additional_infos.push(additional_info);
+	}
+
+	let class_info = ClassInfoRecord {
+		ObjectId: object_id,
+		Name: try!(get_str(class_json, "Name")).to_string(),
+		MemberCount: member_names.len() as i32,
+		MemberNames: member_names,
+	};
+	let member_type_info = MemberTypeInfoRecord {
+		BinaryTypeEnums: binary_types,
+		AdditionalInfos: additional_infos,
+	};
+	Ok((class_info, member_type_info))
+}
+
+/// Builds the class record written for the first object of a class; later objects use ClassWithId
+fn class_from_json(object_id: i32, class_json: &JValue) -> Result<Box<ClassRecordForClassWithId>, String> {
+	let (class_info, member_type_info) = try!(class_info_from_json(object_id, class_json));
+	Ok(match class_json.find("LibraryId") {
+		Some(&JValue::Null) | None => box(SystemClassWithMembersAndTypesRecord {
+			ClassInfo: class_info,
+			MemberTypeInfo: member_type_info,
+		}),
+		Some(_) => box(ClassWithMembersAndTypesRecord {
+			ClassInfo: class_info,
+			MemberTypeInfo: member_type_info,
+			LibraryId: try!(get_i32(class_json, "LibraryId")),
+		}),
+	})
+}
+
+/// References to the arrays being written, from within themselves, take their new ids
+fn reference_from_json(json: &JValue, classes: &JsonClasses) -> Result<Value, String> {
+	Ok(match json {
+		&JValue::Null => Value::ObjectNull(ObjectNullRecord {}),
+		_ => Value::MemberReference(MemberReferenceRecord { IdRef: classes.get_array_id(try!(get_i32(json, "IdRef"))) }),
+	})
+}
+
+/// Element type name and rank of an array type name such as "Foo[]", "System.Int32[,]" or "System.String[][]".
+/// None for other names, including generic ones like "List`1[[System.Int32, mscorlib]]".
+fn array_type_from_name(name: &str) -> Option<(&str, usize)> {
+	if !name.ends_with("]") {
+		return None;
+	}
+	let start = name.rfind('[').unwrap();
+	let brackets = &name[start + 1..name.len() - 1];
+	if brackets.chars().all(|c| c == ',') {
+		Some((&name[..start], brackets.len() + 1))
+	} else {
+		None
+	}
+}
+
+fn primitive_type_from_name(name: &str) -> Option<PrimitiveTypeEnumeration> {
+	if !name.starts_with("System.") {
+		return None;
+	}
+	match name["System.".len()..].parse() {
+		Ok(PrimitiveTypeEnumeration::String) | Ok(PrimitiveTypeEnumeration::Null) | Err(_) => None,
+		Ok(primitive_type) => Some(primitive_type),
+	}
+}
+
+/// Member type of the elements of an array given the element type name.
+/// Classes of a library are Class, the others SystemClass.
+fn element_type_from_name(name: &str, library_id: Option<i32>) -> (BinaryTypeEnumeration, AdditionalInfo) {
+	if let Some((element_name, 1)) = array_type_from_name(name) {
+		match element_name {
+			"System.String" => return (BinaryTypeEnumeration::StringArray, AdditionalInfo::None),
+			"System.Object" => return (BinaryTypeEnumeration::ObjectArray, AdditionalInfo::None),
+			_ => if let Some(primitive_type) = primitive_type_from_name(element_name) {
+				return (BinaryTypeEnumeration::PrimitiveArray, AdditionalInfo::Primitive(primitive_type));
+			},
+		}
+	}
+	match name {
+		"System.String" => return (BinaryTypeEnumeration::String, AdditionalInfo::None),
+		"System.Object" => return (BinaryTypeEnumeration::Object, AdditionalInfo::None),
+		_ => if let Some(primitive_type) = primitive_type_from_name(name) {
+			return (BinaryTypeEnumeration::Primitive, AdditionalInfo::Primitive(primitive_type));
+		},
+	}
+	match library_id {
+		Some(library_id) => (BinaryTypeEnumeration::Class, AdditionalInfo::Class(ClassTypeInfoRecord {
+			TypeName: name.to_string(),
+			LibraryId: library_id,
+		})),
+		None => (BinaryTypeEnumeration::SystemClass, AdditionalInfo::SystemClass(name.to_string())),
+	}
+}
+
+/// Elements of arrays holding records, where consecutive nulls are written as a single record
+fn elements_from_json(json: &Vec<&JValue>, binary_type: &BinaryTypeEnumeration, additional_info: &AdditionalInfo,
+	classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Vec<Value>, String> {
+	let mut values = vec![];
+	let mut null_count = 0;
+	for (i, element) in json.iter().enumerate() {
+		if let &&JValue::Null = element {
+			null_count += 1;
+			let is_last_null = match json.get(i + 1) {
+				Some(&&JValue::Null) => false,
+				_ => true,
+			};
+			if is_last_null {
+				values.push(Value::null_run(null_count));
+				null_count = 0;
+			}
+			continue;
+		}
+		values.push(try!(value_from_json(element, binary_type, additional_info, classes, next_id, arrays).map_err(|e| format!("Element {}: {}", i, e))));
+	}
+	Ok(values)
+}
+
+/// Flattens rectangular arrays nested by rank, giving the length of every dimension
+fn flatten_array_json<'a>(json: &'a JValue, dimension: usize, rank: usize, lengths: &mut Vec<i32>, elements: &mut Vec<&'a JValue>) -> Result<(), String> {
+	let array = match json.as_array() {
+		Some(array) => array,
+		None => return Err(format!("Expect an array of rank {} in {:?}", rank, json)),
+	};
+	if dimension == lengths.len() {
+		lengths.push(array.len() as i32);
+	} else if lengths[dimension] != array.len() as i32 {
+		return Err(format!("Every row of a rectangular array needs the same length: {:?}", json));
+	}
+	for element in array.iter() {
+		if dimension + 1 < rank {
+			try!(flatten_array_json(element, dimension + 1, rank, lengths, elements));
+		} else {
+			elements.push(element);
+		}
+	}
+	Ok(())
+}
+
+/// Arrays given by the type name of a Class or SystemClass member, written as BinaryArrayRecord
+fn binary_array_from_json(json: &JValue, element_name: &str, rank: usize, library_id: Option<i32>,
+	classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Value, String> {
+	let values_json = json.find("Values").unwrap_or(json);
+	let lower_bounds = match json.find("LowerBounds") {
+		Some(_) => {
+			let mut lower_bounds = vec![];
+			for x in try!(get_array(json, "LowerBounds")).iter() {
+				lower_bounds.push(try!(x.as_i64().and_then(|x| x.to_i32()).ok_or_else(|| format!("Expect 32-bit integer lower bounds in {:?}", json))));
+			}
+			if lower_bounds.len() != rank {
+				return Err(format!("Expect {} lower bounds in {:?}", rank, json));
+			}
+			Some(lower_bounds)
+		},
+		None => None,
+	};
+	let mut lengths = vec![];
+	let mut elements = vec![];
+	try!(flatten_array_json(values_json, 0, rank, &mut lengths, &mut elements));
+	// Dimensions after an empty one have no rows telling their length
+	while lengths.len() < rank {
+		lengths.push(0);
+	}
+
+	let (binary_type, additional_info) = element_type_from_name(element_name, library_id);
+	let is_jagged = array_type_from_name(element_name).is_some();
+	let binary_array_type = match (rank > 1, is_jagged, lower_bounds.is_some()) {
+		(true, _, false) => BinaryArrayTypeEnumeration::Rectangular,
+		(true, _, true) => BinaryArrayTypeEnumeration::RectangularOffset,
+		(false, true, false) => BinaryArrayTypeEnumeration::Jagged,
+		(false, true, true) => BinaryArrayTypeEnumeration::JaggedOffset,
+		(false, false, false) => BinaryArrayTypeEnumeration::Single,
+		(false, false, true) => BinaryArrayTypeEnumeration::SingleOffset,
+	};
+	let object_id = *next_id;
+	*next_id += 1;
+	try!(classes.enter_array(json, object_id));
+	let element_values = match binary_type {
+		BinaryTypeEnumeration::Primitive => {
+			let primitive_type = additional_info.get_primitive_type().unwrap();
+			let mut values = PrimitiveValues::empty(primitive_type);
+			for (i, element) in elements.iter().enumerate() {
+				values.push(try!(primitive_from_json(primitive_type, element).map_err(|e| format!("Element {}: {}", i, e))));
+			}
+			ArrayValues::Primitive(values)
+		},
+		_ => ArrayValues::Records(try!(elements_from_json(&elements, &binary_type, &additional_info, classes, next_id, arrays))),
+	};
+	classes.leave_array(json);
+	arrays.push(box(BinaryArrayRecord {
+		ObjectId: object_id,
+		BinaryArrayTypeEnum: binary_array_type,
+		Rank: rank as i32,
+		Lengths: lengths,
+		LowerBounds: lower_bounds,
+		TypeEnum: binary_type,
+		AdditionalTypeInfo: additional_info,
+		Values: element_values,
+	}));
+	Ok(Value::MemberReference(MemberReferenceRecord { IdRef: object_id }))
+}
+
+/// Strings are always written as new BinaryObjectStringRecords, taking ids from next_id.
+/// So are arrays, which are referenced and collected in arrays to be written after the objects.
+/// Arrays of unknown type, as for members of type Object, are written as object[].
+fn value_from_json(json: &JValue, binary_type: &BinaryTypeEnumeration, additional_info: &AdditionalInfo,
+	classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Value, String> {
+	if *binary_type == BinaryTypeEnumeration::Object || *binary_type == BinaryTypeEnumeration::ObjectArray {
+		if let Some(value) = try!(typed_primitive_from_json(json)) {
+			return Ok(Value::MemberPrimitiveTyped(MemberPrimitiveTypedRecord {
+				PrimitiveTypeEnum: value.get_primitive_type(),
+				Value: value,
+			}));
+		}
+	}
+	Ok(match (binary_type, json) {
+		(&BinaryTypeEnumeration::Primitive, _) => {
+			let primitive_type = additional_info.get_primitive_type().unwrap();
+			Value::Primitive(try!(primitive_from_json(primitive_type, json)))
+		},
+		(&BinaryTypeEnumeration::Class, _) | (&BinaryTypeEnumeration::SystemClass, _)
+			if additional_info.get_class_name().map_or(false, |name| is_known_json(name, json, classes)) => {
+			try!(known_from_json(json, additional_info.get_class_name().unwrap(), classes, next_id, arrays))
+		},
+		(_, &JValue::String(ref s)) => {
+			*next_id += 1;
+			Value::BinaryObjectString(BinaryObjectStringRecord {
+				ObjectId: *next_id - 1,
+				Value: s.clone(),
+			})
+		},
+		(&BinaryTypeEnumeration::PrimitiveArray, &JValue::Array(_)) => {
+			let primitive_type = additional_info.get_primitive_type().unwrap();
+			let array_values = try!(primitive_values_from_json(primitive_type, json));
+			*next_id += 1;
+			arrays.push(box(ArraySinglePrimitiveRecord {
+				ArrayInfo: ArrayInfoRecord {
+					ObjectId: *next_id - 1,
+					Length: array_values.len() as i32,
+				},
+				PrimitiveTypeEnum: *primitive_type,
+				Values: array_values,
+			}));
+			Value::MemberReference(MemberReferenceRecord { IdRef: *next_id - 1 })
+		},
+		(&BinaryTypeEnumeration::StringArray, _) | (&BinaryTypeEnumeration::Object, _) | (&BinaryTypeEnumeration::ObjectArray, _)
+			if json.is_array() || json.find("ArrayId").is_some() => {
+			let elements = try!(json.find("Values").unwrap_or(json).as_array().ok_or_else(|| format!("Expect an array of values in {:?}", json)));
+			let element_type = match binary_type {
+				&BinaryTypeEnumeration::StringArray => BinaryTypeEnumeration::String,
+				_ => BinaryTypeEnumeration::Object,
+			};
+			let object_id = *next_id;
+			*next_id += 1;
+			try!(classes.enter_array(json, object_id));
+			let element_values = try!(elements_from_json(&elements.iter().collect(), &element_type, &AdditionalInfo::None, classes, next_id, arrays));
+			classes.leave_array(json);
+			let array_info = ArrayInfoRecord {
+				ObjectId: object_id,
+				Length: elements.len() as i32,
+			};
+			arrays.push(match element_type {
+				BinaryTypeEnumeration::String => box(ArraySingleStringRecord {
+					ArrayInfo: array_info,
+					Values: element_values,
+				}),
+				_ => box(ArraySingleObjectRecord {
+					ArrayInfo: array_info,
+					Values: element_values,
+				}),
+			});
+			Value::MemberReference(MemberReferenceRecord { IdRef: object_id })
+		},
+		(&BinaryTypeEnumeration::Class, _) | (&BinaryTypeEnumeration::SystemClass, _) if json.is_array() || json.find("Values").is_some() => {
+			let (type_name, library_id) = match additional_info {
+				&AdditionalInfo::Class(ref class_type) => (&class_type.TypeName[..], Some(class_type.LibraryId)),
+				&AdditionalInfo::SystemClass(ref class_name) => (&class_name[..], None),
+				_ => return Err(format!("Expect a class name for {:?}", binary_type)),
+			};
+			match array_type_from_name(type_name) {
+				Some((element_name, rank)) => try!(binary_array_from_json(json, element_name, rank, library_id, classes, next_id, arrays)),
+				None => return Err(format!("{} is not an array type for {:?}", type_name, json)),
+			}
+		},
+		_ => try!(reference_from_json(json, classes)),
+	})
+}
+
+/// The well-known class of a class, enums being told by the members kept with the class
+fn known_class_of_json(class_name: &str, classes: &JsonClasses) -> Option<KnownClass> {
+	known_class(class_name).or_else(|| match classes.get(class_name).and_then(|class_json| class_json.find("Members")) {
+		Some(&JValue::Array(ref members)) if members.len() == 1 && members[0].find("Name").and_then(|name| name.as_str()) == Some("value__") => {
+			Some(KnownClass::Enum)
+		},
+		_ => None,
+	})
+}
+
+/// Whether the json is the values of a well-known object of the class, or the json of its converter,
+/// rather than a reference
+fn is_known_json(class_name: &str, json: &JValue, classes: &JsonClasses) -> bool {
+	let is_reference = json.find("IdRef").is_some();
+	if classes.get_converter(class_name).is_some() {
+		return !json.is_null() && !is_reference;
+	}
+	match known_class_of_json(class_name, classes) {
+		Some(known_class) if known_class.is_sequence() => json.is_array(),
+		Some(known_class) if known_class.is_map() => json.is_array() || json.is_object() && !is_reference,
+		Some(known_class) if known_class.is_text() => json.is_string(),
+		// Nullables are structs, never referenced
+		Some(KnownClass::Nullable) => true,
+		Some(KnownClass::KeyValuePair) => json.find("key").is_some() && json.find("value").is_some(),
+		Some(KnownClass::Enum) => json.is_number() || json.is_string() || json.is_object() && !is_reference,
+		_ => false,
+	}
+}
+
+/// Objects written inline as a member value or array element, as value types are
+fn inline_object(class_json: &JValue, class_info: ClassInfoRecord, member_type_info: MemberTypeInfoRecord, values: Vec<Value>) -> Value {
+	let record = match class_json.find("LibraryId").and_then(|v| v.as_i64()) {
+		Some(library_id) => LosslessRecord::ClassWithMembersAndTypes(Rc::new(ClassWithMembersAndTypesRecord {
+			ClassInfo: class_info,
+			MemberTypeInfo: member_type_info,
+			LibraryId: library_id as i32,
+		}), values),
+		None => LosslessRecord::SystemClassWithMembersAndTypes(Rc::new(SystemClassWithMembersAndTypesRecord {
+			ClassInfo: class_info,
+			MemberTypeInfo: member_type_info,
+		}), values),
+	};
+	Value::Record(box(record))
+}
+
+fn get_class_json<'a>(classes: &JsonClasses<'a>, class_name: &str) -> Result<&'a JValue, String> {
+	classes.get(class_name).ok_or_else(|| format!("Class {} is not defined", class_name))
+}
+
+/// Class info of an object written inline with a new id
+fn inline_class_info(class_json: &JValue, class_name: &str, next_id: &mut i32) -> Result<(ClassInfoRecord, MemberTypeInfoRecord), String> {
+	let object_id = *next_id;
+	*next_id += 1;
+	class_info_from_json(object_id, class_json).map_err(|e| format!("Class {}: {}", class_name, e))
+}
+
+/// The values of the members of a class by name, as built by value_of
+fn members_from_json<F>(class_info: &ClassInfoRecord, member_type_info: &MemberTypeInfoRecord, mut value_of: F) -> Result<Vec<Value>, String>
+	where F: FnMut(&str, &BinaryTypeEnumeration, &AdditionalInfo) -> Option<Result<Value, String>> {
+	let mut values = vec![];
+	for (name, binary_type, additional_info) in Zip::new((&class_info.MemberNames, &member_type_info.BinaryTypeEnums, &member_type_info.AdditionalInfos)) {
+		match value_of(&name[..], binary_type, additional_info) {
+			Some(value) => values.push(try!(value.map_err(|e| format!("Member {} of {}: {}", name, class_info.Name, e)))),
+			None => return Err(format!("Unexpected member {} of {}", name, class_info.Name)),
+		}
+	}
+	Ok(values)
+}
+
+/// A comparer kept with a class by comparer_to_json, written inline
+fn comparer_from_json(json: &JValue, classes: &JsonClasses, next_id: &mut i32) -> Result<Value, String> {
+	if json.is_null() {
+		return Ok(Value::ObjectNull(ObjectNullRecord {}));
+	}
+	let class_name = try!(get_str(json, "Class"));
+	let class_json = try!(get_class_json(classes, class_name));
+	let (class_info, member_type_info) = try!(inline_class_info(class_json, class_name, next_id));
+	let null = JValue::Null;
+	let mut values = vec![];
+	for name in class_info.MemberNames.iter() {
+		let member = json.find("Members").and_then(|members| members.find(name)).unwrap_or(&null);
+		values.push(try!(comparer_from_json(member, classes, next_id)));
+	}
+	Ok(inline_object(class_json, class_info, member_type_info, values))
+}
+
+fn int32_value(x: usize) -> Value {
+	Value::Primitive(Primitive::Int32(x as i32))
+}
+
+/// A well-known object given by the values it holds is written inline with its internal members built from them.
+/// Version counters start again from 0, and the comparer is that kept with the class.
+/// Objects of the classes of the converters are written inline as well, with the members given by their converter.
+fn known_from_json(json: &JValue, class_name: &str, classes: &JsonClasses, next_id: &mut i32,
+	arrays: &mut Vec<Box<Record>>) -> Result<Value, String> {
+	let class_json = try!(get_class_json(classes, class_name));
+	let (class_info, member_type_info) = try!(inline_class_info(class_json, class_name, next_id));
+	if let Some(converter) = classes.get_converter(class_name) {
+		let values = try!(converted_values_from_json(json, converter, &class_info, &member_type_info, classes, next_id, arrays));
+		return Ok(inline_object(class_json, class_info, member_type_info, values));
+	}
+	let null = JValue::Null;
+	let comparer = class_json.find("Comparer").unwrap_or(&null);
+	let values = match known_class_of_json(class_name, classes).unwrap() {
+		known_class if known_class.is_sequence() => {
+			try!(sequence_values_from_json(json, known_class, comparer, &class_info, &member_type_info, classes, next_id, arrays))
+		},
+		known_class if known_class.is_map() => {
+			try!(map_values_from_json(json, known_class, comparer, &class_info, &member_type_info, classes, next_id, arrays))
+		},
+		known_class => try!(struct_values_from_json(json, known_class, &class_info, &member_type_info, classes, next_id, arrays)),
+	};
+	Ok(inline_object(class_json, class_info, member_type_info, values))
+}
+
+fn converted_values_from_json(json: &JValue, converter: &Converter, class_info: &ClassInfoRecord, member_type_info: &MemberTypeInfoRecord,
+	classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Vec<Value>, String> {
+	let members = try!(converter.from_json(&class_info.Name, &class_info.MemberNames, json));
+	if members.len() != class_info.MemberNames.len() {
+		return Err(format!("The converter of {} gave {} members instead of {}", class_info.Name, members.len(), class_info.MemberNames.len()));
+	}
+	let mut values = vec![];
+	for (name, member, binary_type, additional_info) in Zip::new((&class_info.MemberNames, &members, &member_type_info.BinaryTypeEnums, &member_type_info.AdditionalInfos)) {
+		values.push(try!(value_from_json(member, binary_type, additional_info, classes, next_id, arrays)
+			.map_err(|e| format!("Member {} of {}: {}", name, class_info.Name, e))));
+	}
+	Ok(values)
+}
+
+/// The items become the whole backing array, so Queues start at its beginning and their tail goes round to 0.
+/// A HashSet or LinkedList written empty has no member for its items and cannot get any.
+fn sequence_values_from_json(json: &JValue, known_class: KnownClass, comparer: &JValue, class_info: &ClassInfoRecord,
+	member_type_info: &MemberTypeInfoRecord, classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Vec<Value>, String> {
+	let count = json.as_array().unwrap().len();
+	let items_name = match known_class {
+		KnownClass::List => "_items",
+		KnownClass::HashSet => "Elements",
+		KnownClass::LinkedList => "Data",
+		_ => "_array",
+	};
+	if count > 0 && !class_info.MemberNames.iter().any(|name| name == items_name) {
+		return Err(format!("{} was written without {} as it was empty, items cannot be added", class_info.Name, items_name));
+	}
+	members_from_json(class_info, member_type_info, |name, binary_type, additional_info| match (known_class, name) {
+		(_, name) if name == items_name => Some(value_from_json(json, binary_type, additional_info, classes, next_id, arrays)),
+		(KnownClass::List, "_size") | (KnownClass::Stack, "_size") | (KnownClass::Queue, "_size") | (KnownClass::LinkedList, "Count") => {
+			Some(Ok(int32_value(count)))
+		},
+		(KnownClass::Queue, "_head") | (KnownClass::Queue, "_tail") => Some(Ok(int32_value(0))),
+		(KnownClass::HashSet, "Capacity") => Some(Ok(Value::Primitive(Primitive::Int32(if count == 0 { 0 } else { get_prime(count as i32) })))),
+		(KnownClass::HashSet, "Comparer") => Some(comparer_from_json(comparer, classes, next_id)),
+		(_, "_version") | (_, "Version") => Some(Ok(int32_value(0))),
+		_ => None,
+	})
+}
+
+/// The struct T of a Nullable without a value, with default members. Those of a class type are left null.
+fn default_struct(class_name: &str, classes: &JsonClasses, next_id: &mut i32) -> Result<Value, String> {
+	let class_json = try!(get_class_json(classes, class_name));
+	let (class_info, member_type_info) = try!(inline_class_info(class_json, class_name, next_id));
+	let values = member_type_info.BinaryTypeEnums.iter().zip(member_type_info.AdditionalInfos.iter())
+		.map(|(binary_type, additional_info)| default_value(binary_type, additional_info))
+		.collect();
+	Ok(inline_object(class_json, class_info, member_type_info, values))
+}
+
+/// The default of a primitive member, null for the others
+fn default_value(binary_type: &BinaryTypeEnumeration, additional_info: &AdditionalInfo) -> Value {
+	match (binary_type, additional_info.get_primitive_type().and_then(Primitive::default_of)) {
+		(&BinaryTypeEnumeration::Primitive, Some(primitive)) => Value::Primitive(primitive),
+		_ => Value::ObjectNull(ObjectNullRecord {}),
+	}
+}
+
+/// The value__ of an enum given as its number, {"value"} or, for those listed by get_enum_names, the name of a constant
+fn enum_value_from_json(json: &JValue, class_name: &str) -> Result<JValue, String> {
+	match json {
+		&JValue::String(ref name) => match get_enum_names(class_name).and_then(|names| names.iter().position(|known_name| *known_name == &name[..])) {
+			Some(x) => Ok(JValue::I64(x as i64)),
+			None => Err(format!("{} is not a constant of {}", name, class_name)),
+		},
+		&JValue::Object(_) => match json.find("value") {
+			Some(value) => Ok(value.clone()),
+			None => Err(format!("Expect {{\"value\"}} for {} but got {:?}", class_name, json)),
+		},
+		_ => Ok(json.clone()),
+	}
+}
+
+/// Guids, Versions, Uris and DateTimeOffsets given as strings, Nullables as their value or null, KeyValuePairs as
+/// {"key", "value"} and enums as their value
+fn struct_values_from_json(json: &JValue, known_class: KnownClass, class_info: &ClassInfoRecord, member_type_info: &MemberTypeInfoRecord,
+	classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Vec<Value>, String> {
+	let text = json.as_str().unwrap_or("");
+	let invalid = || format!("Invalid {} {:?}", class_info.Name, json);
+	match known_class {
+		KnownClass::Guid => {
+			let (a, b, c, bytes) = try!(parse_guid(text).ok_or_else(&invalid));
+			members_from_json(class_info, member_type_info, |name, _, _| match name {
+				"_a" => Some(Ok(Value::Primitive(Primitive::Int32(a)))),
+				"_b" => Some(Ok(Value::Primitive(Primitive::Int16(b)))),
+				"_c" => Some(Ok(Value::Primitive(Primitive::Int16(c)))),
+				_ if name.len() == 2 && name >= "_d" && name <= "_k" => {
+					Some(Ok(Value::Primitive(Primitive::Byte(bytes[(name.as_bytes()[1] - b'd') as usize]))))
+				},
+				_ => None,
+			})
+		},
+		KnownClass::Version => {
+			let parts = try!(parse_version(text).ok_or_else(&invalid));
+			members_from_json(class_info, member_type_info, |name, _, _| match name {
+				"_Major" => Some(Ok(Value::Primitive(Primitive::Int32(parts[0])))),
+				"_Minor" => Some(Ok(Value::Primitive(Primitive::Int32(parts[1])))),
+				"_Build" => Some(Ok(Value::Primitive(Primitive::Int32(parts[2])))),
+				"_Revision" => Some(Ok(Value::Primitive(Primitive::Int32(parts[3])))),
+				_ => None,
+			})
+		},
+		// Relative uris are given by RelativeUri when the class has it
+		KnownClass::Uri => {
+			let is_relative = class_info.MemberNames.iter().any(|name| name == "RelativeUri");
+			members_from_json(class_info, member_type_info, |name, binary_type, additional_info| match name {
+				"AbsoluteUri" if is_relative => Some(value_from_json(&JValue::String(String::new()), binary_type, additional_info, classes, next_id, arrays)),
+				"AbsoluteUri" | "RelativeUri" => Some(value_from_json(json, binary_type, additional_info, classes, next_id, arrays)),
+				_ => None,
+			})
+		},
+		KnownClass::DateTimeOffset => {
+			let (utc, offset_minutes) = try!(parse_date_time_offset(text).ok_or_else(&invalid));
+			members_from_json(class_info, member_type_info, |name, _, _| match name {
+				"DateTime" => Some(Ok(Value::Primitive(Primitive::DateTime(utc.clone())))),
+				"OffsetMinutes" => Some(Ok(Value::Primitive(Primitive::Int16(offset_minutes)))),
+				_ => None,
+			})
+		},
+		KnownClass::Nullable => members_from_json(class_info, member_type_info, |name, binary_type, additional_info| match name {
+			"hasValue" => Some(Ok(Value::Primitive(Primitive::Boolean(!json.is_null())))),
+			"value" if !json.is_null() => Some(value_from_json(json, binary_type, additional_info, classes, next_id, arrays)),
+			"value" => Some(match (binary_type, additional_info.get_class_name()) {
+				(&BinaryTypeEnumeration::Class, Some(class_name)) | (&BinaryTypeEnumeration::SystemClass, Some(class_name)) => {
+					default_struct(class_name, classes, next_id)
+				},
+				_ => Ok(default_value(binary_type, additional_info)),
+			}),
+			_ => None,
+		}),
+		KnownClass::KeyValuePair => members_from_json(class_info, member_type_info, |name, binary_type, additional_info| match name {
+			"key" | "value" => Some(get_field(json, name).and_then(|member| value_from_json(member, binary_type, additional_info, classes, next_id, arrays))),
+			_ => None,
+		}),
+		KnownClass::Enum => members_from_json(class_info, member_type_info, |name, binary_type, additional_info| match name {
+			"value__" => Some(enum_value_from_json(json, &class_info.Name)
+				.and_then(|value| value_from_json(&value, binary_type, additional_info, classes, next_id, arrays))),
+			_ => None,
+		}),
+		_ => unreachable!(),
+	}
+}
+
+/// The pairs of a map given as an object, the keys being its names, or as an array of {"key", "value"}.
+/// Also whether the keys are names, to be parsed for keys of a primitive type.
+fn pairs_from_json(json: &JValue) -> Result<(Vec<(JValue, &JValue)>, bool), String> {
+	match json {
+		&JValue::Object(ref m) => Ok((m.iter().map(|(key, value)| (JValue::String(key.clone()), value)).collect(), true)),
+		&JValue::Array(ref pairs) => {
+			let mut key_values = vec![];
+			for pair in pairs.iter() {
+				match (pair.find("key"), pair.find("value")) {
+					(Some(key), Some(value)) => key_values.push((key.clone(), value)),
+					_ => return Err(format!("Expect {{\"key\", \"value\"}} but got {:?}", pair)),
+				}
+			}
+			Ok((key_values, false))
+		},
+		_ => Err(format!("Expect an object or an array of pairs but got {:?}", json)),
+	}
+}
+
+fn key_from_name(name: &JValue, binary_type: &BinaryTypeEnumeration) -> Result<JValue, String> {
+	let name = name.as_str().unwrap();
+	Ok(match binary_type {
+		&BinaryTypeEnumeration::Primitive | &BinaryTypeEnumeration::PrimitiveArray => match name.parse::<i64>() {
+			Ok(x) => JValue::I64(x),
+			Err(_) => match name.parse::<u64>() {
+				Ok(x) => JValue::U64(x),
+				Err(_) => return Err(format!("Expect an integer key but got {}", name)),
+			},
+		},
+		_ => JValue::String(name.to_string()),
+	})
+}
+
+/// An array of KeyValuePair objects given its type name, as held by Dictionaries and SortedDictionaries.
+/// The KeyValuePair objects after the first refer to its class with ClassWithId.
+fn key_value_pairs_from_json(pairs: &Vec<(JValue, &JValue)>, are_named: bool, array_type_name: &str,
+	classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Value, String> {
+	let pair_class_name = match array_type_from_name(array_type_name) {
+		Some((element_name, 1)) => element_name,
+		_ => return Err(format!("{} is not an array of KeyValuePair", array_type_name)),
+	};
+	let pair_class_json = try!(get_class_json(classes, pair_class_name));
+	let array_id = *next_id;
+	*next_id += 1;
+	let mut pair_values = vec![];
+	let mut metadata_id = None;
+	for &(ref key, value) in pairs.iter() {
+		let pair_id = *next_id;
+		let (pair_info, pair_type_info) = try!(inline_class_info(pair_class_json, pair_class_name, next_id));
+		let member_values = try!(members_from_json(&pair_info, &pair_type_info, |name, binary_type, additional_info| match name {
+			"key" if are_named => Some(key_from_name(key, binary_type)
+				.and_then(|key| value_from_json(&key, binary_type, additional_info, classes, next_id, arrays))),
+			"key" => Some(value_from_json(key, binary_type, additional_info, classes, next_id, arrays)),
+			"value" => Some(value_from_json(value, binary_type, additional_info, classes, next_id, arrays)),
+			_ => None,
+		}));
+		pair_values.push(match metadata_id {
+			Some(metadata_id) => Value::Record(box(LosslessRecord::ClassWithId(ClassWithIdRecord {
+				ObjectId: pair_id,
+				MetadataId: metadata_id,
+			}, member_values))),
+			None => inline_object(pair_class_json, pair_info, pair_type_info, member_values),
+		});
+		metadata_id = metadata_id.or(Some(pair_id));
+	}
+	arrays.push(box(BinaryArrayRecord {
+		ObjectId: array_id,
+		BinaryArrayTypeEnum: BinaryArrayTypeEnumeration::Single,
+		Rank: 1,
+		Lengths: vec![pair_values.len() as i32],
+		LowerBounds: None,
+		TypeEnum: BinaryTypeEnumeration::SystemClass,
+		AdditionalTypeInfo: AdditionalInfo::SystemClass(pair_class_name.to_string()),
+		Values: ArrayValues::Records(pair_values),
+	}));
+	Ok(Value::MemberReference(MemberReferenceRecord { IdRef: array_id }))
+}
+
+/// The keys or values of a SortedList or Hashtable as a json array, keys given as names being parsed by key_from_name
+fn pair_column_json(pairs: &Vec<(JValue, &JValue)>, are_named: bool, is_key: bool, binary_type: &BinaryTypeEnumeration) -> Result<JValue, String> {
+	let mut column = vec![];
+	for &(ref key, value) in pairs.iter() {
+		column.push(match (is_key, are_named) {
+			(true, true) => try!(key_from_name(key, binary_type)),
+			(true, false) => key.clone(),
+			(false, _) => value.clone(),
+		});
+	}
+	Ok(JValue::Array(column))
+}
+
+/// A SortedList keeps its keys in order, which are sorted here when given as integer names since json names
+/// are ordered as strings. Other keys are to be kept in order when editing.
+/// A Hashtable has the default load factor and no comparer, its keys given as names being strings.
+fn map_values_from_json(json: &JValue, known_class: KnownClass, comparer: &JValue, class_info: &ClassInfoRecord,
+	member_type_info: &MemberTypeInfoRecord, classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Vec<Value>, String> {
+	let (mut pairs, are_named) = try!(pairs_from_json(json));
+	let count = pairs.len();
+	if known_class == KnownClass::SortedList && are_named {
+		let keys_type = class_info.MemberNames.iter().position(|name| name == "keys").map(|i| &member_type_info.BinaryTypeEnums[i]);
+		if let Some(&BinaryTypeEnumeration::PrimitiveArray) = keys_type {
+			pairs.sort_by_key(|&(ref key, _)| key.as_str().and_then(|key| key.parse::<i64>().ok()));
+		}
+	}
+	if count > 0 && known_class == KnownClass::Dictionary && !class_info.MemberNames.iter().any(|name| name == "KeyValuePairs") {
+		return Err(format!("{} was written without KeyValuePairs as it was empty, entries cannot be added", class_info.Name));
+	}
+	members_from_json(class_info, member_type_info, |name, binary_type, additional_info| match (known_class, name) {
+		(KnownClass::Dictionary, "Version") | (KnownClass::Hashtable, "Version") | (KnownClass::SortedList, "version") => Some(Ok(int32_value(0))),
+		(KnownClass::Dictionary, "Comparer") | (KnownClass::SortedList, "comparer") => Some(comparer_from_json(comparer, classes, next_id)),
+		(KnownClass::Dictionary, "HashSize") => Some(Ok(Value::Primitive(Primitive::Int32(if count == 0 { 0 } else { get_prime(count as i32) })))),
+		(KnownClass::Dictionary, "KeyValuePairs") => {
+			Some(key_value_pairs_from_json(&pairs, are_named, additional_info.get_class_name().unwrap_or(""), classes, next_id, arrays))
+		},
+		(KnownClass::Hashtable, "LoadFactor") => Some(Ok(Value::Primitive(Primitive::Single(0.72)))),
+		(KnownClass::Hashtable, "Comparer") | (KnownClass::Hashtable, "HashCodeProvider") |
+		(KnownClass::SortedList, "keyList") | (KnownClass::SortedList, "valueList") => Some(Ok(Value::ObjectNull(ObjectNullRecord {}))),
+		(KnownClass::Hashtable, "HashSize") => Some(Ok(Value::Primitive(Primitive::Int32(get_prime((count as f32 / 0.72) as i32 + 1))))),
+		(KnownClass::Hashtable, "Keys") | (KnownClass::SortedList, "keys") => Some(pair_column_json(&pairs, are_named, true, binary_type)
+			.and_then(|keys| value_from_json(&keys, binary_type, additional_info, classes, next_id, arrays))),
+		(KnownClass::Hashtable, "Values") | (KnownClass::SortedList, "values") => Some(pair_column_json(&pairs, are_named, false, binary_type)
+			.and_then(|values| value_from_json(&values, binary_type, additional_info, classes, next_id, arrays))),
+		(KnownClass::SortedList, "_size") => Some(Ok(int32_value(count))),
+		(KnownClass::SortedDictionary, "_set") => {
+			Some(tree_set_from_json(&pairs, are_named, comparer, additional_info.get_class_name().unwrap_or(""), classes, next_id, arrays))
+		},
+		_ => None,
+	})
+}
+
+/// The TreeSet of KeyValuePair objects behind a SortedDictionary, which sorts them again when deserialized
+fn tree_set_from_json(pairs: &Vec<(JValue, &JValue)>, are_named: bool, comparer: &JValue, class_name: &str,
+	classes: &JsonClasses, next_id: &mut i32, arrays: &mut Vec<Box<Record>>) -> Result<Value, String> {
+	let class_json = try!(get_class_json(classes, class_name));
+	let (class_info, member_type_info) = try!(inline_class_info(class_json, class_name, next_id));
+	if !pairs.is_empty() && !class_info.MemberNames.iter().any(|name| name == "Items") {
+		return Err(format!("{} was written without Items as it was empty, entries cannot be added", class_info.Name));
+	}
+	let values = try!(members_from_json(&class_info, &member_type_info, |name, _, additional_info| match name {
+		"Count" => Some(Ok(int32_value(pairs.len()))),
+		"Comparer" => Some(comparer_from_json(comparer, classes, next_id)),
+		"Version" => Some(Ok(int32_value(0))),
+		"Items" => Some(key_value_pairs_from_json(pairs, are_named, additional_info.get_class_name().unwrap_or(""), classes, next_id, arrays)),
+		_ => None,
+	}));
+	Ok(inline_object(class_json, class_info, member_type_info, values))
+}
+
+fn values_from_json(class: &ClassRecordForClassWithId, members: &JValue, classes: &JsonClasses, next_id: &mut i32,
+	arrays: &mut Vec<Box<Record>>) -> Result<Vec<Value>, String> {
+	let (class_name, member_names, binary_types, additional_infos) = class.get_info_for_json();
+	let mut values = vec![];
+	for (name, binary_type, additional_info) in Zip::new((member_names, binary_types, additional_infos)) {
+		let member = match members.find(name) {
+			Some(member) => member,
+			None => return Err(format!("Member {} of {} is missing", name, class_name)),
+		};
+		values.push(try!(value_from_json(member, binary_type, additional_info, classes, next_id, arrays).map_err(|e| format!("Member {} of {}: {}", name, class_name, e))));
+	}
+	Ok(values)
+}
+
+/// The classes of a json file, the converters of the objects written as something else than their members,
+/// and the arrays being written. A stream may define several classes of the same name, such as those of different libraries.
+struct JsonClasses<'a> {
+	/// In the order of the file
+	classes: Vec<&'a JValue>,
+	/// Indices in classes by name
+	indices: HashMap<&'a str, Vec<usize>>,
+	converters: &'a Converters,
+	/// ArrayId and new id of the arrays being written
+	array_ids: RefCell<Vec<(i32, i32)>>,
+}
+
+impl<'a> JsonClasses<'a> {
+	fn new(converters: &'a Converters) -> Self {
+		JsonClasses {
+			classes: vec![],
+			indices: HashMap::new(),
+			converters: converters,
+			array_ids: RefCell::new(vec![]),
+		}
+	}
+
+	fn add(&mut self, class_json: &'a JValue) -> Result<(), String> {
+		self.indices.entry(try!(get_str(class_json, "Name"))).or_insert(vec![]).push(self.classes.len());
+		self.classes.push(class_json);
+		Ok(())
+	}
+
+	/// The first class of the name
+	fn get(&self, class_name: &str) -> Option<&'a JValue> {
+		self.indices.get(class_name).and_then(|indices| indices.first()).map(|&index| self.classes[index])
+	}
+
+	/// The converter of the first class of the name
+	fn get_converter(&self, class_name: &str) -> Option<&Converter> {
+		if self.converters.is_empty() {
+			return None;
+		}
+		match self.get(class_name).and_then(|class_json| class_info_from_json(0, class_json).ok()) {
+			Some((class_info, member_type_info)) => self.converters.get(class_name, &class_info.MemberNames, &member_type_info.BinaryTypeEnums),
+			None => None,
+		}
+	}
+
+	/// Index of the first class of the name having the members of an object
+	fn find_for_members(&self, class_name: &str, members: &JValue) -> Result<Option<usize>, String> {
+		let member_count = members.as_object().map_or(0, |members| members.len());
+		for &index in self.indices.get(class_name).map_or(&[][..], |indices| &indices[..]).iter() {
+			let member_types = try!(get_array(self.classes[index], "Members"));
+			let mut has_members = member_types.len() == member_count;
+			for member_type in member_types.iter() {
+				has_members = has_members && members.find(try!(get_str(member_type, "Name"))).is_some();
+			}
+			if has_members {
+				return Ok(Some(index));
+			}
+		}
+		Ok(None)
+	}
+
+	fn enter_array(&self, json: &JValue, new_id: i32) -> Result<(), String> {
+		if json.find("ArrayId").is_some() {
+			self.array_ids.borrow_mut().push((try!(get_i32(json, "ArrayId")), new_id));
+		}
+		Ok(())
+	}
+
+	fn leave_array(&self, json: &JValue) {
+		if json.find("ArrayId").is_some() {
+			self.array_ids.borrow_mut().pop();
+		}
+	}
+
+	/// The new id of the innermost array being written with that ArrayId, the id itself for other references
+	fn get_array_id(&self, id: i32) -> i32 {
+		match self.array_ids.borrow().iter().rev().find(|&&(array_id, _)| array_id == id) {
+			Some(&(_, new_id)) => new_id,
+			None => id,
+		}
+	}
+}
+
+/// Writes the records of a json file made by document_to_json, after it has been edited.
+/// Strings and arrays get new ids as they are inlined in the json file.
+pub fn write_from_json(file: &mut Write, document: &JValue, converters: &Converters) -> Result<(), String> {
+	let header_json = try!(get_field(document, "Header"));
+	let mut classes = JsonClasses::new(converters);
+	for class_json in try!(get_array(document, "Classes")).iter() {
+		try!(classes.add(class_json));
+	}
+	let objects = try!(get_array(document, "Objects"));
+
+	// New ids are needed for strings and arrays as they are inlined in the json file
+	let mut next_id = 0;
+	for object in objects.iter() {
+		next_id = cmp::max(next_id, try!(get_i32(object, "ObjectId")));
+	}
+	next_id += 1;
+
+	SerializationHeaderRecord {
+		RootId: try!(get_i32(header_json, "RootId")),
+		HeaderId: try!(get_i32(header_json, "HeaderId")),
+		MajorVersion: try!(get_i32(header_json, "MajorVersion")),
+		MinorVersion: try!(get_i32(header_json, "MinorVersion")),
+	}.write(file);
+
+	for library_json in try!(get_array(document, "Libraries")).iter() {
+		BinaryLibraryRecord {
+			LibraryId: try!(get_i32(library_json, "LibraryId")),
+			LibraryName: try!(get_str(library_json, "LibraryName")).to_string(),
+		}.write(file);
+	}
+
+	// By index of the class, as several classes may have the same name
+	let mut written_classes = HashMap::<usize, (i32, Box<ClassRecordForClassWithId>)>::new();
+	let mut arrays = vec![];
+	for object in objects.iter() {
+		let object_id = try!(get_i32(object, "ObjectId"));
+		let class_name = try!(get_str(object, "Class"));
+		let members = try!(get_field(object, "Members"));
+		let class_index = match try!(classes.find_for_members(class_name, members)) {
+			Some(class_index) => class_index,
+			None => return Err(format!("Class {} with the members of object {} is not defined", class_name, object_id)),
+		};
+		let is_new_class = !written_classes.contains_key(&class_index);
+		if is_new_class {
+			let class = try!(class_from_json(object_id, classes.classes[class_index]).map_err(|e| format!("Class {}: {}", class_name, e)));
+			written_classes.insert(class_index, (object_id, class));
+		}
+
+		let &(metadata_id, ref class) = written_classes.get(&class_index).unwrap();
+		let values = try!(values_from_json(&**class, members, &classes, &mut next_id, &mut arrays).map_err(|e| format!("Object {}: {}", object_id, e)));
+		if is_new_class {
+			class.write_class(file);
+		} else {
+			ClassWithIdRecord {
+				ObjectId: object_id,
+				MetadataId: metadata_id,
+			}.write(file);
+		}
+		class.write_value(file, &values);
+	}
+
+	for array in arrays.iter() {
+		array.write(file);
+	}
+
+	MessageEndRecord {}.write(file);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::{Value as JValue, from_str};
+
+	use builder::StreamBuilder;
+	use converters::{Converters, MembersAsArray};
+	use document::read_document;
+	use model::{Data, MemberType, Primitive};
+	use records::*;
+	use super::{JsonClasses, binary_array_from_json, document_to_json, write_from_json};
+
+	fn written(records: &Vec<Box<Record>>) -> Vec<Vec<u8>> {
+		records.iter().map(|record| {
+			let mut bytes = vec![];
+			record.write(&mut bytes);
+			bytes
+		}).collect()
+	}
+
+	fn int32_array(id: i32, layout: BinaryArrayTypeEnumeration, lengths: Vec<i32>, lower_bounds: Option<Vec<i32>>, values: Vec<i32>) -> Box<Record> {
+		box(BinaryArrayRecord {
+			ObjectId: id,
+			BinaryArrayTypeEnum: layout,
+			Rank: lengths.len() as i32,
+			Lengths: lengths,
+			LowerBounds: lower_bounds,
+			TypeEnum: BinaryTypeEnumeration::Primitive,
+			AdditionalTypeInfo: AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32),
+			Values: ArrayValues::Primitive(PrimitiveValues::Int32(values)),
+		})
+	}
+
+	fn array_from_json(json: &str, element_name: &str, rank: usize) -> Vec<Box<Record>> {
+		let json: JValue = from_str(json).unwrap();
+		let mut next_id = 1;
+		let mut arrays = vec![];
+		binary_array_from_json(&json, element_name, rank, None, &JsonClasses::new(&Converters::new()), &mut next_id, &mut arrays).unwrap();
+		arrays
+	}
+
+	#[test]
+	fn rectangular_array_from_json() {
+		assert_eq!(written(&array_from_json("[[1, 2, 3], [4, 5, 6]]", "System.Int32", 2)),
+			written(&vec![int32_array(1, BinaryArrayTypeEnumeration::Rectangular, vec![2, 3], None, vec![1, 2, 3, 4, 5, 6])]));
+		assert_eq!(written(&array_from_json("[[], []]", "System.Int32", 2)),
+			written(&vec![int32_array(1, BinaryArrayTypeEnumeration::Rectangular, vec![2, 0], None, vec![])]));
+	}
+
+	#[test]
+	fn offset_arrays_from_json() {
+		assert_eq!(written(&array_from_json("{\"LowerBounds\": [5], \"Values\": [7, 8]}", "System.Int32", 1)),
+			written(&vec![int32_array(1, BinaryArrayTypeEnumeration::SingleOffset, vec![2], Some(vec![5]), vec![7, 8])]));
+		assert_eq!(written(&array_from_json("{\"LowerBounds\": [1, -1], \"Values\": [[1], [2]]}", "System.Int32", 2)),
+			written(&vec![int32_array(1, BinaryArrayTypeEnumeration::RectangularOffset, vec![2, 1], Some(vec![1, -1]), vec![1, 2])]));
+	}
+
+	#[test]
+	fn jagged_array_from_json() {
+		let expected: Vec<Box<Record>> = vec![
+			box(ArraySinglePrimitiveRecord {
+				ArrayInfo: ArrayInfoRecord { ObjectId: 2, Length: 2 },
+				PrimitiveTypeEnum: PrimitiveTypeEnumeration::Int32,
+				Values: PrimitiveValues::Int32(vec![1, 2]),
+			}),
+			box(BinaryArrayRecord {
+				ObjectId: 1,
+				BinaryArrayTypeEnum: BinaryArrayTypeEnumeration::Jagged,
+				Rank: 1,
+				Lengths: vec![2],
+				LowerBounds: None,
+				TypeEnum: BinaryTypeEnumeration::PrimitiveArray,
+				AdditionalTypeInfo: AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32),
+				Values: ArrayValues::Records(vec![Value::MemberReference(MemberReferenceRecord { IdRef: 2 }), Value::ObjectNull(ObjectNullRecord {})]),
+			}),
+		];
+		assert_eq!(written(&array_from_json("[[1, 2], null]", "System.Int32[]", 1)), written(&expected));
+	}
+
+	#[test]
+	fn broken_arrays_from_json() {
+		let error = |json: &str, rank: usize| {
+			let json: JValue = from_str(json).unwrap();
+			binary_array_from_json(&json, "System.Int32", rank, None, &JsonClasses::new(&Converters::new()), &mut 1, &mut vec![]).err()
+		};
+		assert!(error("[[1, 2], [3]]", 2).unwrap().starts_with("Every row of a rectangular array needs the same length"));
+		assert!(error("[1, 2]", 2).unwrap().starts_with("Expect an array of rank 2"));
+		assert!(error("{\"LowerBounds\": [1], \"Values\": [[1], [2]]}", 2).unwrap().starts_with("Expect 2 lower bounds"));
+		assert!(error("[1, \"2\"]", 1).unwrap().starts_with("Element 1: "));
+	}
+
+	#[test]
+	fn list_as_array() {
+		let list_name = "System.Collections.Generic.List`1[[System.Int32, mscorlib, Version=4.0.0.0, Culture=neutral, PublicKeyToken=b77a5c561934e089]]";
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let save = builder.define_class("Game.Save", library_id, vec![("Scores", MemberType::SystemClass(list_name.to_string()))]);
+		let list = builder.define_system_class(list_name, vec![
+			("_items", MemberType::PrimitiveArray(PrimitiveTypeEnumeration::Int32)),
+			("_size", MemberType::Primitive(PrimitiveTypeEnumeration::Int32)),
+			("_version", MemberType::Primitive(PrimitiveTypeEnumeration::Int32)),
+		]);
+		let save_id = builder.new_object(save);
+		let list_id = builder.new_object(list);
+		let items_id = builder.new_array(MemberType::Primitive(PrimitiveTypeEnumeration::Int32),
+			vec![Data::Primitive(Primitive::Int32(3)), Data::Primitive(Primitive::Int32(5)), Data::Primitive(Primitive::Int32(0))]);
+		builder.set_reference(save_id, "Scores", list_id).set_reference(list_id, "_items", items_id).set_primitive(list_id, "_size", Primitive::Int32(2));
+		let document = read_document(&builder.to_bytes().unwrap()[..]).unwrap();
+		assert!(document_to_json(&document, false, &Converters::new()).pointer("/Objects/0/Members/Scores/IdRef").is_some());
+		let json = document_to_json(&document, true, &Converters::new());
+		assert_eq!(json.pointer("/Objects/0/Members/Scores"), Some(&JValue::Array(vec![JValue::I64(3), JValue::I64(5)])));
+
+		let mut bytes = vec![];
+		write_from_json(&mut bytes, &json, &Converters::new()).unwrap();
+		assert_eq!(document_to_json(&read_document(&bytes[..]).unwrap(), true, &Converters::new()), json);
+	}
+
+	#[test]
+	fn array_containing_itself() {
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let save = builder.define_class("Game.Save", library_id, vec![("First", MemberType::Object), ("Items", MemberType::Object)]);
+		let save_id = builder.new_object(save);
+		// Written after First, so that it gets another id
+		let items_id = save_id + 1;
+		builder.new_array(MemberType::Object, vec![Data::Primitive(Primitive::Int32(1)), Data::Reference(items_id)]);
+		let first_id = builder.new_array(MemberType::Object, vec![]);
+		builder.set_reference(save_id, "First", first_id).set_reference(save_id, "Items", items_id);
+		let json = document_to_json(&read_document(&builder.to_bytes().unwrap()[..]).unwrap(), true, &Converters::new());
+		let items = json.pointer("/Objects/0/Members/Items").unwrap();
+		assert_eq!(items.find("ArrayId").and_then(|id| id.as_i64()), Some(items_id as i64));
+
+		let mut bytes = vec![];
+		write_from_json(&mut bytes, &json, &Converters::new()).unwrap();
+		let document = read_document(&bytes[..]).unwrap();
+		let save = document.get_root().and_then(|root| root.as_object()).unwrap();
+		let items = document.get_member(save, "Items").and_then(|items| items.as_array()).unwrap();
+		assert_eq!(items.elements[1], Data::Reference(items.id));
+	}
+
+	#[test]
+	fn dictionary_as_object() {
+		let string_name = "[System.String, mscorlib, Version=4.0.0.0, Culture=neutral, PublicKeyToken=b77a5c561934e089]";
+		let int_name = "[System.Int32, mscorlib, Version=4.0.0.0, Culture=neutral, PublicKeyToken=b77a5c561934e089]";
+		let dictionary_name = format!("System.Collections.Generic.Dictionary`2[{},{}]", string_name, int_name);
+		let pair_name = format!("System.Collections.Generic.KeyValuePair`2[{},{}]", string_name, int_name);
+		let comparer_name = format!("System.Collections.Generic.GenericEqualityComparer`1[{}]", string_name);
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let save = builder.define_class("Game.Save", library_id, vec![("Counts", MemberType::SystemClass(dictionary_name.clone()))]);
+		let dictionary = builder.define_system_class(&dictionary_name, vec![
+			("Version", MemberType::Primitive(PrimitiveTypeEnumeration::Int32)),
+			("Comparer", MemberType::SystemClass(comparer_name.clone())),
+			("HashSize", MemberType::Primitive(PrimitiveTypeEnumeration::Int32)),
+			("KeyValuePairs", MemberType::SystemClass(format!("{}[]", pair_name))),
+		]);
+		let comparer = builder.define_system_class(&comparer_name, vec![]);
+		let pair = builder.define_system_class(&pair_name, vec![("key", MemberType::String), ("value", MemberType::Primitive(PrimitiveTypeEnumeration::Int32))]);
+		let save_id = builder.new_object(save);
+		let dictionary_id = builder.new_object(dictionary);
+		let comparer_id = builder.new_object(comparer);
+		let pair_id = builder.new_object(pair);
+		let pairs_id = builder.new_array(MemberType::SystemClass(pair_name.clone()), vec![Data::Reference(pair_id)]);
+		builder.set_reference(save_id, "Counts", dictionary_id)
+			.set_reference(dictionary_id, "Comparer", comparer_id)
+			.set_primitive(dictionary_id, "HashSize", Primitive::Int32(3))
+			.set_reference(dictionary_id, "KeyValuePairs", pairs_id)
+			.set_string(pair_id, "key", "arrows")
+			.set_primitive(pair_id, "value", Primitive::Int32(20));
+		let mut json = document_to_json(&read_document(&builder.to_bytes().unwrap()[..]).unwrap(), true, &Converters::new());
+		assert_eq!(json.as_object().unwrap()["Objects"].as_array().unwrap().len(), 1);
+		assert_eq!(json.pointer("/Objects/0/Members/Counts/arrows"), Some(&JValue::I64(20)));
+
+		{
+			let objects = json.as_object_mut().and_then(|m| m.get_mut("Objects")).and_then(|objects| objects.as_array_mut()).unwrap();
+			let counts = objects[0].as_object_mut().and_then(|m| m.get_mut("Members")).and_then(|members| members.as_object_mut())
+				.and_then(|members| members.get_mut("Counts")).and_then(|counts| counts.as_object_mut()).unwrap();
+			counts.insert("bolts".to_string(), JValue::I64(5));
+		}
+		let mut bytes = vec![];
+		write_from_json(&mut bytes, &json, &Converters::new()).unwrap();
+		let written = document_to_json(&read_document(&bytes[..]).unwrap(), true, &Converters::new());
+		assert_eq!(written.pointer("/Objects/0/Members/Counts"), json.pointer("/Objects/0/Members/Counts"));
+		assert_eq!(written.pointer("/Classes"), json.pointer("/Classes"));
+	}
+
+	#[test]
+	fn classes_of_the_same_name() {
+		let mut builder = StreamBuilder::new();
+		let old_library_id = builder.add_library("Game, Version=1.0.0.0");
+		let new_library_id = builder.add_library("Game, Version=2.0.0.0");
+		let save = builder.define_class("Game.Save", new_library_id, vec![
+			("Old", MemberType::Class("Game.Item".to_string(), old_library_id)),
+			("New", MemberType::Class("Game.Item".to_string(), new_library_id)),
+		]);
+		let old_item = builder.define_class("Game.Item", old_library_id, vec![("Count", MemberType::Primitive(PrimitiveTypeEnumeration::Int32))]);
+		let new_item = builder.define_class("Game.Item", new_library_id, vec![
+			("Count", MemberType::Primitive(PrimitiveTypeEnumeration::Int64)),
+			("Name", MemberType::String),
+		]);
+		let save_id = builder.new_object(save);
+		let old_id = builder.new_object(old_item);
+		let new_id = builder.new_object(new_item);
+		builder.set_reference(save_id, "Old", old_id).set_reference(save_id, "New", new_id).set_string(new_id, "Name", "sword");
+		let json = document_to_json(&read_document(&builder.to_bytes().unwrap()[..]).unwrap(), true, &Converters::new());
+
+		let mut bytes = vec![];
+		write_from_json(&mut bytes, &json, &Converters::new()).unwrap();
+		assert_eq!(document_to_json(&read_document(&bytes[..]).unwrap(), true, &Converters::new()), json);
+	}
+
+	#[test]
+	fn queue_going_round_the_array() {
+		let queue_name = "System.Collections.Generic.Queue`1[[System.Int32, mscorlib, Version=4.0.0.0, Culture=neutral, PublicKeyToken=b77a5c561934e089]]";
+		let int32 = || MemberType::Primitive(PrimitiveTypeEnumeration::Int32);
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let save = builder.define_class("Game.Save", library_id, vec![("Turns", MemberType::SystemClass(queue_name.to_string()))]);
+		let queue = builder.define_system_class(queue_name, vec![
+			("_array", MemberType::PrimitiveArray(PrimitiveTypeEnumeration::Int32)),
+			("_head", int32()), ("_tail", int32()), ("_size", int32()), ("_version", int32()),
+		]);
+		let save_id = builder.new_object(save);
+		let queue_id = builder.new_object(queue);
+		let array_id = builder.new_array(int32(), vec![Data::Primitive(Primitive::Int32(7)), Data::Primitive(Primitive::Int32(0)),
+			Data::Primitive(Primitive::Int32(5)), Data::Primitive(Primitive::Int32(6))]);
+		builder.set_reference(save_id, "Turns", queue_id).set_reference(queue_id, "_array", array_id)
+			.set_primitive(queue_id, "_head", Primitive::Int32(2)).set_primitive(queue_id, "_tail", Primitive::Int32(1))
+			.set_primitive(queue_id, "_size", Primitive::Int32(3));
+		let json = document_to_json(&read_document(&builder.to_bytes().unwrap()[..]).unwrap(), true, &Converters::new());
+		assert_eq!(json.pointer("/Objects/0/Members/Turns"), Some(&JValue::Array(vec![JValue::I64(5), JValue::I64(6), JValue::I64(7)])));
+
+		let mut bytes = vec![];
+		write_from_json(&mut bytes, &json, &Converters::new()).unwrap();
+		let document = read_document(&bytes[..]).unwrap();
+		let save = document.get_root().and_then(|root| root.as_object()).unwrap();
+		let queue = document.get_member(save, "Turns").and_then(|queue| queue.as_object()).unwrap();
+		assert_eq!(document.get_member(queue, "_head"), Some(&Data::Primitive(Primitive::Int32(0))));
+		assert_eq!(document.get_member(queue, "_tail"), Some(&Data::Primitive(Primitive::Int32(0))));
+		assert_eq!(document_to_json(&document, true, &Converters::new()), json);
+	}
+
+	#[test]
+	fn structs_as_values() {
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let save = builder.define_class("Game.Save", library_id, vec![
+			("Id", MemberType::SystemClass("System.Guid".to_string())),
+			("Day", MemberType::SystemClass("System.DayOfWeek".to_string())),
+		]);
+		let mut guid_members = vec![
+			("_a", MemberType::Primitive(PrimitiveTypeEnumeration::Int32)),
+			("_b", MemberType::Primitive(PrimitiveTypeEnumeration::Int16)),
+			("_c", MemberType::Primitive(PrimitiveTypeEnumeration::Int16)),
+		];
+		for name in ["_d", "_e", "_f", "_g", "_h", "_i", "_j", "_k"].iter() {
+			guid_members.push((name, MemberType::Primitive(PrimitiveTypeEnumeration::Byte)));
+		}
+		let guid = builder.define_system_class("System.Guid", guid_members);
+		let day = builder.define_system_class("System.DayOfWeek", vec![("value__", MemberType::Primitive(PrimitiveTypeEnumeration::Int32))]);
+		let save_id = builder.new_object(save);
+		let guid_id = builder.new_object(guid);
+		let day_id = builder.new_object(day);
+		builder.set_reference(save_id, "Id", guid_id).set_reference(save_id, "Day", day_id)
+			.set_primitive(guid_id, "_a", Primitive::Int32(0x12345678)).set_primitive(guid_id, "_k", Primitive::Byte(0xff))
+			.set_primitive(day_id, "value__", Primitive::Int32(2));
+		let json = document_to_json(&read_document(&builder.to_bytes().unwrap()[..]).unwrap(), true, &Converters::new());
+		assert_eq!(json.pointer("/Objects/0/Members/Id"), Some(&JValue::String("12345678-0000-0000-0000-0000000000ff".to_string())));
+		assert_eq!(json.pointer("/Objects/0/Members/Day/name"), Some(&JValue::String("Tuesday".to_string())));
+
+		let mut bytes = vec![];
+		write_from_json(&mut bytes, &json, &Converters::new()).unwrap();
+		assert_eq!(document_to_json(&read_document(&bytes[..]).unwrap(), true, &Converters::new()), json);
+	}
+
+	#[test]
+	fn converted_objects() {
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let save = builder.define_class("Game.Save", library_id, vec![("Position", MemberType::Class("Game.Point".to_string(), library_id))]);
+		let point = builder.define_class("Game.Point", library_id, vec![
+			("x", MemberType::Primitive(PrimitiveTypeEnumeration::Int32)),
+			("y", MemberType::Primitive(PrimitiveTypeEnumeration::Int32)),
+		]);
+		let save_id = builder.new_object(save);
+		let point_id = builder.new_object(point);
+		builder.set_reference(save_id, "Position", point_id)
+			.set_primitive(point_id, "x", Primitive::Int32(3)).set_primitive(point_id, "y", Primitive::Int32(-4));
+		let mut converters = Converters::new();
+		converters.register("Game.Point", box(MembersAsArray { members: vec!["y".to_string(), "x".to_string()] }));
+		let json = document_to_json(&read_document(&builder.to_bytes().unwrap()[..]).unwrap(), true, &converters);
+		assert_eq!(json.as_object().unwrap()["Objects"].as_array().unwrap().len(), 1);
+		assert_eq!(json.pointer("/Objects/0/Members/Position"), Some(&JValue::Array(vec![JValue::I64(-4), JValue::I64(3)])));
+
+		let mut bytes = vec![];
+		write_from_json(&mut bytes, &json, &converters).unwrap();
+		assert_eq!(document_to_json(&read_document(&bytes[..]).unwrap(), true, &converters), json);
+
+		let mut broken = json.clone();
+		{
+			let objects = broken.as_object_mut().and_then(|m| m.get_mut("Objects")).and_then(|objects| objects.as_array_mut()).unwrap();
+			let members = objects[0].as_object_mut().and_then(|m| m.get_mut("Members")).and_then(|members| members.as_object_mut()).unwrap();
+			members.insert("Position".to_string(), JValue::Array(vec![JValue::I64(1)]));
+		}
+		assert!(write_from_json(&mut vec![], &broken, &converters).is_err());
+	}
+}
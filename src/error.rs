@@ -0,0 +1,128 @@
+//! Errors of reading a stream, with where in the stream they happened
+
+use std;
+use std::fmt;
+use std::io;
+
+/// Records nested deeper than this in member values and array elements are taken as a broken stream
+pub const MAX_DEPTH: usize = 256;
+/// Member counts, ranks and array lengths above this are taken as garbage rather than allocated
+pub const MAX_COUNT: i64 = 1 << 28;
+/// The length of a LengthPrefixedString takes at most 5 bytes
+pub const MAX_LENGTH_BYTES: u8 = 5;
+
+/// Offset in the stream and the records being read when the error happened, outermost first
+#[derive(Debug, Clone, Default)]
+pub struct Location {
+	pub offset: u64,
+	pub path: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum DeserializeError {
+	UnexpectedEof(Location),
+	/// Name of the enumeration and the byte read
+	InvalidEnumValue(Location, &'static str, u8),
+	/// The RecordTypeEnumeration byte of a record which cannot be read, or is not expected there
+	UnsupportedRecordType(Location, u8),
+	/// Id of a class used by an object before being defined
+	DanglingReference(Location, i32),
+	InvalidUtf8(Location),
+	/// What is limited and the value read
+	LimitExceeded(Location, &'static str, i64),
+	/// Length of an array and the number of elements given by its records, a run of nulls going past its end
+	TooManyElements(Location, usize, usize),
+	/// A class written without member types which are not given by the schema
+	MissingMemberTypes(Location, String),
+	Io(Location, io::Error),
+}
+
+impl DeserializeError {
+	pub fn location(&self) -> &Location {
+		use self::DeserializeError::*;
+
+		match self {
+			&UnexpectedEof(ref location) => location,
+			&InvalidEnumValue(ref location, _, _) => location,
+			&UnsupportedRecordType(ref location, _) => location,
+			&DanglingReference(ref location, _) => location,
+			&InvalidUtf8(ref location) => location,
+			&LimitExceeded(ref location, _, _) => location,
+			&TooManyElements(ref location, _, _) => location,
+			&MissingMemberTypes(ref location, _) => location,
+			&Io(ref location, _) => location,
+		}
+	}
+
+	fn location_mut(&mut self) -> &mut Location {
+		use self::DeserializeError::*;
+
+		match self {
+			&mut UnexpectedEof(ref mut location) => location,
+			&mut InvalidEnumValue(ref mut location, _, _) => location,
+			&mut UnsupportedRecordType(ref mut location, _) => location,
+			&mut DanglingReference(ref mut location, _) => location,
+			&mut InvalidUtf8(ref mut location) => location,
+			&mut LimitExceeded(ref mut location, _, _) => location,
+			&mut TooManyElements(ref mut location, _, _) => location,
+			&mut MissingMemberTypes(ref mut location, _) => location,
+			&mut Io(ref mut location, _) => location,
+		}
+	}
+
+	pub fn offset(&self) -> u64 {
+		self.location().offset
+	}
+
+	pub fn path(&self) -> &Vec<String> {
+		&self.location().path
+	}
+
+	/// Adds the record or value being read around the place of the error, as the readers return
+	pub fn within(mut self, segment: String) -> Self {
+		self.location_mut().path.insert(0, segment);
+		self
+	}
+}
+
+impl fmt::Display for DeserializeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		use self::DeserializeError::*;
+
+		try!(match self {
+			&UnexpectedEof(_) => write!(f, "Unexpected end of stream"),
+			&InvalidEnumValue(_, name, byte) => write!(f, "Invalid {} {}", name, byte),
+			&UnsupportedRecordType(_, byte) => write!(f, "Unsupported record type {}", byte),
+			&DanglingReference(_, id) => write!(f, "Class {} is not defined before", id),
+			&InvalidUtf8(_) => write!(f, "Invalid UTF-8"),
+			&LimitExceeded(_, what, value) => write!(f, "{} {} exceeds the limit", what, value),
+			&TooManyElements(_, length, count) => write!(f, "{} elements in an array of length {}", count, length),
+			&MissingMemberTypes(_, ref message) => write!(f, "{}", message),
+			&Io(_, ref e) => write!(f, "{}", e),
+		});
+		let location = self.location();
+		try!(write!(f, " at offset {}", location.offset));
+		if !location.path.is_empty() {
+			try!(write!(f, " in {}", location.path.join(" > ")));
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for DeserializeError {
+	fn description(&self) -> &str {
+		use self::DeserializeError::*;
+
+		match self {
+			&UnexpectedEof(_) => "unexpected end of stream",
+			&InvalidEnumValue(_, _, _) => "invalid enumeration value",
+			&UnsupportedRecordType(_, _) => "unsupported record type",
+			&DanglingReference(_, _) => "dangling reference",
+			&InvalidUtf8(_) => "invalid UTF-8",
+			&LimitExceeded(_, _, _) => "limit exceeded",
+			&TooManyElements(_, _, _) => "too many array elements",
+			&MissingMemberTypes(_, _) => "missing member types",
+			&Io(_, ref e) => e.description(),
+		}
+	}
+}
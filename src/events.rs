@@ -0,0 +1,448 @@
+//! Reading a stream as a sequence of events, for streams too large to be held in memory.
+//!
+//! Records are read one after another with the readers of the records module, and every part of
+//! them is given to a `Visitor` as soon as it is read: an object or an array is a start event, then
+//! its members or elements, then an end event. Only the classes are kept, for the objects written
+//! after them, so a visitor can filter or transform a stream of any size, or write an output of its own.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::rc::Rc;
+
+use records::*;
+use model::{Primitive, Class, MemberType};
+use document::{class_of, member_type_of};
+use schema::Schema;
+
+/// What an array record gives before its elements
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayHeader {
+	pub id: i32,
+	pub element_type: MemberType,
+	/// Length of every dimension, a single one except for rectangular arrays
+	pub lengths: Vec<i32>,
+	pub lower_bounds: Option<Vec<i32>>,
+}
+
+/// Parts of a stream in the order they are read. Objects, arrays and strings written inline as
+/// member values or array elements come right after the Member or Element event.
+#[derive(Debug)]
+pub enum Event<'e> {
+	Header(&'e SerializationHeaderRecord),
+	Library(&'e BinaryLibraryRecord),
+	/// A class defined by the record of an object, right before the ObjectStart of that object
+	ClassDefinition(&'e Class),
+	/// Object id and class
+	ObjectStart(i32, &'e Class),
+	/// Index and name of the member whose value follows
+	Member(usize, &'e str),
+	/// Object id
+	ObjectEnd(i32),
+	ArrayStart(&'e ArrayHeader),
+	/// Index of the element which follows, the last dimension varying fastest
+	Element(usize),
+	/// Array id
+	ArrayEnd(i32),
+	/// A member value or an array element of a primitive type, or a MemberPrimitiveTyped record
+	Primitive(&'e Primitive),
+	/// Object id and value of a BinaryObjectString record
+	String(i32, &'e str),
+	/// Id of an object, array or string written elsewhere in the stream
+	Reference(i32),
+	/// Number of nulls, more than one for the runs of nulls in arrays
+	Null(usize),
+	MessageEnd,
+}
+
+pub trait Visitor {
+	fn visit(&mut self, event: Event);
+}
+
+/// Closures taking an Event are visitors too
+impl<F: FnMut(Event)> Visitor for F {
+	fn visit(&mut self, event: Event) {
+		(*self)(event)
+	}
+}
+
+struct Context<'s> {
+	/// Classes by the object id of the record defining them
+	classes: HashMap<i32, Rc<Class>>,
+	schema: &'s mut Schema,
+	/// Records being read inside each other, up to MAX_DEPTH
+	depth: usize,
+}
+
+/// Registered before the values of the first object, which may be objects of the same class
+fn define_class(context: &mut Context, visitor: &mut Visitor, record: &ClassRecordForClassWithId, class_id: i32) -> Rc<Class> {
+	let class = Rc::new(class_of(class_id, record));
+	context.classes.insert(class_id, class.clone());
+	visitor.visit(Event::ClassDefinition(&class));
+	class
+}
+
+fn read_object(file: &mut OffsetRead, context: &mut Context, visitor: &mut Visitor, object_id: i32, class: Rc<Class>) -> Result<(), DeserializeError> {
+	visitor.visit(Event::ObjectStart(object_id, &class));
+	for (i, member_type) in class.member_types.iter().enumerate() {
+		visitor.visit(Event::Member(i, &class.member_names[i]));
+		try!(read_member_value(file, context, visitor, member_type).map_err(|e| e.within(format!("{}.{}", class.name, class.member_names[i]))));
+	}
+	visitor.visit(Event::ObjectEnd(object_id));
+	Ok(())
+}
+
+fn read_member_value(file: &mut OffsetRead, context: &mut Context, visitor: &mut Visitor, member_type: &MemberType) -> Result<(), DeserializeError> {
+	match member_type {
+		&MemberType::Primitive(ref primitive_type) => {
+			let primitive = try!(read_primitive(file, primitive_type));
+			visitor.visit(Event::Primitive(&primitive));
+		},
+		_ => {
+			try!(read_value(file, context, visitor));
+		},
+	}
+	Ok(())
+}
+
+/// Elements of primitive arrays are read one by one, the others are records where a run of nulls may be a single record
+fn read_array(file: &mut OffsetRead, context: &mut Context, visitor: &mut Visitor, header: ArrayHeader, length: usize) -> Result<(), DeserializeError> {
+	visitor.visit(Event::ArrayStart(&header));
+	match header.element_type {
+		MemberType::Primitive(ref primitive_type) => {
+			for i in 0..length {
+				visitor.visit(Event::Element(i));
+				let primitive = try!(read_primitive(file, primitive_type).map_err(|e| e.within(format!("element {}", i))));
+				visitor.visit(Event::Primitive(&primitive));
+			}
+		},
+		_ => {
+			let mut i = 0;
+			while i < length {
+				visitor.visit(Event::Element(i));
+				let element_count = try!(read_value(file, context, visitor).map_err(|e| e.within(format!("element {}", i))));
+				if i + element_count > length {
+					return Err(DeserializeError::TooManyElements(error_at(file, 0), length, i + element_count).within(format!("element {}", i)));
+				}
+				i += element_count;
+			}
+		},
+	}
+	visitor.visit(Event::ArrayEnd(header.id));
+	Ok(())
+}
+
+fn read_binary_array(file: &mut OffsetRead, context: &mut Context, visitor: &mut Visitor) -> Result<(), DeserializeError> {
+	use sub_records::BinaryArrayTypeEnumeration::*;
+
+	let id = try!(read_l_i32(file));
+	let array_type = try!(read_enum(file));
+	let rank = try!(read_count(file, "Rank")) as usize;
+	let mut lengths = vec![];
+	for _ in 0..rank {
+		lengths.push(try!(read_count(file, "array length")));
+	}
+	let lower_bounds = match array_type {
+		SingleOffset | JaggedOffset | RectangularOffset => {
+			let mut lower_bounds = vec![];
+			for _ in 0..rank {
+				lower_bounds.push(try!(read_l_i32(file)));
+			}
+			Some(lower_bounds)
+		},
+		Single | Jagged | Rectangular => None,
+		Unknown => unreachable!(),
+	};
+	let binary_type = try!(read_enum(file));
+	let additional_info = try!(read_additional_info(file, &binary_type));
+
+	let mut element_count: i64 = 1;
+	for length in lengths.iter() {
+		element_count = element_count.saturating_mul(*length as i64);
+	}
+	if element_count > MAX_COUNT {
+		return Err(DeserializeError::LimitExceeded(error_at(file, 0), "array element count", element_count));
+	}
+	let header = ArrayHeader {
+		id: id,
+		element_type: member_type_of(&binary_type, &additional_info),
+		lengths: lengths,
+		lower_bounds: lower_bounds,
+	};
+	read_array(file, context, visitor, header, element_count as usize)
+}
+
+fn single_array_header(array_info: &ArrayInfoRecord, element_type: MemberType) -> ArrayHeader {
+	ArrayHeader {
+		id: array_info.ObjectId,
+		element_type: element_type,
+		lengths: vec![array_info.Length],
+		lower_bounds: None,
+	}
+}
+
+/// Reads one record and everything written inside it.
+/// Returns the type of the record and the number of array elements it stands for.
+fn read_record(file: &mut OffsetRead, context: &mut Context, visitor: &mut Visitor, is_value: bool) -> Result<(RecordTypeEnumeration, usize), DeserializeError> {
+	if context.depth >= MAX_DEPTH {
+		return Err(DeserializeError::LimitExceeded(error_at(file, 0), "record depth", context.depth as i64));
+	}
+	let record_type: RecordTypeEnumeration = try!(read_enum(file));
+	match record_type {
+		RecordTypeEnumeration::SerializedStreamHeader | RecordTypeEnumeration::MessageEnd if is_value => {
+			return Err(DeserializeError::UnsupportedRecordType(error_at(file, 1), record_type as u8));
+		},
+		_ => {},
+	}
+	context.depth += 1;
+	let count = read_record_of_type(file, context, visitor, record_type);
+	context.depth -= 1;
+	count.map(|count| (record_type, count)).map_err(|e| e.within(format!("{:?}", record_type)))
+}
+
+fn read_record_of_type(file: &mut OffsetRead, context: &mut Context, visitor: &mut Visitor, record_type: RecordTypeEnumeration) -> Result<usize, DeserializeError> {
+	use records::RecordTypeEnumeration::*;
+
+	Ok(match record_type {
+		SerializedStreamHeader => {
+			visitor.visit(Event::Header(&try!(SerializationHeaderRecord::new(file))));
+			0
+		},
+		ClassWithId => {
+			let class_with_id = try!(ClassWithIdRecord::new(file));
+			let class = match context.classes.get(&class_with_id.MetadataId) {
+				Some(class) => class.clone(),
+				None => return Err(DeserializeError::DanglingReference(error_at(file, 4), class_with_id.MetadataId)),
+			};
+			try!(read_object(file, context, visitor, class_with_id.ObjectId, class));
+			1
+		},
+		SystemClassWithMembers => {
+			let mut record = try!(SystemClassWithMembersRecord::new(file));
+			record.MemberTypeInfo = try!(context.schema.get_member_type_info(&record.ClassInfo)
+				.map_err(|message| DeserializeError::MissingMemberTypes(error_at(file, 0), message)));
+			let class = define_class(context, visitor, &record, record.ClassInfo.ObjectId);
+			try!(read_object(file, context, visitor, record.ClassInfo.ObjectId, class));
+			1
+		},
+		ClassWithMembers => {
+			let mut record = try!(ClassWithMembersRecord::new(file));
+			record.MemberTypeInfo = try!(context.schema.get_member_type_info(&record.ClassInfo)
+				.map_err(|message| DeserializeError::MissingMemberTypes(error_at(file, 0), message)));
+			let class = define_class(context, visitor, &record, record.ClassInfo.ObjectId);
+			try!(read_object(file, context, visitor, record.ClassInfo.ObjectId, class));
+			1
+		},
+		SystemClassWithMembersAndTypes => {
+			let record = try!(SystemClassWithMembersAndTypesRecord::new(file));
+			context.schema.add_class(&record);
+			let class = define_class(context, visitor, &record, record.ClassInfo.ObjectId);
+			try!(read_object(file, context, visitor, record.ClassInfo.ObjectId, class));
+			1
+		},
+		ClassWithMembersAndTypes => {
+			let record = try!(ClassWithMembersAndTypesRecord::new(file));
+			context.schema.add_class(&record);
+			let class = define_class(context, visitor, &record, record.ClassInfo.ObjectId);
+			try!(read_object(file, context, visitor, record.ClassInfo.ObjectId, class));
+			1
+		},
+		RecordTypeEnumeration::BinaryObjectString => {
+			let record = try!(BinaryObjectStringRecord::new(file));
+			visitor.visit(Event::String(record.ObjectId, &record.Value));
+			1
+		},
+		BinaryArray => {
+			try!(read_binary_array(file, context, visitor));
+			1
+		},
+		MemberPrimitiveTyped => {
+			let record = try!(MemberPrimitiveTypedRecord::new(file));
+			visitor.visit(Event::Primitive(&record.Value));
+			1
+		},
+		MemberReference => {
+			let record = try!(MemberReferenceRecord::new(file));
+			visitor.visit(Event::Reference(record.IdRef));
+			1
+		},
+		ObjectNull => {
+			try!(ObjectNullRecord::new(file));
+			visitor.visit(Event::Null(1));
+			1
+		},
+		MessageEnd => {
+			try!(MessageEndRecord::new(file));
+			visitor.visit(Event::MessageEnd);
+			0
+		},
+		BinaryLibrary => {
+			visitor.visit(Event::Library(&try!(BinaryLibraryRecord::new(file))));
+			0
+		},
+		ObjectNullMultiple256 => {
+			let count = try!(ObjectNullMultiple256Record::new(file)).NullCount as usize;
+			visitor.visit(Event::Null(count));
+			count
+		},
+		ObjectNullMultiple => {
+			let count = try!(ObjectNullMultipleRecord::new(file)).NullCount as usize;
+			visitor.visit(Event::Null(count));
+			count
+		},
+		ArraySinglePrimitive => {
+			let array_info = try!(ArrayInfoRecord::new(file));
+			let primitive_type = try!(read_enum(file));
+			try!(read_array(file, context, visitor, single_array_header(&array_info, MemberType::Primitive(primitive_type)), array_info.Length as usize));
+			1
+		},
+		ArraySingleObject => {
+			let array_info = try!(ArrayInfoRecord::new(file));
+			try!(read_array(file, context, visitor, single_array_header(&array_info, MemberType::Object), array_info.Length as usize));
+			1
+		},
+		ArraySingleString => {
+			let array_info = try!(ArrayInfoRecord::new(file));
+			try!(read_array(file, context, visitor, single_array_header(&array_info, MemberType::String), array_info.Length as usize));
+			1
+		},
+		MethodCall | MethodReturn | Unknown => {
+			return Err(DeserializeError::UnsupportedRecordType(error_at(file, 1), record_type as u8));
+		},
+	})
+}
+
+/// Reads the record of a member value or an array element, after the BinaryLibrary records written before it.
+/// Returns the number of array elements it stands for.
+fn read_value(file: &mut OffsetRead, context: &mut Context, visitor: &mut Visitor) -> Result<usize, DeserializeError> {
+	loop {
+		match try!(read_record(file, context, visitor, true)) {
+			(RecordTypeEnumeration::BinaryLibrary, _) => continue,
+			(_, count) => return Ok(count),
+		}
+	}
+}
+
+/// Reads records until MessageEnd, giving their events to the visitor as they are read.
+/// Classes written without member types are looked up in the schema, classes read with their member types are added to it.
+pub fn read_events(file: &mut OffsetRead, schema: &mut Schema, visitor: &mut Visitor) -> Result<(), DeserializeError> {
+	let mut context = Context {
+		classes: HashMap::new(),
+		schema: schema,
+		depth: 0,
+	};
+	let mut record_count = 0;
+	loop {
+		let record = read_record(file, &mut context, visitor, false).map_err(|e| e.within(format!("record {}", record_count)));
+		if let (RecordTypeEnumeration::MessageEnd, _) = try!(record) {
+			return Ok(());
+		}
+		record_count += 1;
+	}
+}
+
+/// Reads a stream whose classes are all written with their member types from any reader
+pub fn visit_stream<R: Read>(reader: R, visitor: &mut Visitor) -> Result<(), DeserializeError> {
+	read_events(&mut OffsetReader::new(reader), &mut Schema::new(), visitor)
+}
+
+#[cfg(test)]
+mod tests {
+	use model::Primitive;
+	use records::*;
+	use super::{Event, visit_stream};
+
+	fn describe(event: Event) -> String {
+		match event {
+			Event::Header(_) => "Header".to_string(),
+			Event::Library(library) => format!("Library {}", library.LibraryName),
+			Event::ClassDefinition(class) => format!("ClassDefinition {}", class.name),
+			Event::ObjectStart(id, class) => format!("ObjectStart {} {}", id, class.name),
+			Event::ArrayStart(header) => format!("ArrayStart {} {:?}", header.id, header.lengths),
+			event => format!("{:?}", event),
+		}
+	}
+
+	/// Two items, the first one holding an array of tags
+	fn items() -> Vec<u8> {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 2, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		BinaryLibraryRecord { LibraryId: 1, LibraryName: "Game".to_string() }.write(&mut bytes);
+		ClassWithMembersAndTypesRecord {
+			ClassInfo: ClassInfoRecord {
+				ObjectId: 2,
+				Name: "Game.Item".to_string(),
+				MemberCount: 3,
+				MemberNames: vec!["Name".to_string(), "Count".to_string(), "Tags".to_string()],
+			},
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![BinaryTypeEnumeration::String, BinaryTypeEnumeration::Primitive, BinaryTypeEnumeration::ObjectArray],
+				AdditionalInfos: vec![AdditionalInfo::None, AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32), AdditionalInfo::None],
+			},
+			LibraryId: 1,
+		}.write(&mut bytes);
+		BinaryObjectStringRecord { ObjectId: 5, Value: "arrow".to_string() }.write(&mut bytes);
+		write_primitive(&mut bytes, &Primitive::Int32(20));
+		MemberReferenceRecord { IdRef: 4 }.write(&mut bytes);
+		ClassWithIdRecord { ObjectId: 3, MetadataId: 2 }.write(&mut bytes);
+		ObjectNullRecord {}.write(&mut bytes);
+		write_primitive(&mut bytes, &Primitive::Int32(0));
+		ObjectNullRecord {}.write(&mut bytes);
+		ArraySingleObjectRecord {
+			ArrayInfo: ArrayInfoRecord { ObjectId: 4, Length: 4 },
+			Values: vec![
+				Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: 6, Value: "new".to_string() }),
+				Value::ObjectNullMultiple256(ObjectNullMultiple256Record { NullCount: 2 }),
+				Value::MemberReference(MemberReferenceRecord { IdRef: 3 }),
+			],
+		}.write(&mut bytes);
+		MessageEndRecord {}.write(&mut bytes);
+		bytes
+	}
+
+	#[test]
+	fn events_of_a_stream() {
+		let mut events = vec![];
+		visit_stream(&items()[..], &mut |event: Event| events.push(describe(event))).unwrap();
+		assert_eq!(events, vec![
+			"Header", "Library Game", "ClassDefinition Game.Item",
+			"ObjectStart 2 Game.Item",
+			"Member(0, \"Name\")", "String(5, \"arrow\")", "Member(1, \"Count\")", "Primitive(Int32(20))", "Member(2, \"Tags\")", "Reference(4)",
+			"ObjectEnd(2)",
+			"ObjectStart 3 Game.Item",
+			"Member(0, \"Name\")", "Null(1)", "Member(1, \"Count\")", "Primitive(Int32(0))", "Member(2, \"Tags\")", "Null(1)",
+			"ObjectEnd(3)",
+			"ArrayStart 4 [4]", "Element(0)", "String(6, \"new\")", "Element(1)", "Null(2)", "Element(3)", "Reference(3)", "ArrayEnd(4)",
+			"MessageEnd",
+		]);
+	}
+
+	#[test]
+	fn truncated_stream() {
+		let bytes = items();
+		// Without MessageEnd, the last element and the run of nulls
+		match visit_stream(&bytes[..bytes.len() - 8], &mut |_: Event| {}) {
+			Err(e @ DeserializeError::UnexpectedEof(..)) => {
+				assert_eq!(e.offset(), bytes.len() as u64 - 8);
+				assert_eq!(e.path(), &vec!["record 4".to_string(), "ArraySingleObject".to_string(), "element 1".to_string()]);
+			},
+			result => panic!("Expected the end of the stream, got {:?}", result),
+		}
+	}
+
+	#[test]
+	fn null_run_past_the_array_end() {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		// object[] of two elements given three nulls
+		bytes.extend_from_slice(&[16, 1, 0, 0, 0, 2, 0, 0, 0]);
+		ObjectNullMultiple256Record { NullCount: 3 }.write(&mut bytes);
+		MessageEndRecord {}.write(&mut bytes);
+		match visit_stream(&bytes[..], &mut |_: Event| {}) {
+			Err(e @ DeserializeError::TooManyElements(..)) => {
+				assert_eq!(e.offset(), 28);
+				assert_eq!(e.path(), &vec!["record 1".to_string(), "ArraySingleObject".to_string(), "element 0".to_string()]);
+			},
+			result => panic!("Expected too many elements, got {:?}", result),
+		}
+	}
+}
@@ -0,0 +1,300 @@
+//! Object graph of a stream.
+//!
+//! Starting from the root object given by the header, referenced objects and arrays are inlined
+//! where they are used. Objects and arrays referenced more than once are written with a "$id"
+//! the first time and as {"$ref": id} afterwards, which also stops cycles. Objects and arrays
+//! nested deeper than MAX_DEPTH are written as {"$ref": id} as well. Objects of well-known
+//! classes are written as the values they hold unless asked otherwise, see the known_types module,
+//! and objects of the classes of the converters as their converter writes them.
+
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{Value as JValue, Map};
+use itertools::Zip;
+
+use json::*;
+use model::Data;
+use known_types::{Known, recognize, map_keys};
+use document::Document;
+use converters::Converters;
+
+/// Writing, reading and dropping json recurse as deep as it is nested
+pub const MAX_DEPTH: usize = 256;
+
+struct Graph<'a> {
+	document: &'a Document,
+	reference_counts: HashMap<i32, usize>,
+	written: HashSet<i32>,
+	depth: usize,
+	known_types: bool,
+	converters: &'a Converters,
+}
+
+impl<'a> Graph<'a> {
+	fn new(document: &'a Document, known_types: bool, converters: &'a Converters) -> Self {
+		// The header references the root too, so that a root referenced back from its members is shared
+		let mut reference_counts = document.get_reference_counts();
+		*reference_counts.entry(document.root_id).or_insert(0) += 1;
+		Graph {
+			document: document,
+			reference_counts: reference_counts,
+			written: HashSet::new(),
+			depth: 0,
+			known_types: known_types,
+			converters: converters,
+		}
+	}
+
+	fn is_shared(&self, id: i32) -> bool {
+		self.reference_counts.get(&id).map_or(false, |count| *count > 1)
+	}
+
+	/// Objects and arrays, which have an id; strings are always inlined
+	fn node_to_json(&mut self, id: i32, data: &'a Data) -> JValue {
+		if let &Data::String(ref s) = data {
+			return JValue::String(s.clone());
+		}
+		let is_shared = self.is_shared(id);
+		if (is_shared && self.written.contains(&id)) || self.depth == MAX_DEPTH {
+			let mut m = Map::new();
+			m.insert("$ref".to_string(), JValue::I64(id as i64));
+			return JValue::Object(m);
+		}
+		self.written.insert(id);
+
+		self.depth += 1;
+		let json = self.node_contents_to_json(id, is_shared, data);
+		self.depth -= 1;
+		json
+	}
+
+	fn node_contents_to_json(&mut self, id: i32, is_shared: bool, data: &'a Data) -> JValue {
+		match data {
+			&Data::Object(ref object) => {
+				let class = self.document.get_class(object.class_id).unwrap();
+				if let Some(converter) = self.converters.get_for_class(class) {
+					let mut members = vec![];
+					for member in object.members.iter() {
+						members.push(self.value_to_json(member));
+					}
+					let json = converter.to_json(&class.name, &class.member_names, members);
+					return self.array_to_json(id, is_shared, json);
+				}
+				if self.known_types {
+					if let Some(known) = recognize(self.document, object) {
+						return self.known_to_json(id, is_shared, known);
+					}
+				}
+				let mut m = Map::new();
+				if is_shared {
+					m.insert("$id".to_string(), JValue::I64(id as i64));
+				}
+				m.insert("$type".to_string(), JValue::String(class.name.clone()));
+				for (name, member) in Zip::new((&class.member_names, &object.members)) {
+					m.insert(name.clone(), self.value_to_json(member));
+				}
+				JValue::Object(m)
+			},
+			&Data::Array(ref array) => {
+				let mut elements = vec![];
+				for element in array.elements.iter() {
+					elements.push(self.value_to_json(element));
+				}
+				self.array_to_json(id, is_shared, shape_array_json(elements, &array.lengths, &array.lower_bounds))
+			},
+			_ => JValue::Null,
+		}
+	}
+
+	fn known_to_json(&mut self, id: i32, is_shared: bool, known: Known<'a>) -> JValue {
+		match known {
+			Known::Sequence(sequence) => {
+				let mut elements = vec![];
+				for &item in sequence.items.iter() {
+					elements.push(self.value_to_json(item));
+				}
+				self.array_to_json(id, is_shared, JValue::Array(elements))
+			},
+			// An object when the keys can be names, an array of {"key", "value"} otherwise
+			Known::Map(map) => {
+				let json = match map_keys(self.document, &map) {
+					Some(keys) => {
+						let mut m = Map::new();
+						for (key, &(_, value)) in keys.into_iter().zip(map.pairs.iter()) {
+							m.insert(key, self.value_to_json(value));
+						}
+						JValue::Object(m)
+					},
+					None => {
+						let mut pairs = vec![];
+						for &(key, value) in map.pairs.iter() {
+							let mut m = Map::new();
+							m.insert("key".to_string(), self.value_to_json(key));
+							m.insert("value".to_string(), self.value_to_json(value));
+							pairs.push(JValue::Object(m));
+						}
+						JValue::Array(pairs)
+					},
+				};
+				// Keys could clash with "$id", so shared maps are wrapped as arrays are
+				self.array_to_json(id, is_shared, json)
+			},
+			// Versions and Uris are classes which may be shared, as may be boxed structs
+			Known::Text(s) => self.array_to_json(id, is_shared, JValue::String(s)),
+			Known::Nullable(Some(value), _) => {
+				let json = self.value_to_json(value);
+				self.array_to_json(id, is_shared, json)
+			},
+			Known::Nullable(None, _) => self.array_to_json(id, is_shared, JValue::Null),
+			Known::Pair(key, value, _, _) => {
+				let mut m = Map::new();
+				m.insert("key".to_string(), self.value_to_json(key));
+				m.insert("value".to_string(), self.value_to_json(value));
+				self.array_to_json(id, is_shared, JValue::Object(m))
+			},
+			Known::Enum(value, name) => self.array_to_json(id, is_shared, enum_to_json(value, name)),
+		}
+	}
+
+	/// Shared arrays need an object to carry their "$id"
+	fn array_to_json(&self, id: i32, is_shared: bool, values: JValue) -> JValue {
+		if !is_shared {
+			return values;
+		}
+		let mut m = Map::new();
+		m.insert("$id".to_string(), JValue::I64(id as i64));
+		m.insert("$values".to_string(), values);
+		JValue::Object(m)
+	}
+
+	fn value_to_json(&mut self, data: &'a Data) -> JValue {
+		match data {
+			&Data::Primitive(ref primitive) => primitive_to_json(primitive),
+			&Data::String(ref s) => JValue::String(s.clone()),
+			&Data::Null => JValue::Null,
+			&Data::Object(ref object) => self.node_to_json(object.id, data),
+			&Data::Array(ref array) => self.node_to_json(array.id, data),
+			&Data::Reference(id) => match self.document.get(id) {
+				Some(node) => self.node_to_json(id, node),
+				None => JValue::Null,
+			},
+		}
+	}
+}
+
+/// The root object with everything reachable from it inlined.
+/// Objects of well-known collection classes such as `List<T>`, `Queue<T>` or `Dictionary<TKey, TValue>` are written
+/// as plain arrays and objects unless known_types is false.
+pub fn document_to_graph(document: &Document, known_types: bool, converters: &Converters) -> JValue {
+	let mut graph = Graph::new(document, known_types, converters);
+	match document.get_root() {
+		Some(root) => graph.node_to_json(document.root_id, root),
+		None => JValue::Null,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::Value as JValue;
+
+	use std::rc::Rc;
+
+	use records::*;
+	use builder::StreamBuilder;
+	use converters::Converters;
+	use model::{Data, MemberType, Primitive};
+	use lossless::{LosslessRecord, write_records};
+	use document::read_document;
+	use super::document_to_graph;
+
+	/// Node objects with an Int32 Value and a Next node, Next given by index into ids
+	fn node_graph(next: &[Option<usize>]) -> JValue {
+		let ids: Vec<i32> = (0..next.len()).map(|i| i as i32 + 1).collect();
+		let values = |i: usize| vec![Value::Primitive(Primitive::Int32(i as i32)), match next[i] {
+			Some(n) => Value::MemberReference(MemberReferenceRecord { IdRef: ids[n] }),
+			None => Value::ObjectNull(ObjectNullRecord {}),
+		}];
+		let mut records = vec![LosslessRecord::SerializedStreamHeader(SerializationHeaderRecord {
+			RootId: ids[0],
+			HeaderId: -1,
+			MajorVersion: 1,
+			MinorVersion: 0,
+		})];
+		records.push(LosslessRecord::ClassWithMembersAndTypes(Rc::new(ClassWithMembersAndTypesRecord {
+			ClassInfo: ClassInfoRecord {
+				ObjectId: ids[0],
+				Name: "Game.Node".to_string(),
+				MemberCount: 2,
+				MemberNames: vec!["Value".to_string(), "Next".to_string()],
+			},
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![BinaryTypeEnumeration::Primitive, BinaryTypeEnumeration::Class],
+				AdditionalInfos: vec![
+					AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32),
+					AdditionalInfo::Class(ClassTypeInfoRecord { TypeName: "Game.Node".to_string(), LibraryId: 2 }),
+				],
+			},
+			LibraryId: 2,
+		}), values(0)));
+		for i in 1..next.len() {
+			records.push(LosslessRecord::ClassWithId(ClassWithIdRecord { ObjectId: ids[i], MetadataId: ids[0] }, values(i)));
+		}
+		records.push(LosslessRecord::MessageEnd(MessageEndRecord {}));
+		let mut bytes = vec![];
+		write_records(&mut bytes, &records);
+		document_to_graph(&read_document(&bytes[..]).unwrap(), true, &Converters::new())
+	}
+
+	#[test]
+	fn root_referencing_itself() {
+		let graph = node_graph(&[Some(0)]);
+		assert_eq!(graph.find("$id").and_then(JValue::as_i64), Some(1));
+		assert_eq!(graph.find("Next").and_then(|next| next.find("$ref")).and_then(JValue::as_i64), Some(1));
+	}
+
+	#[test]
+	fn child_referencing_the_root() {
+		let graph = node_graph(&[Some(1), Some(0)]);
+		let child = graph.find("Next").unwrap();
+		assert_eq!(child.find("$id"), None);
+		assert_eq!(child.find("Value").and_then(JValue::as_i64), Some(1));
+		assert_eq!(child.find("Next").and_then(|next| next.find("$ref")).and_then(JValue::as_i64), Some(1));
+	}
+
+	#[test]
+	fn tree_without_ids() {
+		let graph = node_graph(&[Some(1), None]);
+		assert_eq!(graph.find("$id"), None);
+		assert_eq!(graph.find("Next").and_then(|next| next.find("Next")), Some(&JValue::Null));
+	}
+
+	#[test]
+	fn long_chain_stops_at_the_depth_limit() {
+		let length = 200000;
+		let next: Vec<Option<usize>> = (0..length).map(|i| if i + 1 < length { Some(i + 1) } else { None }).collect();
+		let graph = node_graph(&next);
+		let mut node = &graph;
+		for i in 0..super::MAX_DEPTH {
+			assert_eq!(node.find("Value").and_then(JValue::as_i64), Some(i as i64));
+			node = node.find("Next").unwrap();
+		}
+		assert_eq!(node.find("$ref").and_then(JValue::as_i64), Some(super::MAX_DEPTH as i64 + 1));
+	}
+
+	#[test]
+	fn shared_boxed_enum() {
+		let mut builder = StreamBuilder::new();
+		let library_id = builder.add_library("Game");
+		let color = builder.define_class("Game.Color", library_id, vec![("value__", MemberType::Primitive(PrimitiveTypeEnumeration::Int32))]);
+		let color_id = builder.new_object(color);
+		builder.set_primitive(color_id, "value__", Primitive::Int32(2));
+		let array_id = builder.new_array(MemberType::Object, vec![Data::Reference(color_id), Data::Reference(color_id)]);
+		builder.set_root(array_id);
+		let document = read_document(&builder.to_bytes().unwrap()[..]).unwrap();
+		let graph = document_to_graph(&document, true, &Converters::new());
+		let elements = graph.as_array().unwrap();
+		assert_eq!(elements[0].find("$id").and_then(JValue::as_i64), Some(color_id as i64));
+		assert_eq!(elements[0].find("$values").and_then(JValue::as_i64), Some(2));
+		assert_eq!(elements[1].find("$ref").and_then(JValue::as_i64), Some(color_id as i64));
+	}
+}
@@ -0,0 +1,443 @@
+//! Conversions between the records and json shared by the json and the lossless formats
+
+use num::ToPrimitive;
+use byteorder::{ByteOrder, LittleEndian};
+use serde_json::{Value as JValue, Map};
+
+use records::*;
+use model::{Primitive, MemberType};
+
+/// Non-finite floats have no json number, so their bits are kept instead
+fn bits_to_json(bits: u64) -> JValue {
+	let mut m = Map::new();
+	m.insert("Bits".to_string(), JValue::U64(bits));
+	JValue::Object(m)
+}
+
+fn bits_from_json(json: &JValue) -> Result<u64, String> {
+	json.find("Bits").and_then(|v| v.as_u64()).ok_or_else(|| format!("Expect a number or Bits in {:?}", json))
+}
+
+/// The bits go through bytes, the same way as they are written in a stream
+fn f64_to_bits(x: f64) -> u64 {
+	let mut buffer = [0; 8];
+	LittleEndian::write_f64(&mut buffer, x);
+	LittleEndian::read_u64(&buffer)
+}
+
+fn f64_from_bits(bits: u64) -> f64 {
+	let mut buffer = [0; 8];
+	LittleEndian::write_u64(&mut buffer, bits);
+	LittleEndian::read_f64(&buffer)
+}
+
+fn f32_to_bits(x: f32) -> u32 {
+	let mut buffer = [0; 4];
+	LittleEndian::write_f32(&mut buffer, x);
+	LittleEndian::read_u32(&buffer)
+}
+
+fn f32_from_bits(bits: u32) -> f32 {
+	let mut buffer = [0; 4];
+	LittleEndian::write_u32(&mut buffer, bits);
+	LittleEndian::read_f32(&buffer)
+}
+
+fn f64_to_json(x: f64) -> JValue {
+	if x.is_finite() {
+		JValue::F64(x)
+	} else {
+		bits_to_json(f64_to_bits(x))
+	}
+}
+
+fn f64_from_json(json: &JValue) -> Result<f64, String> {
+	Ok(match json.as_f64() {
+		Some(x) => x,
+		None => f64_from_bits(try!(bits_from_json(json))),
+	})
+}
+
+fn f32_to_json(x: f32) -> JValue {
+	if x.is_finite() {
+		JValue::F64(x as f64)
+	} else {
+		bits_to_json(f32_to_bits(x) as u64)
+	}
+}
+
+fn f32_from_json(json: &JValue) -> Result<f32, String> {
+	Ok(match json.as_f64() {
+		Some(x) => x as f32,
+		None => {
+			let bits = try!(try!(bits_from_json(json)).to_u32().ok_or_else(|| format!("Bits of Single do not fit 32 bits in {:?}", json)));
+			f32_from_bits(bits)
+		},
+	})
+}
+
+/// Char, Decimal, TimeSpan and DateTime are written as strings
+pub fn primitive_to_json(primitive: &Primitive) -> JValue {
+	match primitive {
+		&Primitive::Boolean(x) => JValue::Bool(x),
+		&Primitive::Byte(x) => JValue::U64(x as u64),
+		&Primitive::Char(x) => JValue::String(x.to_string()),
+		&Primitive::Decimal(ref x) => JValue::String(x.Value.clone()),
+		&Primitive::Double(x) => f64_to_json(x),
+		&Primitive::Int16(x) => JValue::I64(x as i64),
+		&Primitive::Int32(x) => JValue::I64(x as i64),
+		&Primitive::Int64(x) => JValue::I64(x),
+		&Primitive::SByte(x) => JValue::I64(x as i64),
+		&Primitive::Single(x) => f32_to_json(x),
+		&Primitive::TimeSpan(ref x) => JValue::String(x.to_string()),
+		&Primitive::DateTime(ref x) => JValue::String(x.to_iso_string()),
+		&Primitive::UInt16(x) => JValue::U64(x as u64),
+		&Primitive::UInt32(x) => JValue::U64(x as u64),
+		&Primitive::UInt64(x) => JValue::U64(x),
+	}
+}
+
+/// Null and String are not primitive values, see primitive_values_from_json for strings.
+/// Values out of the range of the type are errors rather than truncated.
+pub fn primitive_from_json(primitive_type: &PrimitiveTypeEnumeration, json: &JValue) -> Result<Primitive, String> {
+	use records::PrimitiveTypeEnumeration::*;
+
+	let unexpected = || format!("Expect {:?} but got {:?}", primitive_type, json);
+	let out_of_range = || format!("{} does not fit {:?}", json, primitive_type);
+	let as_i64 = || json.as_i64().ok_or_else(|| if json.is_u64() { out_of_range() } else { unexpected() });
+	let as_u64 = || json.as_u64().ok_or_else(|| if json.is_i64() { out_of_range() } else { unexpected() });
+	let as_str = || json.as_str().ok_or_else(|| unexpected());
+	Ok(match primitive_type {
+		&Boolean => Primitive::Boolean(try!(json.as_bool().ok_or_else(|| unexpected()))),
+		&Byte => Primitive::Byte(try!(try!(as_u64()).to_u8().ok_or_else(|| out_of_range()))),
+		&Char => {
+			let s = try!(as_str());
+			let mut chars = s.chars();
+			match (chars.next(), chars.next()) {
+				(Some(c), None) => Primitive::Char(c),
+				_ => return Err(format!("Expect a single Char but got {:?}", s)),
+			}
+		},
+		&Decimal => Primitive::Decimal(::sub_records::Decimal { Value: try!(as_str()).to_string() }),
+		&Double => Primitive::Double(try!(f64_from_json(json))),
+		&Int16 => Primitive::Int16(try!(try!(as_i64()).to_i16().ok_or_else(|| out_of_range()))),
+		&Int32 => Primitive::Int32(try!(try!(as_i64()).to_i32().ok_or_else(|| out_of_range()))),
+		&Int64 => Primitive::Int64(try!(as_i64())),
+		&SByte => Primitive::SByte(try!(try!(as_i64()).to_i8().ok_or_else(|| out_of_range()))),
+		&Single => Primitive::Single(try!(f32_from_json(json))),
+		&TimeSpan => Primitive::TimeSpan(try!(::sub_records::TimeSpan::from_str(try!(as_str())))),
+		&DateTime => Primitive::DateTime(try!(::sub_records::DateTime::from_iso_string(try!(as_str())))),
+		&UInt16 => Primitive::UInt16(try!(try!(as_u64()).to_u16().ok_or_else(|| out_of_range()))),
+		&UInt32 => Primitive::UInt32(try!(try!(as_u64()).to_u32().ok_or_else(|| out_of_range()))),
+		&UInt64 => Primitive::UInt64(try!(as_u64())),
+		s @ &Null | s @ &String | s @ &Unknown => return Err(format!("Unprocessed PrimitiveType: {:?}", s)),
+	})
+}
+
+fn primitive_vec_to_json<T: Copy, F: Fn(T) -> Primitive>(values: &Vec<T>, primitive: F) -> Vec<JValue> {
+	values.iter().map(|x| primitive_to_json(&primitive(*x))).collect()
+}
+
+pub fn primitive_values_to_json(values: &PrimitiveValues) -> JValue {
+	use records::PrimitiveValues as V;
+
+	JValue::Array(match values {
+		&V::Boolean(ref v) => primitive_vec_to_json(v, Primitive::Boolean),
+		&V::Byte(ref v) => primitive_vec_to_json(v, Primitive::Byte),
+		&V::Char(ref v) => primitive_vec_to_json(v, Primitive::Char),
+		&V::Decimal(ref v) => v.iter().map(|x| JValue::String(x.Value.clone())).collect(),
+		&V::Double(ref v) => primitive_vec_to_json(v, Primitive::Double),
+		&V::Int16(ref v) => primitive_vec_to_json(v, Primitive::Int16),
+		&V::Int32(ref v) => primitive_vec_to_json(v, Primitive::Int32),
+		&V::Int64(ref v) => primitive_vec_to_json(v, Primitive::Int64),
+		&V::SByte(ref v) => primitive_vec_to_json(v, Primitive::SByte),
+		&V::Single(ref v) => primitive_vec_to_json(v, Primitive::Single),
+		&V::TimeSpan(ref v) => primitive_vec_to_json(v, Primitive::TimeSpan),
+		&V::DateTime(ref v) => primitive_vec_to_json(v, Primitive::DateTime),
+		&V::UInt16(ref v) => primitive_vec_to_json(v, Primitive::UInt16),
+		&V::UInt32(ref v) => primitive_vec_to_json(v, Primitive::UInt32),
+		&V::UInt64(ref v) => primitive_vec_to_json(v, Primitive::UInt64),
+		&V::String(ref v) => v.iter().map(|x| JValue::String(x.clone())).collect(),
+	})
+}
+
+pub fn primitive_values_from_json(primitive_type: &PrimitiveTypeEnumeration, json: &JValue) -> Result<PrimitiveValues, String> {
+	let json = match json.as_array() {
+		Some(json) => json,
+		None => return Err(format!("Expect array of {:?} but got {:?}", primitive_type, json)),
+	};
+	if let &PrimitiveTypeEnumeration::String = primitive_type {
+		let mut values = vec![];
+		for (i, x) in json.iter().enumerate() {
+			match x.as_str() {
+				Some(s) => values.push(s.to_string()),
+				None => return Err(format!("Element {}: Expect String but got {:?}", i, x)),
+			}
+		}
+		return Ok(PrimitiveValues::String(values));
+	}
+	if let &PrimitiveTypeEnumeration::Null = primitive_type {
+		return Err(format!("Unprocessed PrimitiveType: {:?}", primitive_type));
+	}
+	let mut values = PrimitiveValues::empty(primitive_type);
+	for (i, x) in json.iter().enumerate() {
+		values.push(try!(primitive_from_json(primitive_type, x).map_err(|e| format!("Element {}: {}", i, e))));
+	}
+	Ok(values)
+}
+
+pub fn additional_info_to_json(additional_info: &AdditionalInfo) -> JValue {
+	match additional_info {
+		&AdditionalInfo::Primitive(pte) => JValue::String(format!("{:?}", pte)),
+		&AdditionalInfo::SystemClass(ref class_name) => JValue::String(class_name.clone()),
+		&AdditionalInfo::Class(ref class_type_info) => {
+			let mut c = Map::new();
+			c.insert("TypeName".to_string(), JValue::String(class_type_info.TypeName.clone()));
+			c.insert("LibraryId".to_string(), JValue::I64(class_type_info.LibraryId as i64));
+			JValue::Object(c)
+		},
+		&AdditionalInfo::None => JValue::Null,
+	}
+}
+
+pub fn additional_info_from_json(binary_type: BinaryTypeEnumeration, additional_info: &JValue) -> Result<AdditionalInfo, String> {
+	let as_str = || additional_info.as_str().ok_or_else(|| format!("Expect a string AdditionalInfo for {:?} but got {:?}", binary_type, additional_info));
+	Ok(match binary_type {
+		BinaryTypeEnumeration::Primitive | BinaryTypeEnumeration::PrimitiveArray => {
+			AdditionalInfo::Primitive(try!(try!(as_str()).parse()))
+		},
+		BinaryTypeEnumeration::SystemClass => {
+			AdditionalInfo::SystemClass(try!(as_str()).to_string())
+		},
+		BinaryTypeEnumeration::Class => {
+			AdditionalInfo::Class(ClassTypeInfoRecord {
+				TypeName: try!(get_str(additional_info, "TypeName")).to_string(),
+				LibraryId: try!(get_i32(additional_info, "LibraryId")),
+			})
+		},
+		_ => AdditionalInfo::None,
+	})
+}
+
+/// Elements of binary arrays are written with the last dimension varying fastest, so rectangular arrays are nested by rank.
+/// Non-zero lower bounds are kept next to the elements.
+pub fn shape_array_json(elements: Vec<JValue>, lengths: &Vec<i32>, lower_bounds: &Option<Vec<i32>>) -> JValue {
+	let mut nested = elements;
+	for dimension in (1..lengths.len()).rev() {
+		let length = lengths[dimension] as usize;
+		nested = if length == 0 {
+			let row_count = lengths[..dimension].iter().fold(1, |count, length| count * *length as usize);
+			(0..row_count).map(|_| JValue::Array(vec![])).collect()
+		} else {
+			nested.chunks(length).map(|row| JValue::Array(row.to_vec())).collect()
+		};
+	}
+	let has_lower_bounds = lower_bounds.as_ref().map_or(false, |lower_bounds| lower_bounds.iter().any(|lower_bound| *lower_bound != 0));
+	match lower_bounds {
+		&Some(ref lower_bounds) if has_lower_bounds => {
+			let mut m = Map::new();
+			m.insert("LowerBounds".to_string(), JValue::Array(lower_bounds.iter().map(|x| JValue::I64(*x as i64)).collect()));
+			m.insert("Values".to_string(), JValue::Array(nested));
+			JValue::Object(m)
+		},
+		_ => JValue::Array(nested),
+	}
+}
+
+/// Primitives where a record is expected keep their type, as the member type does not give it
+pub fn typed_primitive_to_json(primitive: &Primitive) -> JValue {
+	let mut m = Map::new();
+	m.insert("PrimitiveType".to_string(), JValue::String(format!("{:?}", primitive.get_primitive_type())));
+	m.insert("Value".to_string(), primitive_to_json(primitive));
+	JValue::Object(m)
+}
+
+/// Also takes plain booleans and numbers, typed as Boolean, Int32, Int64, UInt64 or Double by their value.
+/// None when the json is no primitive.
+pub fn typed_primitive_from_json(json: &JValue) -> Result<Option<Primitive>, String> {
+	Ok(match json {
+		&JValue::Bool(b) => Some(Primitive::Boolean(b)),
+		&JValue::I64(x) => match x.to_i32() {
+			Some(x) => Some(Primitive::Int32(x)),
+			None => Some(Primitive::Int64(x)),
+		},
+		&JValue::U64(x) => match (x.to_i32(), x.to_i64()) {
+			(Some(x), _) => Some(Primitive::Int32(x)),
+			(None, Some(x)) => Some(Primitive::Int64(x)),
+			(None, None) => Some(Primitive::UInt64(x)),
+		},
+		&JValue::F64(x) => Some(Primitive::Double(x)),
+		&JValue::Object(_) => {
+			match json.find("PrimitiveType").and_then(|v| v.as_str()) {
+				Some(primitive_type) => {
+					let primitive_type: PrimitiveTypeEnumeration = try!(primitive_type.parse());
+					let value = match json.find("Value") {
+						Some(value) => try!(primitive_from_json(&primitive_type, value)),
+						None => return Err(format!("Expect Value in {:?}", json)),
+					};
+					Some(value)
+				},
+				None => None,
+			}
+		},
+		_ => None,
+	})
+}
+
+/// Enums are their number, or {"value", "name"} when the name of the constant is known
+pub fn enum_to_json(value: &Primitive, name: Option<&str>) -> JValue {
+	match name {
+		Some(name) => {
+			let mut m = Map::new();
+			m.insert("value".to_string(), primitive_to_json(value));
+			m.insert("name".to_string(), JValue::String(name.to_string()));
+			JValue::Object(m)
+		},
+		None => primitive_to_json(value),
+	}
+}
+
+pub fn member_type_to_json(name: &String, binary_type: &BinaryTypeEnumeration, additional_info: &AdditionalInfo) -> JValue {
+	let mut m = Map::new();
+	m.insert("Name".to_string(), JValue::String(name.clone()));
+	m.insert("BinaryType".to_string(), JValue::String(format!("{:?}", binary_type)));
+	m.insert("AdditionalInfo".to_string(), additional_info_to_json(additional_info));
+	JValue::Object(m)
+}
+
+/// The same json as member_type_to_json
+pub fn model_member_type_to_json(name: &String, member_type: &MemberType) -> JValue {
+	let additional_info = match member_type {
+		&MemberType::Primitive(primitive_type) | &MemberType::PrimitiveArray(primitive_type) => JValue::String(format!("{:?}", primitive_type)),
+		&MemberType::SystemClass(ref class_name) => JValue::String(class_name.clone()),
+		&MemberType::Class(ref type_name, library_id) => {
+			let mut c = Map::new();
+			c.insert("TypeName".to_string(), JValue::String(type_name.clone()));
+			c.insert("LibraryId".to_string(), JValue::I64(library_id as i64));
+			JValue::Object(c)
+		},
+		_ => JValue::Null,
+	};
+	let mut m = Map::new();
+	m.insert("Name".to_string(), JValue::String(name.clone()));
+	m.insert("BinaryType".to_string(), JValue::String(format!("{:?}", member_type.get_binary_type())));
+	m.insert("AdditionalInfo".to_string(), additional_info);
+	JValue::Object(m)
+}
+
+pub fn member_type_from_json(member_type: &JValue) -> Result<(String, BinaryTypeEnumeration, AdditionalInfo), String> {
+	let name = try!(get_str(member_type, "Name")).to_string();
+	let binary_type: BinaryTypeEnumeration = try!(try!(get_str(member_type, "BinaryType")).parse().map_err(|e| format!("Member {}: {}", name, e)));
+	let additional_info = match member_type.find("AdditionalInfo") {
+		Some(additional_info) => try!(additional_info_from_json(binary_type, additional_info).map_err(|e| format!("Member {}: {}", name, e))),
+		None => return Err(format!("Expect AdditionalInfo of member {}", name)),
+	};
+	Ok((name, binary_type, additional_info))
+}
+
+pub fn get_field<'a>(json: &'a JValue, key: &str) -> Result<&'a JValue, String> {
+	json.find(key).ok_or_else(|| format!("Expect {:?} in {:?}", key, json))
+}
+
+pub fn get_str<'a>(json: &'a JValue, key: &str) -> Result<&'a str, String> {
+	json.find(key).and_then(|v| v.as_str()).ok_or_else(|| format!("Expect string {:?} in {:?}", key, json))
+}
+
+pub fn get_i32(json: &JValue, key: &str) -> Result<i32, String> {
+	json.find(key).and_then(|v| v.as_i64()).and_then(|x| x.to_i32()).ok_or_else(|| format!("Expect 32-bit integer {:?} in {:?}", key, json))
+}
+
+pub fn get_array<'a>(json: &'a JValue, key: &str) -> Result<&'a Vec<JValue>, String> {
+	json.find(key).and_then(|v| v.as_array()).ok_or_else(|| format!("Expect array {:?} in {:?}", key, json))
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::Value as JValue;
+
+	use records::PrimitiveTypeEnumeration::*;
+	use model::Primitive;
+	use super::*;
+
+	#[test]
+	fn primitive_in_range() {
+		assert_eq!(primitive_from_json(&Byte, &JValue::U64(255)), Ok(Primitive::Byte(255)));
+		assert_eq!(primitive_from_json(&Int16, &JValue::I64(-32768)), Ok(Primitive::Int16(-32768)));
+		assert_eq!(primitive_from_json(&Char, &JValue::String("é".to_string())), Ok(Primitive::Char('é')));
+	}
+
+	#[test]
+	fn primitive_out_of_range() {
+		assert!(primitive_from_json(&Byte, &JValue::U64(256)).is_err());
+		assert!(primitive_from_json(&Byte, &JValue::I64(-1)).is_err());
+		assert!(primitive_from_json(&SByte, &JValue::I64(128)).is_err());
+		assert!(primitive_from_json(&Int16, &JValue::I64(-32769)).is_err());
+		assert!(primitive_from_json(&Int32, &JValue::U64(1 << 31)).is_err());
+		assert!(primitive_from_json(&UInt16, &JValue::U64(65536)).is_err());
+		assert!(primitive_from_json(&UInt32, &JValue::U64(1 << 32)).is_err());
+		assert!(primitive_from_json(&Int64, &JValue::U64(1 << 63)).is_err());
+	}
+
+	#[test]
+	fn primitive_values_round_trip() {
+		use records::PrimitiveValues as V;
+
+		for values in vec![
+			V::Boolean(vec![true, false]),
+			V::Char(vec!['x']),
+			V::Double(vec![0.5, ::std::f64::INFINITY]),
+			V::Int16(vec![-2, 3]),
+			V::UInt64(vec![::std::u64::MAX]),
+			V::String(vec!["s".to_string()]),
+		] {
+			let json = primitive_values_to_json(&values);
+			assert_eq!(primitive_values_from_json(&values.get_primitive_type(), &json), Ok(values));
+		}
+		assert!(primitive_values_from_json(&Byte, &JValue::Array(vec![JValue::U64(1), JValue::U64(256)])).is_err());
+	}
+
+	#[test]
+	fn typed_primitive_by_value() {
+		let primitive_type = |json: JValue| typed_primitive_from_json(&json).unwrap().map(|primitive| primitive.get_primitive_type());
+		assert_eq!(primitive_type(JValue::Bool(true)), Some(Boolean));
+		assert_eq!(primitive_type(JValue::I64(-5)), Some(Int32));
+		assert_eq!(primitive_type(JValue::I64(-1 << 40)), Some(Int64));
+		assert_eq!(primitive_type(JValue::U64(1 << 31)), Some(Int64));
+		assert_eq!(primitive_type(JValue::U64(1 << 63)), Some(UInt64));
+		assert_eq!(primitive_type(JValue::F64(0.5)), Some(Double));
+		assert_eq!(primitive_type(JValue::String("s".to_string())), None);
+
+		let typed = typed_primitive_to_json(&Primitive::Int16(7));
+		assert_eq!(primitive_type(typed), Some(Int16));
+		let mut wrong = ::serde_json::Map::new();
+		wrong.insert("PrimitiveType".to_string(), JValue::String("Byte".to_string()));
+		wrong.insert("Value".to_string(), JValue::I64(-1));
+		assert!(typed_primitive_from_json(&JValue::Object(wrong)).is_err());
+	}
+
+	#[test]
+	fn shape_arrays() {
+		let elements = |count: i64| (0..count).map(JValue::I64).collect::<Vec<_>>();
+		let rows = |rows: Vec<Vec<i64>>| JValue::Array(rows.into_iter().map(|row| JValue::Array(row.into_iter().map(JValue::I64).collect())).collect());
+		assert_eq!(shape_array_json(elements(3), &vec![3], &None), JValue::Array(elements(3)));
+		assert_eq!(shape_array_json(elements(6), &vec![2, 3], &None), rows(vec![vec![0, 1, 2], vec![3, 4, 5]]));
+		assert_eq!(shape_array_json(elements(0), &vec![2, 0], &None), rows(vec![vec![], vec![]]));
+		assert_eq!(shape_array_json(elements(2), &vec![2], &Some(vec![0])), JValue::Array(elements(2)));
+
+		let offset = shape_array_json(elements(4), &vec![2, 2], &Some(vec![1, -1]));
+		assert_eq!(offset.find("LowerBounds"), Some(&JValue::Array(vec![JValue::I64(1), JValue::I64(-1)])));
+		assert_eq!(offset.find("Values"), Some(&rows(vec![vec![0, 1], vec![2, 3]])));
+	}
+
+	#[test]
+	fn primitive_wrong_type() {
+		assert!(primitive_from_json(&Char, &JValue::String("".to_string())).is_err());
+		assert!(primitive_from_json(&Char, &JValue::String("ab".to_string())).is_err());
+		assert!(primitive_from_json(&Boolean, &JValue::I64(1)).is_err());
+		assert!(primitive_from_json(&TimeSpan, &JValue::String("1:2".to_string())).is_err());
+		assert!(primitive_from_json(&DateTime, &JValue::Null).is_err());
+		assert!(primitive_from_json(&Int32, &JValue::F64(1.5)).is_err());
+		assert!(primitive_from_json(&String, &JValue::String("s".to_string())).is_err());
+		assert!(primitive_from_json(&Null, &JValue::Null).is_err());
+	}
+}
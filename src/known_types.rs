@@ -0,0 +1,670 @@
+//! Well-known classes of the .NET base class library, whose objects are written to json as the values they
+//! hold rather than as their internal members, e.g. a `List<T>` as the array of its items or a `Guid` as a string.
+//! Objects are only recognized when they can be built back from those values.
+
+use std::collections::HashSet;
+use std::cmp::max;
+
+use model::{Data, Object, Array, Class, Primitive, MemberType};
+use sub_records::DateTime;
+use document::Document;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KnownClass {
+	List,
+	HashSet,
+	Queue,
+	Stack,
+	LinkedList,
+	Dictionary,
+	Hashtable,
+	SortedList,
+	SortedDictionary,
+	Guid,
+	Version,
+	Uri,
+	DateTimeOffset,
+	Nullable,
+	KeyValuePair,
+	/// Classes with the single member `value__`
+	Enum,
+}
+
+impl KnownClass {
+	/// Whether the objects of the class hold items
+	pub fn is_sequence(&self) -> bool {
+		match self {
+			&KnownClass::List | &KnownClass::HashSet | &KnownClass::Queue | &KnownClass::Stack | &KnownClass::LinkedList => true,
+			_ => false,
+		}
+	}
+
+	/// Whether the objects of the class hold pairs
+	pub fn is_map(&self) -> bool {
+		match self {
+			&KnownClass::Dictionary | &KnownClass::Hashtable | &KnownClass::SortedList | &KnownClass::SortedDictionary => true,
+			_ => false,
+		}
+	}
+
+	/// Whether the objects of the class are written as strings
+	pub fn is_text(&self) -> bool {
+		match self {
+			&KnownClass::Guid | &KnownClass::Version | &KnownClass::Uri | &KnownClass::DateTimeOffset => true,
+			_ => false,
+		}
+	}
+}
+
+/// The well-known class of a class name such as `System.Collections.Generic.List`1[[T, assembly]]`.
+/// Enums are told by their members, see is_enum.
+pub fn known_class(class_name: &str) -> Option<KnownClass> {
+	let generic_classes = [
+		("System.Collections.Generic.List`1[[", KnownClass::List),
+		("System.Collections.Generic.HashSet`1[[", KnownClass::HashSet),
+		("System.Collections.Generic.Queue`1[[", KnownClass::Queue),
+		("System.Collections.Generic.Stack`1[[", KnownClass::Stack),
+		("System.Collections.Generic.LinkedList`1[[", KnownClass::LinkedList),
+		("System.Collections.Generic.Dictionary`2[[", KnownClass::Dictionary),
+		("System.Collections.Generic.SortedList`2[[", KnownClass::SortedList),
+		("System.Collections.Generic.SortedDictionary`2[[", KnownClass::SortedDictionary),
+		("System.Collections.Generic.KeyValuePair`2[[", KnownClass::KeyValuePair),
+		("System.Nullable`1[[", KnownClass::Nullable),
+	];
+	match class_name {
+		"System.Collections.Hashtable" => return Some(KnownClass::Hashtable),
+		"System.Guid" => return Some(KnownClass::Guid),
+		"System.Version" => return Some(KnownClass::Version),
+		"System.Uri" => return Some(KnownClass::Uri),
+		"System.DateTimeOffset" => return Some(KnownClass::DateTimeOffset),
+		_ => {},
+	}
+	generic_classes.iter()
+		.find(|&&(prefix, _)| class_name.starts_with(prefix))
+		.map(|&(_, known_class)| known_class)
+}
+
+/// The values a recognized object stands for
+#[derive(Debug)]
+pub enum Known<'d> {
+	/// `List<T>`, `HashSet<T>`, `Queue<T>` (from the next item to be dequeued), `Stack<T>` (from the bottom)
+	/// and `LinkedList<T>`
+	Sequence(KnownSequence<'d>),
+	/// `Dictionary<TKey, TValue>`, `Hashtable`, `SortedList<TKey, TValue>` and `SortedDictionary<TKey, TValue>`
+	Map(KnownMap<'d>),
+	/// `Guid`, `Version`, `Uri` and `DateTimeOffset` in their usual string forms,
+	/// e.g. "0f8fad5b-d9cb-469f-a165-70867728950e", "1.2.3" and "2016-05-01T12:00:00.0000000+02:00"
+	Text(String),
+	/// `Nullable<T>`: the value if there is one, and its member type
+	Nullable(Option<&'d Data>, &'d MemberType),
+	/// `KeyValuePair<TKey, TValue>`: the key and value, and their member types
+	Pair(&'d Data, &'d Data, &'d MemberType, &'d MemberType),
+	/// Enums: the value and, for the enums listed by get_enum_names, the name of the constant
+	Enum(&'d Primitive, Option<&'static str>),
+}
+
+#[derive(Debug)]
+pub struct KnownSequence<'d> {
+	pub items: Vec<&'d Data>,
+	/// Member type of the items, `object` when there is no backing array telling it
+	pub item_type: MemberType,
+	/// Comparer of a HashSet, see is_plain_comparer
+	pub comparer: Option<&'d Data>,
+}
+
+#[derive(Debug)]
+pub struct KnownMap<'d> {
+	pub pairs: Vec<(&'d Data, &'d Data)>,
+	/// Member types of the keys and values, `object` for a Hashtable or when there is no pair telling them
+	pub key_type: MemberType,
+	pub value_type: MemberType,
+	/// Comparer of the keys, see is_plain_comparer. None for a Hashtable.
+	pub comparer: Option<&'d Data>,
+}
+
+/// Enums have the single member `value__` of an integer type
+pub fn is_enum(member_names: &[String]) -> bool {
+	member_names.len() == 1 && member_names[0] == "value__"
+}
+
+static DAY_OF_WEEK_NAMES: [&'static str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+static DATE_TIME_KIND_NAMES: [&'static str; 3] = ["Unspecified", "Utc", "Local"];
+
+/// Names of the constants of the enums of the base class library likely to be found in saves, by value from 0
+pub fn get_enum_names(class_name: &str) -> Option<&'static [&'static str]> {
+	match class_name {
+		"System.DayOfWeek" => Some(&DAY_OF_WEEK_NAMES[..]),
+		"System.DateTimeKind" => Some(&DATE_TIME_KIND_NAMES[..]),
+		_ => None,
+	}
+}
+
+fn integer_value(primitive: &Primitive) -> Option<i64> {
+	match primitive {
+		&Primitive::Byte(x) => Some(x as i64),
+		&Primitive::SByte(x) => Some(x as i64),
+		&Primitive::Int16(x) => Some(x as i64),
+		&Primitive::Int32(x) => Some(x as i64),
+		&Primitive::Int64(x) => Some(x),
+		&Primitive::UInt16(x) => Some(x as i64),
+		&Primitive::UInt32(x) => Some(x as i64),
+		&Primitive::UInt64(x) => Some(x as i64),
+		_ => None,
+	}
+}
+
+/// The members _a, _b, _c and _d to _k of a Guid: an Int32, two Int16 and eight bytes
+pub fn guid_to_string(a: i32, b: i16, c: i16, bytes: &[u8]) -> String {
+	let mut s = format!("{:08x}-{:04x}-{:04x}-", a as u32, b as u16, c as u16);
+	for (i, byte) in bytes.iter().enumerate() {
+		if i == 2 {
+			s.push('-');
+		}
+		s.push_str(&format!("{:02x}", byte));
+	}
+	s
+}
+
+/// The members of a Guid from its 32 hexadecimal digits, hyphens and braces being ignored
+pub fn parse_guid(s: &str) -> Option<(i32, i16, i16, Vec<u8>)> {
+	let digits: String = s.chars().filter(|&c| c != '-' && c != '{' && c != '}').collect();
+	if digits.len() != 32 || !digits.chars().all(|c| c.is_digit(16)) {
+		return None;
+	}
+	let a = u32::from_str_radix(&digits[0..8], 16).unwrap() as i32;
+	let b = u16::from_str_radix(&digits[8..12], 16).unwrap() as i16;
+	let c = u16::from_str_radix(&digits[12..16], 16).unwrap() as i16;
+	let bytes = (0..8).map(|i| u8::from_str_radix(&digits[16 + 2 * i..18 + 2 * i], 16).unwrap()).collect();
+	Some((a, b, c, bytes))
+}
+
+/// Major.Minor[.Build[.Revision]], the missing parts being -1 in the members of a Version
+pub fn version_to_string(parts: &[i32; 4]) -> String {
+	let count = parts.iter().position(|&part| part < 0).unwrap_or(4);
+	parts[..max(count, 2)].iter().map(|part| part.to_string()).collect::<Vec<_>>().join(".")
+}
+
+pub fn parse_version(s: &str) -> Option<[i32; 4]> {
+	let mut parts = [-1; 4];
+	let numbers: Vec<&str> = s.split('.').collect();
+	if numbers.len() < 2 || numbers.len() > 4 {
+		return None;
+	}
+	for (part, number) in parts.iter_mut().zip(numbers.iter()) {
+		*part = match number.parse() {
+			Ok(x) if x >= 0 => x,
+			_ => return None,
+		};
+	}
+	Some(parts)
+}
+
+const TICKS_PER_MINUTE: i64 = 600000000;
+/// Ticks of DateTime.MaxValue, 9999-12-31T23:59:59.9999999
+const MAX_DATE_TIME_TICKS: i64 = 3155378975999999999;
+/// Offsets are within 14 hours
+const MAX_OFFSET_MINUTES: i16 = 14 * 60;
+
+/// A DateTimeOffset keeps the UTC time and the offset in minutes. It is written as the local time
+/// followed by the offset, as ISO 8601. None when the local time or the offset is out of range.
+pub fn date_time_offset_to_string(utc: &DateTime, offset_minutes: i16) -> Option<String> {
+	let local = DateTime {
+		Ticks: utc.Ticks + offset_minutes as i64 * TICKS_PER_MINUTE,
+		Kind: 0,
+	};
+	if local.Ticks < 0 || local.Ticks > MAX_DATE_TIME_TICKS || offset_minutes.abs() > MAX_OFFSET_MINUTES {
+		return None;
+	}
+	let sign = if offset_minutes < 0 { '-' } else { '+' };
+	let offset = offset_minutes.abs();
+	Some(format!("{}{}{:02}:{:02}", local.to_iso_string(), sign, offset / 60, offset % 60))
+}
+
+/// The UTC time and the offset in minutes, "Z" standing for no offset
+pub fn parse_date_time_offset(s: &str) -> Option<(DateTime, i16)> {
+	let (local, offset_minutes) = if s.ends_with('Z') {
+		(&s[..s.len() - 1], 0)
+	} else if s.len() > 6 && s.is_char_boundary(s.len() - 6) && (s[s.len() - 6..].starts_with('+') || s[s.len() - 6..].starts_with('-')) {
+		let offset = &s[s.len() - 6..];
+		let minutes = match (offset[1..3].parse::<i16>(), offset[4..6].parse::<i16>()) {
+			(Ok(hours), Ok(minutes)) if &offset[3..4] == ":" => hours * 60 + minutes,
+			_ => return None,
+		};
+		(&s[..s.len() - 6], if offset.starts_with('-') { -minutes } else { minutes })
+	} else {
+		return None;
+	};
+	match DateTime::from_iso_string(local) {
+		Ok(ref local) if local.Kind == 0 => Some((DateTime {
+			Ticks: local.Ticks - offset_minutes as i64 * TICKS_PER_MINUTE,
+			Kind: 0,
+		}, offset_minutes)),
+		_ => None,
+	}
+}
+
+/// The smallest prime at least n, as the bucket count of a hashed collection
+pub fn get_prime(n: i32) -> i32 {
+	let mut candidate = if n < 3 { 3 } else { n | 1 };
+	loop {
+		let mut divisor = 3;
+		while divisor * divisor <= candidate && candidate % divisor != 0 {
+			divisor += 2;
+		}
+		if divisor * divisor > candidate {
+			return candidate;
+		}
+		candidate += 2;
+	}
+}
+
+/// Comparers which can be built back from their classes alone: null, or objects whose members are all such
+/// comparers, as the default comparers of a type are. Cycles are cut at a small depth.
+pub fn is_plain_comparer(document: &Document, data: &Data) -> bool {
+	is_plain_comparer_at(document, data, 0)
+}
+
+fn is_plain_comparer_at(document: &Document, data: &Data, depth: usize) -> bool {
+	if depth > 4 {
+		return false;
+	}
+	match document.resolve(data) {
+		Some(&Data::Null) => true,
+		Some(&Data::Object(ref object)) => object.members.iter().all(|member| is_plain_comparer_at(document, member, depth + 1)),
+		_ => false,
+	}
+}
+
+fn integer_to_string(primitive: &Primitive) -> Option<String> {
+	match primitive {
+		&Primitive::Byte(x) => Some(x.to_string()),
+		&Primitive::SByte(x) => Some(x.to_string()),
+		&Primitive::Int16(x) => Some(x.to_string()),
+		&Primitive::Int32(x) => Some(x.to_string()),
+		&Primitive::Int64(x) => Some(x.to_string()),
+		&Primitive::UInt16(x) => Some(x.to_string()),
+		&Primitive::UInt32(x) => Some(x.to_string()),
+		&Primitive::UInt64(x) => Some(x.to_string()),
+		_ => None,
+	}
+}
+
+/// The keys of the map as the names of a json object, if they are all strings, or integers of an integer key type,
+/// and different from each other
+pub fn map_keys(document: &Document, map: &KnownMap) -> Option<Vec<String>> {
+	let mut keys = vec![];
+	let mut seen = HashSet::new();
+	for &(key, _) in map.pairs.iter() {
+		let key = match (document.resolve(key), &map.key_type) {
+			(Some(&Data::String(ref s)), _) => s.clone(),
+			(Some(&Data::Primitive(ref primitive)), &MemberType::Primitive(_)) => match integer_to_string(primitive) {
+				Some(s) => s,
+				None => return None,
+			},
+			_ => return None,
+		};
+		if !seen.insert(key.clone()) {
+			return None;
+		}
+		keys.push(key);
+	}
+	Some(keys)
+}
+
+fn get_primitive_member<'d>(document: &'d Document, object: &'d Object, name: &str) -> Option<&'d Primitive> {
+	document.get_member(object, name).and_then(Data::as_primitive)
+}
+
+fn get_i32_member(document: &Document, object: &Object, name: &str) -> Option<i32> {
+	match get_primitive_member(document, object, name) {
+		Some(&Primitive::Int32(value)) => Some(value),
+		_ => None,
+	}
+}
+
+/// A member as it is written in the object, with its member type
+fn get_member_with_type<'d>(class: &'d Class, object: &'d Object, name: &str) -> Option<(&'d Data, &'d MemberType)> {
+	class.get_member_index(name).map(|i| (&object.members[i], &class.member_types[i]))
+}
+
+/// A single-dimensional array member, or no array when the member is missing or null as for empty collections.
+/// The outer None is for any other value.
+fn get_array_member<'d>(document: &'d Document, object: &'d Object, name: &str) -> Option<Option<&'d Array>> {
+	match document.get_member(object, name) {
+		None | Some(&Data::Null) => Some(None),
+		Some(&Data::Array(ref array)) if array.lengths.len() == 1 => Some(Some(array)),
+		Some(_) => None,
+	}
+}
+
+/// A comparer member if it is a plain one
+fn get_comparer<'d>(document: &'d Document, object: &'d Object, name: &str) -> Option<&'d Data> {
+	match document.get_member(object, name) {
+		Some(comparer) if is_plain_comparer(document, comparer) => Some(comparer),
+		_ => None,
+	}
+}
+
+/// All the items of an array, or none without one
+fn array_sequence<'d>(array: Option<&'d Array>, comparer: Option<&'d Data>) -> Option<Known<'d>> {
+	Some(Known::Sequence(KnownSequence {
+		items: array.map_or(vec![], |array| array.elements.iter().collect()),
+		item_type: array.map_or(MemberType::Object, |array| array.element_type.clone()),
+		comparer: comparer,
+	}))
+}
+
+/// The first items of the backing array of a List or Stack, the rest being spare capacity
+fn sized_sequence<'d>(document: &'d Document, object: &'d Object, array_name: &str) -> Option<Known<'d>> {
+	let array = document.get_member(object, array_name).and_then(Data::as_array);
+	let size = get_i32_member(document, object, "_size");
+	match (array, size) {
+		(Some(array), Some(size)) if array.lengths.len() == 1 && size >= 0 && size as usize <= array.elements.len() => {
+			Some(Known::Sequence(KnownSequence {
+				items: array.elements[..size as usize].iter().collect(),
+				item_type: array.element_type.clone(),
+				comparer: None,
+			}))
+		},
+		_ => None,
+	}
+}
+
+/// The keys and values of an array of KeyValuePair objects, with members key and value
+fn key_value_pairs<'d>(document: &'d Document, array: Option<&'d Array>, comparer: &'d Data) -> Option<Known<'d>> {
+	let mut map = KnownMap {
+		pairs: vec![],
+		key_type: MemberType::Object,
+		value_type: MemberType::Object,
+		comparer: Some(comparer),
+	};
+	for pair in array.map_or(&[][..], |array| &array.elements[..]).iter() {
+		let pair = match document.resolve(pair).and_then(Data::as_object) {
+			Some(pair) => pair,
+			None => return None,
+		};
+		let pair_class = match document.get_class(pair.class_id) {
+			Some(pair_class) => pair_class,
+			None => return None,
+		};
+		let (key_index, value_index) = match (pair_class.get_member_index("key"), pair_class.get_member_index("value")) {
+			(Some(key_index), Some(value_index)) => (key_index, value_index),
+			_ => return None,
+		};
+		map.key_type = pair_class.member_types[key_index].clone();
+		map.value_type = pair_class.member_types[value_index].clone();
+		map.pairs.push((&pair.members[key_index], &pair.members[value_index]));
+	}
+	Some(Known::Map(map))
+}
+
+/// The keys and values of the same index in two arrays, up to size
+fn parallel_pairs<'d>(keys: &'d Array, values: &'d Array, size: usize, comparer: Option<&'d Data>) -> Option<Known<'d>> {
+	if keys.lengths.len() != 1 || values.lengths.len() != 1 || size > keys.elements.len() || size > values.elements.len() {
+		return None;
+	}
+	Some(Known::Map(KnownMap {
+		pairs: keys.elements[..size].iter().zip(values.elements[..size].iter()).collect(),
+		key_type: keys.element_type.clone(),
+		value_type: values.element_type.clone(),
+		comparer: comparer,
+	}))
+}
+
+/// What the object stands for if its class is a well-known one and its members are as expected.
+/// The members are those the .NET Framework writes, which Mono shares.
+pub fn recognize<'d>(document: &'d Document, object: &'d Object) -> Option<Known<'d>> {
+	let class = match document.get_class(object.class_id) {
+		Some(class) => class,
+		None => return None,
+	};
+	let known_class = match known_class(&class.name) {
+		Some(known_class) => known_class,
+		None if is_enum(&class.member_names) => KnownClass::Enum,
+		None => return None,
+	};
+	match known_class {
+		// _items, _size and _version
+		KnownClass::List => sized_sequence(document, object, "_items"),
+		// _array, _size and _version
+		KnownClass::Stack => sized_sequence(document, object, "_array"),
+		// _array, _head, _tail, _size and _version, the items going round the end of the array
+		KnownClass::Queue => {
+			let array = document.get_member(object, "_array").and_then(Data::as_array);
+			let head = get_i32_member(document, object, "_head");
+			let size = get_i32_member(document, object, "_size");
+			match (array, head, size) {
+				(Some(array), Some(head), Some(size)) if array.lengths.len() == 1 && head >= 0 && size >= 0
+					&& size as usize <= array.elements.len() && (size == 0 || (head as usize) < array.elements.len()) => {
+					Some(Known::Sequence(KnownSequence {
+						items: (0..size as usize).map(|i| &array.elements[(head as usize + i) % array.elements.len()]).collect(),
+						item_type: array.element_type.clone(),
+						comparer: None,
+					}))
+				},
+				_ => None,
+			}
+		},
+		// Version, Comparer, Capacity and, unless empty, Elements
+		KnownClass::HashSet => {
+			let comparer = match get_comparer(document, object, "Comparer") {
+				Some(comparer) => comparer,
+				None => return None,
+			};
+			get_array_member(document, object, "Elements").and_then(|elements| array_sequence(elements, Some(comparer)))
+		},
+		// Version, Count and, unless empty, Data
+		KnownClass::LinkedList => get_array_member(document, object, "Data").and_then(|data| array_sequence(data, None)),
+		// Version, Comparer, HashSize and, unless empty, a KeyValuePairs array
+		KnownClass::Dictionary => {
+			let comparer = match get_comparer(document, object, "Comparer") {
+				Some(comparer) => comparer,
+				None => return None,
+			};
+			get_array_member(document, object, "KeyValuePairs").and_then(|pairs| key_value_pairs(document, pairs, comparer))
+		},
+		// LoadFactor, Version, Comparer, HashCodeProvider, HashSize, Keys and Values.
+		// Those with a comparer or hash code provider are left as they are.
+		KnownClass::Hashtable => {
+			let is_null = |name: &str| document.get_member(object, name).map_or(true, Data::is_null);
+			if !is_null("Comparer") || !is_null("HashCodeProvider") {
+				return None;
+			}
+			let keys = document.get_member(object, "Keys").and_then(Data::as_array);
+			let values = document.get_member(object, "Values").and_then(Data::as_array);
+			match (keys, values) {
+				(Some(keys), Some(values)) if keys.elements.len() == values.elements.len() => parallel_pairs(keys, values, keys.elements.len(), None),
+				_ => None,
+			}
+		},
+		// keys, values, _size, version, comparer, keyList and valueList
+		KnownClass::SortedList => {
+			let comparer = match get_comparer(document, object, "comparer") {
+				Some(comparer) => comparer,
+				None => return None,
+			};
+			let keys = document.get_member(object, "keys").and_then(Data::as_array);
+			let values = document.get_member(object, "values").and_then(Data::as_array);
+			let size = get_i32_member(document, object, "_size");
+			match (keys, values, size) {
+				(Some(keys), Some(values), Some(size)) if size >= 0 => parallel_pairs(keys, values, size as usize, Some(comparer)),
+				_ => None,
+			}
+		},
+		// _set, a TreeSet of KeyValuePair objects with Count, Comparer, Version and, unless empty, Items
+		KnownClass::SortedDictionary => {
+			let set = match document.get_member(object, "_set").and_then(Data::as_object) {
+				Some(set) => set,
+				None => return None,
+			};
+			let comparer = match get_comparer(document, set, "Comparer") {
+				Some(comparer) => comparer,
+				None => return None,
+			};
+			get_array_member(document, set, "Items").and_then(|items| key_value_pairs(document, items, comparer))
+		},
+		// _a, _b, _c and _d to _k
+		KnownClass::Guid => {
+			let mut bytes = vec![];
+			for name in ["_d", "_e", "_f", "_g", "_h", "_i", "_j", "_k"].iter() {
+				match get_primitive_member(document, object, name) {
+					Some(&Primitive::Byte(byte)) => bytes.push(byte),
+					_ => return None,
+				}
+			}
+			let a = get_i32_member(document, object, "_a");
+			let b = get_primitive_member(document, object, "_b");
+			let c = get_primitive_member(document, object, "_c");
+			match (a, b, c) {
+				(Some(a), Some(&Primitive::Int16(b)), Some(&Primitive::Int16(c))) => Some(Known::Text(guid_to_string(a, b, c, &bytes))),
+				_ => None,
+			}
+		},
+		// _Major, _Minor, _Build and _Revision
+		KnownClass::Version => {
+			let mut parts = [0; 4];
+			for (part, name) in parts.iter_mut().zip(["_Major", "_Minor", "_Build", "_Revision"].iter()) {
+				*part = match get_i32_member(document, object, name) {
+					Some(x) => x,
+					None => return None,
+				};
+			}
+			// Parts after a missing one cannot be given in the string
+			let is_written = match parts.iter().position(|&part| part < 0) {
+				Some(i) => i >= 2 && parts[i..].iter().all(|&part| part < 0),
+				None => true,
+			};
+			if is_written { Some(Known::Text(version_to_string(&parts))) } else { None }
+		},
+		// AbsoluteUri, which is empty for a relative uri given by RelativeUri
+		KnownClass::Uri => {
+			let absolute = document.get_member(object, "AbsoluteUri").and_then(Data::as_str);
+			let relative = document.get_member(object, "RelativeUri").and_then(Data::as_str);
+			match (absolute, relative, class.member_names.len()) {
+				(Some(absolute), None, 1) => Some(Known::Text(absolute.to_string())),
+				(Some(""), Some(relative), 2) => Some(Known::Text(relative.to_string())),
+				_ => None,
+			}
+		},
+		// DateTime, in UTC, and OffsetMinutes. Those which cannot be written as a string are kept as they are.
+		KnownClass::DateTimeOffset => {
+			let utc = get_primitive_member(document, object, "DateTime");
+			let offset_minutes = get_primitive_member(document, object, "OffsetMinutes");
+			match (utc, offset_minutes) {
+				(Some(&Primitive::DateTime(ref utc)), Some(&Primitive::Int16(offset_minutes))) if utc.Kind == 0 => {
+					date_time_offset_to_string(utc, offset_minutes).map(Known::Text)
+				},
+				_ => None,
+			}
+		},
+		// hasValue and value
+		KnownClass::Nullable => {
+			let has_value = get_primitive_member(document, object, "hasValue");
+			match (has_value, get_member_with_type(class, object, "value")) {
+				(Some(&Primitive::Boolean(has_value)), Some((value, value_type))) => {
+					Some(Known::Nullable(if has_value { Some(value) } else { None }, value_type))
+				},
+				_ => None,
+			}
+		},
+		// key and value
+		KnownClass::KeyValuePair => match (get_member_with_type(class, object, "key"), get_member_with_type(class, object, "value")) {
+			(Some((key, key_type)), Some((value, value_type))) => Some(Known::Pair(key, value, key_type, value_type)),
+			_ => None,
+		},
+		// value__
+		KnownClass::Enum => match (&object.members[0], &class.member_types[0]) {
+			(&Data::Primitive(ref value), &MemberType::Primitive(_)) => match integer_value(value) {
+				Some(x) => {
+					let name = get_enum_names(&class.name).and_then(|names| if x >= 0 && (x as usize) < names.len() { Some(names[x as usize]) } else { None });
+					Some(Known::Enum(value, name))
+				},
+				None => None,
+			},
+			_ => None,
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use builder::StreamBuilder;
+	use document::{Document, read_document};
+	use model::{Data, MemberType, Primitive};
+	use sub_records::{DateTime, PrimitiveTypeEnumeration};
+	use super::{Known, recognize, date_time_offset_to_string, parse_date_time_offset, MAX_DATE_TIME_TICKS, TICKS_PER_MINUTE};
+
+	const INT32: &'static str = "[System.Int32, mscorlib, Version=4.0.0.0, Culture=neutral, PublicKeyToken=b77a5c561934e089]";
+
+	/// A collection of Int32 as the root, with a backing array of the given elements and Int32 members
+	fn collection(class_name: &str, elements: Vec<i32>, members: Vec<(&str, i32)>) -> Document {
+		let mut member_types = vec![("_array", MemberType::PrimitiveArray(PrimitiveTypeEnumeration::Int32))];
+		for &(name, _) in members.iter() {
+			member_types.push((name, MemberType::Primitive(PrimitiveTypeEnumeration::Int32)));
+		}
+		let mut builder = StreamBuilder::new();
+		let class = builder.define_system_class(&format!("{}[{}]]", class_name, INT32), member_types);
+		let id = builder.new_object(class);
+		let array_id = builder.new_array(MemberType::Primitive(PrimitiveTypeEnumeration::Int32),
+			elements.into_iter().map(|x| Data::Primitive(Primitive::Int32(x))).collect());
+		builder.set_reference(id, "_array", array_id);
+		for (name, x) in members.into_iter() {
+			builder.set_primitive(id, name, Primitive::Int32(x));
+		}
+		read_document(&builder.to_bytes().unwrap()[..]).unwrap()
+	}
+
+	fn items(document: &Document) -> Vec<Data> {
+		match recognize(document, document.get_root().and_then(Data::as_object).unwrap()) {
+			Some(Known::Sequence(sequence)) => sequence.items.into_iter().cloned().collect(),
+			known => panic!("Expect a sequence but got {:?}", known),
+		}
+	}
+
+	fn int32s(xs: Vec<i32>) -> Vec<Data> {
+		xs.into_iter().map(|x| Data::Primitive(Primitive::Int32(x))).collect()
+	}
+
+	#[test]
+	fn queue_going_round_the_array() {
+		let document = collection("System.Collections.Generic.Queue`1[", vec![7, 0, 5, 6],
+			vec![("_head", 2), ("_tail", 1), ("_size", 3), ("_version", 4)]);
+		assert_eq!(items(&document), int32s(vec![5, 6, 7]));
+		let full = collection("System.Collections.Generic.Queue`1[", vec![3, 1, 2], vec![("_head", 1), ("_tail", 1), ("_size", 3), ("_version", 0)]);
+		assert_eq!(items(&full), int32s(vec![1, 2, 3]));
+		let empty = collection("System.Collections.Generic.Queue`1[", vec![], vec![("_head", 0), ("_tail", 0), ("_size", 0), ("_version", 0)]);
+		assert_eq!(items(&empty), vec![]);
+	}
+
+	#[test]
+	fn broken_queue() {
+		let document = collection("System.Collections.Generic.Queue`1[", vec![1, 2], vec![("_head", 2), ("_tail", 0), ("_size", 1), ("_version", 0)]);
+		assert!(recognize(&document, document.get_root().and_then(Data::as_object).unwrap()).is_none());
+		let document = collection("System.Collections.Generic.Queue`1[", vec![1, 2], vec![("_head", 0), ("_tail", 0), ("_size", 3), ("_version", 0)]);
+		assert!(recognize(&document, document.get_root().and_then(Data::as_object).unwrap()).is_none());
+	}
+
+	#[test]
+	fn stack_from_the_bottom() {
+		// Pushed 1, 2 then 3, the spare capacity holding an item popped before
+		let document = collection("System.Collections.Generic.Stack`1[", vec![1, 2, 3, 9], vec![("_size", 3), ("_version", 5)]);
+		assert_eq!(items(&document), int32s(vec![1, 2, 3]));
+	}
+
+	#[test]
+	fn date_time_offset_round_trip() {
+		let values = [(0, 0), (0, 60), (5, 14 * 60), (636000000000000000, -300), (MAX_DATE_TIME_TICKS, 0), (MAX_DATE_TIME_TICKS, -90)];
+		for &(ticks, offset_minutes) in values.iter() {
+			let utc = DateTime { Ticks: ticks, Kind: 0 };
+			let s = date_time_offset_to_string(&utc, offset_minutes).unwrap();
+			assert_eq!(parse_date_time_offset(&s), Some((utc, offset_minutes)), "{}", s);
+		}
+	}
+
+	#[test]
+	fn date_time_offset_out_of_range() {
+		assert_eq!(date_time_offset_to_string(&DateTime { Ticks: 5, Kind: 0 }, -60), None);
+		assert_eq!(date_time_offset_to_string(&DateTime { Ticks: MAX_DATE_TIME_TICKS - TICKS_PER_MINUTE, Kind: 0 }, 2), None);
+		assert_eq!(date_time_offset_to_string(&DateTime { Ticks: 636000000000000000, Kind: 0 }, 6000), None);
+	}
+}
@@ -0,0 +1,48 @@
+//! Reading and writing streams of the .NET BinaryFormatter (MS-NRBF), as used by the saves of Unity games.
+//!
+//! `document::read_document` parses a stream into a `Document` holding the records, and the classes
+//! and values of the stream as the plain data of the `model` module, looked up by id. The `lossless`,
+//! `editable` and `graph` modules convert to and from json in the formats written by the command line tool.
+//! `borrowed::read_slice` reads records borrowing their strings and primitive arrays from a byte slice,
+//! and `events::read_events` gives the parts of a stream to a visitor as they are read, without keeping them.
+//! `from_reader` fills Rust types deriving `Deserialize` from the root object of a stream, and
+//! `to_writer` writes Rust types deriving `Serialize` as a stream. `builder::StreamBuilder` builds a
+//! stream by hand from classes, objects, arrays and strings. `converters::Converters` registers how the objects
+//! of given classes are written to json by the `editable` and `graph` modules, and read back.
+
+#![feature(box_syntax, custom_derive, plugin)]
+#![plugin(serde_macros)]
+
+#![allow(non_snake_case, dead_code)]
+
+extern crate num;
+extern crate serde;
+extern crate byteorder;
+extern crate serde_json;
+extern crate itertools;
+
+pub mod error;
+pub mod util;
+pub mod sub_records;
+pub mod records;
+pub mod json;
+pub mod lossless;
+pub mod graph;
+pub mod model;
+pub mod schema;
+pub mod document;
+pub mod known_types;
+pub mod converters;
+pub mod editable;
+pub mod borrowed;
+pub mod events;
+pub mod de;
+pub mod ser;
+pub mod builder;
+
+pub use error::DeserializeError;
+pub use schema::Schema;
+pub use document::{Document, read_document};
+pub use model::{Data, Primitive, Object, Array, Class, MemberType};
+pub use de::{from_reader, from_document};
+pub use ser::{to_writer, to_vec};
@@ -0,0 +1,1061 @@
+//! Lossless representation of a stream.
+//!
+//! Every record is kept in stream order together with its ids, member type infos and
+//! the values of class records as they were encoded (inlined strings, references and nulls),
+//! so that writing the records back reproduces the original bytes.
+//! Records written inline as member values or array elements are kept as values of the record containing them.
+
+use std::io::prelude::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use num::ToPrimitive;
+use serde_json::{Value as JValue, Map};
+use itertools::Zip;
+
+use records::*;
+use json::*;
+
+#[derive(Debug)]
+pub enum LosslessRecord {
+	SerializedStreamHeader(SerializationHeaderRecord),
+	ClassWithId(ClassWithIdRecord, Vec<Value>),
+	SystemClassWithMembers(Rc<SystemClassWithMembersRecord>, Vec<Value>),
+	ClassWithMembers(Rc<ClassWithMembersRecord>, Vec<Value>),
+	SystemClassWithMembersAndTypes(Rc<SystemClassWithMembersAndTypesRecord>, Vec<Value>),
+	ClassWithMembersAndTypes(Rc<ClassWithMembersAndTypesRecord>, Vec<Value>),
+	BinaryObjectString(BinaryObjectStringRecord),
+	BinaryArray(BinaryArrayRecord),
+	MemberPrimitiveTyped(MemberPrimitiveTypedRecord),
+	MemberReference(MemberReferenceRecord),
+	ObjectNull(ObjectNullRecord),
+	MessageEnd(MessageEndRecord),
+	BinaryLibrary(BinaryLibraryRecord),
+	ObjectNullMultiple256(ObjectNullMultiple256Record),
+	ObjectNullMultiple(ObjectNullMultipleRecord),
+	ArraySinglePrimitive(ArraySinglePrimitiveRecord),
+	ArraySingleObject(ArraySingleObjectRecord),
+	ArraySingleString(ArraySingleStringRecord),
+	/// A BinaryLibrary record written right before a member value or an array element needing it
+	LibraryWithValue(BinaryLibraryRecord, Box<Value>),
+}
+
+/// Reads one record together with everything belonging to it,
+/// i.e. the member values of class records and the elements of arrays, which may be records themselves.
+pub fn read_record(file: &mut OffsetRead, context: &mut ReadContext) -> Result<LosslessRecord, DeserializeError> {
+	if context.depth >= MAX_DEPTH {
+		return Err(DeserializeError::LimitExceeded(error_at(file, 0), "record depth", context.depth as i64));
+	}
+	let record_type: RecordTypeEnumeration = try!(read_enum(file));
+	context.depth += 1;
+	let record = read_record_of_type(file, context, record_type);
+	context.depth -= 1;
+	record.map_err(|e| e.within(format!("{:?}", record_type)))
+}
+
+fn read_record_of_type(file: &mut OffsetRead, context: &mut ReadContext, record_type: RecordTypeEnumeration) -> Result<LosslessRecord, DeserializeError> {
+	use records::RecordTypeEnumeration::*;
+
+	Ok(match record_type {
+		SerializedStreamHeader => {
+			LosslessRecord::SerializedStreamHeader(try!(SerializationHeaderRecord::new(file)))
+		},
+		ClassWithId => {
+			let class_with_id = try!(ClassWithIdRecord::new(file));
+			let class = match context.id_to_class.get(&class_with_id.MetadataId) {
+				Some(class) => class.clone(),
+				None => return Err(DeserializeError::DanglingReference(error_at(file, 4), class_with_id.MetadataId)),
+			};
+			let values = try!(class.read_value(file, context));
+			LosslessRecord::ClassWithId(class_with_id, values)
+		},
+		SystemClassWithMembers => {
+			let mut class = try!(SystemClassWithMembersRecord::new(file));
+			class.MemberTypeInfo = try!(context.schema.get_member_type_info(&class.ClassInfo)
+				.map_err(|message| DeserializeError::MissingMemberTypes(error_at(file, 0), message)));
+			let class = Rc::new(class);
+			context.id_to_class.insert(class.ClassInfo.ObjectId, class.clone());
+			let values = try!(class.read_value(file, context));
+			LosslessRecord::SystemClassWithMembers(class, values)
+		},
+		ClassWithMembers => {
+			let mut class = try!(ClassWithMembersRecord::new(file));
+			class.MemberTypeInfo = try!(context.schema.get_member_type_info(&class.ClassInfo)
+				.map_err(|message| DeserializeError::MissingMemberTypes(error_at(file, 0), message)));
+			let class = Rc::new(class);
+			context.id_to_class.insert(class.ClassInfo.ObjectId, class.clone());
+			let values = try!(class.read_value(file, context));
+			LosslessRecord::ClassWithMembers(class, values)
+		},
+		SystemClassWithMembersAndTypes => {
+			// Registered before the values, which may be objects of the same class
+			let class = Rc::new(try!(SystemClassWithMembersAndTypesRecord::new(file)));
+			context.id_to_class.insert(class.ClassInfo.ObjectId, class.clone());
+			context.schema.add_class(&*class);
+			let values = try!(class.read_value(file, context));
+			LosslessRecord::SystemClassWithMembersAndTypes(class, values)
+		},
+		ClassWithMembersAndTypes => {
+			let class = Rc::new(try!(ClassWithMembersAndTypesRecord::new(file)));
+			context.id_to_class.insert(class.ClassInfo.ObjectId, class.clone());
+			context.schema.add_class(&*class);
+			let values = try!(class.read_value(file, context));
+			LosslessRecord::ClassWithMembersAndTypes(class, values)
+		},
+		RecordTypeEnumeration::BinaryObjectString => {
+			LosslessRecord::BinaryObjectString(try!(BinaryObjectStringRecord::new(file)))
+		},
+		BinaryArray => LosslessRecord::BinaryArray(try!(BinaryArrayRecord::read(file, context))),
+		MemberPrimitiveTyped => LosslessRecord::MemberPrimitiveTyped(try!(MemberPrimitiveTypedRecord::new(file))),
+		MemberReference => LosslessRecord::MemberReference(try!(MemberReferenceRecord::new(file))),
+		ObjectNull => LosslessRecord::ObjectNull(try!(ObjectNullRecord::new(file))),
+		MessageEnd => LosslessRecord::MessageEnd(try!(MessageEndRecord::new(file))),
+		BinaryLibrary => LosslessRecord::BinaryLibrary(try!(BinaryLibraryRecord::new(file))),
+		ObjectNullMultiple256 => LosslessRecord::ObjectNullMultiple256(try!(ObjectNullMultiple256Record::new(file))),
+		ObjectNullMultiple => LosslessRecord::ObjectNullMultiple(try!(ObjectNullMultipleRecord::new(file))),
+		ArraySinglePrimitive => LosslessRecord::ArraySinglePrimitive(try!(ArraySinglePrimitiveRecord::new(file))),
+		ArraySingleObject => LosslessRecord::ArraySingleObject(try!(ArraySingleObjectRecord::read(file, context))),
+		ArraySingleString => LosslessRecord::ArraySingleString(try!(ArraySingleStringRecord::read(file, context))),
+		MethodCall | MethodReturn | Unknown => {
+			return Err(DeserializeError::UnsupportedRecordType(error_at(file, 1), record_type as u8));
+		},
+	})
+}
+
+/// Reads the record of a member value or an array element.
+/// Strings, references and nulls are returned as their own values, the other records as Value::Record.
+pub fn read_value_record(file: &mut OffsetRead, context: &mut ReadContext) -> Result<Value, DeserializeError> {
+	use self::LosslessRecord::*;
+
+	Ok(match try!(read_record(file, context)) {
+		BinaryObjectString(r) => Value::BinaryObjectString(r),
+		MemberPrimitiveTyped(r) => Value::MemberPrimitiveTyped(r),
+		MemberReference(r) => Value::MemberReference(r),
+		ObjectNull(r) => Value::ObjectNull(r),
+		ObjectNullMultiple256(r) => Value::ObjectNullMultiple256(r),
+		ObjectNullMultiple(r) => Value::ObjectNullMultiple(r),
+		// The value is nested in the library, so libraries count towards the depth
+		BinaryLibrary(r) => {
+			context.depth += 1;
+			let value = read_value_record(file, context);
+			context.depth -= 1;
+			let value = try!(value.map_err(|e| e.within("BinaryLibrary".to_string())));
+			Value::Record(box(LibraryWithValue(r, box(value))))
+		},
+		// The offsets are of the record type bytes, the header being 17 bytes long
+		SerializedStreamHeader(_) => return Err(DeserializeError::UnsupportedRecordType(error_at(file, 17), 0)),
+		MessageEnd(_) => return Err(DeserializeError::UnsupportedRecordType(error_at(file, 1), 11)),
+		r @ _ => Value::Record(box(r)),
+	})
+}
+
+/// Reads records until MessageEnd.
+/// Classes are registered in the context on the way, for ClassWithId records to use.
+pub fn read_records(file: &mut OffsetRead, context: &mut ReadContext) -> Result<Vec<LosslessRecord>, DeserializeError> {
+	let mut records = vec![];
+	loop {
+		let record = try!(read_record(file, context).map_err(|e| e.within(format!("record {}", records.len()))));
+		let is_end = match record {
+			LosslessRecord::MessageEnd(_) => true,
+			_ => false,
+		};
+		records.push(record);
+		if is_end {
+			break;
+		}
+	}
+	Ok(records)
+}
+
+impl LosslessRecord {
+	fn as_record(&self) -> &Record {
+		use self::LosslessRecord::*;
+		match self {
+			&SerializedStreamHeader(ref r) => r,
+			&ClassWithId(ref r, _) => r,
+			&SystemClassWithMembers(ref r, _) => &**r,
+			&ClassWithMembers(ref r, _) => &**r,
+			&SystemClassWithMembersAndTypes(ref r, _) => &**r,
+			&ClassWithMembersAndTypes(ref r, _) => &**r,
+			&BinaryObjectString(ref r) => r,
+			&BinaryArray(ref r) => r,
+			&MemberPrimitiveTyped(ref r) => r,
+			&MemberReference(ref r) => r,
+			&ObjectNull(ref r) => r,
+			&MessageEnd(ref r) => r,
+			&BinaryLibrary(ref r) => r,
+			&ObjectNullMultiple256(ref r) => r,
+			&ObjectNullMultiple(ref r) => r,
+			&ArraySinglePrimitive(ref r) => r,
+			&ArraySingleObject(ref r) => r,
+			&ArraySingleString(ref r) => r,
+			&LibraryWithValue(ref r, _) => r,
+		}
+	}
+
+	/// The class record and values of records carrying member values
+	pub fn as_class<'a>(&'a self, classes: &HashMap<i32, &'a ClassRecordForClassWithId>) -> Option<(&'a ClassRecordForClassWithId, &'a Vec<Value>)> {
+		use self::LosslessRecord::*;
+		match self {
+			&ClassWithId(ref r, ref values) => Some((*classes.get(&r.MetadataId).unwrap(), values)),
+			&SystemClassWithMembers(ref r, ref values) => Some((&**r, values)),
+			&ClassWithMembers(ref r, ref values) => Some((&**r, values)),
+			&SystemClassWithMembersAndTypes(ref r, ref values) => Some((&**r, values)),
+			&ClassWithMembersAndTypes(ref r, ref values) => Some((&**r, values)),
+			_ => None,
+		}
+	}
+
+	pub fn register_class<'a>(&'a self, classes: &mut HashMap<i32, &'a ClassRecordForClassWithId>) {
+		use self::LosslessRecord::*;
+		match self {
+			&SystemClassWithMembers(ref r, _) => { classes.insert(r.ClassInfo.ObjectId, &**r); },
+			&ClassWithMembers(ref r, _) => { classes.insert(r.ClassInfo.ObjectId, &**r); },
+			&SystemClassWithMembersAndTypes(ref r, _) => { classes.insert(r.ClassInfo.ObjectId, &**r); },
+			&ClassWithMembersAndTypes(ref r, _) => { classes.insert(r.ClassInfo.ObjectId, &**r); },
+			_ => {},
+		}
+	}
+
+	/// Id of objects, strings and arrays
+	pub fn get_object_id(&self) -> Option<i32> {
+		use self::LosslessRecord::*;
+		match self {
+			&ClassWithId(ref r, _) => Some(r.ObjectId),
+			&SystemClassWithMembers(ref r, _) => Some(r.ClassInfo.ObjectId),
+			&ClassWithMembers(ref r, _) => Some(r.ClassInfo.ObjectId),
+			&SystemClassWithMembersAndTypes(ref r, _) => Some(r.ClassInfo.ObjectId),
+			&ClassWithMembersAndTypes(ref r, _) => Some(r.ClassInfo.ObjectId),
+			&BinaryObjectString(ref r) => Some(r.ObjectId),
+			&BinaryArray(ref r) => Some(r.ObjectId),
+			&ArraySinglePrimitive(ref r) => Some(r.ArrayInfo.ObjectId),
+			&ArraySingleObject(ref r) => Some(r.ArrayInfo.ObjectId),
+			&ArraySingleString(ref r) => Some(r.ArrayInfo.ObjectId),
+			_ => None,
+		}
+	}
+
+	/// Member values of class records and elements of arrays holding records
+	pub fn get_values(&self) -> Option<&Vec<Value>> {
+		use self::LosslessRecord::*;
+		match self {
+			&ClassWithId(_, ref values) => Some(values),
+			&SystemClassWithMembers(_, ref values) => Some(values),
+			&ClassWithMembers(_, ref values) => Some(values),
+			&SystemClassWithMembersAndTypes(_, ref values) => Some(values),
+			&ClassWithMembersAndTypes(_, ref values) => Some(values),
+			&BinaryArray(ref r) => match r.Values {
+				ArrayValues::Records(ref values) => Some(values),
+				ArrayValues::Primitive(_) => None,
+			},
+			&ArraySingleObject(ref r) => Some(&r.Values),
+			&ArraySingleString(ref r) => Some(&r.Values),
+			_ => None,
+		}
+	}
+
+	/// Number of array elements the record stands for
+	pub fn get_element_count(&self) -> usize {
+		match self {
+			&LosslessRecord::ObjectNullMultiple256(ref r) => r.NullCount as usize,
+			&LosslessRecord::ObjectNullMultiple(ref r) => r.NullCount as usize,
+			&LosslessRecord::LibraryWithValue(_, ref value) => value.get_element_count(),
+			_ => 1,
+		}
+	}
+
+	/// LibraryWithValue is a BinaryLibrary record followed by the value
+	pub fn get_record_type(&self) -> RecordTypeEnumeration {
+		use self::LosslessRecord::*;
+		match self {
+			&SerializedStreamHeader(_) => RecordTypeEnumeration::SerializedStreamHeader,
+			&ClassWithId(_, _) => RecordTypeEnumeration::ClassWithId,
+			&SystemClassWithMembers(_, _) => RecordTypeEnumeration::SystemClassWithMembers,
+			&ClassWithMembers(_, _) => RecordTypeEnumeration::ClassWithMembers,
+			&SystemClassWithMembersAndTypes(_, _) => RecordTypeEnumeration::SystemClassWithMembersAndTypes,
+			&ClassWithMembersAndTypes(_, _) => RecordTypeEnumeration::ClassWithMembersAndTypes,
+			&BinaryObjectString(_) => RecordTypeEnumeration::BinaryObjectString,
+			&BinaryArray(_) => RecordTypeEnumeration::BinaryArray,
+			&MemberPrimitiveTyped(_) => RecordTypeEnumeration::MemberPrimitiveTyped,
+			&MemberReference(_) => RecordTypeEnumeration::MemberReference,
+			&ObjectNull(_) => RecordTypeEnumeration::ObjectNull,
+			&MessageEnd(_) => RecordTypeEnumeration::MessageEnd,
+			&BinaryLibrary(_) | &LibraryWithValue(_, _) => RecordTypeEnumeration::BinaryLibrary,
+			&ObjectNullMultiple256(_) => RecordTypeEnumeration::ObjectNullMultiple256,
+			&ObjectNullMultiple(_) => RecordTypeEnumeration::ObjectNullMultiple,
+			&ArraySinglePrimitive(_) => RecordTypeEnumeration::ArraySinglePrimitive,
+			&ArraySingleObject(_) => RecordTypeEnumeration::ArraySingleObject,
+			&ArraySingleString(_) => RecordTypeEnumeration::ArraySingleString,
+		}
+	}
+}
+
+/// The records in stream order, including those written inline as member values or array elements
+pub fn nested_records(records: &Vec<LosslessRecord>) -> Vec<&LosslessRecord> {
+	let mut nested = vec![];
+	for record in records.iter() {
+		push_nested_record(record, &mut nested);
+	}
+	nested
+}
+
+fn push_nested_record<'a>(record: &'a LosslessRecord, nested: &mut Vec<&'a LosslessRecord>) {
+	nested.push(record);
+	if let &LosslessRecord::LibraryWithValue(_, ref value) = record {
+		push_nested_value(value, nested);
+	}
+	if let Some(values) = record.get_values() {
+		for value in values.iter() {
+			push_nested_value(value, nested);
+		}
+	}
+}
+
+fn push_nested_value<'a>(value: &'a Value, nested: &mut Vec<&'a LosslessRecord>) {
+	if let &Value::Record(ref record) = value {
+		push_nested_record(record, nested);
+	}
+}
+
+/// Writes the record and everything belonging to it
+pub fn write_record(file: &mut Write, record: &LosslessRecord) {
+	record.as_record().write(file);
+	match record {
+		// Member values are written by their own type so that the class record is not needed
+		&LosslessRecord::ClassWithId(_, ref values) |
+		&LosslessRecord::SystemClassWithMembers(_, ref values) |
+		&LosslessRecord::ClassWithMembers(_, ref values) |
+		&LosslessRecord::SystemClassWithMembersAndTypes(_, ref values) |
+		&LosslessRecord::ClassWithMembersAndTypes(_, ref values) => {
+			for value in values.iter() {
+				value.write(file);
+			}
+		},
+		&LosslessRecord::LibraryWithValue(_, ref value) => value.write(file),
+		_ => {},
+	}
+}
+
+pub fn write_records(file: &mut Write, records: &Vec<LosslessRecord>) {
+	for record in records.iter() {
+		write_record(file, record);
+	}
+}
+
+fn i32_to_json(x: i32) -> JValue {
+	JValue::I64(x as i64)
+}
+
+fn record_to_json(record_type: &str, fields: Vec<(&str, JValue)>) -> JValue {
+	let mut m = Map::new();
+	m.insert("RecordType".to_string(), JValue::String(record_type.to_string()));
+	for (key, value) in fields.into_iter() {
+		m.insert(key.to_string(), value);
+	}
+	JValue::Object(m)
+}
+
+fn string_to_json(r: &BinaryObjectStringRecord) -> JValue {
+	record_to_json("BinaryObjectString", vec![("ObjectId", i32_to_json(r.ObjectId)), ("Value", JValue::String(r.Value.clone()))])
+}
+
+fn member_primitive_typed_to_json(r: &MemberPrimitiveTypedRecord) -> JValue {
+	record_to_json("MemberPrimitiveTyped", vec![
+		("PrimitiveType", JValue::String(format!("{:?}", r.PrimitiveTypeEnum))),
+		("Value", primitive_to_json(&r.Value)),
+	])
+}
+
+fn member_reference_to_json(r: &MemberReferenceRecord) -> JValue {
+	record_to_json("MemberReference", vec![("IdRef", i32_to_json(r.IdRef))])
+}
+
+fn object_null_multiple_256_to_json(r: &ObjectNullMultiple256Record) -> JValue {
+	record_to_json("ObjectNullMultiple256", vec![("NullCount", JValue::U64(r.NullCount as u64))])
+}
+
+fn object_null_multiple_to_json(r: &ObjectNullMultipleRecord) -> JValue {
+	record_to_json("ObjectNullMultiple", vec![("NullCount", i32_to_json(r.NullCount))])
+}
+
+/// Records found in member values and array elements
+fn value_record_to_json(value: &Value) -> JValue {
+	match value {
+		&Value::BinaryObjectString(ref r) => string_to_json(r),
+		&Value::MemberPrimitiveTyped(ref r) => member_primitive_typed_to_json(r),
+		&Value::MemberReference(ref r) => member_reference_to_json(r),
+		&Value::ObjectNull(_) => record_to_json("ObjectNull", vec![]),
+		&Value::ObjectNullMultiple256(ref r) => object_null_multiple_256_to_json(r),
+		&Value::ObjectNullMultiple(ref r) => object_null_multiple_to_json(r),
+		&Value::Record(ref record) => lossless_record_to_json(record),
+		&Value::Primitive(ref primitive) => panic!("Unprocessed value record: {:?}", primitive),
+	}
+}
+
+fn value_record_from_json(json: &JValue, classes: &mut HashMap<i32, Rc<ClassRecordForClassWithId>>) -> Result<Value, String> {
+	use self::LosslessRecord::*;
+
+	Ok(match try!(lossless_record_from_json(json, classes)) {
+		BinaryObjectString(r) => Value::BinaryObjectString(r),
+		MemberPrimitiveTyped(r) => Value::MemberPrimitiveTyped(r),
+		MemberReference(r) => Value::MemberReference(r),
+		ObjectNull(r) => Value::ObjectNull(r),
+		ObjectNullMultiple256(r) => Value::ObjectNullMultiple256(r),
+		ObjectNullMultiple(r) => Value::ObjectNullMultiple(r),
+		r @ SerializedStreamHeader(_) | r @ MessageEnd(_) | r @ BinaryLibrary(_) => return Err(format!("Unexpected {:?} in a value", r)),
+		r @ _ => Value::Record(box(r)),
+	})
+}
+
+fn value_records_to_json(values: &Vec<Value>) -> JValue {
+	JValue::Array(values.iter().map(value_record_to_json).collect())
+}
+
+fn value_records_from_json(json: &Vec<JValue>, classes: &mut HashMap<i32, Rc<ClassRecordForClassWithId>>) -> Result<Vec<Value>, String> {
+	let mut values = vec![];
+	for (i, value) in json.iter().enumerate() {
+		values.push(try!(value_record_from_json(value, classes).map_err(|e| format!("Element {}: {}", i, e))));
+	}
+	Ok(values)
+}
+
+/// Member values are converted by their own type, as for writing
+fn values_to_json(values: &Vec<Value>) -> JValue {
+	JValue::Array(values.iter()
+		.map(|value| {
+			match value {
+				&Value::Primitive(ref primitive) => primitive_to_json(primitive),
+				_ => value_record_to_json(value),
+			}
+		})
+		.collect())
+}
+
+fn values_from_json(class: &ClassRecordForClassWithId, json: &Vec<JValue>,
+	classes: &mut HashMap<i32, Rc<ClassRecordForClassWithId>>) -> Result<Vec<Value>, String> {
+	let (class_name, member_names, binary_types, additional_infos) = class.get_info_for_json();
+	if json.len() != member_names.len() {
+		return Err(format!("{} has {} members but {} values", class_name, member_names.len(), json.len()));
+	}
+	let mut values = vec![];
+	for (name, binary_type, additional_info, value) in Zip::new((member_names, binary_types, additional_infos, json)) {
+		let value = match binary_type {
+			&BinaryTypeEnumeration::Primitive => {
+				let primitive_type = additional_info.get_primitive_type().unwrap();
+				primitive_from_json(primitive_type, value).map(Value::Primitive)
+			},
+			_ => value_record_from_json(value, classes),
+		};
+		values.push(try!(value.map_err(|e| format!("Member {} of {}: {}", name, class_name, e))));
+	}
+	Ok(values)
+}
+
+fn class_to_json(class_info: &ClassInfoRecord, member_type_info: &MemberTypeInfoRecord) -> Vec<(&'static str, JValue)> {
+	vec![
+		("ObjectId", i32_to_json(class_info.ObjectId)),
+		("Name", JValue::String(class_info.Name.clone())),
+		("Members", JValue::Array(Zip::new((&class_info.MemberNames, &member_type_info.BinaryTypeEnums, &member_type_info.AdditionalInfos))
+			.map(|(name, binary_type, additional_info)| member_type_to_json(name, binary_type, additional_info))
+			.collect())),
+	]
+}
+
+fn class_from_json(json: &JValue) -> Result<(ClassInfoRecord, MemberTypeInfoRecord), String> {
+	let mut member_names = vec![];
+	let mut binary_types = vec![];
+	let mut additional_infos = vec![];
+	for member_type in try!(get_array(json, "Members")).iter() {
+		let (name, binary_type, additional_info) = try!(member_type_from_json(member_type));
+		member_names.push(name);
+		binary_types.push(binary_type);
+		additional_infos.push(additional_info);
+	}
+	Ok((ClassInfoRecord {
+		ObjectId: try!(get_i32(json, "ObjectId")),
+		Name: try!(get_str(json, "Name")).to_string(),
+		MemberCount: member_names.len() as i32,
+		MemberNames: member_names,
+	}, MemberTypeInfoRecord {
+		BinaryTypeEnums: binary_types,
+		AdditionalInfos: additional_infos,
+	}))
+}
+
+fn i32_vec_to_json(v: &Vec<i32>) -> JValue {
+	JValue::Array(v.iter().map(|x| i32_to_json(*x)).collect())
+}
+
+fn i32_vec_from_json(json: &JValue) -> Result<Vec<i32>, String> {
+	let mut v = vec![];
+	for x in try!(json.as_array().ok_or_else(|| format!("Expect an array of integers but got {:?}", json))).iter() {
+		v.push(try!(x.as_i64().and_then(|x| x.to_i32()).ok_or_else(|| format!("Expect a 32-bit integer but got {:?}", x))));
+	}
+	Ok(v)
+}
+
+fn lossless_record_to_json(record: &LosslessRecord) -> JValue {
+	use self::LosslessRecord::*;
+
+	match record {
+		&SerializedStreamHeader(ref r) => record_to_json("SerializedStreamHeader", vec![
+			("RootId", i32_to_json(r.RootId)),
+			("HeaderId", i32_to_json(r.HeaderId)),
+			("MajorVersion", i32_to_json(r.MajorVersion)),
+			("MinorVersion", i32_to_json(r.MinorVersion)),
+		]),
+		&ClassWithId(ref r, ref values) => record_to_json("ClassWithId", vec![
+			("ObjectId", i32_to_json(r.ObjectId)),
+			("MetadataId", i32_to_json(r.MetadataId)),
+			("Values", values_to_json(values)),
+		]),
+		// The member types of typeless records are kept as well, to read their values back
+		&SystemClassWithMembers(ref r, ref values) => {
+			let mut fields = class_to_json(&r.ClassInfo, &r.MemberTypeInfo);
+			fields.push(("Values", values_to_json(values)));
+			record_to_json("SystemClassWithMembers", fields)
+		},
+		&ClassWithMembers(ref r, ref values) => {
+			let mut fields = class_to_json(&r.ClassInfo, &r.MemberTypeInfo);
+			fields.push(("LibraryId", i32_to_json(r.LibraryId)));
+			fields.push(("Values", values_to_json(values)));
+			record_to_json("ClassWithMembers", fields)
+		},
+		&SystemClassWithMembersAndTypes(ref r, ref values) => {
+			let mut fields = class_to_json(&r.ClassInfo, &r.MemberTypeInfo);
+			fields.push(("Values", values_to_json(values)));
+			record_to_json("SystemClassWithMembersAndTypes", fields)
+		},
+		&ClassWithMembersAndTypes(ref r, ref values) => {
+			let mut fields = class_to_json(&r.ClassInfo, &r.MemberTypeInfo);
+			fields.push(("LibraryId", i32_to_json(r.LibraryId)));
+			fields.push(("Values", values_to_json(values)));
+			record_to_json("ClassWithMembersAndTypes", fields)
+		},
+		&BinaryObjectString(ref r) => string_to_json(r),
+		&BinaryArray(ref r) => record_to_json("BinaryArray", vec![
+			("ObjectId", i32_to_json(r.ObjectId)),
+			("BinaryArrayType", JValue::String(format!("{:?}", r.BinaryArrayTypeEnum))),
+			("Lengths", i32_vec_to_json(&r.Lengths)),
+			("LowerBounds", match r.LowerBounds {
+				Some(ref lower_bounds) => i32_vec_to_json(lower_bounds),
+				None => JValue::Null,
+			}),
+			("TypeEnum", JValue::String(format!("{:?}", r.TypeEnum))),
+			("AdditionalTypeInfo", additional_info_to_json(&r.AdditionalTypeInfo)),
+			("Values", match r.Values {
+				ArrayValues::Primitive(ref values) => primitive_values_to_json(values),
+				ArrayValues::Records(ref values) => value_records_to_json(values),
+			}),
+		]),
+		&MemberPrimitiveTyped(ref r) => member_primitive_typed_to_json(r),
+		&MemberReference(ref r) => member_reference_to_json(r),
+		&ObjectNull(_) => record_to_json("ObjectNull", vec![]),
+		&MessageEnd(_) => record_to_json("MessageEnd", vec![]),
+		&BinaryLibrary(ref r) => record_to_json("BinaryLibrary", vec![
+			("LibraryId", i32_to_json(r.LibraryId)),
+			("LibraryName", JValue::String(r.LibraryName.clone())),
+		]),
+		&ObjectNullMultiple256(ref r) => object_null_multiple_256_to_json(r),
+		&ObjectNullMultiple(ref r) => object_null_multiple_to_json(r),
+		&ArraySinglePrimitive(ref r) => record_to_json("ArraySinglePrimitive", vec![
+			("ObjectId", i32_to_json(r.ArrayInfo.ObjectId)),
+			("PrimitiveType", JValue::String(format!("{:?}", r.PrimitiveTypeEnum))),
+			("Values", primitive_values_to_json(&r.Values)),
+		]),
+		&ArraySingleObject(ref r) => record_to_json("ArraySingleObject", vec![
+			("ObjectId", i32_to_json(r.ArrayInfo.ObjectId)),
+			("Length", i32_to_json(r.ArrayInfo.Length)),
+			("Values", value_records_to_json(&r.Values)),
+		]),
+		&ArraySingleString(ref r) => record_to_json("ArraySingleString", vec![
+			("ObjectId", i32_to_json(r.ArrayInfo.ObjectId)),
+			("Length", i32_to_json(r.ArrayInfo.Length)),
+			("Values", value_records_to_json(&r.Values)),
+		]),
+		// The value needing the library is kept in the library record
+		&LibraryWithValue(ref r, ref value) => record_to_json("BinaryLibrary", vec![
+			("LibraryId", i32_to_json(r.LibraryId)),
+			("LibraryName", JValue::String(r.LibraryName.clone())),
+			("Value", value_record_to_json(&**value)),
+		]),
+	}
+}
+
+/// Classes are registered in classes as ClassWithId needs the member types to read its values
+fn lossless_record_from_json(record: &JValue, classes: &mut HashMap<i32, Rc<ClassRecordForClassWithId>>) -> Result<LosslessRecord, String> {
+	Ok(match try!(get_str(record, "RecordType")) {
+		"SerializedStreamHeader" => LosslessRecord::SerializedStreamHeader(SerializationHeaderRecord {
+			RootId: try!(get_i32(record, "RootId")),
+			HeaderId: try!(get_i32(record, "HeaderId")),
+			MajorVersion: try!(get_i32(record, "MajorVersion")),
+			MinorVersion: try!(get_i32(record, "MinorVersion")),
+		}),
+		"ClassWithId" => {
+			let class_with_id = ClassWithIdRecord {
+				ObjectId: try!(get_i32(record, "ObjectId")),
+				MetadataId: try!(get_i32(record, "MetadataId")),
+			};
+			let class = match classes.get(&class_with_id.MetadataId) {
+				Some(class) => class.clone(),
+				None => return Err(format!("Class {} is not defined before", class_with_id.MetadataId)),
+			};
+			let values = try!(values_from_json(&*class, try!(get_array(record, "Values")), classes));
+			LosslessRecord::ClassWithId(class_with_id, values)
+		},
+		"SystemClassWithMembers" => {
+			let (class_info, member_type_info) = try!(class_from_json(record));
+			let class = Rc::new(SystemClassWithMembersRecord {
+				ClassInfo: class_info,
+				MemberTypeInfo: member_type_info,
+			});
+			classes.insert(class.ClassInfo.ObjectId, class.clone());
+			let values = try!(values_from_json(&*class, try!(get_array(record, "Values")), classes));
+			LosslessRecord::SystemClassWithMembers(class, values)
+		},
+		"ClassWithMembers" => {
+			let (class_info, member_type_info) = try!(class_from_json(record));
+			let class = Rc::new(ClassWithMembersRecord {
+				ClassInfo: class_info,
+				MemberTypeInfo: member_type_info,
+				LibraryId: try!(get_i32(record, "LibraryId")),
+			});
+			classes.insert(class.ClassInfo.ObjectId, class.clone());
+			let values = try!(values_from_json(&*class, try!(get_array(record, "Values")), classes));
+			LosslessRecord::ClassWithMembers(class, values)
+		},
+		"SystemClassWithMembersAndTypes" => {
+			let (class_info, member_type_info) = try!(class_from_json(record));
+			let class = Rc::new(SystemClassWithMembersAndTypesRecord {
+				ClassInfo: class_info,
+				MemberTypeInfo: member_type_info,
+			});
+			classes.insert(class.ClassInfo.ObjectId, class.clone());
+			let values = try!(values_from_json(&*class, try!(get_array(record, "Values")), classes));
+			LosslessRecord::SystemClassWithMembersAndTypes(class, values)
+		},
+		"ClassWithMembersAndTypes" => {
+			let (class_info, member_type_info) = try!(class_from_json(record));
+			let class = Rc::new(ClassWithMembersAndTypesRecord {
+				ClassInfo: class_info,
+				MemberTypeInfo: member_type_info,
+				LibraryId: try!(get_i32(record, "LibraryId")),
+			});
+			classes.insert(class.ClassInfo.ObjectId, class.clone());
+			let values = try!(values_from_json(&*class, try!(get_array(record, "Values")), classes));
+			LosslessRecord::ClassWithMembersAndTypes(class, values)
+		},
+		"BinaryObjectString" => LosslessRecord::BinaryObjectString(BinaryObjectStringRecord {
+			ObjectId: try!(get_i32(record, "ObjectId")),
+			Value: try!(get_str(record, "Value")).to_string(),
+		}),
+		"BinaryArray" => {
+			let type_enum: BinaryTypeEnumeration = try!(try!(get_str(record, "TypeEnum")).parse());
+			let lengths = try!(i32_vec_from_json(try!(get_field(record, "Lengths"))));
+			let additional_info = try!(additional_info_from_json(type_enum, try!(get_field(record, "AdditionalTypeInfo"))));
+			let values = match type_enum {
+				BinaryTypeEnumeration::Primitive => {
+					let primitive_type = additional_info.get_primitive_type().unwrap();
+					ArrayValues::Primitive(try!(primitive_values_from_json(primitive_type, try!(get_field(record, "Values")))))
+				},
+				_ => ArrayValues::Records(try!(value_records_from_json(try!(get_array(record, "Values")), classes))),
+			};
+			LosslessRecord::BinaryArray(BinaryArrayRecord {
+				ObjectId: try!(get_i32(record, "ObjectId")),
+				BinaryArrayTypeEnum: try!(try!(get_str(record, "BinaryArrayType")).parse()),
+				Rank: lengths.len() as i32,
+				Lengths: lengths,
+				LowerBounds: match record.find("LowerBounds") {
+					Some(&JValue::Null) | None => None,
+					Some(lower_bounds) => Some(try!(i32_vec_from_json(lower_bounds))),
+				},
+				TypeEnum: type_enum,
+				AdditionalTypeInfo: additional_info,
+				Values: values,
+			})
+		},
+		"MemberPrimitiveTyped" => {
+			let primitive_type: PrimitiveTypeEnumeration = try!(try!(get_str(record, "PrimitiveType")).parse());
+			LosslessRecord::MemberPrimitiveTyped(MemberPrimitiveTypedRecord {
+				PrimitiveTypeEnum: primitive_type,
+				Value: try!(primitive_from_json(&primitive_type, try!(get_field(record, "Value")))),
+			})
+		},
+		"MemberReference" => LosslessRecord::MemberReference(MemberReferenceRecord { IdRef: try!(get_i32(record, "IdRef")) }),
+		"ObjectNull" => LosslessRecord::ObjectNull(ObjectNullRecord {}),
+		"MessageEnd" => LosslessRecord::MessageEnd(MessageEndRecord {}),
+		"BinaryLibrary" => {
+			let library = BinaryLibraryRecord {
+				LibraryId: try!(get_i32(record, "LibraryId")),
+				LibraryName: try!(get_str(record, "LibraryName")).to_string(),
+			};
+			match record.find("Value") {
+				Some(value) => LosslessRecord::LibraryWithValue(library, box(try!(value_record_from_json(value, classes)))),
+				None => LosslessRecord::BinaryLibrary(library),
+			}
+		},
+		"ObjectNullMultiple256" => LosslessRecord::ObjectNullMultiple256(ObjectNullMultiple256Record {
+			NullCount: try!(try!(get_i32(record, "NullCount")).to_u8().ok_or_else(|| format!("NullCount of ObjectNullMultiple256 does not fit a byte in {:?}", record))),
+		}),
+		"ObjectNullMultiple" => LosslessRecord::ObjectNullMultiple(ObjectNullMultipleRecord {
+			NullCount: try!(get_i32(record, "NullCount")),
+		}),
+		"ArraySinglePrimitive" => {
+			let primitive_type: PrimitiveTypeEnumeration = try!(try!(get_str(record, "PrimitiveType")).parse());
+			let values = try!(primitive_values_from_json(&primitive_type, try!(get_field(record, "Values"))));
+			LosslessRecord::ArraySinglePrimitive(ArraySinglePrimitiveRecord {
+				ArrayInfo: ArrayInfoRecord {
+					ObjectId: try!(get_i32(record, "ObjectId")),
+					Length: values.len() as i32,
+				},
+				PrimitiveTypeEnum: primitive_type,
+				Values: values,
+			})
+		},
+		"ArraySingleObject" => LosslessRecord::ArraySingleObject(ArraySingleObjectRecord {
+			ArrayInfo: ArrayInfoRecord {
+				ObjectId: try!(get_i32(record, "ObjectId")),
+				Length: try!(get_i32(record, "Length")),
+			},
+			Values: try!(value_records_from_json(try!(get_array(record, "Values")), classes)),
+		}),
+		"ArraySingleString" => LosslessRecord::ArraySingleString(ArraySingleStringRecord {
+			ArrayInfo: ArrayInfoRecord {
+				ObjectId: try!(get_i32(record, "ObjectId")),
+				Length: try!(get_i32(record, "Length")),
+			},
+			Values: try!(value_records_from_json(try!(get_array(record, "Values")), classes)),
+		}),
+		s @ _ => return Err(format!("Unprocessed RecordType: {:?}", s)),
+	})
+}
+
+pub fn records_to_json(records: &Vec<LosslessRecord>) -> JValue {
+	let mut m = Map::new();
+	m.insert("Records".to_string(), JValue::Array(records.iter().map(lossless_record_to_json).collect()));
+	JValue::Object(m)
+}
+
+/// Errors name the record they are in by its index
+pub fn records_from_json(json: &JValue) -> Result<Vec<LosslessRecord>, String> {
+	let mut classes = HashMap::new();
+	let mut records = vec![];
+	for (i, record) in try!(get_array(json, "Records")).iter().enumerate() {
+		records.push(try!(lossless_record_from_json(record, &mut classes).map_err(|e| format!("Record {}: {}", i, e))));
+	}
+	Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+	use std;
+	use std::fs::File;
+	use std::io::prelude::*;
+	use std::rc::Rc;
+
+	use records::*;
+	use model::Primitive;
+	use json::*;
+	use schema::Schema;
+	use super::*;
+
+	fn class(id: i32, name: &str, library_id: i32, members: Vec<(&str, BinaryTypeEnumeration, AdditionalInfo)>) -> ClassWithMembersAndTypesRecord {
+		let mut class = ClassWithMembersAndTypesRecord {
+			ClassInfo: ClassInfoRecord {
+				ObjectId: id,
+				Name: name.to_string(),
+				MemberCount: members.len() as i32,
+				MemberNames: vec![],
+			},
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![],
+				AdditionalInfos: vec![],
+			},
+			LibraryId: library_id,
+		};
+		for (name, binary_type, additional_info) in members {
+			class.ClassInfo.MemberNames.push(name.to_string());
+			class.MemberTypeInfo.BinaryTypeEnums.push(binary_type);
+			class.MemberTypeInfo.AdditionalInfos.push(additional_info);
+		}
+		class
+	}
+
+	/// A Box holding a Leaf of another library, a second Leaf of the same class and a name, all inline
+	fn nested_stream() -> Vec<LosslessRecord> {
+		let leaf_type = || AdditionalInfo::Class(ClassTypeInfoRecord { TypeName: "Other.Leaf".to_string(), LibraryId: 3 });
+		let leaf = LosslessRecord::ClassWithMembersAndTypes(
+			Rc::new(class(4, "Other.Leaf", 3, vec![("Count", BinaryTypeEnumeration::Primitive, AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32))])),
+			vec![Value::Primitive(Primitive::Int32(7))]);
+		let other_leaf = LosslessRecord::ClassWithId(ClassWithIdRecord { ObjectId: 5, MetadataId: 4 }, vec![Value::Primitive(Primitive::Int32(8))]);
+		vec![
+			LosslessRecord::SerializedStreamHeader(SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }),
+			LosslessRecord::BinaryLibrary(BinaryLibraryRecord { LibraryId: 2, LibraryName: "Game".to_string() }),
+			LosslessRecord::ClassWithMembersAndTypes(
+				Rc::new(class(1, "Game.Box", 2, vec![
+					("Item", BinaryTypeEnumeration::Class, leaf_type()),
+					("Other", BinaryTypeEnumeration::Class, leaf_type()),
+					("Name", BinaryTypeEnumeration::String, AdditionalInfo::None),
+				])),
+				vec![
+					Value::Record(box(LosslessRecord::LibraryWithValue(BinaryLibraryRecord { LibraryId: 3, LibraryName: "Other".to_string() }, box(Value::Record(box(leaf)))))),
+					Value::Record(box(other_leaf)),
+					Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: 6, Value: "box".to_string() }),
+				]),
+			LosslessRecord::MessageEnd(MessageEndRecord {}),
+		]
+	}
+
+	fn read_bytes(bytes: &[u8]) -> Result<Vec<LosslessRecord>, DeserializeError> {
+		read_bytes_with_schema(bytes, &mut Schema::new())
+	}
+
+	fn read_bytes_with_schema(bytes: &[u8], schema: &mut Schema) -> Result<Vec<LosslessRecord>, DeserializeError> {
+		read_records(&mut OffsetReader::new(bytes), &mut ReadContext::new(schema))
+	}
+
+	#[test]
+	fn nested_records_round_trip() {
+		let mut bytes = vec![];
+		write_records(&mut bytes, &nested_stream());
+
+		let records = read_bytes(&bytes).unwrap();
+		assert_eq!(records.len(), 4);
+		let nested: Vec<String> = nested_records(&records).iter().map(|record| format!("{:?}", record).split('(').next().unwrap().to_string()).collect();
+		assert_eq!(nested, vec!["SerializedStreamHeader", "BinaryLibrary", "ClassWithMembersAndTypes", "LibraryWithValue",
+			"ClassWithMembersAndTypes", "ClassWithId", "MessageEnd"]);
+		let mut written = vec![];
+		write_records(&mut written, &records);
+		assert_eq!(written, bytes);
+
+		let mut from_json = vec![];
+		write_records(&mut from_json, &records_from_json(&records_to_json(&records)).unwrap());
+		assert_eq!(from_json, bytes);
+	}
+
+	#[test]
+	fn object_members_round_trip() {
+		let int32 = |x: i32| Value::MemberPrimitiveTyped(MemberPrimitiveTypedRecord { PrimitiveTypeEnum: PrimitiveTypeEnumeration::Int32, Value: Primitive::Int32(x) });
+		let items = LosslessRecord::ArraySingleObject(ArraySingleObjectRecord {
+			ArrayInfo: ArrayInfoRecord { ObjectId: 2, Length: 3 },
+			Values: vec![int32(5), Value::ObjectNullMultiple256(ObjectNullMultiple256Record { NullCount: 2 })],
+		});
+		let records = vec![
+			LosslessRecord::SerializedStreamHeader(SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }),
+			LosslessRecord::BinaryLibrary(BinaryLibraryRecord { LibraryId: 3, LibraryName: "Game".to_string() }),
+			LosslessRecord::ClassWithMembersAndTypes(
+				Rc::new(class(1, "Game.Bag", 3, vec![("Any", BinaryTypeEnumeration::Object, AdditionalInfo::None), ("Items", BinaryTypeEnumeration::ObjectArray, AdditionalInfo::None)])),
+				vec![int32(-1), Value::Record(box(items))]),
+			LosslessRecord::MessageEnd(MessageEndRecord {}),
+		];
+		let mut bytes = vec![];
+		write_records(&mut bytes, &records);
+
+		let read = read_bytes(&bytes).unwrap();
+		let json = records_to_json(&read);
+		let bag = &get_array(&json, "Records").unwrap()[2];
+		let member_types: Vec<&str> = get_array(bag, "Members").unwrap().iter().map(|member| get_str(member, "BinaryType").unwrap()).collect();
+		assert_eq!(member_types, vec!["Object", "ObjectArray"]);
+		let mut from_json = vec![];
+		write_records(&mut from_json, &records_from_json(&json).unwrap());
+		assert_eq!(from_json, bytes);
+	}
+
+	#[test]
+	fn binary_arrays_round_trip() {
+		let int32 = || AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32);
+		let array = |id: i32, layout: BinaryArrayTypeEnumeration, lengths: Vec<i32>, lower_bounds: Option<Vec<i32>>,
+			type_enum: BinaryTypeEnumeration, additional_info: AdditionalInfo, values: ArrayValues| {
+			LosslessRecord::BinaryArray(BinaryArrayRecord {
+				ObjectId: id,
+				BinaryArrayTypeEnum: layout,
+				Rank: lengths.len() as i32,
+				Lengths: lengths,
+				LowerBounds: lower_bounds,
+				TypeEnum: type_enum,
+				AdditionalTypeInfo: additional_info,
+				Values: values,
+			})
+		};
+		let row = LosslessRecord::ArraySinglePrimitive(ArraySinglePrimitiveRecord {
+			ArrayInfo: ArrayInfoRecord { ObjectId: 6, Length: 2 },
+			PrimitiveTypeEnum: PrimitiveTypeEnumeration::Int32,
+			Values: PrimitiveValues::Int32(vec![1, 2]),
+		});
+		let records = vec![
+			LosslessRecord::SerializedStreamHeader(SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }),
+			// int[2,3]
+			array(1, BinaryArrayTypeEnumeration::Rectangular, vec![2, 3], None, BinaryTypeEnumeration::Primitive, int32(),
+				ArrayValues::Primitive(PrimitiveValues::Int32((0..6).collect()))),
+			// string[1..2,-1..0] of nulls and a string
+			array(2, BinaryArrayTypeEnumeration::RectangularOffset, vec![2, 2], Some(vec![1, -1]), BinaryTypeEnumeration::String, AdditionalInfo::None,
+				ArrayValues::Records(vec![
+					Value::ObjectNullMultiple256(ObjectNullMultiple256Record { NullCount: 3 }),
+					Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: 3, Value: "x".to_string() }),
+				])),
+			// int[5..6]
+			array(4, BinaryArrayTypeEnumeration::SingleOffset, vec![2], Some(vec![5]), BinaryTypeEnumeration::Primitive, int32(),
+				ArrayValues::Primitive(PrimitiveValues::Int32(vec![7, 8]))),
+			// int[][] of an inline row and a null
+			array(5, BinaryArrayTypeEnumeration::Jagged, vec![2], None, BinaryTypeEnumeration::PrimitiveArray, int32(),
+				ArrayValues::Records(vec![Value::Record(box(row)), Value::ObjectNull(ObjectNullRecord {})])),
+			LosslessRecord::MessageEnd(MessageEndRecord {}),
+		];
+		let mut bytes = vec![];
+		write_records(&mut bytes, &records);
+
+		let read = read_bytes(&bytes).unwrap();
+		match &read[2] {
+			&LosslessRecord::BinaryArray(ref array) => {
+				assert_eq!(array.Lengths, vec![2, 2]);
+				assert_eq!(array.LowerBounds, Some(vec![1, -1]));
+				match array.Values {
+					ArrayValues::Records(ref values) => assert_eq!(values.len(), 2),
+					_ => panic!("Expected records"),
+				}
+			},
+			_ => panic!("Expected a BinaryArray"),
+		}
+		let mut written = vec![];
+		write_records(&mut written, &read);
+		assert_eq!(written, bytes);
+		let mut from_json = vec![];
+		write_records(&mut from_json, &records_from_json(&records_to_json(&read)).unwrap());
+		assert_eq!(from_json, bytes);
+	}
+
+	fn node_class() -> ClassWithMembersAndTypesRecord {
+		class(1, "Game.Node", 2, vec![
+			("Value", BinaryTypeEnumeration::Primitive, AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int32)),
+			("Name", BinaryTypeEnumeration::String, AdditionalInfo::None),
+		])
+	}
+
+	/// A Node written with its member types, if typed, and one written without
+	fn typeless_stream(typed: bool) -> Vec<u8> {
+		let node = Rc::new(node_class());
+		let same_class = node_class();
+		let typeless = ClassWithMembersRecord {
+			ClassInfo: ClassInfoRecord { ObjectId: 3, ..same_class.ClassInfo },
+			MemberTypeInfo: same_class.MemberTypeInfo,
+			LibraryId: 2,
+		};
+		let mut records = vec![
+			LosslessRecord::SerializedStreamHeader(SerializationHeaderRecord { RootId: 3, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }),
+			LosslessRecord::BinaryLibrary(BinaryLibraryRecord { LibraryId: 2, LibraryName: "Game".to_string() }),
+		];
+		if typed {
+			records.push(LosslessRecord::ClassWithMembersAndTypes(node, vec![Value::Primitive(Primitive::Int32(4)), Value::ObjectNull(ObjectNullRecord {})]));
+		}
+		records.push(LosslessRecord::ClassWithMembers(Rc::new(typeless),
+			vec![Value::Primitive(Primitive::Int32(5)), Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: 4, Value: "five".to_string() })]));
+		records.push(LosslessRecord::MessageEnd(MessageEndRecord {}));
+		let mut bytes = vec![];
+		write_records(&mut bytes, &records);
+		bytes
+	}
+
+	#[test]
+	fn typeless_class_after_typed() {
+		let bytes = typeless_stream(true);
+		let read = read_bytes(&bytes).unwrap();
+		let mut written = vec![];
+		write_records(&mut written, &read);
+		assert_eq!(written, bytes);
+		let mut from_json = vec![];
+		write_records(&mut from_json, &records_from_json(&records_to_json(&read)).unwrap());
+		assert_eq!(from_json, bytes);
+	}
+
+	#[test]
+	fn typeless_class_from_schema() {
+		let mut learned = Schema::new();
+		learned.add_class(&node_class());
+		let path = std::env::temp_dir().join("csharp_deserialize_schema.json");
+		::serde_json::to_writer(&mut File::create(&path).unwrap(), &learned.to_json()).unwrap();
+		let mut schema = Schema::new();
+		schema.load(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		let bytes = typeless_stream(false);
+		let mut written = vec![];
+		write_records(&mut written, &read_bytes_with_schema(&bytes, &mut schema).unwrap());
+		assert_eq!(written, bytes);
+	}
+
+	#[test]
+	fn broken_schema() {
+		let path = std::env::temp_dir().join("csharp_deserialize_broken_schema.json");
+		File::create(&path).unwrap().write_all(b"{\"Classes\": [{\"Name\": \"Game.Node\", \"Members\": [{\"Name\": \"Next\", \"BinaryType\": \"Pointer\", \"AdditionalInfo\": null}]}]}").unwrap();
+		let loaded = Schema::new().load(&path);
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(loaded, Err("Class Game.Node: Member Next: Invalid BinaryTypeEnumeration \"Pointer\"".to_string()));
+	}
+
+	#[test]
+	fn typeless_class_without_schema() {
+		match read_bytes(&typeless_stream(false)) {
+			Err(DeserializeError::MissingMemberTypes(_, ref message)) if message.starts_with("Member types of Game.Node are unknown") => {},
+			result => panic!("Expected missing member types, got {:?}", result.map(|records| records.len())),
+		}
+	}
+
+	/// An object[] of one element preceded by that many BinaryLibrary records
+	fn libraries_before_value(library_count: i32) -> Vec<u8> {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		bytes.extend_from_slice(&[16, 1, 0, 0, 0, 1, 0, 0, 0]);
+		for i in 0..library_count {
+			BinaryLibraryRecord { LibraryId: i + 2, LibraryName: "Game".to_string() }.write(&mut bytes);
+		}
+		ObjectNullRecord {}.write(&mut bytes);
+		MessageEndRecord {}.write(&mut bytes);
+		bytes
+	}
+
+	#[test]
+	fn libraries_before_a_value() {
+		assert!(read_bytes(&libraries_before_value(2)).is_ok());
+	}
+
+	#[test]
+	fn too_many_libraries_before_a_value() {
+		match read_bytes(&libraries_before_value(100000)) {
+			Err(DeserializeError::LimitExceeded(_, "record depth", _)) => {},
+			result => panic!("Expected the depth to be exceeded, got {:?}", result.map(|records| records.len())),
+		}
+	}
+
+	#[test]
+	fn huge_counts_in_a_short_stream() {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		// A ClassWithMembersAndTypes of 1 << 27 members, and a BinaryArray of 1 << 27 Int32, both ending right away
+		bytes.extend_from_slice(&[5, 1, 0, 0, 0, 1, b'A', 0, 0, 0, 8]);
+		for bytes in vec![bytes.clone(), {
+			let mut array = bytes[..17].to_vec();
+			array.extend_from_slice(&[7, 1, 0, 0, 0, 0, 1, 0, 0, 0, 8, 0, 0, 0, 0]);
+			array
+		}] {
+			match read_bytes(&bytes) {
+				Err(DeserializeError::UnexpectedEof(_)) => {},
+				result => panic!("Expected the end of the stream, got {:?}", result.map(|records| records.len())),
+			}
+		}
+	}
+
+	#[test]
+	fn broken_records_from_json() {
+		let error = |json: &str| records_from_json(&::serde_json::from_str(json).unwrap()).err().unwrap();
+		assert_eq!(error("{}"), "Expect array \"Records\" in {}");
+		assert!(error("{\"Records\": [{\"RecordType\": \"MessageEnd\"}, {\"RecordType\": \"ObjectNullMultiple256\", \"NullCount\": 256}]}")
+			.starts_with("Record 1: NullCount of ObjectNullMultiple256 does not fit a byte"));
+		assert!(error("{\"Records\": [{\"RecordType\": \"ClassWithId\", \"ObjectId\": 2, \"MetadataId\": 1, \"Values\": []}]}")
+			.starts_with("Record 0: Class 1 is not defined before"));
+
+		let json = ::serde_json::to_string(&records_to_json(&nested_stream())).unwrap();
+		assert!(json.contains("\"Values\":[7]"));
+		assert_eq!(error(&json.replace("\"Values\":[7]", "\"Values\":[1099511627776]")),
+			"Record 2: Member Item of Game.Box: Member Count of Other.Leaf: 1099511627776 does not fit Int32");
+	}
+}
@@ -1,240 +1,365 @@
 //! https://stackoverflow.com/questions/3052202/how-to-analyse-contents-of-binary-serialization-stream
 
-#![feature(box_syntax, custom_derive, plugin)]
-#![plugin(serde_macros)]
-
-#![allow(non_snake_case, dead_code)]
-
-extern crate num;
-extern crate byteorder;
 extern crate serde_json;
-extern crate itertools;
-
-mod util;
-mod sub_records;
-mod records;
+extern crate csharp_deserialize;
 
 use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
 use std::path::Path;
-use std::fs::OpenOptions;
-use std::collections::HashMap;
+use std::fs::File;
 
-use byteorder::ReadBytesExt;
-use serde_json::{Value as JValue, Map, to_value, to_writer_pretty};
-use itertools::Zip;
+use serde_json::{Value as JValue, to_writer_pretty, from_reader};
 
-use records::*;
+use csharp_deserialize::{lossless, editable, graph, events};
+use csharp_deserialize::events::Event;
+use csharp_deserialize::lossless::LosslessRecord;
+use csharp_deserialize::records::ReadContext;
+use csharp_deserialize::util::{OffsetRead, OffsetReader};
+use csharp_deserialize::{Document, DeserializeError, Schema};
+use csharp_deserialize::converters::Converters;
 
-fn deserialize() {
-	use records::RecordTypeEnumeration::*;
+fn deserialize(paths: &Vec<String>, schema: &mut Schema, known_types: bool, converters: &Converters) {
+	for path_str in paths.iter() {
+		let path = Path::new(path_str);
+		if path.extension().map_or(true, |extension| extension != "bytes") {
+			continue;
+		}
+		let document = match read_file(path, |file| Document::read(file, schema)) {
+			Ok(document) => document,
+			Err(e) => {
+				println!("{}: {}", path_str, e);
+				continue;
+			},
+		};
 
-	let parse_bool = |value: &Box<Value>| {
-		JValue::Bool(*value.as_any_value().downcast_ref::<bool>().unwrap())
-	};
-	let parse_i32 = |value: &Box<Value>| {
-		JValue::I64(*value.as_any_value().downcast_ref::<i32>().unwrap() as i64)
-	};
-	let parse_f32 = |value: &Box<Value>| {
-		JValue::F64(*value.as_any_value().downcast_ref::<f32>().unwrap() as f64)
-	};
-	let parse_u64 = |value: &Box<Value>| {
-		JValue::U64(*value.as_any_value().downcast_ref::<u64>().unwrap())
-	};
-	
-	let parse_MemberReferenceRecord_or_ObjectNullRecord = |value: &Box<Value>| {
-		match value.as_any_value().downcast_ref::<MemberReferenceRecord>() {
-			Some(mem_ref) => to_value(mem_ref),
-			None => JValue::Null,
+		// Use the dump command for the records themselves
+		let json_path = path.parent().unwrap().join(Path::new(path.file_name().unwrap()).with_extension("json"));
+		if let Err(e) = write_json(&json_path, &editable::document_to_json(&document, known_types, converters)) {
+			println!("{}: {}", path_str, e);
 		}
-	};
+	}
+}
+
+/// The bytes are written only when the whole json file could be converted
+fn serialize_file(path: &Path, converters: &Converters) -> Result<(), String> {
+	let json_file = try!(File::open(path).map_err(|e| format!("Error while opening json file: {}", e)));
+	let document: JValue = try!(from_reader(json_file).map_err(|e| format!("Error while reading json file: {:?}", e)));
+	let mut bytes = vec![];
+
+	if document.find("Converters").is_some() {
+		// A converters file given along with the json files
+		return Ok(());
+	}
+
+	if document.find("Records").is_some() {
+		// Written by the dump command, "name.records.json" goes back to "name.bytes"
+		let records = try!(lossless::records_from_json(&document));
+		lossless::write_records(&mut bytes, &records);
+		let bytes_path = path.parent().unwrap().join(Path::new(path.file_stem().unwrap()).with_extension("bytes"));
+		return write_bytes(&bytes_path, &bytes);
+	}
+
+	try!(editable::write_from_json(&mut bytes, &document, converters));
+	let bytes_path = path.parent().unwrap().join(Path::new(path.file_name().unwrap()).with_extension("bytes"));
+	write_bytes(&bytes_path, &bytes)
+}
 
-	for path_str in std::env::args().skip(1) {
-		let path = Path::new(&path_str);
-		if path.extension().unwrap() != "bytes" {
+fn serialize(paths: &Vec<String>, converters: &Converters) {
+	for path_str in paths.iter() {
+		let path = Path::new(path_str);
+		if path.extension().map_or(true, |extension| extension != "json") {
 			continue;
 		}
-		let mut file = OpenOptions::new().read(true).open(path).unwrap();
-		let mut metadata_vec: Vec<Box<Record>> = vec![];
-		let mut id_to_class = HashMap::<i32, *const ClassRecordForClassWithId>::new();
-		let mut id_and_values_vec = vec![];
-		let mut string_map = HashMap::<i32, *const String>::new();
-		loop {
-			match RecordTypeEnumeration::from(file.read_u8().unwrap()) {
-				SerializedStreamHeader => {
-					metadata_vec.push(box(SerializationHeaderRecord::new(&mut file)));
-				},
-				ClassWithId => {
-					let boxed_class_with_id = box(ClassWithIdRecord::new(&mut file));
-					let class_id = boxed_class_with_id.MetadataId;
-					let raw_class = id_to_class.get(&class_id).unwrap();
-					id_and_values_vec.push((class_id, unsafe {&**raw_class}.read_value(&mut file, &mut string_map)));
-					metadata_vec.push(boxed_class_with_id);
-				},
-				SystemClassWithMembersAndTypes => {
-					let boxed_class = box(SystemClassWithMembersAndTypesRecord::new(&mut file));
-					let class_id = boxed_class.ClassInfo.ObjectId;
-					id_and_values_vec.push((class_id, boxed_class.read_value(&mut file, &mut string_map)));
-					let raw_class: *const _ = &*boxed_class;
-					id_to_class.insert(class_id, raw_class);
-					metadata_vec.push(boxed_class);
-				}
-				ClassWithMembersAndTypes => {
-					let boxed_class = box(ClassWithMembersAndTypesRecord::new(&mut file));
-					let class_id = boxed_class.ClassInfo.ObjectId;
-					id_and_values_vec.push((class_id, boxed_class.read_value(&mut file, &mut string_map)));
-					let raw_class: *const _ = &*boxed_class;
-					id_to_class.insert(class_id, raw_class);
-					metadata_vec.push(boxed_class);
-				}
-				RecordTypeEnumeration::BinaryObjectString => {
-					let boxed_string = box(BinaryObjectStringRecord::new(&mut file));
-					let raw_s: *const _ = &boxed_string.Value;
-					string_map.insert(boxed_string.ObjectId, raw_s);
-					metadata_vec.push(boxed_string);
-				}
-				BinaryArray => {
-					metadata_vec.push(box(BinaryArrayRecord::new(&mut file)));
-				}
-				MemberReference => {
-					metadata_vec.push(box(MemberReferenceRecord::new(&mut file)));
-				}
-				ObjectNull => {
-					metadata_vec.push(box(ObjectNullRecord::new(&mut file)));
-				}
-				MessageEnd => {
-					metadata_vec.push(box(MessageEndRecord::new(&mut file)));
-					break;
-				}
-				BinaryLibrary => {
-					metadata_vec.push(box(BinaryLibraryRecord::new(&mut file)));
-				}
-				ObjectNullMultiple256 => {
-					metadata_vec.push(box(ObjectNullMultiple256Record::new(&mut file)));
-				}
-				ObjectNullMultiple => {
-					metadata_vec.push(box(ObjectNullMultipleRecord::new(&mut file)));
-				}
-				ArraySinglePrimitive => {
-					metadata_vec.push(box(ArraySinglePrimitiveRecord::new(&mut file)));
-				}
-				ArraySingleString => {
-					metadata_vec.push(box(ArraySingleStringRecord::new(&mut file)));
-				}
-				s @ _ => {
-					println!("pos: {:?}", file.seek(std::io::SeekFrom::Current(0)));
-					println!("Unprocessed RecordTypeEnumeration: {:?}", s);
-					break;
-				}
-			}
+		if let Err(e) = serialize_file(path, converters) {
+			println!("{}: {}", path_str, e);
 		}
+	}
+}
 
-		assert_eq!(file.seek(std::io::SeekFrom::Current(0)).unwrap(), file.metadata().unwrap().len());
+/// Reads a bytes file with read, which has to stop right at the end of the file after MessageEnd.
+/// The file is read at once and parsed from memory, which is much faster than reading it record by record.
+fn read_file<T, F>(path: &Path, read: F) -> Result<T, String> where F: FnOnce(&mut OffsetReader<&[u8]>) -> Result<T, DeserializeError> {
+	let mut bytes = vec![];
+	try!(File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)).map_err(|e| format!("Error while reading bytes file: {}", e)));
+	let mut reader = OffsetReader::new(&bytes[..]);
+	let result = try!(read(&mut reader).map_err(|e| e.to_string()));
+	let offset = reader.offset();
+	if offset != bytes.len() as u64 {
+		return Err(format!("{} bytes after MessageEnd at offset {}", bytes.len() as u64 - offset, offset));
+	}
+	Ok(result)
+}
 
+/// Reads the records of a bytes file
+fn read_records(path: &Path, schema: &mut Schema) -> Result<Vec<LosslessRecord>, String> {
+	read_file(path, |file| lossless::read_records(file, &mut ReadContext::new(schema)))
+}
+
+fn write_json(path: &Path, json: &JValue) -> Result<(), String> {
+	let mut file = try!(File::create(path).map_err(|e| format!("Error while creating {}: {}", path.display(), e)));
+	to_writer_pretty(&mut file, json).map_err(|e| format!("Error while writing {}: {:?}", path.display(), e))
+}
 
-		let parent_dir = path.parent().unwrap();
-		let filename = path.file_name().unwrap();
-		// let metadata_path = parent_dir.join(Path::new(filename).with_extension("metadata"));
-		// let mut metadata_file = OpenOptions::new().write(true).create(true).truncate(true).open(metadata_path).unwrap();
-		// write!(metadata_file, "{:#?}", metadata_vec).unwrap();
+fn write_bytes(path: &Path, bytes: &[u8]) -> Result<(), String> {
+	let mut file = try!(File::create(path).map_err(|e| format!("Error while creating {}: {}", path.display(), e)));
+	file.write_all(bytes).map_err(|e| format!("Error while writing {}: {}", path.display(), e))
+}
 
+fn print_usage() {
+	println!("Usage: drag&drop bytes or json files.");
+	println!("       csharp_deserialize dump <bytes files>");
+	println!("       csharp_deserialize verify-roundtrip <bytes files>");
+	println!("       csharp_deserialize graph <bytes files>");
+	println!("       csharp_deserialize schema <bytes files>");
+	println!("       csharp_deserialize events <bytes files>");
+	println!("Bytes files can be preceded by --schema <json file> giving the member types of classes written without them.");
+	println!("--raw writes objects of well-known classes such as List<T> with their internal members instead of their values.");
+	println!("--converters <json file> writes objects of the classes it gives as arrays or single values, both ways.");
+}
 
-		let parse_String = |value: &Box<Value>| {
-			// Try BinaryObjectStringRecord first as it seems more
-			match value.as_any_value().downcast_ref::<BinaryObjectStringRecord>() {
-				Some(s) => {
-					JValue::String(s.Value.clone())
+/// Paths given after the command, with the schema files given by --schema and the converters files given by
+/// --converters loaded, and whether --raw was not given. Converters files which cannot be loaded are reported and left out.
+fn parse_args(skip: usize) -> Result<(Vec<String>, Schema, bool, Converters), String> {
+	let mut paths = vec![];
+	let mut schema = Schema::new();
+	let mut known_types = true;
+	let mut converters = Converters::new();
+	let mut args = std::env::args().skip(skip);
+	while let Some(arg) = args.next() {
+		if arg == "--schema" {
+			match args.next() {
+				Some(schema_path) => try!(schema.load(Path::new(&schema_path)).map_err(|e| format!("{}: {}", schema_path, e))),
+				None => return Err("--schema needs a json file".to_string()),
+			}
+		} else if arg == "--converters" {
+			match args.next() {
+				Some(converters_path) => if let Err(e) = converters.load(Path::new(&converters_path)) {
+					println!("{}: {}", converters_path, e);
 				},
-				None => {
-					match value.as_any_value().downcast_ref::<MemberReferenceRecord>() {
-						Some(mem_ref) => {
-							match string_map.get(&mem_ref.IdRef) {
-								Some(raw_s) => JValue::String(unsafe { &**raw_s }.clone()),
-								None => to_value(mem_ref),
-							}
-						}
-						None => {
-							// let null = value.as_any_value().downcast_ref::<ObjectNullRecord>().unwrap();
-							JValue::Null
-						}
-					}
-				}
+				None => return Err("--converters needs a json file".to_string()),
 			}
-		};
+		} else if arg == "--raw" {
+			known_types = false;
+		} else {
+			paths.push(arg);
+		}
+	}
+	Ok((paths, schema, known_types, converters))
+}
 
-		let create_parse_class_vec = |binary_types, additional_infos: &Vec<Option<Box<AdditionalInfo>>>| {
-			let mut parse_class_vec: Vec<Box<Fn(&Box<Value>) -> JValue>> = vec![];
-			for (binary_type, additional_info) in Zip::new((binary_types, additional_infos)) {
-				use records::BinaryTypeEnumeration::*;
-				match binary_type {
-					&Primitive => {
-						match additional_info.as_ref().unwrap().as_any_ai().downcast_ref::<PrimitiveTypeEnumeration>().unwrap() {
-							&PrimitiveTypeEnumeration::Boolean => parse_class_vec.push(box(&parse_bool)),
-							&PrimitiveTypeEnumeration::Int32 => parse_class_vec.push(box(&parse_i32)),
-							&PrimitiveTypeEnumeration::Single => parse_class_vec.push(box(&parse_f32)),
-							&PrimitiveTypeEnumeration::UInt64 => parse_class_vec.push(box(&parse_u64)),
-							s @ _ => panic!("{:?}", s),
-						}
-					}
-					&Class | &SystemClass | &PrimitiveArray | &StringArray => {
-						// ignore additional_info
-						parse_class_vec.push(box(&parse_MemberReferenceRecord_or_ObjectNullRecord));
-					}
-					&String => {
-						parse_class_vec.push(box(&parse_String));
-					}
-					s @ _ => panic!("{:?}", s),
-				}
-			}
-			parse_class_vec
+/// Writes every record of the bytes files to "name.records.json", which can be serialized back unchanged
+fn dump(paths: &Vec<String>, schema: &mut Schema) {
+	for path_str in paths.iter() {
+		let path = Path::new(path_str);
+		let records = match read_records(path, schema) {
+			Ok(records) => records,
+			Err(e) => {
+				println!("{}: {}", path_str, e);
+				continue;
+			},
 		};
 
-		let mut parse_class_map = HashMap::new();
-		let mut json_vec = vec![];
-		for id_and_values in id_and_values_vec.iter() {
-			let &(class_id, ref values) = id_and_values;
-			let (class_name, member_names, binary_types, additional_infos) = {
-				let raw_class = id_to_class.get(&class_id).unwrap();
-				unsafe { (&**raw_class).get_info_for_json() }
-			};
+		let json_path = path.parent().unwrap().join(Path::new(path.file_name().unwrap()).with_extension("records.json"));
+		if let Err(e) = write_json(&json_path, &lossless::records_to_json(&records)) {
+			println!("{}: {}", path_str, e);
+		}
+	}
+}
 
-			let parse_class_vec = parse_class_map.entry(class_id).or_insert_with(|| {
-				create_parse_class_vec(binary_types, additional_infos)
-			});
+/// Writes the object graph from the root object to "name.graph.json"
+fn graph(paths: &Vec<String>, schema: &mut Schema, known_types: bool, converters: &Converters) {
+	for path_str in paths.iter() {
+		let path = Path::new(path_str);
+		let document = match read_file(path, |file| Document::read(file, schema)) {
+			Ok(document) => document,
+			Err(e) => {
+				println!("{}: {}", path_str, e);
+				continue;
+			},
+		};
 
-			let mut map = Map::new();
-			for (name, value, parse_class) in Zip::new((member_names, values, parse_class_vec)) {
-				map.insert(name.clone(), parse_class(value));
-			}
+		let json_path = path.parent().unwrap().join(Path::new(path.file_name().unwrap()).with_extension("graph.json"));
+		if let Err(e) = write_json(&json_path, &graph::document_to_graph(&document, known_types, converters)) {
+			println!("{}: {}", path_str, e);
+		}
+	}
+}
+
+/// Checks that dumping the bytes files and serializing the dump gives the same bytes
+fn verify_roundtrip(paths: &Vec<String>, schema: &mut Schema) {
+	let mut all_ok = true;
+	for path_str in paths.iter() {
+		let path = Path::new(path_str);
+		let mut original = vec![];
+		let records = match read_records(path, schema) {
+			Ok(records) => records,
+			Err(e) => {
+				all_ok = false;
+				println!("{}: {}", path_str, e);
+				continue;
+			},
+		};
+		if let Err(e) = File::open(path).and_then(|mut file| file.read_to_end(&mut original)) {
+			all_ok = false;
+			println!("{}: {}", path_str, e);
+			continue;
+		}
+		// Go through the json text as well, as that is what gets edited
+		let json_str = serde_json::to_string(&lossless::records_to_json(&records)).unwrap();
+		let json: JValue = serde_json::from_str(&json_str).unwrap();
+		let mut written = vec![];
+		match lossless::records_from_json(&json) {
+			Ok(records) => lossless::write_records(&mut written, &records),
+			Err(e) => {
+				all_ok = false;
+				println!("{}: {}", path_str, e);
+				continue;
+			},
+		}
 
-			json_vec.push({
-				let mut m = Map::new();
-				m.insert(class_name, JValue::Object(map));
-				JValue::Object(m)
-			});
+		match original.iter().zip(written.iter()).position(|(a, b)| a != b) {
+			None if original.len() == written.len() => {
+				println!("{}: OK, {} bytes", path_str, original.len());
+			},
+			None => {
+				all_ok = false;
+				println!("{}: {} bytes read but {} bytes written", path_str, original.len(), written.len());
+			},
+			Some(offset) => {
+				all_ok = false;
+				println!("{}: first difference at offset {:#x}", path_str, offset);
+			},
 		}
+	}
+	if !all_ok {
+		std::process::exit(1);
+	}
+}
 
-		let json_path = parent_dir.join(Path::new(filename).with_extension("json"));
-		let mut json_file = OpenOptions::new().write(true).create(true).truncate(true).open(json_path).unwrap();
-		if to_writer_pretty(&mut json_file, &JValue::Array(json_vec)).is_err() {
-			panic!("Error while writing json file");
+/// Collects the member types of the classes in the bytes files to "schema.json" next to the first file.
+/// Files are read in the given order, so classes written without member types can use those of the files before.
+fn write_schema(paths: &Vec<String>, schema: &mut Schema) {
+	for path_str in paths.iter() {
+		if let Err(e) = read_records(Path::new(path_str), schema) {
+			println!("{}: {}", path_str, e);
 		}
 	}
+
+	let schema_path = match paths.first() {
+		Some(path_str) => Path::new(path_str).parent().unwrap().join("schema.json"),
+		None => return print_usage(),
+	};
+	if let Err(e) = write_json(&schema_path, &schema.to_json()) {
+		println!("{}", e);
+	}
 }
 
-fn serialize() {
-	println!("Not implemented.");
+fn try_args<T>(args: Result<T, String>) -> T {
+	match args {
+		Ok(args) => args,
+		Err(e) => {
+			println!("{}", e);
+			print_usage();
+			std::process::exit(1);
+		},
+	}
+}
+
+/// Prints the events of the bytes files, one per line and indented by nesting.
+/// Files are read as a stream instead of at once, so that files of any size can be looked through.
+fn print_events(paths: &Vec<String>, schema: &mut Schema) {
+	let stdout = std::io::stdout();
+	let mut out = BufWriter::new(stdout.lock());
+	for path_str in paths.iter() {
+		let file = match File::open(path_str) {
+			Ok(file) => BufReader::new(file),
+			Err(e) => {
+				writeln!(out, "{}: Error while reading bytes file: {}", path_str, e).unwrap();
+				continue;
+			},
+		};
+		writeln!(out, "{}", path_str).unwrap();
+		let mut depth = 1;
+		let result = events::read_events(&mut OffsetReader::new(file), schema, &mut |event: Event| {
+			match event {
+				Event::ObjectEnd(_) | Event::ArrayEnd(_) => depth -= 1,
+				_ => {},
+			}
+			let written = match event {
+				Event::ClassDefinition(class) => writeln!(out, "{:2$}ClassDefinition({})", "", class.name, depth * 2),
+				Event::ObjectStart(id, class) => writeln!(out, "{:3$}ObjectStart({}, {})", "", id, class.name, depth * 2),
+				_ => writeln!(out, "{:2$}{:?}", "", event, depth * 2),
+			};
+			written.unwrap();
+			match event {
+				Event::ObjectStart(_, _) | Event::ArrayStart(_) => depth += 1,
+				_ => {},
+			}
+		});
+		if let Err(e) = result {
+			writeln!(out, "{}: {}", path_str, e).unwrap();
+		}
+	}
 }
 
 fn main() {
 	match std::env::args().nth(1) {
-		Some(path_str) => {
-			let path = Path::new(&path_str);
-			match path.extension().unwrap().to_str().unwrap() {
-				"bytes" => deserialize(),
-				"json" => serialize(),
-				_ => println!("Usage: drag&drop bytes or json files."),
+		Some(ref command) if command == "dump" => {
+			let (paths, mut schema, _, _) = try_args(parse_args(2));
+			dump(&paths, &mut schema);
+		},
+		Some(ref command) if command == "verify-roundtrip" => {
+			let (paths, mut schema, _, _) = try_args(parse_args(2));
+			verify_roundtrip(&paths, &mut schema);
+		},
+		Some(ref command) if command == "graph" => {
+			let (paths, mut schema, known_types, converters) = try_args(parse_args(2));
+			graph(&paths, &mut schema, known_types, &converters);
+		},
+		Some(ref command) if command == "schema" => {
+			let (paths, mut schema, _, _) = try_args(parse_args(2));
+			write_schema(&paths, &mut schema);
+		},
+		Some(ref command) if command == "events" => {
+			let (paths, mut schema, _, _) = try_args(parse_args(2));
+			print_events(&paths, &mut schema);
+		},
+		Some(_) => {
+			let (paths, mut schema, known_types, converters) = try_args(parse_args(1));
+			match paths.first().and_then(|path_str| Path::new(path_str).extension()).and_then(|extension| extension.to_str()) {
+				Some("bytes") => deserialize(&paths, &mut schema, known_types, &converters),
+				Some("json") => serialize(&paths, &converters),
+				_ => print_usage(),
 			}
 		},
-		None => println!("Usage: drag&drop bytes or json files."),
+		None => print_usage(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std;
+	use std::fs::File;
+	use std::io::prelude::*;
+
+	use csharp_deserialize::records::*;
+	use csharp_deserialize::Schema;
+	use super::read_records;
+
+	#[test]
+	fn trailing_bytes_after_message_end() {
+		let mut bytes = vec![];
+		SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+		MessageEndRecord {}.write(&mut bytes);
+		let path = std::env::temp_dir().join("csharp_deserialize_trailing.bytes");
+		File::create(&path).unwrap().write_all(&bytes).unwrap();
+		let complete = read_records(&path, &mut Schema::new()).map(|records| records.len());
+		bytes.extend_from_slice(&[0, 0]);
+		File::create(&path).unwrap().write_all(&bytes).unwrap();
+		let trailing = read_records(&path, &mut Schema::new()).map(|records| records.len());
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(complete, Ok(2));
+		assert_eq!(trailing, Err("2 bytes after MessageEnd at offset 18".to_string()));
 	}
 }
@@ -0,0 +1,217 @@
+//! Values of a stream as plain Rust data, for code using the parser as a library.
+//!
+//! Member values and array elements are `Data`, matched like any enum. Objects, arrays and strings
+//! written inline stay where they were written, those written as records of their own are listed
+//! by the `Document`, and `Data::Reference` gives the id to look them up with `Document::get`.
+//! Runs of nulls are expanded, and strings are not told apart by how they were written.
+
+use sub_records::{PrimitiveTypeEnumeration, BinaryTypeEnumeration, AdditionalInfo, ClassTypeInfoRecord, Decimal, TimeSpan, DateTime};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Primitive {
+	Boolean(bool),
+	Byte(u8),
+	Char(char),
+	Decimal(Decimal),
+	Double(f64),
+	Int16(i16),
+	Int32(i32),
+	Int64(i64),
+	SByte(i8),
+	Single(f32),
+	TimeSpan(TimeSpan),
+	DateTime(DateTime),
+	UInt16(u16),
+	UInt32(u32),
+	UInt64(u64),
+}
+
+impl Primitive {
+	pub fn get_primitive_type(&self) -> PrimitiveTypeEnumeration {
+		use sub_records::PrimitiveTypeEnumeration as P;
+		use self::Primitive as V;
+
+		match self {
+			&V::Boolean(_) => P::Boolean,
+			&V::Byte(_) => P::Byte,
+			&V::Char(_) => P::Char,
+			&V::Decimal(_) => P::Decimal,
+			&V::Double(_) => P::Double,
+			&V::Int16(_) => P::Int16,
+			&V::Int32(_) => P::Int32,
+			&V::Int64(_) => P::Int64,
+			&V::SByte(_) => P::SByte,
+			&V::Single(_) => P::Single,
+			&V::TimeSpan(_) => P::TimeSpan,
+			&V::DateTime(_) => P::DateTime,
+			&V::UInt16(_) => P::UInt16,
+			&V::UInt32(_) => P::UInt32,
+			&V::UInt64(_) => P::UInt64,
+		}
+	}
+
+	/// The zero of a primitive type, as C# `default(T)`. None for String and Null.
+	pub fn default_of(primitive_type: &PrimitiveTypeEnumeration) -> Option<Primitive> {
+		use sub_records::PrimitiveTypeEnumeration as P;
+		use self::Primitive as V;
+
+		Some(match primitive_type {
+			&P::Boolean => V::Boolean(false),
+			&P::Byte => V::Byte(0),
+			&P::Char => V::Char('\0'),
+			&P::Decimal => V::Decimal(Decimal { Value: "0".to_string() }),
+			&P::Double => V::Double(0.0),
+			&P::Int16 => V::Int16(0),
+			&P::Int32 => V::Int32(0),
+			&P::Int64 => V::Int64(0),
+			&P::SByte => V::SByte(0),
+			&P::Single => V::Single(0.0),
+			&P::TimeSpan => V::TimeSpan(TimeSpan { Ticks: 0 }),
+			&P::DateTime => V::DateTime(DateTime { Ticks: 0, Kind: 0 }),
+			&P::UInt16 => V::UInt16(0),
+			&P::UInt32 => V::UInt32(0),
+			&P::UInt64 => V::UInt64(0),
+			&P::String | &P::Null | &P::Unknown => return None,
+		})
+	}
+}
+
+/// Type of a member or of the elements of an array
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemberType {
+	Primitive(PrimitiveTypeEnumeration),
+	String,
+	Object,
+	/// Name of a class of mscorlib
+	SystemClass(String),
+	/// Name of the class and id of its library
+	Class(String, i32),
+	ObjectArray,
+	StringArray,
+	PrimitiveArray(PrimitiveTypeEnumeration),
+}
+
+impl MemberType {
+	pub fn get_binary_type(&self) -> BinaryTypeEnumeration {
+		match self {
+			&MemberType::Primitive(_) => BinaryTypeEnumeration::Primitive,
+			&MemberType::String => BinaryTypeEnumeration::String,
+			&MemberType::Object => BinaryTypeEnumeration::Object,
+			&MemberType::SystemClass(_) => BinaryTypeEnumeration::SystemClass,
+			&MemberType::Class(_, _) => BinaryTypeEnumeration::Class,
+			&MemberType::ObjectArray => BinaryTypeEnumeration::ObjectArray,
+			&MemberType::StringArray => BinaryTypeEnumeration::StringArray,
+			&MemberType::PrimitiveArray(_) => BinaryTypeEnumeration::PrimitiveArray,
+		}
+	}
+	/// The additional info written after the binary type in a MemberTypeInfo record
+	pub fn get_additional_info(&self) -> AdditionalInfo {
+		match self {
+			&MemberType::Primitive(primitive_type) | &MemberType::PrimitiveArray(primitive_type) => AdditionalInfo::Primitive(primitive_type),
+			&MemberType::SystemClass(ref name) => AdditionalInfo::SystemClass(name.clone()),
+			&MemberType::Class(ref name, library_id) => AdditionalInfo::Class(ClassTypeInfoRecord { TypeName: name.clone(), LibraryId: library_id }),
+			_ => AdditionalInfo::None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+	/// Object id of the record defining the class, i.e. of the first object of the class
+	pub id: i32,
+	pub name: String,
+	/// None for classes of mscorlib
+	pub library_id: Option<i32>,
+	pub member_names: Vec<String>,
+	pub member_types: Vec<MemberType>,
+}
+
+impl Class {
+	pub fn get_member_index(&self, name: &str) -> Option<usize> {
+		self.member_names.iter().position(|member_name| member_name == name)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+	pub id: i32,
+	/// Id of the class, see Document::get_class
+	pub class_id: i32,
+	/// In the order of the member names of the class
+	pub members: Vec<Data>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array {
+	pub id: i32,
+	pub element_type: MemberType,
+	/// Length of every dimension, a single one except for rectangular arrays
+	pub lengths: Vec<i32>,
+	pub lower_bounds: Option<Vec<i32>>,
+	/// The last dimension varies fastest
+	pub elements: Vec<Data>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+	Primitive(Primitive),
+	String(String),
+	Null,
+	Object(Object),
+	Array(Array),
+	/// Id of an object, array or string written elsewhere in the stream
+	Reference(i32),
+}
+
+impl Data {
+	pub fn as_primitive(&self) -> Option<&Primitive> {
+		match self {
+			&Data::Primitive(ref primitive) => Some(primitive),
+			_ => None,
+		}
+	}
+
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			&Data::String(ref s) => Some(&s[..]),
+			_ => None,
+		}
+	}
+
+	pub fn as_object(&self) -> Option<&Object> {
+		match self {
+			&Data::Object(ref object) => Some(object),
+			_ => None,
+		}
+	}
+
+	pub fn as_array(&self) -> Option<&Array> {
+		match self {
+			&Data::Array(ref array) => Some(array),
+			_ => None,
+		}
+	}
+
+	pub fn as_reference(&self) -> Option<i32> {
+		match self {
+			&Data::Reference(id) => Some(id),
+			_ => None,
+		}
+	}
+
+	pub fn is_null(&self) -> bool {
+		match self {
+			&Data::Null => true,
+			_ => false,
+		}
+	}
+
+	/// Members of an object or elements of an array, empty for other values
+	pub fn get_children(&self) -> &[Data] {
+		match self {
+			&Data::Object(ref object) => &object.members[..],
+			&Data::Array(ref array) => &array.elements[..],
+			_ => &[],
+		}
+	}
+}
@@ -1,32 +1,165 @@
-use std;
-use std::fs::File;
-use std::any::Any;
-use std::mem::transmute;
 use std::io::prelude::*;
 use std::collections::HashMap;
+use std::rc::Rc;
 
-use byteorder::ReadBytesExt;
-use itertools::Zip;
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
 
 pub use sub_records::*;
+use model::Primitive;
+use lossless::LosslessRecord;
+use schema::Schema;
 
 
-pub trait Value { fn as_any_value(&self) -> &Any; }
-impl Value for bool { fn as_any_value(&self) -> &Any { self } }
-impl Value for i32 { fn as_any_value(&self) -> &Any { self } }
-impl Value for f32 { fn as_any_value(&self) -> &Any { self } }
-impl Value for u64 { fn as_any_value(&self) -> &Any { self } }
-impl Value for MemberReferenceRecord { fn as_any_value(&self) -> &Any { self } }
-impl Value for ObjectNullRecord { fn as_any_value(&self) -> &Any { self } }
-impl Value for BinaryObjectStringRecord { fn as_any_value(&self) -> &Any { self } }
-impl Value for ArraySinglePrimitiveRecord { fn as_any_value(&self) -> &Any { self } }
+/// What the readers of a stream need to know from the records read before
+pub struct ReadContext<'a> {
+	/// Classes by the object id of the record defining them, shared with the records read
+	pub id_to_class: HashMap<i32, Rc<ClassRecordForClassWithId>>,
+	/// Member types of classes written without them, classes read with their member types are added
+	pub schema: &'a mut Schema,
+	/// Records being read inside each other, up to MAX_DEPTH
+	pub depth: usize,
+}
+
+impl<'a> ReadContext<'a> {
+	pub fn new(schema: &'a mut Schema) -> Self {
+		ReadContext {
+			id_to_class: HashMap::new(),
+			schema: schema,
+			depth: 0,
+		}
+	}
+}
+
+/// A member value or an array element as it was written: a primitive without a record, or a record.
+/// Only the class and array records, which are rarely written inline, are boxed.
+#[derive(Debug)]
+pub enum Value {
+	Primitive(Primitive),
+	BinaryObjectString(BinaryObjectStringRecord),
+	MemberPrimitiveTyped(MemberPrimitiveTypedRecord),
+	MemberReference(MemberReferenceRecord),
+	ObjectNull(ObjectNullRecord),
+	ObjectNullMultiple256(ObjectNullMultiple256Record),
+	ObjectNullMultiple(ObjectNullMultipleRecord),
+	/// Class and array records, and BinaryLibrary records written right before a value
+	Record(Box<LosslessRecord>),
+}
+
+impl Value {
+	/// Number of array elements the value stands for
+	pub fn get_element_count(&self) -> usize {
+		match self {
+			&Value::ObjectNullMultiple256(ref r) => r.NullCount as usize,
+			&Value::ObjectNullMultiple(ref r) => r.NullCount as usize,
+			&Value::Record(ref record) => record.get_element_count(),
+			_ => 1,
+		}
+	}
+
+	/// The record standing for that many consecutive null elements
+	pub fn null_run(null_count: i32) -> Self {
+		match null_count {
+			1 => Value::ObjectNull(ObjectNullRecord {}),
+			2...255 => Value::ObjectNullMultiple256(ObjectNullMultiple256Record { NullCount: null_count as u8 }),
+			_ => Value::ObjectNullMultiple(ObjectNullMultipleRecord { NullCount: null_count }),
+		}
+	}
+
+	/// Primitives are written as they are, as the member type gives their type
+	pub fn write(&self, file: &mut Write) {
+		match self {
+			&Value::Primitive(ref primitive) => write_primitive(file, primitive),
+			&Value::BinaryObjectString(ref r) => r.write(file),
+			&Value::MemberPrimitiveTyped(ref r) => r.write(file),
+			&Value::MemberReference(ref r) => r.write(file),
+			&Value::ObjectNull(ref r) => r.write(file),
+			&Value::ObjectNullMultiple256(ref r) => r.write(file),
+			&Value::ObjectNullMultiple(ref r) => r.write(file),
+			&Value::Record(ref record) => ::lossless::write_record(file, record),
+		}
+	}
+}
+
+/// Booleans are written as 0 or 1, other bytes could not be written back the same
+fn read_boolean(file: &mut OffsetRead) -> Result<bool, DeserializeError> {
+	match try!(read_u8(file)) {
+		0 => Ok(false),
+		1 => Ok(true),
+		x => Err(DeserializeError::InvalidEnumValue(error_at(file, 1), "Boolean", x)),
+	}
+}
+
+/// Primitive values are written without any record.
+/// Null and String are not primitive values, they are written as records.
+pub fn read_primitive(file: &mut OffsetRead, primitive_type: &PrimitiveTypeEnumeration) -> Result<Primitive, DeserializeError> {
+	use sub_records::PrimitiveTypeEnumeration::*;
+
+	Ok(match primitive_type {
+		&Boolean => Primitive::Boolean(try!(read_boolean(file))),
+		&Byte => Primitive::Byte(try!(read_u8(file))),
+		&Char => Primitive::Char(try!(read_char(file))),
+		&Decimal => Primitive::Decimal(::sub_records::Decimal { Value: try!(read_LengthPrefixedString(file)) }),
+		&Double => Primitive::Double(try!(read_l_f64(file))),
+		&Int16 => Primitive::Int16(try!(read_l_i16(file))),
+		&Int32 => Primitive::Int32(try!(read_l_i32(file))),
+		&Int64 => Primitive::Int64(try!(read_l_i64(file))),
+		&SByte => Primitive::SByte(try!(read_i8(file))),
+		&Single => Primitive::Single(try!(read_l_f32(file))),
+		&TimeSpan => Primitive::TimeSpan(::sub_records::TimeSpan { Ticks: try!(read_l_i64(file)) }),
+		&DateTime => Primitive::DateTime(::sub_records::DateTime::from_raw(try!(read_l_i64(file)))),
+		&UInt16 => Primitive::UInt16(try!(read_l_u16(file))),
+		&UInt32 => Primitive::UInt32(try!(read_l_u32(file))),
+		&UInt64 => Primitive::UInt64(try!(read_l_u64(file))),
+		&Null | &String => {
+			return Err(DeserializeError::InvalidEnumValue(error_at(file, 0), "PrimitiveTypeEnumeration of a primitive value", *primitive_type as u8));
+		},
+		&Unknown => unreachable!(),
+	})
+}
+
+pub fn write_primitive(file: &mut Write, primitive: &Primitive) {
+	match primitive {
+		&Primitive::Boolean(x) => file.write_u8(x as u8).unwrap(),
+		&Primitive::Byte(x) => file.write_u8(x).unwrap(),
+		&Primitive::Char(x) => write_char(file, x),
+		&Primitive::Decimal(ref x) => write_LengthPrefixedString(file, &x.Value),
+		&Primitive::Double(x) => write_l_f64(file, x),
+		&Primitive::Int16(x) => write_l_i16(file, x),
+		&Primitive::Int32(x) => write_l_i32(file, x),
+		&Primitive::Int64(x) => write_l_i64(file, x),
+		&Primitive::SByte(x) => file.write_i8(x).unwrap(),
+		&Primitive::Single(x) => write_l_f32(file, x),
+		&Primitive::TimeSpan(x) => write_l_i64(file, x.Ticks),
+		&Primitive::DateTime(x) => write_l_i64(file, x.to_raw()),
+		&Primitive::UInt16(x) => write_l_u16(file, x),
+		&Primitive::UInt32(x) => write_l_u32(file, x),
+		&Primitive::UInt64(x) => write_l_u64(file, x),
+	}
+}
+
+/// Elements of string and object arrays are records, where a run of nulls may be a single record
+pub fn read_element_records(file: &mut OffsetRead, length: usize, context: &mut ReadContext) -> Result<Vec<Value>, DeserializeError> {
+	let mut values = vec![];
+	let mut count = 0;
+	while count < length {
+		let value = try!(::lossless::read_value_record(file, context).map_err(|e| e.within(format!("element {}", count))));
+		let element_count = value.get_element_count();
+		if count + element_count > length {
+			return Err(DeserializeError::TooManyElements(error_at(file, 0), length, count + element_count).within(format!("element {}", count)));
+		}
+		count += element_count;
+		values.push(value);
+	}
+	Ok(values)
+}
 
 pub trait Record {
-	fn new(file: &mut File) -> Self where Self: Sized;
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> where Self: Sized;
 	fn get_record_type_value() -> u8 where Self: Sized;
+	fn write(&self, file: &mut Write);
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum RecordTypeEnumeration {
 	SerializedStreamHeader,
 	ClassWithId,
@@ -52,125 +185,127 @@ pub enum RecordTypeEnumeration {
 	Unknown,
 }
 
-impl From<u8> for RecordTypeEnumeration {
-	fn from(x: u8) -> Self {
-		if x >= RecordTypeEnumeration::Unknown as u8 || (x > 17 && x < 21) {
-			panic!("Invalid RecordTypeEnumeration {:?}", x);
-		} else {
-			unsafe { transmute(x) }
-		}
+impl ByteEnum for RecordTypeEnumeration {
+	fn from_byte(x: u8) -> Option<Self> {
+		use self::RecordTypeEnumeration::*;
+		Some(match x {
+			0 => SerializedStreamHeader,
+			1 => ClassWithId,
+			2 => SystemClassWithMembers,
+			3 => ClassWithMembers,
+			4 => SystemClassWithMembersAndTypes,
+			5 => ClassWithMembersAndTypes,
+			6 => BinaryObjectString,
+			7 => BinaryArray,
+			8 => MemberPrimitiveTyped,
+			9 => MemberReference,
+			10 => ObjectNull,
+			11 => MessageEnd,
+			12 => BinaryLibrary,
+			13 => ObjectNullMultiple256,
+			14 => ObjectNullMultiple,
+			15 => ArraySinglePrimitive,
+			16 => ArraySingleObject,
+			17 => ArraySingleString,
+			21 => MethodCall,
+			22 => MethodReturn,
+			_ => return None,
+		})
+	}
+	fn enum_name() -> &'static str {
+		"RecordTypeEnumeration"
 	}
 }
 
 
 #[derive(Debug)]
 pub struct SerializationHeaderRecord {
-	RootId: i32,
-	HeaderId: i32,
-	MajorVersion: i32,
-	MinorVersion: i32,
+	pub RootId: i32,
+	pub HeaderId: i32,
+	pub MajorVersion: i32,
+	pub MinorVersion: i32,
 }
 
 impl Record for SerializationHeaderRecord {
-	fn new(file: &mut File) -> Self {
-		SerializationHeaderRecord {
-			RootId: read_l_i32(file),
-			HeaderId: read_l_i32(file),
-			MajorVersion: read_l_i32(file),
-			MinorVersion: read_l_i32(file),
-		}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(SerializationHeaderRecord {
+			RootId: try!(read_l_i32(file)),
+			HeaderId: try!(read_l_i32(file)),
+			MajorVersion: try!(read_l_i32(file)),
+			MinorVersion: try!(read_l_i32(file)),
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		0
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		write_l_i32(file, self.RootId);
+		write_l_i32(file, self.HeaderId);
+		write_l_i32(file, self.MajorVersion);
+		write_l_i32(file, self.MinorVersion);
+	}
 }
 
 #[derive(Debug)]
 pub struct ClassWithIdRecord {
-	ObjectId: i32,
+	pub ObjectId: i32,
 	pub MetadataId: i32,
 }
 
 impl Record for ClassWithIdRecord {
-	fn new(file: &mut File) -> Self {
-		ClassWithIdRecord {
-			ObjectId: read_l_i32(file),
-			MetadataId: read_l_i32(file),
-		}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(ClassWithIdRecord {
+			ObjectId: try!(read_l_i32(file)),
+			MetadataId: try!(read_l_i32(file)),
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		1
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		write_l_i32(file, self.ObjectId);
+		write_l_i32(file, self.MetadataId);
+	}
 }
 
 pub trait ClassRecordForClassWithId {
 	fn get_member_info(&self) -> (&MemberTypeInfoRecord, usize);
-	fn get_info_for_json(&self) -> (String, &Vec<String>, &Vec<BinaryTypeEnumeration>, &Vec<Option<Box<AdditionalInfo>>>);
-	fn read_value(&self, file: &mut File, string_map: &mut HashMap<i32, *const String>) -> Vec<Box<Value>> {
+	fn get_info_for_json(&self) -> (String, &Vec<String>, &Vec<BinaryTypeEnumeration>, &Vec<AdditionalInfo>);
+	/// None for system classes, which are not bound to a BinaryLibrary
+	fn get_library_id(&self) -> Option<i32>;
+	/// Writes the record itself, including the RecordTypeEnumeration byte
+	fn write_class(&self, file: &mut Write);
+	/// Other records may be written inline as member values, so the classes and strings met on the way are registered
+	fn read_value(&self, file: &mut OffsetRead, context: &mut ReadContext) -> Result<Vec<Value>, DeserializeError> {
 		use sub_records::BinaryTypeEnumeration::*;
 
-		let (member_type_info, member_count) = self.get_member_info();
-		let mut values: Vec<Box<Value>> = Vec::with_capacity(member_count);
-
-		for (binary_type, additional_info) in Zip::new((&member_type_info.BinaryTypeEnums, &member_type_info.AdditionalInfos)) {
-			values.push(
-				match binary_type {
-					&Primitive => {
-						match additional_info.as_ref().unwrap().as_any_ai().downcast_ref::<PrimitiveTypeEnumeration>().unwrap() {
-							&PrimitiveTypeEnumeration::Boolean => {
-								box(file.read_u8().unwrap() == 1)
-							}
-							&PrimitiveTypeEnumeration::Int32 => {
-								box(read_l_i32(file))
-							},
-							&PrimitiveTypeEnumeration::Single => {
-								box(read_l_f32(file))
-							},
-							&PrimitiveTypeEnumeration::UInt64 => {
-								box(read_l_u64(file))
-							},
-							s @ _ => panic!("Unprocessed PrimitiveType: {:?}", s),
-						}
-					},
-					&String => {
-						match RecordTypeEnumeration::from(file.read_u8().unwrap()) {
-							RecordTypeEnumeration::BinaryObjectString => {
-								let boxed_string = box(BinaryObjectStringRecord::new(file));
-								let raw_s: *const _ = &boxed_string.Value;
-								string_map.insert(boxed_string.ObjectId, raw_s);
-								boxed_string
-							}
-							RecordTypeEnumeration::MemberReference => {
-								box(MemberReferenceRecord::new(file))
-							}
-							RecordTypeEnumeration::ObjectNull => {
-								box(ObjectNullRecord::new(file))
-							}
-							s @ _ => {
-								println!("pos: {:?}", file.seek(std::io::SeekFrom::Current(0)));
-								panic!("Unprocessed ValueTypeEnum: {:?}", s);
-							}
-						}
-					},
-					t @ &Class | t @ &SystemClass | t @ &PrimitiveArray | t @ &StringArray => {
-						match RecordTypeEnumeration::from(file.read_u8().unwrap()) {
-							RecordTypeEnumeration::MemberReference => {
-								box(MemberReferenceRecord::new(file))
-							}
-							RecordTypeEnumeration::ObjectNull => {
-								box(ObjectNullRecord::new(file))
-							}
-							s @ _ => panic!("Unprocessed ValueTypeEnum: {:?} of {:?}", s, t),
-						}
-					},
-					s @ _ => {
-						println!("pos: {:?}", file.seek(std::io::SeekFrom::Current(0)));
-						panic!("Unprocessed BinaryTypeEnums: {:?}", s); 	
-					}
-				}
-			);
+		let (member_type_info, _) = self.get_member_info();
+		let mut values = vec![];
+
+		for (i, (binary_type, additional_info)) in member_type_info.BinaryTypeEnums.iter().zip(member_type_info.AdditionalInfos.iter()).enumerate() {
+			let value = match binary_type {
+				&Primitive => {
+					read_primitive(file, additional_info.get_primitive_type().unwrap()).map(Value::Primitive)
+				},
+				&String | &Object | &Class | &SystemClass | &ObjectArray | &StringArray | &PrimitiveArray => {
+					::lossless::read_value_record(file, context)
+				},
+				&Unknown => unreachable!(),
+			};
+			values.push(try!(value.map_err(|e| {
+				let (class_name, member_names, _, _) = self.get_info_for_json();
+				e.within(format!("{}.{}", class_name, member_names[i]))
+			})));
+		}
+		Ok(values)
+	}
+	/// The values are written by their own type, which the member types of the class were used to read
+	fn write_value(&self, file: &mut Write, values: &Vec<Value>) {
+		for value in values.iter() {
+			value.write(file);
 		}
-		values
 	}
 }
 
@@ -184,59 +319,170 @@ impl ClassRecordForClassWithId for SystemClassWithMembersAndTypesRecord {
 	fn get_member_info(&self) -> (&MemberTypeInfoRecord, usize) {
 		(&self.MemberTypeInfo, self.ClassInfo.MemberCount as usize)
 	}
-	fn get_info_for_json(&self) -> (String, &Vec<String>, &Vec<BinaryTypeEnumeration>, &Vec<Option<Box<AdditionalInfo>>>) {
+	fn get_info_for_json(&self) -> (String, &Vec<String>, &Vec<BinaryTypeEnumeration>, &Vec<AdditionalInfo>) {
 		(self.ClassInfo.Name.clone(), &self.ClassInfo.MemberNames, &self.MemberTypeInfo.BinaryTypeEnums, 
 			&self.MemberTypeInfo.AdditionalInfos)
 	}
+	fn get_library_id(&self) -> Option<i32> {
+		None
+	}
+	fn write_class(&self, file: &mut Write) {
+		self.write(file);
+	}
 }
 
 impl Record for SystemClassWithMembersAndTypesRecord {
-	fn new(file: &mut File) -> Self {
-		let class_info = ClassInfoRecord::new(file);
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		let class_info = try!(ClassInfoRecord::new(file));
 		let member_count = class_info.MemberCount as usize;
-		let member_type_info = MemberTypeInfoRecord::new(file, member_count);
-		SystemClassWithMembersAndTypesRecord {
+		let member_type_info = try!(MemberTypeInfoRecord::new(file, member_count));
+		Ok(SystemClassWithMembersAndTypesRecord {
 			ClassInfo: class_info,
 			MemberTypeInfo: member_type_info,
-		}
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		4
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		self.ClassInfo.write(file);
+		self.MemberTypeInfo.write(file);
+	}
 }
 
 #[derive(Debug)]
 pub struct ClassWithMembersAndTypesRecord {
 	pub ClassInfo: ClassInfoRecord,
 	pub MemberTypeInfo: MemberTypeInfoRecord,
-	LibraryId: i32,
+	pub LibraryId: i32,
 }
 
 impl ClassRecordForClassWithId for ClassWithMembersAndTypesRecord {
 	fn get_member_info(&self) -> (&MemberTypeInfoRecord, usize) {
 		(&self.MemberTypeInfo, self.ClassInfo.MemberCount as usize)
 	}
-	fn get_info_for_json(&self) -> (String, &Vec<String>, &Vec<BinaryTypeEnumeration>, &Vec<Option<Box<AdditionalInfo>>>) {
+	fn get_info_for_json(&self) -> (String, &Vec<String>, &Vec<BinaryTypeEnumeration>, &Vec<AdditionalInfo>) {
 		(self.ClassInfo.Name.clone(), &self.ClassInfo.MemberNames, &self.MemberTypeInfo.BinaryTypeEnums, 
 			&self.MemberTypeInfo.AdditionalInfos)
 	}
+	fn get_library_id(&self) -> Option<i32> {
+		Some(self.LibraryId)
+	}
+	fn write_class(&self, file: &mut Write) {
+		self.write(file);
+	}
 }
 
 impl Record for ClassWithMembersAndTypesRecord {
-	fn new(file: &mut File) -> Self {
-		let class_info = ClassInfoRecord::new(file);
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		let class_info = try!(ClassInfoRecord::new(file));
 		let member_count = class_info.MemberCount as usize;
-		let member_type_info = MemberTypeInfoRecord::new(file, member_count);
+		let member_type_info = try!(MemberTypeInfoRecord::new(file, member_count));
 		
-		ClassWithMembersAndTypesRecord {
+		Ok(ClassWithMembersAndTypesRecord {
 			ClassInfo: class_info,
 			MemberTypeInfo: member_type_info,
-			LibraryId: read_l_i32(file),
-		}
+			LibraryId: try!(read_l_i32(file)),
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		5
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		self.ClassInfo.write(file);
+		self.MemberTypeInfo.write(file);
+		write_l_i32(file, self.LibraryId);
+	}
+}
+
+/// Member types are not written, they are taken from a schema when read
+#[derive(Debug)]
+pub struct SystemClassWithMembersRecord {
+	pub ClassInfo: ClassInfoRecord,
+	pub MemberTypeInfo: MemberTypeInfoRecord,
+}
+
+impl ClassRecordForClassWithId for SystemClassWithMembersRecord {
+	fn get_member_info(&self) -> (&MemberTypeInfoRecord, usize) {
+		(&self.MemberTypeInfo, self.ClassInfo.MemberCount as usize)
+	}
+	fn get_info_for_json(&self) -> (String, &Vec<String>, &Vec<BinaryTypeEnumeration>, &Vec<AdditionalInfo>) {
+		(self.ClassInfo.Name.clone(), &self.ClassInfo.MemberNames, &self.MemberTypeInfo.BinaryTypeEnums,
+			&self.MemberTypeInfo.AdditionalInfos)
+	}
+	fn get_library_id(&self) -> Option<i32> {
+		None
+	}
+	fn write_class(&self, file: &mut Write) {
+		self.write(file);
+	}
+}
+
+impl Record for SystemClassWithMembersRecord {
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(SystemClassWithMembersRecord {
+			ClassInfo: try!(ClassInfoRecord::new(file)),
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![],
+				AdditionalInfos: vec![],
+			},
+		})
+	}
+	fn get_record_type_value() -> u8 {
+		2
+	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		self.ClassInfo.write(file);
+	}
+}
+
+/// Member types are not written, they are taken from a schema when read
+#[derive(Debug)]
+pub struct ClassWithMembersRecord {
+	pub ClassInfo: ClassInfoRecord,
+	pub MemberTypeInfo: MemberTypeInfoRecord,
+	pub LibraryId: i32,
+}
+
+impl ClassRecordForClassWithId for ClassWithMembersRecord {
+	fn get_member_info(&self) -> (&MemberTypeInfoRecord, usize) {
+		(&self.MemberTypeInfo, self.ClassInfo.MemberCount as usize)
+	}
+	fn get_info_for_json(&self) -> (String, &Vec<String>, &Vec<BinaryTypeEnumeration>, &Vec<AdditionalInfo>) {
+		(self.ClassInfo.Name.clone(), &self.ClassInfo.MemberNames, &self.MemberTypeInfo.BinaryTypeEnums,
+			&self.MemberTypeInfo.AdditionalInfos)
+	}
+	fn get_library_id(&self) -> Option<i32> {
+		Some(self.LibraryId)
+	}
+	fn write_class(&self, file: &mut Write) {
+		self.write(file);
+	}
+}
+
+impl Record for ClassWithMembersRecord {
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		let class_info = try!(ClassInfoRecord::new(file));
+		Ok(ClassWithMembersRecord {
+			ClassInfo: class_info,
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![],
+				AdditionalInfos: vec![],
+			},
+			LibraryId: try!(read_l_i32(file)),
+		})
+	}
+	fn get_record_type_value() -> u8 {
+		3
+	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		self.ClassInfo.write(file);
+		write_l_i32(file, self.LibraryId);
+	}
 }
 
 #[derive(Debug, Serialize)]
@@ -247,45 +493,72 @@ pub struct BinaryObjectStringRecord {
 
 
 impl Record for BinaryObjectStringRecord {
-	fn new(file: &mut File) -> Self {
-		BinaryObjectStringRecord {
-			ObjectId: read_l_i32(file),
-			Value: read_LengthPrefixedString(file),
-		}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(BinaryObjectStringRecord {
+			ObjectId: try!(read_l_i32(file)),
+			Value: try!(read_LengthPrefixedString(file)),
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		6
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		write_l_i32(file, self.ObjectId);
+		write_LengthPrefixedString(file, &self.Value);
+	}
 }
 
 #[derive(Debug)]
 pub struct BinaryArrayRecord {
-	ObjectId: i32,
-	BinaryArrayTypeEnum: BinaryArrayTypeEnumeration,
-	Rank: i32,
-	Lengths: Vec<i32>,
-	LowerBounds: Option<Vec<i32>>,
-	TypeEnum: BinaryTypeEnumeration,
-	AdditionalTypeInfo: Option<Box<AdditionalInfo>>,
+	pub ObjectId: i32,
+	pub BinaryArrayTypeEnum: BinaryArrayTypeEnumeration,
+	pub Rank: i32,
+	pub Lengths: Vec<i32>,
+	pub LowerBounds: Option<Vec<i32>>,
+	pub TypeEnum: BinaryTypeEnumeration,
+	pub AdditionalTypeInfo: AdditionalInfo,
+	pub Values: ArrayValues,
 }
 
-impl Record for BinaryArrayRecord {
-	fn new(file: &mut File) -> Self {
+/// Elements of a BinaryArrayRecord, primitives being stored by type as for ArraySinglePrimitiveRecord
+#[derive(Debug)]
+pub enum ArrayValues {
+	Primitive(PrimitiveValues),
+	Records(Vec<Value>),
+}
+
+impl ArrayValues {
+	pub fn write(&self, file: &mut Write) {
+		match self {
+			&ArrayValues::Primitive(ref values) => values.write(file),
+			&ArrayValues::Records(ref values) => {
+				for value in values.iter() {
+					value.write(file);
+				}
+			},
+		}
+	}
+}
+
+impl BinaryArrayRecord {
+	/// Elements may be class records using the classes read before
+	pub fn read(file: &mut OffsetRead, context: &mut ReadContext) -> Result<Self, DeserializeError> {
 		use sub_records::BinaryArrayTypeEnumeration::*;
 
-		let oi = read_l_i32(file);
-		let bat = BinaryArrayTypeEnumeration::from(file.read_u8().unwrap());
-		let rank = read_l_i32(file);
+		let oi = try!(read_l_i32(file));
+		let bat = try!(read_enum(file));
+		let rank = try!(read_count(file, "Rank"));
 		let l = rank as usize;
-		let mut lengths = Vec::with_capacity(l);
+		let mut lengths = vec![];
 		for _ in 0..l {
-			lengths.push(read_l_i32(file));
+			lengths.push(try!(read_count(file, "array length")));
 		}
 		let lower_bounds = match bat {
 			SingleOffset | JaggedOffset | RectangularOffset => {
-				let mut lower_bounds = Vec::with_capacity(l);
+				let mut lower_bounds = vec![];
 				for _ in 0..l {
-					lower_bounds.push(read_l_i32(file));
+					lower_bounds.push(try!(read_l_i32(file)));
 				}
 				Some(lower_bounds)
 			},
@@ -293,15 +566,26 @@ impl Record for BinaryArrayRecord {
 			Unknown => unreachable!(),
 		};
 
-		let type_enum = BinaryTypeEnumeration::from(file.read_u8().unwrap());
-		let ati: Option<Box<AdditionalInfo>> = match type_enum {
-			BinaryTypeEnumeration::Class => {
-				Some(box(ClassTypeInfoRecord::new(file)))
-			}
-			s @ _ => panic!("Unprocessed AdditionalTypeInfo: {:?}", s),
+		let type_enum = try!(read_enum(file));
+		let ati = try!(read_additional_info(file, &type_enum));
+		// Elements of every layout are written one after another, the last dimension varying fastest
+		let mut element_count: i64 = 1;
+		for length in lengths.iter() {
+			element_count = element_count.saturating_mul(*length as i64);
+		}
+		if element_count > MAX_COUNT {
+			return Err(DeserializeError::LimitExceeded(error_at(file, 0), "array element count", element_count));
+		}
+		let element_count = element_count as usize;
+		let values = match type_enum {
+			BinaryTypeEnumeration::Primitive => {
+				let primitive_type = ati.get_primitive_type().unwrap();
+				ArrayValues::Primitive(try!(PrimitiveValues::new(file, primitive_type, element_count)))
+			},
+			_ => ArrayValues::Records(try!(read_element_records(file, element_count, context))),
 		};
 
-		BinaryArrayRecord {
+		Ok(BinaryArrayRecord {
 			ObjectId: oi,
 			BinaryArrayTypeEnum: bat,
 			Rank: rank,
@@ -309,11 +593,61 @@ impl Record for BinaryArrayRecord {
 			LowerBounds: lower_bounds,
 			TypeEnum: type_enum,
 			AdditionalTypeInfo: ati,
-		}
+			Values: values,
+		})
+	}
+}
+
+impl Record for BinaryArrayRecord {
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		BinaryArrayRecord::read(file, &mut ReadContext::new(&mut Schema::new()))
 	}
 	fn get_record_type_value() -> u8 {
 		7
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		write_l_i32(file, self.ObjectId);
+		file.write_u8(self.BinaryArrayTypeEnum as u8).unwrap();
+		write_l_i32(file, self.Rank);
+		for length in self.Lengths.iter() {
+			write_l_i32(file, *length);
+		}
+		if let Some(ref lower_bounds) = self.LowerBounds {
+			for lower_bound in lower_bounds.iter() {
+				write_l_i32(file, *lower_bound);
+			}
+		}
+		file.write_u8(self.TypeEnum as u8).unwrap();
+		self.AdditionalTypeInfo.write(file);
+		self.Values.write(file);
+	}
+}
+
+/// A primitive value where a record is expected, as for members of type Object
+#[derive(Debug)]
+pub struct MemberPrimitiveTypedRecord {
+	pub PrimitiveTypeEnum: PrimitiveTypeEnumeration,
+	pub Value: Primitive,
+}
+
+impl Record for MemberPrimitiveTypedRecord {
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		let primitive_type = try!(read_enum(file));
+		let value = try!(read_primitive(file, &primitive_type));
+		Ok(MemberPrimitiveTypedRecord {
+			PrimitiveTypeEnum: primitive_type,
+			Value: value,
+		})
+	}
+	fn get_record_type_value() -> u8 {
+		8
+	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		file.write_u8(self.PrimitiveTypeEnum as u8).unwrap();
+		write_primitive(file, &self.Value);
+	}
 }
 
 #[derive(Debug, Serialize)]
@@ -323,14 +657,18 @@ pub struct MemberReferenceRecord {
 
 
 impl Record for MemberReferenceRecord {
-	fn new(file: &mut File) -> Self {
-		MemberReferenceRecord {
-			IdRef: read_l_i32(file),
-		}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(MemberReferenceRecord {
+			IdRef: try!(read_l_i32(file)),
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		9
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		write_l_i32(file, self.IdRef);
+	}
 }
 
 #[derive(Debug)]
@@ -338,12 +676,15 @@ pub struct ObjectNullRecord {}
 
 impl Record for ObjectNullRecord {
 	#[allow(unused_variables)]
-	fn new(file: &mut File) -> Self {
-		ObjectNullRecord {}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(ObjectNullRecord {})
 	}
 	fn get_record_type_value() -> u8 {
 		10
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+	}
 }
 
 
@@ -352,109 +693,510 @@ pub struct MessageEndRecord {}
 
 impl Record for MessageEndRecord {
 	#[allow(unused_variables)]
-	fn new(file: &mut File) -> Self {
-		MessageEndRecord {}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(MessageEndRecord {})
 	}
 	fn get_record_type_value() -> u8 {
 		11
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+	}
 }
 
 #[derive(Debug)]
 pub struct BinaryLibraryRecord {
-	LibraryId: i32,
-	LibraryName: String,
+	pub LibraryId: i32,
+	pub LibraryName: String,
 }
 
 impl Record for BinaryLibraryRecord {
-	fn new(file: &mut File) -> Self {
-		BinaryLibraryRecord {
-			LibraryId: read_l_i32(file),
-			LibraryName: read_LengthPrefixedString(file),
-		}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(BinaryLibraryRecord {
+			LibraryId: try!(read_l_i32(file)),
+			LibraryName: try!(read_LengthPrefixedString(file)),
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		12
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		write_l_i32(file, self.LibraryId);
+		write_LengthPrefixedString(file, &self.LibraryName);
+	}
 }
 
 #[derive(Debug)]
 pub struct ObjectNullMultiple256Record {
-	NullCount: u8,
+	pub NullCount: u8,
 }
 
 impl Record for ObjectNullMultiple256Record {
-	fn new(file: &mut File) -> Self {
-		ObjectNullMultiple256Record {
-			NullCount: file.read_u8().unwrap(),
-		}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(ObjectNullMultiple256Record {
+			NullCount: try!(read_u8(file)),
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		13
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		file.write_u8(self.NullCount).unwrap();
+	}
 }
 
 #[derive(Debug)]
 pub struct ObjectNullMultipleRecord {
-	NullCount: i32,
+	pub NullCount: i32,
 }
 
 impl Record for ObjectNullMultipleRecord {
-	fn new(file: &mut File) -> Self {
-		ObjectNullMultipleRecord {
-			NullCount: read_l_i32(file),
-		}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(ObjectNullMultipleRecord {
+			NullCount: try!(read_count(file, "NullCount")),
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		14
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		write_l_i32(file, self.NullCount);
+	}
+}
+
+/// Elements of a primitive array, stored by type
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrimitiveValues {
+	Boolean(Vec<bool>),
+	Byte(Vec<u8>),
+	Char(Vec<char>),
+	Decimal(Vec<Decimal>),
+	Double(Vec<f64>),
+	Int16(Vec<i16>),
+	Int32(Vec<i32>),
+	Int64(Vec<i64>),
+	SByte(Vec<i8>),
+	Single(Vec<f32>),
+	TimeSpan(Vec<TimeSpan>),
+	DateTime(Vec<DateTime>),
+	UInt16(Vec<u16>),
+	UInt32(Vec<u32>),
+	UInt64(Vec<u64>),
+	String(Vec<String>),
+}
+
+/// Fixed size elements are read in one go, an error being located at the element it happened in
+fn read_element_bytes(file: &mut OffsetRead, length: usize, size: usize) -> Result<Vec<u8>, DeserializeError> {
+	let start = file.offset();
+	read_n_bytes(file, length * size).map_err(|e| {
+		let i = (e.offset() - start) / size as u64;
+		e.within(format!("element {}", i))
+	})
+}
+
+/// Elements of variable size are read one by one
+fn read_element_vec<T, F>(file: &mut OffsetRead, length: usize, read: F) -> Result<Vec<T>, DeserializeError>
+	where F: Fn(&mut OffsetRead) -> Result<T, DeserializeError> {
+	let mut v = vec![];
+	for i in 0..length {
+		v.push(try!(read(file).map_err(|e| e.within(format!("element {}", i)))));
+	}
+	Ok(v)
+}
+
+impl PrimitiveValues {
+	/// Elements are decoded in bulk, from the bytes of the whole array for the types of fixed size
+	pub fn new(file: &mut OffsetRead, primitive_type: &PrimitiveTypeEnumeration, length: usize) -> Result<Self, DeserializeError> {
+		use sub_records::PrimitiveTypeEnumeration as P;
+		use self::PrimitiveValues as V;
+
+		Ok(match primitive_type {
+			&P::Boolean => V::Boolean(try!(read_element_vec(file, length, read_boolean))),
+			&P::Byte => V::Byte(try!(read_element_bytes(file, length, 1))),
+			&P::Char => V::Char(try!(read_element_vec(file, length, read_char))),
+			&P::Decimal => V::Decimal(try!(read_element_vec(file, length, |file| {
+				read_LengthPrefixedString(file).map(|s| Decimal { Value: s })
+			}))),
+			&P::Double => V::Double(try!(read_element_bytes(file, length, 8)).chunks(8).map(LittleEndian::read_f64).collect()),
+			&P::Int16 => V::Int16(try!(read_element_bytes(file, length, 2)).chunks(2).map(LittleEndian::read_i16).collect()),
+			&P::Int32 => V::Int32(try!(read_element_bytes(file, length, 4)).chunks(4).map(LittleEndian::read_i32).collect()),
+			&P::Int64 => V::Int64(try!(read_element_bytes(file, length, 8)).chunks(8).map(LittleEndian::read_i64).collect()),
+			&P::SByte => V::SByte(try!(read_element_bytes(file, length, 1)).into_iter().map(|x| x as i8).collect()),
+			&P::Single => V::Single(try!(read_element_bytes(file, length, 4)).chunks(4).map(LittleEndian::read_f32).collect()),
+			&P::TimeSpan => V::TimeSpan(try!(read_element_bytes(file, length, 8)).chunks(8)
+				.map(|bytes| TimeSpan { Ticks: LittleEndian::read_i64(bytes) })
+				.collect()),
+			&P::DateTime => V::DateTime(try!(read_element_bytes(file, length, 8)).chunks(8)
+				.map(|bytes| DateTime::from_raw(LittleEndian::read_i64(bytes)))
+				.collect()),
+			&P::UInt16 => V::UInt16(try!(read_element_bytes(file, length, 2)).chunks(2).map(LittleEndian::read_u16).collect()),
+			&P::UInt32 => V::UInt32(try!(read_element_bytes(file, length, 4)).chunks(4).map(LittleEndian::read_u32).collect()),
+			&P::UInt64 => V::UInt64(try!(read_element_bytes(file, length, 8)).chunks(8).map(LittleEndian::read_u64).collect()),
+			&P::String => V::String(try!(read_element_vec(file, length, read_LengthPrefixedString))),
+			&P::Null => return Err(DeserializeError::InvalidEnumValue(error_at(file, 0), "PrimitiveTypeEnumeration of an array", P::Null as u8)),
+			&P::Unknown => unreachable!(),
+		})
+	}
+
+	/// No elements yet, see push
+	pub fn empty(primitive_type: &PrimitiveTypeEnumeration) -> Self {
+		use sub_records::PrimitiveTypeEnumeration as P;
+		use self::PrimitiveValues as V;
+
+		match primitive_type {
+			&P::Boolean => V::Boolean(vec![]),
+			&P::Byte => V::Byte(vec![]),
+			&P::Char => V::Char(vec![]),
+			&P::Decimal => V::Decimal(vec![]),
+			&P::Double => V::Double(vec![]),
+			&P::Int16 => V::Int16(vec![]),
+			&P::Int32 => V::Int32(vec![]),
+			&P::Int64 => V::Int64(vec![]),
+			&P::SByte => V::SByte(vec![]),
+			&P::Single => V::Single(vec![]),
+			&P::TimeSpan => V::TimeSpan(vec![]),
+			&P::DateTime => V::DateTime(vec![]),
+			&P::UInt16 => V::UInt16(vec![]),
+			&P::UInt32 => V::UInt32(vec![]),
+			&P::UInt64 => V::UInt64(vec![]),
+			&P::String => V::String(vec![]),
+			s @ &P::Null | s @ &P::Unknown => panic!("Unprocessed PrimitiveType: {:?}", s),
+		}
+	}
+
+	/// Adds an element of the type of the array
+	pub fn push(&mut self, primitive: Primitive) {
+		use self::PrimitiveValues as V;
+
+		match (self, &primitive) {
+			(&mut V::Boolean(ref mut v), &Primitive::Boolean(x)) => v.push(x),
+			(&mut V::Byte(ref mut v), &Primitive::Byte(x)) => v.push(x),
+			(&mut V::Char(ref mut v), &Primitive::Char(x)) => v.push(x),
+			(&mut V::Decimal(ref mut v), &Primitive::Decimal(ref x)) => v.push(x.clone()),
+			(&mut V::Double(ref mut v), &Primitive::Double(x)) => v.push(x),
+			(&mut V::Int16(ref mut v), &Primitive::Int16(x)) => v.push(x),
+			(&mut V::Int32(ref mut v), &Primitive::Int32(x)) => v.push(x),
+			(&mut V::Int64(ref mut v), &Primitive::Int64(x)) => v.push(x),
+			(&mut V::SByte(ref mut v), &Primitive::SByte(x)) => v.push(x),
+			(&mut V::Single(ref mut v), &Primitive::Single(x)) => v.push(x),
+			(&mut V::TimeSpan(ref mut v), &Primitive::TimeSpan(x)) => v.push(x),
+			(&mut V::DateTime(ref mut v), &Primitive::DateTime(x)) => v.push(x),
+			(&mut V::UInt16(ref mut v), &Primitive::UInt16(x)) => v.push(x),
+			(&mut V::UInt32(ref mut v), &Primitive::UInt32(x)) => v.push(x),
+			(&mut V::UInt64(ref mut v), &Primitive::UInt64(x)) => v.push(x),
+			(values, primitive) => panic!("{:?} cannot be added to {:?} elements", primitive, values.get_primitive_type()),
+		}
+	}
+
+	pub fn get_primitive_type(&self) -> PrimitiveTypeEnumeration {
+		use sub_records::PrimitiveTypeEnumeration as P;
+		use self::PrimitiveValues as V;
+
+		match self {
+			&V::Boolean(_) => P::Boolean,
+			&V::Byte(_) => P::Byte,
+			&V::Char(_) => P::Char,
+			&V::Decimal(_) => P::Decimal,
+			&V::Double(_) => P::Double,
+			&V::Int16(_) => P::Int16,
+			&V::Int32(_) => P::Int32,
+			&V::Int64(_) => P::Int64,
+			&V::SByte(_) => P::SByte,
+			&V::Single(_) => P::Single,
+			&V::TimeSpan(_) => P::TimeSpan,
+			&V::DateTime(_) => P::DateTime,
+			&V::UInt16(_) => P::UInt16,
+			&V::UInt32(_) => P::UInt32,
+			&V::UInt64(_) => P::UInt64,
+			&V::String(_) => P::String,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		use self::PrimitiveValues as V;
+
+		match self {
+			&V::Boolean(ref v) => v.len(),
+			&V::Byte(ref v) => v.len(),
+			&V::Char(ref v) => v.len(),
+			&V::Decimal(ref v) => v.len(),
+			&V::Double(ref v) => v.len(),
+			&V::Int16(ref v) => v.len(),
+			&V::Int32(ref v) => v.len(),
+			&V::Int64(ref v) => v.len(),
+			&V::SByte(ref v) => v.len(),
+			&V::Single(ref v) => v.len(),
+			&V::TimeSpan(ref v) => v.len(),
+			&V::DateTime(ref v) => v.len(),
+			&V::UInt16(ref v) => v.len(),
+			&V::UInt32(ref v) => v.len(),
+			&V::UInt64(ref v) => v.len(),
+			&V::String(ref v) => v.len(),
+		}
+	}
+
+	pub fn write(&self, file: &mut Write) {
+		use self::PrimitiveValues as V;
+
+		match self {
+			&V::Boolean(ref v) => for x in v.iter() { file.write_u8(*x as u8).unwrap() },
+			&V::Byte(ref v) => file.write_all(v).unwrap(),
+			&V::Char(ref v) => for x in v.iter() { write_char(file, *x) },
+			&V::Decimal(ref v) => for x in v.iter() { write_LengthPrefixedString(file, &x.Value) },
+			&V::Double(ref v) => for x in v.iter() { write_l_f64(file, *x) },
+			&V::Int16(ref v) => for x in v.iter() { write_l_i16(file, *x) },
+			&V::Int32(ref v) => for x in v.iter() { write_l_i32(file, *x) },
+			&V::Int64(ref v) => for x in v.iter() { write_l_i64(file, *x) },
+			&V::SByte(ref v) => for x in v.iter() { file.write_i8(*x).unwrap() },
+			&V::Single(ref v) => for x in v.iter() { write_l_f32(file, *x) },
+			&V::TimeSpan(ref v) => for x in v.iter() { write_l_i64(file, x.Ticks) },
+			&V::DateTime(ref v) => for x in v.iter() { write_l_i64(file, x.to_raw()) },
+			&V::UInt16(ref v) => for x in v.iter() { write_l_u16(file, *x) },
+			&V::UInt32(ref v) => for x in v.iter() { write_l_u32(file, *x) },
+			&V::UInt64(ref v) => for x in v.iter() { write_l_u64(file, *x) },
+			&V::String(ref v) => for x in v.iter() { write_LengthPrefixedString(file, x) },
+		}
+	}
 }
 
 #[derive(Debug)]
 pub struct ArraySinglePrimitiveRecord {
-	ArrayInfo: ArrayInfoRecord,
-	PrimitiveTypeEnum: PrimitiveTypeEnumeration,
-	Values: Vec<i32>,
+	pub ArrayInfo: ArrayInfoRecord,
+	pub PrimitiveTypeEnum: PrimitiveTypeEnumeration,
+	pub Values: PrimitiveValues,
 }
 
 impl Record for ArraySinglePrimitiveRecord {
-	fn new(file: &mut File) -> Self {
-		let ai = ArrayInfoRecord::new(file);
-		let pte = PrimitiveTypeEnumeration::from(file.read_u8().unwrap());
-		let v = match pte {
-			PrimitiveTypeEnumeration::Int32 => {
-				let mut v = Vec::with_capacity(ai.Length as usize);
-				for _ in 0..(ai.Length as usize) {
-					v.push(read_l_i32(file));
-				}
-				v
-			},
-			s @ _ => panic!("Unprocessed PrimitiveType: {:?}", s),
-		};
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		let ai = try!(ArrayInfoRecord::new(file));
+		let pte = try!(read_enum(file));
+		let v = try!(PrimitiveValues::new(file, &pte, ai.Length as usize));
 
-		ArraySinglePrimitiveRecord {
+		Ok(ArraySinglePrimitiveRecord {
 			ArrayInfo: ai,
 			PrimitiveTypeEnum: pte,
 			Values: v,
-		}
+		})
 	}
 	fn get_record_type_value() -> u8 {
 		15
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		self.ArrayInfo.write(file);
+		file.write_u8(self.PrimitiveTypeEnum as u8).unwrap();
+		self.Values.write(file);
+	}
+}
+
+#[derive(Debug)]
+pub struct ArraySingleObjectRecord {
+	pub ArrayInfo: ArrayInfoRecord,
+	pub Values: Vec<Value>,
+}
+
+impl ArraySingleObjectRecord {
+	/// Elements may be class records using the classes read before
+	pub fn read(file: &mut OffsetRead, context: &mut ReadContext) -> Result<Self, DeserializeError> {
+		let ai = try!(ArrayInfoRecord::new(file));
+		let values = try!(read_element_records(file, ai.Length as usize, context));
+		Ok(ArraySingleObjectRecord {
+			ArrayInfo: ai,
+			Values: values,
+		})
+	}
+}
+
+impl Record for ArraySingleObjectRecord {
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		ArraySingleObjectRecord::read(file, &mut ReadContext::new(&mut Schema::new()))
+	}
+	fn get_record_type_value() -> u8 {
+		16
+	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		self.ArrayInfo.write(file);
+		for value in self.Values.iter() {
+			value.write(file);
+		}
+	}
 }
 
 #[derive(Debug)]
 pub struct ArraySingleStringRecord {
-	ArrayInfo: ArrayInfoRecord,
+	pub ArrayInfo: ArrayInfoRecord,
+	pub Values: Vec<Value>,
+}
+
+impl ArraySingleStringRecord {
+	/// Elements may be class records using the classes read before
+	pub fn read(file: &mut OffsetRead, context: &mut ReadContext) -> Result<Self, DeserializeError> {
+		let ai = try!(ArrayInfoRecord::new(file));
+		let values = try!(read_element_records(file, ai.Length as usize, context));
+		Ok(ArraySingleStringRecord {
+			ArrayInfo: ai,
+			Values: values,
+		})
+	}
 }
 
 impl Record for ArraySingleStringRecord {
-	fn new(file: &mut File) -> Self {
-		ArraySingleStringRecord {
-			ArrayInfo: ArrayInfoRecord::new(file),
-		}
+	fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		ArraySingleStringRecord::read(file, &mut ReadContext::new(&mut Schema::new()))
 	}
 	fn get_record_type_value() -> u8 {
 		17
 	}
+	fn write(&self, file: &mut Write) {
+		file.write_u8(Self::get_record_type_value()).unwrap();
+		self.ArrayInfo.write(file);
+		for value in self.Values.iter() {
+			value.write(file);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std;
+
+	use byteorder::ReadBytesExt;
+
+	use model::Primitive;
+	use schema::Schema;
+	use super::*;
+
+	fn all_primitive_values() -> Vec<PrimitiveValues> {
+		use super::PrimitiveValues as V;
+
+		vec![
+			V::Boolean(vec![true, false]),
+			V::Byte(vec![0, 255]),
+			V::Char(vec!['a', 'é', '中']),
+			V::Decimal(vec![Decimal { Value: "-1.5".to_string() }]),
+			V::Double(vec![1.5, -0.0, std::f64::MAX]),
+			V::Int16(vec![std::i16::MIN, std::i16::MAX]),
+			V::Int32(vec![std::i32::MIN, std::i32::MAX]),
+			V::Int64(vec![std::i64::MIN, std::i64::MAX]),
+			V::SByte(vec![std::i8::MIN, std::i8::MAX]),
+			V::Single(vec![0.25, std::f32::MIN]),
+			V::TimeSpan(vec![TimeSpan { Ticks: -1 }, TimeSpan { Ticks: 864000000000 }]),
+			V::DateTime(vec![DateTime { Ticks: 630822816000000000, Kind: 1 }]),
+			V::UInt16(vec![0, std::u16::MAX]),
+			V::UInt32(vec![0, std::u32::MAX]),
+			V::UInt64(vec![0, std::u64::MAX]),
+			V::String(vec!["".to_string(), "ab".to_string()]),
+			V::Int32(vec![]),
+		]
+	}
+
+	#[test]
+	fn primitive_array_round_trip() {
+		for values in all_primitive_values() {
+			let record = ArraySinglePrimitiveRecord {
+				ArrayInfo: ArrayInfoRecord { ObjectId: 1, Length: values.len() as i32 },
+				PrimitiveTypeEnum: values.get_primitive_type(),
+				Values: values.clone(),
+			};
+			let mut bytes = vec![];
+			record.write(&mut bytes);
+
+			let mut file = OffsetReader::new(&bytes[..]);
+			assert_eq!(file.read_u8().unwrap(), ArraySinglePrimitiveRecord::get_record_type_value());
+			let read = ArraySinglePrimitiveRecord::new(&mut file).unwrap();
+			assert_eq!(read.PrimitiveTypeEnum, values.get_primitive_type());
+			assert_eq!(read.Values, values);
+			assert_eq!(file.offset(), bytes.len() as u64);
+		}
+	}
+
+	#[test]
+	fn truncated_primitive_array() {
+		let bytes = [1, 0, 0, 0, 2, 0, 0, 0, 3];
+		let error = PrimitiveValues::new(&mut OffsetReader::new(&bytes[..]), &PrimitiveTypeEnumeration::Int32, 3).unwrap_err();
+		match error {
+			DeserializeError::UnexpectedEof(_) => {},
+			_ => panic!("Expected the end of the stream, got {:?}", error),
+		}
+		assert_eq!(error.path(), &vec!["element 2".to_string()]);
+	}
+
+	#[test]
+	fn invalid_booleans() {
+		let bytes = [1, 0, 2];
+		match PrimitiveValues::new(&mut OffsetReader::new(&bytes[..]), &PrimitiveTypeEnumeration::Boolean, 3) {
+			Err(DeserializeError::InvalidEnumValue(location, "Boolean", 2)) => {
+				assert_eq!(location.offset, 2);
+				assert_eq!(location.path, vec!["element 2".to_string()]);
+			},
+			result => panic!("Expected an invalid Boolean, got {:?}", result),
+		}
+		assert!(read_primitive(&mut OffsetReader::new(&[3u8][..]), &PrimitiveTypeEnumeration::Boolean).is_err());
+	}
+
+	#[test]
+	fn strings_and_nulls_are_no_primitives() {
+		assert!(read_primitive(&mut OffsetReader::new(&[0u8][..]), &PrimitiveTypeEnumeration::String).is_err());
+		assert!(read_primitive(&mut OffsetReader::new(&[][..]), &PrimitiveTypeEnumeration::Null).is_err());
+		assert!(PrimitiveValues::new(&mut OffsetReader::new(&[][..]), &PrimitiveTypeEnumeration::Null, 0).is_err());
+	}
+
+	#[test]
+	fn primitive_values_one_by_one() {
+		for values in all_primitive_values() {
+			let primitive_type = values.get_primitive_type();
+			if primitive_type == PrimitiveTypeEnumeration::String {
+				continue;
+			}
+			let mut bytes = vec![];
+			values.write(&mut bytes);
+			let mut file = OffsetReader::new(&bytes[..]);
+			let mut pushed = PrimitiveValues::empty(&primitive_type);
+			for _ in 0..values.len() {
+				pushed.push(read_primitive(&mut file, &primitive_type).unwrap());
+			}
+			assert_eq!(pushed, values);
+			assert_eq!(file.offset(), bytes.len() as u64);
+		}
+	}
+
+	#[test]
+	fn member_values_round_trip() {
+		let class = ClassWithMembersAndTypesRecord {
+			ClassInfo: ClassInfoRecord {
+				ObjectId: 1,
+				Name: "Game.Item".to_string(),
+				MemberCount: 3,
+				MemberNames: vec!["Count".to_string(), "Name".to_string(), "Owner".to_string()],
+			},
+			MemberTypeInfo: MemberTypeInfoRecord {
+				BinaryTypeEnums: vec![BinaryTypeEnumeration::Primitive, BinaryTypeEnumeration::String, BinaryTypeEnumeration::Object],
+				AdditionalInfos: vec![AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int16), AdditionalInfo::None, AdditionalInfo::None],
+			},
+			LibraryId: 2,
+		};
+		let mut bytes = vec![];
+		class.write_value(&mut bytes, &vec![
+			Value::Primitive(Primitive::Int16(-3)),
+			Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: 2, Value: "sword".to_string() }),
+			Value::ObjectNull(ObjectNullRecord {}),
+		]);
+		// An Int16 without a record, then the string and the null records
+		assert_eq!(&bytes[..3], &[0xfd, 0xff, 6][..]);
+
+		let mut schema = Schema::new();
+		let values = class.read_value(&mut OffsetReader::new(&bytes[..]), &mut ReadContext::new(&mut schema)).unwrap();
+		assert_eq!(values.len(), 3);
+		match (&values[0], &values[1], &values[2]) {
+			(&Value::Primitive(Primitive::Int16(-3)), &Value::BinaryObjectString(ref s), &Value::ObjectNull(_)) => assert_eq!(s.Value, "sword"),
+			_ => panic!("Unexpected values {:?}", values),
+		}
+		let mut written = vec![];
+		class.write_value(&mut written, &values);
+		assert_eq!(written, bytes);
+	}
 }
@@ -0,0 +1,90 @@
+//! Member types of classes by name, for SystemClassWithMembers and ClassWithMembers records which only give the member names.
+//!
+//! A schema file has the format of the "Classes" of the json files,
+//! `{"Classes": [{"Name": ..., "Members": [{"Name": ..., "BinaryType": ..., "AdditionalInfo": ...}]}]}`,
+//! so any json file written from a bytes file can be used as a schema as well.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use serde_json::{Value as JValue, Map, from_reader};
+use itertools::Zip;
+
+use records::*;
+use json::*;
+
+pub struct Schema {
+	classes: HashMap<String, JValue>,
+}
+
+impl Schema {
+	pub fn new() -> Self {
+		Schema {
+			classes: HashMap::new(),
+		}
+	}
+
+	/// Adds the classes of a schema file, checking their member types so that a broken schema fails here
+	pub fn load(&mut self, path: &Path) -> Result<(), String> {
+		let file = try!(OpenOptions::new().read(true).open(path).map_err(|e| format!("Error while opening schema file: {}", e)));
+		let json: JValue = try!(from_reader(file).map_err(|e| format!("Error while reading schema file: {:?}", e)));
+		for class_json in try!(get_array(&json, "Classes")).iter() {
+			let name = try!(get_str(class_json, "Name"));
+			for member_type in try!(get_array(class_json, "Members").map_err(|e| format!("Class {}: {}", name, e))).iter() {
+				try!(member_type_from_json(member_type).map_err(|e| format!("Class {}: {}", name, e)));
+			}
+			self.classes.insert(name.to_string(), class_json.clone());
+		}
+		Ok(())
+	}
+
+	/// Keeps the member types of a class read with them
+	pub fn add_class(&mut self, class: &ClassRecordForClassWithId) {
+		let (class_name, member_names, binary_types, additional_infos) = class.get_info_for_json();
+		let mut m = Map::new();
+		m.insert("Name".to_string(), JValue::String(class_name.clone()));
+		m.insert("Members".to_string(), JValue::Array(Zip::new((member_names, binary_types, additional_infos))
+			.map(|(name, binary_type, additional_info)| member_type_to_json(name, binary_type, additional_info))
+			.collect()));
+		self.classes.insert(class_name, JValue::Object(m));
+	}
+
+	pub fn get_member_type_info(&self, class_info: &ClassInfoRecord) -> Result<MemberTypeInfoRecord, String> {
+		self.get_member_types(&class_info.Name, &class_info.MemberNames)
+	}
+
+	/// Member types of a class given by its name and member names, which have to match those of the schema
+	pub fn get_member_types<S: AsRef<str>>(&self, class_name: &str, member_names: &[S]) -> Result<MemberTypeInfoRecord, String> {
+		let class_json = match self.classes.get(class_name) {
+			Some(class_json) => class_json,
+			None => return Err(format!("Member types of {} are unknown, give a schema with --schema or a file containing the class with its member types first", class_name)),
+		};
+		let mut binary_types = vec![];
+		let mut additional_infos = vec![];
+		let member_types = try!(get_array(class_json, "Members"));
+		if member_types.len() != member_names.len() {
+			return Err(format!("{} has {} members but {} in the schema", class_name, member_names.len(), member_types.len()));
+		}
+		for (member_name, member_type) in member_names.iter().zip(member_types.iter()) {
+			let (name, binary_type, additional_info) = try!(member_type_from_json(member_type));
+			if name != member_name.as_ref() {
+				return Err(format!("Member {} of {} is {} in the schema", member_name.as_ref(), class_name, name));
+			}
+			binary_types.push(binary_type);
+			additional_infos.push(additional_info);
+		}
+		Ok(MemberTypeInfoRecord {
+			BinaryTypeEnums: binary_types,
+			AdditionalInfos: additional_infos,
+		})
+	}
+
+	pub fn to_json(&self) -> JValue {
+		let mut names: Vec<&String> = self.classes.keys().collect();
+		names.sort();
+		let mut m = Map::new();
+		m.insert("Classes".to_string(), JValue::Array(names.into_iter().map(|name| self.classes[name].clone()).collect()));
+		JValue::Object(m)
+	}
+}
@@ -0,0 +1,638 @@
+//! Writing Rust types deriving `Serialize` as BinaryFormatter streams.
+//!
+//! Structs are written as classes of a single library, named after the Rust type unless a C# name
+//! is given in the `Config`. The member types of a class come from its first object, so the value is
+//! first serialized into a tree and then written the way .NET does: the root object, then the
+//! objects and arrays it references in the order they are met, strings being written inline.
+
+use std;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::collections::{HashMap, VecDeque};
+
+use serde::ser;
+use serde::ser::Serialize;
+
+use records::*;
+use model::{Primitive, MemberType};
+
+#[derive(Debug)]
+pub enum Error {
+	/// Raised by a `Serialize` implementation
+	Custom(String),
+	/// A Rust value which has no form in a stream, e.g. a map or an enum variant with fields
+	Unsupported(&'static str),
+	/// An object which does not fit the class written for the first object of its type: class name and what differs
+	ClassMismatch(String, String),
+	/// Writing the stream to the file failed
+	Io(io::Error),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			&Error::Custom(ref message) => write!(f, "{}", message),
+			&Error::Unsupported(what) => write!(f, "Cannot write {}", what),
+			&Error::ClassMismatch(ref class_name, ref message) => write!(f, "{} in an object of class {}", message, class_name),
+			&Error::Io(ref e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn description(&self) -> &str {
+		match self {
+			&Error::Custom(ref message) => &message[..],
+			&Error::Unsupported(_) => "Value which cannot be written",
+			&Error::ClassMismatch(_, ref message) => &message[..],
+			&Error::Io(ref e) => std::error::Error::description(e),
+		}
+	}
+}
+
+impl ser::Error for Error {
+	fn custom<T: Into<String>>(message: T) -> Self {
+		Error::Custom(message.into())
+	}
+}
+
+/// How Rust types are written as C# types
+#[derive(Debug, Clone)]
+pub struct Config {
+	/// Full name of the assembly the classes belong to
+	pub library_name: String,
+	/// C# type names (with their namespace) by Rust type name, other types keep their Rust name
+	pub type_names: HashMap<String, String>,
+}
+
+impl Config {
+	pub fn new(library_name: &str) -> Self {
+		Config {
+			library_name: library_name.to_string(),
+			type_names: HashMap::new(),
+		}
+	}
+
+	/// Writes the Rust type as the given C# type, e.g. `.type_name("Save", "Game.SaveData")`
+	pub fn type_name(mut self, rust_name: &str, csharp_name: &str) -> Self {
+		self.type_names.insert(rust_name.to_string(), csharp_name.to_string());
+		self
+	}
+
+	pub fn get_type_name<'c>(&'c self, rust_name: &'c str) -> &'c str {
+		match self.type_names.get(rust_name) {
+			Some(csharp_name) => csharp_name,
+			None => rust_name,
+		}
+	}
+}
+
+impl Default for Config {
+	/// The assembly of the scripts of Unity games
+	fn default() -> Self {
+		Config::new("Assembly-CSharp, Version=0.0.0.0, Culture=neutral, PublicKeyToken=null")
+	}
+}
+
+/// A serialized Rust value, before the member types of its classes are known
+#[derive(Debug)]
+enum Node {
+	Primitive(Primitive),
+	/// A primitive of an `Option`, written with its type as it is held by an `object` member
+	Typed(Primitive),
+	String(String),
+	Null,
+	/// Rust type name and members
+	Object(&'static str, Vec<(&'static str, Node)>),
+	Array(Vec<Node>),
+}
+
+fn to_node<T: Serialize>(value: T) -> Result<Node, Error> {
+	let mut serializer = NodeSerializer { node: None };
+	try!(value.serialize(&mut serializer));
+	Ok(serializer.node.unwrap_or(Node::Null))
+}
+
+struct NodeSerializer {
+	node: Option<Node>,
+}
+
+impl NodeSerializer {
+	fn primitive(&mut self, primitive: Primitive) -> Result<(), Error> {
+		self.node = Some(Node::Primitive(primitive));
+		Ok(())
+	}
+}
+
+impl ser::Serializer for NodeSerializer {
+	type Error = Error;
+	type SeqState = Vec<Node>;
+	type TupleState = Vec<Node>;
+	type TupleStructState = Vec<Node>;
+	type TupleVariantState = ();
+	type MapState = ();
+	type StructState = (&'static str, Vec<(&'static str, Node)>);
+	type StructVariantState = ();
+
+	fn serialize_bool(&mut self, v: bool) -> Result<(), Error> {
+		self.primitive(Primitive::Boolean(v))
+	}
+	fn serialize_isize(&mut self, v: isize) -> Result<(), Error> {
+		self.primitive(Primitive::Int64(v as i64))
+	}
+	fn serialize_i8(&mut self, v: i8) -> Result<(), Error> {
+		self.primitive(Primitive::SByte(v))
+	}
+	fn serialize_i16(&mut self, v: i16) -> Result<(), Error> {
+		self.primitive(Primitive::Int16(v))
+	}
+	fn serialize_i32(&mut self, v: i32) -> Result<(), Error> {
+		self.primitive(Primitive::Int32(v))
+	}
+	fn serialize_i64(&mut self, v: i64) -> Result<(), Error> {
+		self.primitive(Primitive::Int64(v))
+	}
+	fn serialize_usize(&mut self, v: usize) -> Result<(), Error> {
+		self.primitive(Primitive::UInt64(v as u64))
+	}
+	fn serialize_u8(&mut self, v: u8) -> Result<(), Error> {
+		self.primitive(Primitive::Byte(v))
+	}
+	fn serialize_u16(&mut self, v: u16) -> Result<(), Error> {
+		self.primitive(Primitive::UInt16(v))
+	}
+	fn serialize_u32(&mut self, v: u32) -> Result<(), Error> {
+		self.primitive(Primitive::UInt32(v))
+	}
+	fn serialize_u64(&mut self, v: u64) -> Result<(), Error> {
+		self.primitive(Primitive::UInt64(v))
+	}
+	fn serialize_f32(&mut self, v: f32) -> Result<(), Error> {
+		self.primitive(Primitive::Single(v))
+	}
+	fn serialize_f64(&mut self, v: f64) -> Result<(), Error> {
+		self.primitive(Primitive::Double(v))
+	}
+	fn serialize_char(&mut self, v: char) -> Result<(), Error> {
+		self.primitive(Primitive::Char(v))
+	}
+	fn serialize_str(&mut self, value: &str) -> Result<(), Error> {
+		self.node = Some(Node::String(value.to_string()));
+		Ok(())
+	}
+	fn serialize_bytes(&mut self, value: &[u8]) -> Result<(), Error> {
+		self.node = Some(Node::Array(value.iter().map(|&b| Node::Primitive(Primitive::Byte(b))).collect()));
+		Ok(())
+	}
+	fn serialize_unit(&mut self) -> Result<(), Error> {
+		self.node = Some(Node::Null);
+		Ok(())
+	}
+	fn serialize_unit_struct(&mut self, name: &'static str) -> Result<(), Error> {
+		self.node = Some(Node::Object(name, vec![]));
+		Ok(())
+	}
+	/// C# enums are objects with their value in `value__`
+	fn serialize_unit_variant(&mut self, name: &'static str, variant_index: usize, _variant: &'static str) -> Result<(), Error> {
+		self.node = Some(Node::Object(name, vec![("value__", Node::Primitive(Primitive::Int32(variant_index as i32)))]));
+		Ok(())
+	}
+	fn serialize_newtype_struct<T: Serialize>(&mut self, _name: &'static str, value: T) -> Result<(), Error> {
+		value.serialize(self)
+	}
+	fn serialize_newtype_variant<T: Serialize>(&mut self, _name: &'static str, _variant_index: usize, _variant: &'static str, _value: T) -> Result<(), Error> {
+		Err(Error::Unsupported("an enum variant with fields"))
+	}
+	fn serialize_none(&mut self) -> Result<(), Error> {
+		self.node = Some(Node::Null);
+		Ok(())
+	}
+	fn serialize_some<T: Serialize>(&mut self, value: T) -> Result<(), Error> {
+		self.node = Some(match try!(to_node(value)) {
+			Node::Primitive(primitive) => Node::Typed(primitive),
+			node => node,
+		});
+		Ok(())
+	}
+
+	fn serialize_seq(&mut self, len: Option<usize>) -> Result<Vec<Node>, Error> {
+		Ok(Vec::with_capacity(len.unwrap_or(0)))
+	}
+	fn serialize_seq_elt<T: Serialize>(&mut self, state: &mut Vec<Node>, value: T) -> Result<(), Error> {
+		state.push(try!(to_node(value)));
+		Ok(())
+	}
+	fn serialize_seq_end(&mut self, state: Vec<Node>) -> Result<(), Error> {
+		self.node = Some(Node::Array(state));
+		Ok(())
+	}
+	fn serialize_seq_fixed_size(&mut self, size: usize) -> Result<Vec<Node>, Error> {
+		self.serialize_seq(Some(size))
+	}
+
+	fn serialize_tuple(&mut self, len: usize) -> Result<Vec<Node>, Error> {
+		self.serialize_seq(Some(len))
+	}
+	fn serialize_tuple_elt<T: Serialize>(&mut self, state: &mut Vec<Node>, value: T) -> Result<(), Error> {
+		self.serialize_seq_elt(state, value)
+	}
+	fn serialize_tuple_end(&mut self, state: Vec<Node>) -> Result<(), Error> {
+		self.serialize_seq_end(state)
+	}
+
+	fn serialize_tuple_struct(&mut self, _name: &'static str, len: usize) -> Result<Vec<Node>, Error> {
+		self.serialize_seq(Some(len))
+	}
+	fn serialize_tuple_struct_elt<T: Serialize>(&mut self, state: &mut Vec<Node>, value: T) -> Result<(), Error> {
+		self.serialize_seq_elt(state, value)
+	}
+	fn serialize_tuple_struct_end(&mut self, state: Vec<Node>) -> Result<(), Error> {
+		self.serialize_seq_end(state)
+	}
+
+	fn serialize_tuple_variant(&mut self, _name: &'static str, _variant_index: usize, _variant: &'static str, _len: usize) -> Result<(), Error> {
+		Err(Error::Unsupported("an enum variant with fields"))
+	}
+	fn serialize_tuple_variant_elt<T: Serialize>(&mut self, _state: &mut (), _value: T) -> Result<(), Error> {
+		unreachable!()
+	}
+	fn serialize_tuple_variant_end(&mut self, _state: ()) -> Result<(), Error> {
+		unreachable!()
+	}
+
+	fn serialize_map(&mut self, _len: Option<usize>) -> Result<(), Error> {
+		Err(Error::Unsupported("a map"))
+	}
+	fn serialize_map_key<T: Serialize>(&mut self, _state: &mut (), _key: T) -> Result<(), Error> {
+		unreachable!()
+	}
+	fn serialize_map_value<T: Serialize>(&mut self, _state: &mut (), _value: T) -> Result<(), Error> {
+		unreachable!()
+	}
+	fn serialize_map_end(&mut self, _state: ()) -> Result<(), Error> {
+		unreachable!()
+	}
+
+	fn serialize_struct(&mut self, name: &'static str, len: usize) -> Result<(&'static str, Vec<(&'static str, Node)>), Error> {
+		Ok((name, Vec::with_capacity(len)))
+	}
+	fn serialize_struct_elt<V: Serialize>(&mut self, state: &mut (&'static str, Vec<(&'static str, Node)>), key: &'static str, value: V) -> Result<(), Error> {
+		state.1.push((key, try!(to_node(value))));
+		Ok(())
+	}
+	fn serialize_struct_end(&mut self, state: (&'static str, Vec<(&'static str, Node)>)) -> Result<(), Error> {
+		self.node = Some(Node::Object(state.0, state.1));
+		Ok(())
+	}
+
+	fn serialize_struct_variant(&mut self, _name: &'static str, _variant_index: usize, _variant: &'static str, _len: usize) -> Result<(), Error> {
+		Err(Error::Unsupported("an enum variant with fields"))
+	}
+	fn serialize_struct_variant_elt<V: Serialize>(&mut self, _state: &mut (), _key: &'static str, _value: V) -> Result<(), Error> {
+		unreachable!()
+	}
+	fn serialize_struct_variant_end(&mut self, _state: ()) -> Result<(), Error> {
+		unreachable!()
+	}
+}
+
+/// A class written with its member types, which the next objects of the class refer to
+struct WrittenClass {
+	id: i32,
+	member_names: Vec<&'static str>,
+	member_types: Vec<MemberType>,
+}
+
+struct Writer<'c> {
+	config: &'c Config,
+	library_id: i32,
+	next_id: i32,
+	/// Classes by C# name
+	classes: HashMap<String, WrittenClass>,
+	/// Objects and arrays referenced but not written yet, in the order they are written
+	pending: VecDeque<(i32, Node)>,
+}
+
+impl<'c> Writer<'c> {
+	fn new_id(&mut self) -> i32 {
+		let id = self.next_id;
+		self.next_id += 1;
+		id
+	}
+
+	fn member_type_of(&self, node: &Node) -> MemberType {
+		match node {
+			&Node::Primitive(ref primitive) => MemberType::Primitive(primitive.get_primitive_type()),
+			&Node::Typed(_) | &Node::Null => MemberType::Object,
+			&Node::String(_) => MemberType::String,
+			&Node::Object(name, _) => MemberType::Class(self.config.get_type_name(name).to_string(), self.library_id),
+			&Node::Array(ref elements) => match self.element_type_of(elements) {
+				MemberType::Primitive(primitive_type) => MemberType::PrimitiveArray(primitive_type),
+				MemberType::String => MemberType::StringArray,
+				MemberType::Class(name, library_id) => MemberType::Class(format!("{}[]", name), library_id),
+				_ => MemberType::ObjectArray,
+			},
+		}
+	}
+
+	/// The type shared by the elements, or Object for arrays of mixed types, of arrays or without elements
+	fn element_type_of(&self, elements: &[Node]) -> MemberType {
+		let mut element_type = None;
+		let mut has_null = false;
+		for element in elements.iter() {
+			if let &Node::Null = element {
+				has_null = true;
+				continue;
+			}
+			let member_type = self.member_type_of(element);
+			let same = match element_type {
+				Some(ref previous) => *previous == member_type,
+				None => true,
+			};
+			if !same {
+				return MemberType::Object;
+			}
+			element_type = Some(member_type);
+		}
+		match element_type {
+			Some(MemberType::Primitive(primitive_type)) if !has_null => MemberType::Primitive(primitive_type),
+			Some(MemberType::String) => MemberType::String,
+			Some(MemberType::Class(name, library_id)) => {
+				if name.ends_with("]") {
+					MemberType::Object
+				} else {
+					MemberType::Class(name, library_id)
+				}
+			},
+			_ => MemberType::Object,
+		}
+	}
+
+	/// Strings are written inline and objects and arrays are referenced, to be written later
+	fn value_of(&mut self, node: Node, member_type: &MemberType) -> Result<Value, String> {
+		match (node, member_type) {
+			(Node::Primitive(primitive), &MemberType::Primitive(primitive_type)) => {
+				if primitive.get_primitive_type() == primitive_type {
+					Ok(Value::Primitive(primitive))
+				} else {
+					Err(format!("{:?} given for a member of type {:?}", primitive.get_primitive_type(), primitive_type))
+				}
+			},
+			(node, &MemberType::Primitive(primitive_type)) => Err(format!("{:?} given for a member of type {:?}", node, primitive_type)),
+			(Node::Primitive(primitive), _) | (Node::Typed(primitive), _) => {
+				Ok(Value::MemberPrimitiveTyped(MemberPrimitiveTypedRecord { PrimitiveTypeEnum: primitive.get_primitive_type(), Value: primitive }))
+			},
+			(Node::Null, _) => Ok(Value::ObjectNull(ObjectNullRecord {})),
+			(Node::String(string), _) => {
+				let id = self.new_id();
+				Ok(Value::BinaryObjectString(BinaryObjectStringRecord { ObjectId: id, Value: string }))
+			},
+			(node, _) => {
+				let id = self.new_id();
+				self.pending.push_back((id, node));
+				Ok(Value::MemberReference(MemberReferenceRecord { IdRef: id }))
+			},
+		}
+	}
+
+	/// Consecutive nulls are written as a single record
+	fn element_values(&mut self, elements: Vec<Node>, element_type: &MemberType) -> Result<Vec<Value>, Error> {
+		let mut values = vec![];
+		let mut null_count = 0;
+		for (i, element) in elements.into_iter().enumerate() {
+			if let Node::Null = element {
+				null_count += 1;
+				continue;
+			}
+			push_nulls(&mut values, null_count);
+			null_count = 0;
+			values.push(try!(self.value_of(element, element_type).map_err(|message| Error::Custom(format!("{} in element {}", message, i)))));
+		}
+		push_nulls(&mut values, null_count);
+		Ok(values)
+	}
+
+	fn write_record(&mut self, file: &mut Write, id: i32, node: Node) -> Result<(), Error> {
+		match node {
+			Node::Object(rust_name, members) => self.write_object(file, id, rust_name, members),
+			Node::Array(elements) => self.write_array(file, id, elements),
+			_ => unreachable!(),
+		}
+	}
+
+	/// The first object of a class is written with the member types of the class, the next ones refer to it
+	fn write_object(&mut self, file: &mut Write, id: i32, rust_name: &'static str, members: Vec<(&'static str, Node)>) -> Result<(), Error> {
+		let name = self.config.get_type_name(rust_name).to_string();
+		let member_names: Vec<&'static str> = members.iter().map(|&(member_name, _)| member_name).collect();
+		let written = self.classes.get(&name).map(|class| (class.id, class.member_names.clone(), class.member_types.clone()));
+		let member_types = match written {
+			Some((class_id, class_member_names, member_types)) => {
+				if class_member_names != member_names {
+					return Err(Error::ClassMismatch(name, format!("Members {:?} given for members {:?}", member_names, class_member_names)));
+				}
+				ClassWithIdRecord { ObjectId: id, MetadataId: class_id }.write(file);
+				member_types
+			},
+			None => {
+				let member_types: Vec<MemberType> = members.iter().map(|&(_, ref node)| self.member_type_of(node)).collect();
+				ClassWithMembersAndTypesRecord {
+					ClassInfo: ClassInfoRecord {
+						ObjectId: id,
+						Name: name.clone(),
+						MemberCount: members.len() as i32,
+						MemberNames: member_names.iter().map(|member_name| member_name.to_string()).collect(),
+					},
+					MemberTypeInfo: MemberTypeInfoRecord {
+						BinaryTypeEnums: member_types.iter().map(MemberType::get_binary_type).collect(),
+						AdditionalInfos: member_types.iter().map(MemberType::get_additional_info).collect(),
+					},
+					LibraryId: self.library_id,
+				}.write(file);
+				self.classes.insert(name.clone(), WrittenClass {
+					id: id,
+					member_names: member_names,
+					member_types: member_types.clone(),
+				});
+				member_types
+			},
+		};
+
+		for ((member_name, node), member_type) in members.into_iter().zip(member_types.iter()) {
+			match self.value_of(node, member_type) {
+				Ok(value) => value.write(file),
+				Err(message) => return Err(Error::ClassMismatch(name, format!("{} in member {}", message, member_name))),
+			}
+		}
+		Ok(())
+	}
+
+	fn write_array(&mut self, file: &mut Write, id: i32, elements: Vec<Node>) -> Result<(), Error> {
+		let length = elements.len() as i32;
+		match self.element_type_of(&elements) {
+			MemberType::Primitive(primitive_type) => {
+				let mut values = PrimitiveValues::empty(&primitive_type);
+				for element in elements.into_iter() {
+					match element {
+						Node::Primitive(primitive) => values.push(primitive),
+						_ => unreachable!(),
+					}
+				}
+				ArraySinglePrimitiveRecord {
+					ArrayInfo: ArrayInfoRecord { ObjectId: id, Length: length },
+					PrimitiveTypeEnum: primitive_type,
+					Values: values,
+				}.write(file);
+			},
+			MemberType::String => {
+				ArraySingleStringRecord {
+					ArrayInfo: ArrayInfoRecord { ObjectId: id, Length: length },
+					Values: try!(self.element_values(elements, &MemberType::String)),
+				}.write(file);
+			},
+			element_type @ MemberType::Class(..) => {
+				// Arrays of a class are typed, e.g. Foo[] rather than object[]
+				let values = try!(self.element_values(elements, &element_type));
+				BinaryArrayRecord {
+					ObjectId: id,
+					BinaryArrayTypeEnum: BinaryArrayTypeEnumeration::Single,
+					Rank: 1,
+					Lengths: vec![length],
+					LowerBounds: None,
+					TypeEnum: element_type.get_binary_type(),
+					AdditionalTypeInfo: element_type.get_additional_info(),
+					Values: ArrayValues::Records(values),
+				}.write(file);
+			},
+			_ => {
+				ArraySingleObjectRecord {
+					ArrayInfo: ArrayInfoRecord { ObjectId: id, Length: length },
+					Values: try!(self.element_values(elements, &MemberType::Object)),
+				}.write(file);
+			},
+		}
+		Ok(())
+	}
+}
+
+fn push_nulls(values: &mut Vec<Value>, null_count: i32) {
+	if null_count > 0 {
+		values.push(Value::null_run(null_count));
+	}
+}
+
+/// Writes the value, a struct or a sequence, as the root of a stream.
+/// The stream is built in memory first, so nothing is written if the value cannot be.
+pub fn to_vec<T: Serialize>(value: &T, config: &Config) -> Result<Vec<u8>, Error> {
+	let root = try!(to_node(value));
+	match root {
+		Node::Object(..) | Node::Array(..) => {},
+		_ => return Err(Error::Unsupported("a root which is not a struct or a sequence")),
+	}
+
+	let mut bytes = vec![];
+	let mut writer = Writer {
+		config: config,
+		library_id: 2,
+		next_id: 3,
+		classes: HashMap::new(),
+		pending: VecDeque::new(),
+	};
+	SerializationHeaderRecord { RootId: 1, HeaderId: -1, MajorVersion: 1, MinorVersion: 0 }.write(&mut bytes);
+	BinaryLibraryRecord { LibraryId: writer.library_id, LibraryName: config.library_name.clone() }.write(&mut bytes);
+	writer.pending.push_back((1, root));
+	while let Some((id, node)) = writer.pending.pop_front() {
+		try!(writer.write_record(&mut bytes, id, node));
+	}
+	MessageEndRecord {}.write(&mut bytes);
+	Ok(bytes)
+}
+
+pub fn to_writer<T: Serialize>(file: &mut Write, value: &T, config: &Config) -> Result<(), Error> {
+	let bytes = try!(to_vec(value, config));
+	file.write_all(&bytes).map_err(Error::Io)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::io::Write;
+	use std::collections::HashMap;
+
+	use de::from_reader;
+	use document::read_document;
+	use lossless::{read_records, write_records};
+	use records::*;
+	use schema::Schema;
+	use super::{Config, Error, to_vec, to_writer};
+
+	#[derive(Serialize, Deserialize, Debug, PartialEq)]
+	struct Item {
+		name: String,
+		count: i32,
+	}
+
+	#[derive(Serialize, Deserialize, Debug, PartialEq)]
+	struct Save {
+		gold: i64,
+		scores: Vec<i32>,
+		items: Vec<Item>,
+		best: Option<Item>,
+	}
+
+	fn save() -> Save {
+		Save {
+			gold: 1000,
+			scores: vec![3, 5, 8],
+			items: vec![Item { name: "sword".to_string(), count: 1 }, Item { name: "arrow".to_string(), count: 20 }],
+			best: None,
+		}
+	}
+
+	/// A file which cannot be written
+	struct Full;
+
+	impl Write for Full {
+		fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+			Err(io::Error::new(io::ErrorKind::Other, "full"))
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn read_back() {
+		let bytes = to_vec(&save(), &Config::new("Game").type_name("Save", "Game.SaveData")).unwrap();
+		let document = read_document(&bytes[..]).unwrap();
+		let root = document.get_root().and_then(|root| root.as_object()).unwrap();
+		assert_eq!(document.get_class(root.class_id).unwrap().name, "Game.SaveData");
+		assert_eq!(from_reader::<Save, _>(&bytes[..]).unwrap(), save());
+	}
+
+	#[test]
+	fn lossless_round_trip() {
+		let bytes = to_vec(&save(), &Config::new("Game")).unwrap();
+		let records = read_records(&mut OffsetReader::new(&bytes[..]), &mut ReadContext::new(&mut Schema::new())).unwrap();
+		let mut written = vec![];
+		write_records(&mut written, &records);
+		assert_eq!(written, bytes);
+	}
+
+	#[test]
+	fn map_root() {
+		match to_vec(&HashMap::<String, i32>::new(), &Config::new("Game")) {
+			Err(Error::Unsupported(_)) => {},
+			result => panic!("Expected a map to be unsupported, got {:?}", result),
+		}
+	}
+
+	#[test]
+	fn file_error() {
+		match to_writer(&mut Full, &save(), &Config::new("Game")) {
+			Err(Error::Io(_)) => {},
+			result => panic!("Expected a write error, got {:?}", result),
+		}
+	}
+}
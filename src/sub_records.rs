@@ -1,19 +1,49 @@
 use std;
-use std::any::Any;
-use std::fs::File;
-use std::mem::transmute;
+use std::io::prelude::*;
+use std::str::FromStr;
 
-use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
 
 pub use util::*;
 
-// See http://stackoverflow.com/questions/33687447/how-to-get-struct-reference-from-boxed-trait
-// I'd like the default Debug so that AdditionalInfo trait is necessary
-// And as_any cannot be derived yet...
-pub trait AdditionalInfo: std::fmt::Debug { fn as_any_ai(&self) -> &Any; }
-impl AdditionalInfo for String { fn as_any_ai(&self) -> &Any { self } }
-impl AdditionalInfo for ClassTypeInfoRecord { fn as_any_ai(&self) -> &Any { self } }
-impl AdditionalInfo for PrimitiveTypeEnumeration { fn as_any_ai(&self) -> &Any { self } }
+/// The additional info following a BinaryTypeEnumeration, in member type infos and binary arrays
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdditionalInfo {
+	/// For Primitive and PrimitiveArray
+	Primitive(PrimitiveTypeEnumeration),
+	/// Name of a class of mscorlib
+	SystemClass(String),
+	Class(ClassTypeInfoRecord),
+	/// For String, Object, ObjectArray and StringArray
+	None,
+}
+
+impl AdditionalInfo {
+	pub fn get_primitive_type(&self) -> Option<&PrimitiveTypeEnumeration> {
+		match self {
+			&AdditionalInfo::Primitive(ref primitive_type) => Some(primitive_type),
+			_ => None,
+		}
+	}
+
+	/// Name of the class of Class and SystemClass members
+	pub fn get_class_name(&self) -> Option<&str> {
+		match self {
+			&AdditionalInfo::SystemClass(ref class_name) => Some(class_name),
+			&AdditionalInfo::Class(ref class_type) => Some(&class_type.TypeName),
+			_ => None,
+		}
+	}
+
+	pub fn write(&self, file: &mut Write) {
+		match self {
+			&AdditionalInfo::Primitive(primitive_type) => file.write_u8(primitive_type as u8).unwrap(),
+			&AdditionalInfo::SystemClass(ref class_name) => write_LengthPrefixedString(file, class_name),
+			&AdditionalInfo::Class(ref class_type) => class_type.write(file),
+			&AdditionalInfo::None => {},
+		}
+	}
+}
 
 #[derive(Debug)]
 pub struct ClassInfoRecord {
@@ -24,24 +54,32 @@ pub struct ClassInfoRecord {
 }
 
 impl ClassInfoRecord {
-	pub fn new(file: &mut File) -> Self {
-		let oi = read_l_i32(file);
-		let name = read_LengthPrefixedString(file);
-		let mc = read_l_i32(file);
-		let mut v = Vec::with_capacity(mc as usize);
+	pub fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		let oi = try!(read_l_i32(file));
+		let name = try!(read_LengthPrefixedString(file));
+		let mc = try!(read_count(file, "MemberCount"));
+		let mut v = vec![];
 		for _ in 0..mc {
-			v.push(read_LengthPrefixedString(file));
+			v.push(try!(read_LengthPrefixedString(file)));
 		}
-		ClassInfoRecord {
+		Ok(ClassInfoRecord {
 			ObjectId: oi,
 			Name: name,
 			MemberCount: mc,
 			MemberNames: v,
+		})
+	}
+	pub fn write(&self, file: &mut Write) {
+		write_l_i32(file, self.ObjectId);
+		write_LengthPrefixedString(file, &self.Name);
+		write_l_i32(file, self.MemberCount);
+		for name in self.MemberNames.iter() {
+			write_LengthPrefixedString(file, name);
 		}
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PrimitiveTypeEnumeration {
 	Boolean=1,
 	Byte,
@@ -64,17 +102,252 @@ pub enum PrimitiveTypeEnumeration {
 	Unknown,
 }
 
-impl From<u8> for PrimitiveTypeEnumeration {
-	fn from(x: u8) -> Self {
-		if x >= PrimitiveTypeEnumeration::Unknown as u8 || x == 0 || x == 4 {
-			panic!("Invalid PrimitiveTypeEnumeration {:?}", x);
-		} else {
-			unsafe { transmute(x) }
+impl PrimitiveTypeEnumeration {
+	/// Bytes taken by a value, None for Char, Decimal and String, whose size varies
+	pub fn get_size(&self) -> Option<usize> {
+		use self::PrimitiveTypeEnumeration::*;
+		match *self {
+			Boolean | Byte | SByte => Some(1),
+			Int16 | UInt16 => Some(2),
+			Int32 | UInt32 | Single => Some(4),
+			Double | Int64 | UInt64 | TimeSpan | DateTime => Some(8),
+			Char | Decimal | Null | String | Unknown => None,
 		}
 	}
 }
 
-#[derive(Debug)]
+impl ByteEnum for PrimitiveTypeEnumeration {
+	fn from_byte(x: u8) -> Option<Self> {
+		use self::PrimitiveTypeEnumeration::*;
+		Some(match x {
+			1 => Boolean,
+			2 => Byte,
+			3 => Char,
+			5 => Decimal,
+			6 => Double,
+			7 => Int16,
+			8 => Int32,
+			9 => Int64,
+			10 => SByte,
+			11 => Single,
+			12 => TimeSpan,
+			13 => DateTime,
+			14 => UInt16,
+			15 => UInt32,
+			16 => UInt64,
+			17 => Null,
+			18 => String,
+			_ => return None,
+		})
+	}
+	fn enum_name() -> &'static str {
+		"PrimitiveTypeEnumeration"
+	}
+}
+
+impl FromStr for PrimitiveTypeEnumeration {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, String> {
+		use self::PrimitiveTypeEnumeration::*;
+		Ok(match s {
+			"Boolean" => Boolean,
+			"Byte" => Byte,
+			"Char" => Char,
+			"Decimal" => Decimal,
+			"Double" => Double,
+			"Int16" => Int16,
+			"Int32" => Int32,
+			"Int64" => Int64,
+			"SByte" => SByte,
+			"Single" => Single,
+			"TimeSpan" => TimeSpan,
+			"DateTime" => DateTime,
+			"UInt16" => UInt16,
+			"UInt32" => UInt32,
+			"UInt64" => UInt64,
+			"Null" => Null,
+			"String" => String,
+			_ => return Err(format!("Invalid PrimitiveTypeEnumeration {:?}", s)),
+		})
+	}
+}
+
+/// Decimal is written as a LengthPrefixedString, which is kept as is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decimal {
+	pub Value: String,
+}
+
+const TICKS_PER_SECOND: i64 = 10000000;
+const TICKS_PER_DAY: i64 = 86400 * TICKS_PER_SECOND;
+/// Days from 0001-01-01 to 1970-01-01
+const DAYS_TO_UNIX_EPOCH: i64 = 719162;
+
+/// See http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days_since_unix_epoch: i64) -> (i64, i64, i64) {
+	let z = days_since_unix_epoch + 719468;
+	let era = if z >= 0 { z } else { z - 146096 } / 146097;
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = doy - (153 * mp + 2) / 5 + 1;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 };
+	(yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+	let y = if m <= 2 { y - 1 } else { y };
+	let era = if y >= 0 { y } else { y - 399 } / 400;
+	let yoe = y - era * 400;
+	let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	era * 146097 + doe - 719468
+}
+
+/// "7" digits of ticks after the decimal point, shorter fractions are padded
+fn parse_fraction(s: &str) -> Result<i64, String> {
+	if s.len() > 7 {
+		return Err(format!("Too many digits in fraction {:?}", s));
+	}
+	let padded = format!("{:0<7}", s);
+	padded.parse().map_err(|_| format!("Invalid fraction {:?}", s))
+}
+
+fn parse_number(s: &str) -> Result<i64, String> {
+	s.parse().map_err(|_| format!("Invalid number {:?}", s))
+}
+
+fn parse_unsigned(s: &str) -> Result<u64, String> {
+	s.parse().map_err(|_| format!("Invalid number {:?}", s))
+}
+
+/// None when the ticks do not fit in 64 bits
+fn checked_ticks(days: u64, hours: u64, minutes: u64, seconds: u64, fraction: u64) -> Option<u64> {
+	hours.checked_mul(60).and_then(|x| x.checked_add(minutes))
+		.and_then(|x| x.checked_mul(60)).and_then(|x| x.checked_add(seconds))
+		.and_then(|x| x.checked_mul(TICKS_PER_SECOND as u64))
+		.and_then(|x| days.checked_mul(TICKS_PER_DAY as u64).and_then(|day_ticks| day_ticks.checked_add(x)))
+		.and_then(|x| x.checked_add(fraction))
+}
+
+/// Ticks of 100 nanoseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSpan {
+	pub Ticks: i64,
+}
+
+impl TimeSpan {
+	/// Same as the "c" format of .NET, [-][d.]hh:mm:ss[.fffffff]
+	pub fn to_string(&self) -> String {
+		let sign = if self.Ticks < 0 { "-" } else { "" };
+		// u64 so that the minimum value can be negated
+		let ticks = if self.Ticks < 0 { (self.Ticks as u64).wrapping_neg() } else { self.Ticks as u64 };
+		let days = ticks / TICKS_PER_DAY as u64;
+		let ticks = ticks % TICKS_PER_DAY as u64;
+		let seconds = ticks / TICKS_PER_SECOND as u64;
+		let fraction = ticks % TICKS_PER_SECOND as u64;
+		let mut s = String::from(sign);
+		if days > 0 {
+			s.push_str(&format!("{}.", days));
+		}
+		s.push_str(&format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60));
+		if fraction > 0 {
+			s.push_str(&format!(".{:07}", fraction));
+		}
+		s
+	}
+
+	pub fn from_str(s: &str) -> Result<Self, String> {
+		let (negative, s) = if s.starts_with('-') { (true, &s[1..]) } else { (false, s) };
+		let parts: Vec<&str> = s.split(':').collect();
+		if parts.len() != 3 {
+			return Err(format!("Invalid TimeSpan {:?}", s));
+		}
+		let (days, hours) = match parts[0].find('.') {
+			Some(i) => (try!(parse_unsigned(&parts[0][..i])), try!(parse_unsigned(&parts[0][i + 1..]))),
+			None => (0, try!(parse_unsigned(parts[0]))),
+		};
+		let minutes = try!(parse_unsigned(parts[1]));
+		let (seconds, fraction) = match parts[2].find('.') {
+			Some(i) => (try!(parse_unsigned(&parts[2][..i])), try!(parse_fraction(&parts[2][i + 1..])) as u64),
+			None => (try!(parse_unsigned(parts[2])), 0),
+		};
+		// The magnitude of the minimum value is one more than the maximum
+		let ticks = match (checked_ticks(days, hours, minutes, seconds, fraction), negative) {
+			(Some(ticks), false) if ticks <= std::i64::MAX as u64 => ticks as i64,
+			(Some(ticks), true) if ticks <= std::i64::MAX as u64 + 1 => (ticks as i64).wrapping_neg(),
+			_ => return Err(format!("TimeSpan {:?} out of range", s)),
+		};
+		Ok(TimeSpan { Ticks: ticks })
+	}
+}
+
+/// Ticks since 0001-01-01 in the lower 62 bits and DateTimeKind in the upper 2 bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateTime {
+	pub Ticks: i64,
+	pub Kind: u8,
+}
+
+const DATE_TIME_KIND_SUFFIXES: [&'static str; 4] = ["", "Z", " Local", " LocalAmbiguousDst"];
+
+impl DateTime {
+	pub fn from_raw(raw: i64) -> Self {
+		DateTime {
+			Ticks: raw & 0x3fffffffffffffff,
+			Kind: ((raw as u64) >> 62) as u8,
+		}
+	}
+
+	pub fn to_raw(&self) -> i64 {
+		self.Ticks | ((self.Kind as i64) << 62)
+	}
+
+	/// ISO 8601 with 7 digits of fraction, "Z" for UTC. Local times have no offset in the stream,
+	/// so they are marked by a suffix instead.
+	pub fn to_iso_string(&self) -> String {
+		let days = self.Ticks / TICKS_PER_DAY;
+		let ticks = self.Ticks % TICKS_PER_DAY;
+		let seconds = ticks / TICKS_PER_SECOND;
+		let (y, m, d) = civil_from_days(days - DAYS_TO_UNIX_EPOCH);
+		format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:07}{}", y, m, d,
+			seconds / 3600, seconds / 60 % 60, seconds % 60, ticks % TICKS_PER_SECOND,
+			DATE_TIME_KIND_SUFFIXES[self.Kind as usize])
+	}
+
+	pub fn from_iso_string(s: &str) -> Result<Self, String> {
+		let mut kind = 0;
+		let mut s = s;
+		for (i, suffix) in DATE_TIME_KIND_SUFFIXES.iter().enumerate().skip(1) {
+			if s.ends_with(suffix) {
+				kind = i as u8;
+				s = &s[..s.len() - suffix.len()];
+				break;
+			}
+		}
+
+		let invalid = || format!("Invalid DateTime {:?}", s);
+		let t = try!(s.find('T').ok_or_else(&invalid));
+		let date: Vec<&str> = s[..t].split('-').collect();
+		let time: Vec<&str> = s[t + 1..].split(':').collect();
+		if date.len() != 3 || time.len() != 3 {
+			return Err(invalid());
+		}
+		let (seconds, fraction) = match time[2].find('.') {
+			Some(i) => (try!(parse_number(&time[2][..i])), try!(parse_fraction(&time[2][i + 1..]))),
+			None => (try!(parse_number(time[2])), 0),
+		};
+		let days = days_from_civil(try!(parse_number(date[0])), try!(parse_number(date[1])), try!(parse_number(date[2]))) + DAYS_TO_UNIX_EPOCH;
+		let seconds = (try!(parse_number(time[0])) * 60 + try!(parse_number(time[1]))) * 60 + seconds;
+		Ok(DateTime {
+			Ticks: days * TICKS_PER_DAY + seconds * TICKS_PER_SECOND + fraction,
+			Kind: kind,
+		})
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinaryTypeEnumeration {
 	Primitive,
 	String,
@@ -88,111 +361,220 @@ pub enum BinaryTypeEnumeration {
 	Unknown,
 }
 
-impl From<u8> for BinaryTypeEnumeration {
-	fn from(x: u8) -> Self {
-		if x >= BinaryTypeEnumeration::Unknown as u8 {
-			panic!("Invalid BinaryTypeEnumeration {:?}", x);
-		} else {
-			unsafe { transmute(x) }
-		}
+impl ByteEnum for BinaryTypeEnumeration {
+	fn from_byte(x: u8) -> Option<Self> {
+		use self::BinaryTypeEnumeration::*;
+		Some(match x {
+			0 => Primitive,
+			1 => String,
+			2 => Object,
+			3 => SystemClass,
+			4 => Class,
+			5 => ObjectArray,
+			6 => StringArray,
+			7 => PrimitiveArray,
+			_ => return None,
+		})
+	}
+	fn enum_name() -> &'static str {
+		"BinaryTypeEnumeration"
 	}
 }
 
-#[derive(Debug)]
+impl FromStr for BinaryTypeEnumeration {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, String> {
+		use self::BinaryTypeEnumeration::*;
+		Ok(match s {
+			"Primitive" => Primitive,
+			"String" => String,
+			"Object" => Object,
+			"SystemClass" => SystemClass,
+			"Class" => Class,
+			"ObjectArray" => ObjectArray,
+			"StringArray" => StringArray,
+			"PrimitiveArray" => PrimitiveArray,
+			_ => return Err(format!("Invalid BinaryTypeEnumeration {:?}", s)),
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ClassTypeInfoRecord {
-	TypeName: String,
-	LibraryId: i32,
+	pub TypeName: String,
+	pub LibraryId: i32,
 }
 
 impl ClassTypeInfoRecord {
-	pub fn new(file: &mut File) -> Self {
-		ClassTypeInfoRecord {
-			TypeName: read_LengthPrefixedString(file),
-			LibraryId: read_l_i32(file),
-		}
+	pub fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(ClassTypeInfoRecord {
+			TypeName: try!(read_LengthPrefixedString(file)),
+			LibraryId: try!(read_l_i32(file)),
+		})
+	}
+	pub fn write(&self, file: &mut Write) {
+		write_LengthPrefixedString(file, &self.TypeName);
+		write_l_i32(file, self.LibraryId);
 	}
 }
 
 #[derive(Debug)]
 pub struct MemberTypeInfoRecord {
 	pub BinaryTypeEnums: Vec<BinaryTypeEnumeration>,
-	pub AdditionalInfos: Vec<Option<Box<AdditionalInfo>>>,
+	pub AdditionalInfos: Vec<AdditionalInfo>,
+}
+
+/// The additional info following a BinaryTypeEnumeration, in member type infos and binary arrays
+pub fn read_additional_info(file: &mut OffsetRead, binary_type: &BinaryTypeEnumeration) -> Result<AdditionalInfo, DeserializeError> {
+	Ok(match binary_type {
+		&BinaryTypeEnumeration::Primitive | &BinaryTypeEnumeration::PrimitiveArray => {
+			AdditionalInfo::Primitive(try!(read_enum(file)))
+		}
+		&BinaryTypeEnumeration::SystemClass => {
+			AdditionalInfo::SystemClass(try!(read_LengthPrefixedString(file)))
+		}
+		&BinaryTypeEnumeration::Class => {
+			AdditionalInfo::Class(try!(ClassTypeInfoRecord::new(file)))
+		}
+		&BinaryTypeEnumeration::String | &BinaryTypeEnumeration::Object |
+		&BinaryTypeEnumeration::ObjectArray | &BinaryTypeEnumeration::StringArray => {
+			AdditionalInfo::None
+		}
+		&BinaryTypeEnumeration::Unknown => unreachable!(),
+	})
 }
 
 impl MemberTypeInfoRecord {
-	pub fn new(file: &mut File, member_count: usize) -> Self {
-		let mut bte_v = Vec::with_capacity(member_count);
-		let mut ai_v: Vec<Option<Box<AdditionalInfo>>> = Vec::with_capacity(member_count);
+	pub fn new(file: &mut OffsetRead, member_count: usize) -> Result<Self, DeserializeError> {
+		let mut bte_v = vec![];
+		let mut ai_v: Vec<AdditionalInfo> = vec![];
 
 		for _ in 0..member_count {
-			bte_v.push(BinaryTypeEnumeration::from(file.read_u8().unwrap()));
+			bte_v.push(try!(read_enum(file)));
 		}
 		for bte in bte_v.iter() {
-			ai_v.push(
-				match bte {
-					&BinaryTypeEnumeration::Primitive => {
-						Some(box(PrimitiveTypeEnumeration::from(file.read_u8().unwrap())))
-					}
-					&BinaryTypeEnumeration::String => {
-						None
-					}
-					&BinaryTypeEnumeration::SystemClass => {
-						Some(box(read_LengthPrefixedString(file)))
-					}
-					&BinaryTypeEnumeration::Class => {
-						Some(box(ClassTypeInfoRecord::new(file)))
-					}
-					&BinaryTypeEnumeration::StringArray => {
-						None
-					}
-					&BinaryTypeEnumeration::PrimitiveArray => {
-						Some(box(PrimitiveTypeEnumeration::from(file.read_u8().unwrap())))
-					}
-					s @ _ => panic!("Unprocessed AdditionalInfo: {:?}", s),
-				}
-			);
-		}
-
-		MemberTypeInfoRecord {
+			ai_v.push(try!(read_additional_info(file, bte)));
+		}
+
+		Ok(MemberTypeInfoRecord {
 			BinaryTypeEnums: bte_v,
 			AdditionalInfos: ai_v,
+		})
+	}
+	pub fn write(&self, file: &mut Write) {
+		for bte in self.BinaryTypeEnums.iter() {
+			file.write_u8(*bte as u8).unwrap();
+		}
+		for ai in self.AdditionalInfos.iter() {
+			ai.write(file);
 		}
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinaryArrayTypeEnumeration {
-	Single, 
-	Jagged, 
-	Rectangular, 
-	SingleOffset, 
-	JaggedOffset, 
-	RectangularOffset, 
+	Single,
+	Jagged,
+	Rectangular,
+	SingleOffset,
+	JaggedOffset,
+	RectangularOffset,
 
 	Unknown,
 }
 
-impl From<u8> for BinaryArrayTypeEnumeration {
-	fn from(x: u8) -> Self {
-		if x >= BinaryArrayTypeEnumeration::Unknown as u8 {
-			panic!("Invalid BinaryArrayTypeEnumeration {:?}", x);
-		} else {
-			unsafe { transmute(x) }
-		}
+impl ByteEnum for BinaryArrayTypeEnumeration {
+	fn from_byte(x: u8) -> Option<Self> {
+		use self::BinaryArrayTypeEnumeration::*;
+		Some(match x {
+			0 => Single,
+			1 => Jagged,
+			2 => Rectangular,
+			3 => SingleOffset,
+			4 => JaggedOffset,
+			5 => RectangularOffset,
+			_ => return None,
+		})
+	}
+	fn enum_name() -> &'static str {
+		"BinaryArrayTypeEnumeration"
+	}
+}
+
+impl FromStr for BinaryArrayTypeEnumeration {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, String> {
+		use self::BinaryArrayTypeEnumeration::*;
+		Ok(match s {
+			"Single" => Single,
+			"Jagged" => Jagged,
+			"Rectangular" => Rectangular,
+			"SingleOffset" => SingleOffset,
+			"JaggedOffset" => JaggedOffset,
+			"RectangularOffset" => RectangularOffset,
+			_ => return Err(format!("Invalid BinaryArrayTypeEnumeration {:?}", s)),
+		})
 	}
 }
 
 #[derive(Debug)]
 pub struct ArrayInfoRecord {
-	ObjectId: i32,
+	pub ObjectId: i32,
 	pub Length: i32,
 }
 
 impl ArrayInfoRecord {
-	pub fn new(file: &mut File) -> Self {
-		ArrayInfoRecord {
-			ObjectId: read_l_i32(file),
-			Length: read_l_i32(file),
+	pub fn new(file: &mut OffsetRead) -> Result<Self, DeserializeError> {
+		Ok(ArrayInfoRecord {
+			ObjectId: try!(read_l_i32(file)),
+			Length: try!(read_count(file, "array length")),
+		})
+	}
+	pub fn write(&self, file: &mut Write) {
+		write_l_i32(file, self.ObjectId);
+		write_l_i32(file, self.Length);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std;
+	use super::*;
+
+	#[test]
+	fn time_span_round_trip() {
+		for &ticks in [0, 1, -1, 10000000, -36000000000, 864000000000 + 5, std::i64::MAX, std::i64::MIN].iter() {
+			let s = TimeSpan { Ticks: ticks }.to_string();
+			assert_eq!(TimeSpan::from_str(&s), Ok(TimeSpan { Ticks: ticks }), "{}", s);
 		}
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn time_span_out_of_range() {
+		assert!(TimeSpan::from_str("10675199.02:48:05.4775808").is_err());
+		assert!(TimeSpan::from_str("-10675199.02:48:05.4775809").is_err());
+		assert!(TimeSpan::from_str("99999999999999999.00:00:00").is_err());
+		assert!(TimeSpan::from_str("00:-1:00").is_err());
+	}
+
+	#[test]
+	fn member_type_info_round_trip() {
+		let info = MemberTypeInfoRecord {
+			BinaryTypeEnums: vec![BinaryTypeEnumeration::Primitive, BinaryTypeEnumeration::String,
+				BinaryTypeEnumeration::SystemClass, BinaryTypeEnumeration::Class],
+			AdditionalInfos: vec![
+				AdditionalInfo::Primitive(PrimitiveTypeEnumeration::Int64),
+				AdditionalInfo::None,
+				AdditionalInfo::SystemClass("System.Guid".to_string()),
+				AdditionalInfo::Class(ClassTypeInfoRecord { TypeName: "Game.Item".to_string(), LibraryId: 2 }),
+			],
+		};
+		let mut bytes = vec![];
+		info.write(&mut bytes);
+		let read = MemberTypeInfoRecord::new(&mut OffsetReader::new(&bytes[..]), 4).unwrap();
+		assert_eq!(read.BinaryTypeEnums, info.BinaryTypeEnums);
+		assert_eq!(read.AdditionalInfos, info.AdditionalInfos);
+		assert_eq!(read.AdditionalInfos[0].get_primitive_type(), Some(&PrimitiveTypeEnumeration::Int64));
+		assert_eq!(read.AdditionalInfos[2].get_primitive_type(), None);
+	}
+}
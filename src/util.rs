@@ -1,70 +1,278 @@
-use std::fs::File;
+use std;
+use std::io;
 use std::io::prelude::*;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use num::Integer;
 
-pub fn read_n_bytes(file: &mut File, n: usize) -> Vec<u8> {
-	let mut buffer = vec![0; n];
-	match file.read_exact(&mut buffer) {
-		Ok(_) => buffer,
-		_ => panic!("Reading file failed"),
+pub use error::*;
+
+/// Reading with the count of bytes read so far, which gives the offsets of errors without Seek
+pub trait OffsetRead: Read {
+	fn offset(&self) -> u64;
+}
+
+/// Counts the bytes read from any reader, e.g. a file, a byte slice, stdin or a decompressor
+pub struct OffsetReader<R> {
+	inner: R,
+	offset: u64,
+}
+
+impl<R: Read> OffsetReader<R> {
+	pub fn new(inner: R) -> Self {
+		OffsetReader {
+			inner: inner,
+			offset: 0,
+		}
+	}
+
+	pub fn into_inner(self) -> R {
+		self.inner
+	}
+}
+
+impl<R: Read> Read for OffsetReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = try!(self.inner.read(buf));
+		self.offset += n as u64;
+		Ok(n)
+	}
+}
+
+impl<R: Read> OffsetRead for OffsetReader<R> {
+	fn offset(&self) -> u64 {
+		self.offset
+	}
+}
+
+/// Location of an error in what was read the last `back` bytes
+pub fn error_at(file: &OffsetRead, back: u64) -> Location {
+	Location {
+		offset: file.offset().saturating_sub(back),
+		path: vec![],
 	}
 }
 
-pub fn read_LengthPrefixedString(file: &mut File) -> String {
+fn io_error(file: &OffsetRead, e: io::Error) -> DeserializeError {
+	let location = error_at(file, 0);
+	match e.kind() {
+		io::ErrorKind::UnexpectedEof => DeserializeError::UnexpectedEof(location),
+		_ => DeserializeError::Io(location, e),
+	}
+}
+
+/// Enumerations written as a single byte
+pub trait ByteEnum: Sized {
+	fn from_byte(x: u8) -> Option<Self>;
+	fn enum_name() -> &'static str;
+}
+
+pub fn read_enum<T: ByteEnum>(file: &mut OffsetRead) -> Result<T, DeserializeError> {
+	let x = try!(read_u8(file));
+	match T::from_byte(x) {
+		Some(value) => Ok(value),
+		None => Err(DeserializeError::InvalidEnumValue(error_at(file, 1), T::enum_name(), x)),
+	}
+}
+
+/// A member count, rank or array length, which is not negative nor too large to allocate
+pub fn read_count(file: &mut OffsetRead, what: &'static str) -> Result<i32, DeserializeError> {
+	let count = try!(read_l_i32(file));
+	if count < 0 || count as i64 > MAX_COUNT {
+		return Err(DeserializeError::LimitExceeded(error_at(file, 4), what, count as i64));
+	}
+	Ok(count)
+}
+
+/// The buffer grows with the bytes actually read, so a broken length does not allocate everything at once
+pub fn read_n_bytes(file: &mut OffsetRead, n: usize) -> Result<Vec<u8>, DeserializeError> {
+	let mut buffer = vec![];
+	let result = (&mut *file).take(n as u64).read_to_end(&mut buffer);
+	if let Err(e) = result {
+		return Err(io_error(file, e));
+	}
+	if buffer.len() < n {
+		return Err(DeserializeError::UnexpectedEof(error_at(file, 0)));
+	}
+	Ok(buffer)
+}
+
+/// The length of a LengthPrefixedString, 7 bits per byte
+pub fn read_string_length(file: &mut OffsetRead) -> Result<usize, DeserializeError> {
 	let mut length: usize = 0;
 	let mut byte_count: u8 = 0;
 	loop {
-		let current_length = file.read_u8().unwrap();
+		let current_length = try!(read_u8(file));
 		
 		if current_length > 0b01111111 {
 			length += ((current_length & 0b01111111) as usize) << (byte_count * 7);
 			byte_count += 1;
+			if byte_count == MAX_LENGTH_BYTES {
+				return Err(DeserializeError::LimitExceeded(error_at(file, byte_count as u64), "LengthPrefixedString length bytes", byte_count as i64 + 1));
+			}
 		} else {
 			length += (current_length as usize) << (byte_count * 7);
 			break;
 		}
 	}
+	if length > std::i32::MAX as usize {
+		return Err(DeserializeError::LimitExceeded(error_at(file, byte_count as u64 + 1), "LengthPrefixedString length", length as i64));
+	}
+	Ok(length)
+}
 
-	unsafe {
-		String::from_utf8_unchecked(read_n_bytes(file, length))
+pub fn read_LengthPrefixedString(file: &mut OffsetRead) -> Result<String, DeserializeError> {
+	let length = try!(read_string_length(file));
+	match String::from_utf8(try!(read_n_bytes(file, length))) {
+		Ok(s) => Ok(s),
+		Err(e) => {
+			let back = (length - e.utf8_error().valid_up_to()) as u64;
+			Err(DeserializeError::InvalidUtf8(error_at(file, back)))
+		},
 	}
 }
 
-pub fn write_LengthPrefixedString(file: &mut File, string: &str) {
+pub fn write_LengthPrefixedString(file: &mut Write, string: &str) {
 	let mut length = string.len();
 	let mut length_vec: Vec<u8> = vec![];
 	loop {
 		if length > 0b01111111 {
 			let (quotient, remainder) = length.div_rem(&0b10000000);
-			length_vec.push(remainder as u8);
+			length_vec.push(remainder as u8 | 0b10000000);
 			length = quotient;
 		} else {
 			length_vec.push(length as u8);
 			break;
 		}
 	}
-	file.write(&length_vec).unwrap();
+	file.write_all(&length_vec).unwrap();
 	write!(file, "{}", string).unwrap();
 }
 
-pub fn read_l_i32(file: &mut File) -> i32 {
-	file.read_i32::<LittleEndian>().unwrap()
+pub fn read_u8(file: &mut OffsetRead) -> Result<u8, DeserializeError> {
+	file.read_u8().map_err(|e| io_error(file, e))
 }
-pub fn read_l_f32(file: &mut File) -> f32 {
-	file.read_f32::<LittleEndian>().unwrap()
+pub fn read_i8(file: &mut OffsetRead) -> Result<i8, DeserializeError> {
+	file.read_i8().map_err(|e| io_error(file, e))
 }
-pub fn read_l_u64(file: &mut File) -> u64 {
-	file.read_u64::<LittleEndian>().unwrap()
+pub fn read_l_i16(file: &mut OffsetRead) -> Result<i16, DeserializeError> {
+	file.read_i16::<LittleEndian>().map_err(|e| io_error(file, e))
+}
+pub fn read_l_u16(file: &mut OffsetRead) -> Result<u16, DeserializeError> {
+	file.read_u16::<LittleEndian>().map_err(|e| io_error(file, e))
+}
+pub fn read_l_i32(file: &mut OffsetRead) -> Result<i32, DeserializeError> {
+	file.read_i32::<LittleEndian>().map_err(|e| io_error(file, e))
+}
+pub fn read_l_u32(file: &mut OffsetRead) -> Result<u32, DeserializeError> {
+	file.read_u32::<LittleEndian>().map_err(|e| io_error(file, e))
+}
+pub fn read_l_i64(file: &mut OffsetRead) -> Result<i64, DeserializeError> {
+	file.read_i64::<LittleEndian>().map_err(|e| io_error(file, e))
+}
+pub fn read_l_u64(file: &mut OffsetRead) -> Result<u64, DeserializeError> {
+	file.read_u64::<LittleEndian>().map_err(|e| io_error(file, e))
+}
+pub fn read_l_f32(file: &mut OffsetRead) -> Result<f32, DeserializeError> {
+	file.read_f32::<LittleEndian>().map_err(|e| io_error(file, e))
+}
+pub fn read_l_f64(file: &mut OffsetRead) -> Result<f64, DeserializeError> {
+	file.read_f64::<LittleEndian>().map_err(|e| io_error(file, e))
 }
 
-pub fn write_l_i32(file: &mut File, x: i32) {
+/// Char is written as the UTF-8 bytes of a single character
+pub fn read_char(file: &mut OffsetRead) -> Result<char, DeserializeError> {
+	let first = try!(read_u8(file));
+	let length = match first {
+		0x00...0x7f => 1,
+		0xc0...0xdf => 2,
+		0xe0...0xef => 3,
+		0xf0...0xf7 => 4,
+		_ => return Err(DeserializeError::InvalidUtf8(error_at(file, 1))),
+	};
+	let mut buffer = vec![first];
+	buffer.extend(try!(read_n_bytes(file, length - 1)));
+	match std::str::from_utf8(&buffer) {
+		Ok(s) => Ok(s.chars().next().unwrap()),
+		Err(_) => Err(DeserializeError::InvalidUtf8(error_at(file, length as u64))),
+	}
+}
+
+pub fn write_l_i16(file: &mut Write, x: i16) {
+	file.write_i16::<LittleEndian>(x).unwrap()
+}
+pub fn write_l_u16(file: &mut Write, x: u16) {
+	file.write_u16::<LittleEndian>(x).unwrap()
+}
+pub fn write_l_i32(file: &mut Write, x: i32) {
 	file.write_i32::<LittleEndian>(x).unwrap()
 }
-pub fn write_l_f32(file: &mut File, x: f32) {
-	file.write_f32::<LittleEndian>(x).unwrap()
+pub fn write_l_u32(file: &mut Write, x: u32) {
+	file.write_u32::<LittleEndian>(x).unwrap()
 }
-pub fn write_l_u64(file: &mut File, x: u64) {
+pub fn write_l_i64(file: &mut Write, x: i64) {
+	file.write_i64::<LittleEndian>(x).unwrap()
+}
+pub fn write_l_u64(file: &mut Write, x: u64) {
 	file.write_u64::<LittleEndian>(x).unwrap()
 }
+pub fn write_l_f32(file: &mut Write, x: f32) {
+	file.write_f32::<LittleEndian>(x).unwrap()
+}
+pub fn write_l_f64(file: &mut Write, x: f64) {
+	file.write_f64::<LittleEndian>(x).unwrap()
+}
+
+pub fn write_char(file: &mut Write, x: char) {
+	let mut s = String::new();
+	s.push(x);
+	file.write_all(s.as_bytes()).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::prelude::*;
+
+	use super::*;
+
+	#[test]
+	fn offsets_of_a_reader_without_seek() {
+		let mut file = OffsetReader::new((&b"\x05ab"[..]).chain(&b"cde\x07\x00\x00\x00"[..]));
+		assert_eq!(read_LengthPrefixedString(&mut file).unwrap(), "abcde");
+		assert_eq!(file.offset(), 6);
+		assert_eq!(read_l_i32(&mut file).unwrap(), 7);
+		assert_eq!(file.offset(), 10);
+	}
+
+	#[test]
+	fn offsets_of_errors() {
+		let mut file = OffsetReader::new(&b"\x01\x02"[..]);
+		match read_l_i32(&mut file) {
+			Err(DeserializeError::UnexpectedEof(location)) => assert_eq!(location.offset, 2),
+			result => panic!("Expected the end of the stream, got {:?}", result),
+		}
+
+		let mut file = OffsetReader::new(&b"\x03a\xffb"[..]);
+		match read_LengthPrefixedString(&mut file) {
+			Err(DeserializeError::InvalidUtf8(location)) => assert_eq!(location.offset, 2),
+			result => panic!("Expected invalid UTF-8, got {:?}", result),
+		}
+
+		let mut file = OffsetReader::new(&b"\x00\xff\xff\xff\xff"[..]);
+		read_u8(&mut file).unwrap();
+		match read_count(&mut file, "MemberCount") {
+			Err(DeserializeError::LimitExceeded(location, "MemberCount", -1)) => assert_eq!(location.offset, 1),
+			result => panic!("Expected the limit to be exceeded, got {:?}", result),
+		}
+	}
+
+	#[test]
+	fn length_longer_than_the_stream() {
+		// A length of 2^28 - 1 followed by three bytes
+		let mut file = OffsetReader::new(&b"\xff\xff\xff\x7fabc"[..]);
+		match read_LengthPrefixedString(&mut file) {
+			Err(DeserializeError::UnexpectedEof(location)) => assert_eq!(location.offset, 7),
+			result => panic!("Expected the end of the stream, got {:?}", result),
+		}
+	}
+}